@@ -0,0 +1,104 @@
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+use crate::config::{invalid, Section};
+use crate::privileges;
+
+/// Who a rule is about.
+#[derive(Debug)]
+enum Principal {
+    User(libc::uid_t),
+    Group(libc::gid_t),
+    Others,
+}
+
+/// The requests each local user may make of the control socket:
+///
+/// ```text
+/// [acl]
+/// user ci = inject, list, subscribe
+/// group plugdev = list, subscribe
+/// others = subscribe
+/// ```
+///
+/// A client gets what the rules for its user, its groups and others allow
+/// together. Root and the user the watch runs as may do anything; anyone
+/// else without a rule nothing.
+#[derive(Debug)]
+pub struct Acl {
+    rules: Vec<(Principal, Vec<String>)>,
+}
+
+/// The credentials of the process on the other end of a socket.
+pub struct Peer {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    pub groups: Vec<libc::gid_t>,
+}
+
+/// Asks the kernel who connected, with the supplementary groups the
+/// process had at the time, from /proc.
+pub fn peer(stream: &UnixStream) -> io::Result<Peer> {
+    // SAFETY: ucred is plain data, filled in by getsockopt up to len
+    let cred = unsafe {
+        let mut cred: libc::ucred = mem::zeroed();
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let ptr = &mut cred as *mut libc::ucred as *mut libc::c_void;
+        if libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED, ptr, &mut len) != 0 {
+            return Err(io::Error::last_os_error())
+        }
+        cred
+    };
+    let status = fs::read_to_string(format!("/proc/{}/status", cred.pid)).unwrap_or_default();
+    let groups = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .map(|groups| groups.split_whitespace().filter_map(|g| g.parse().ok()).collect())
+        .unwrap_or_default();
+    Ok(Peer{uid: cred.uid, gid: cred.gid, groups})
+}
+
+impl Acl {
+    pub fn new(section: &Section, capabilities: &[&str]) -> io::Result<Acl> {
+        let mut rules = Vec::new();
+        for (key, value) in section.entries() {
+            let principal = match key.split_once(char::is_whitespace) {
+                Some(("user", name)) => Principal::User(privileges::user(name.trim())?.0),
+                Some(("group", name)) => Principal::Group(privileges::group(name.trim())?),
+                None if key == "others" => Principal::Others,
+                _ => return Err(invalid(format!("[acl] {}: expected user NAME, group NAME or others", key))),
+            };
+            let allowed: Vec<String> = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+            if let Some(unknown) = allowed.iter().find(|a| !capabilities.contains(&a.as_str())) {
+                return Err(invalid(format!("[acl] {}: unknown request {}, expected one of {}", key, unknown, capabilities.join(", "))))
+            }
+            rules.push((principal, allowed));
+        }
+        Ok(Acl{rules})
+    }
+
+    /// The requests the peer may make, None for all of them.
+    pub fn allowed(&self, peer: &Peer) -> Option<Vec<String>> {
+        // SAFETY: geteuid can't fail
+        if peer.uid == 0 || peer.uid == unsafe { libc::geteuid() } {
+            return None
+        }
+        let mut allowed: Vec<String> = Vec::new();
+        for (principal, requests) in &self.rules {
+            let applies = match principal {
+                Principal::User(uid) => *uid == peer.uid,
+                Principal::Group(gid) => *gid == peer.gid || peer.groups.contains(gid),
+                Principal::Others => true,
+            };
+            for request in requests.iter().filter(|_| applies) {
+                if !allowed.contains(request) {
+                    allowed.push(request.clone());
+                }
+            }
+        }
+        Some(allowed)
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{invalid, Section};
+use crate::json;
+use crate::sha256;
+use crate::template::Fields;
+use crate::VerifyAuditArgs;
+
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// An append-only, hash-chained record of every event, configured by the
+/// `[audit]` section:
+///
+/// ```text
+/// [audit]
+/// path = /var/log/usbmon/audit.log
+/// ```
+///
+/// Each line is `<hash> <record>`, where the record is a JSON object with
+/// a sequence number, the time, the event fields and the hash of the line
+/// before it, and the hash is the SHA-256 of the record. Removing or
+/// editing a line breaks the chain from there on, which `usbmon
+/// verify-audit` finds. Every record is synced to disk before the event
+/// goes anywhere else.
+pub struct Audit {
+    path: PathBuf,
+    file: File,
+    seq: u64,
+    prev: String,
+}
+
+impl Audit {
+    /// Opens the log, carrying on the sequence and chain of an existing one.
+    pub fn new(section: &Section) -> io::Result<Audit> {
+        let path = PathBuf::from(section.require("path")?);
+        let existing = fs::read_to_string(&path).unwrap_or_default();
+        let (seq, prev) = match existing.lines().last() {
+            Some(line) => {
+                let (hash, record) = line
+                    .split_once(' ')
+                    .ok_or_else(|| invalid(format!("{}: last record is malformed", path.display())))?;
+                let seq = seq(record).ok_or_else(|| invalid(format!("{}: last record has no sequence number", path.display())))?;
+                (seq, hash.to_string())
+            },
+            None => (0, String::from(GENESIS)),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Audit{path, file, seq, prev})
+    }
+
+    pub fn record(&mut self, fields: &Fields) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut object: Vec<(&str, String)> = vec![("seq", (self.seq + 1).to_string()), ("time", time.to_string())];
+        object.extend(fields.iter().map(|(name, value)| (*name, json::string(value))));
+        object.push(("prev", json::string(&self.prev)));
+        let record = json::object(&object);
+        let hash = sha256::hex(record.as_bytes());
+        let written = writeln!(self.file, "{} {}", hash, record).and_then(|_| self.file.sync_data());
+        match written {
+            Ok(()) => {
+                self.seq += 1;
+                self.prev = hash;
+            },
+            Err(e) => eprintln!("audit: {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// The sequence number of a record, which comes first.
+fn seq(record: &str) -> Option<u64> {
+    record.strip_prefix("{\"seq\":")?.split(',').next()?.parse().ok()
+}
+
+/// Checks the chain of an audit log, printing what breaks it. Returns
+/// whether it is intact.
+pub fn run(args: &VerifyAuditArgs) -> Result<bool, String> {
+    let log = fs::read_to_string(&args.path).map_err(|e| format!("{}: {}", args.path.display(), e))?;
+    match verify(&log) {
+        Ok(count) => {
+            println!("{}: {} record(s), chain intact", args.path.display(), count);
+            Ok(true)
+        },
+        Err((line, why)) => {
+            println!("{}:{}: {}", args.path.display(), line, why);
+            Ok(false)
+        },
+    }
+}
+
+/// The number of records of an intact log, or the first line that breaks
+/// the chain and how.
+fn verify(log: &str) -> Result<u64, (usize, String)> {
+    let mut prev = String::from(GENESIS);
+    let mut count = 0;
+    for (i, line) in log.lines().enumerate() {
+        let broken = |why: &str| (i + 1, why.to_string());
+        let (hash, record) = line.split_once(' ').ok_or_else(|| broken("malformed record"))?;
+        if sha256::hex(record.as_bytes()) != hash {
+            return Err(broken("record doesn't match its hash"))
+        }
+        if json::get(record, "prev").as_deref() != Some(prev.as_str()) {
+            return Err(broken("previous hash doesn't match, a record was removed or added"))
+        }
+        if seq(record) != Some(count + 1) {
+            return Err(broken("sequence number out of order"))
+        }
+        prev = hash.to_string();
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn log(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("usbmon-audit-{}-{}", name, std::process::id()));
+        let conf = path.with_extension("conf");
+        fs::write(&conf, format!("[audit]\npath = {}\n", path.display())).unwrap();
+        let config = Config::load(&conf).unwrap();
+        let mut audit = Audit::new(config.section("audit").unwrap()).unwrap();
+        for id in ["1a2b:5678", "1d50:6018", "046d:c52b"] {
+            audit.record(&vec![("event", String::from("attach")), ("id", String::from(id))]);
+        }
+        let log = fs::read_to_string(&path).unwrap();
+        _ = fs::remove_file(&path);
+        _ = fs::remove_file(&conf);
+        log
+    }
+
+    #[test]
+    fn intact_log_verifies() {
+        assert_eq!(verify(&log("intact")), Ok(3));
+    }
+
+    #[test]
+    fn edited_line_fails_verification() {
+        let log = log("edited").replacen("1d50:6018", "1d50:6019", 1);
+        assert_eq!(verify(&log).map_err(|(line, _)| line), Err(2));
+    }
+
+    #[test]
+    fn removed_line_fails_verification() {
+        let log = log("removed");
+        let lines: Vec<&str> = log.lines().collect();
+        let log = format!("{}\n{}\n", lines[0], lines[2]);
+        assert_eq!(verify(&log).map_err(|(line, _)| line), Err(2));
+    }
+}
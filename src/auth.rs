@@ -0,0 +1,93 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::config::invalid;
+
+/// What a token lets its holder do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    /// Reading the merged event stream.
+    Read,
+    /// Pushing events in as an agent. The hub only relays events, there is
+    /// nothing remote to reset or deauthorize a device with.
+    Push,
+}
+
+impl Scope {
+    fn parse(word: &str) -> Option<Scope> {
+        match word {
+            "read" => Some(Scope::Read),
+            "push" => Some(Scope::Push),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Push => "push",
+        }
+    }
+}
+
+struct Token {
+    secret: String,
+    scopes: Vec<Scope>,
+    name: String,
+}
+
+/// The bearer tokens a hub takes, one per line:
+///
+/// ```text
+/// # TOKEN SCOPES [NAME]
+/// 7f3a...e1 read dashboard
+/// 0b9c...44 push lab1
+/// ```
+///
+/// A client proves it holds one by starting with `auth TOKEN`. An agent
+/// is the host its token is named for, so each gets a token of its own.
+/// Tokens without a name are named for their line.
+pub struct Tokens {
+    tokens: Vec<Token>,
+}
+
+/// Compares in time that doesn't depend on where the secrets differ.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+impl Tokens {
+    pub fn load(path: &Path) -> io::Result<Tokens> {
+        let mut tokens = Vec::new();
+        for (n, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let mut words = line.split_whitespace();
+            let (secret, scopes) = match (words.next(), words.next()) {
+                (Some(secret), Some(scopes)) => (secret, scopes),
+                _ => return Err(invalid(format!("line {}: expected TOKEN SCOPES [NAME]", n + 1))),
+            };
+            let scopes = scopes
+                .split(',')
+                .map(|scope| Scope::parse(scope).ok_or_else(|| invalid(format!("line {}: unknown scope {}, expected read or push", n + 1, scope))))
+                .collect::<io::Result<Vec<Scope>>>()?;
+            let name = words.next().map_or_else(|| format!("line {}", n + 1), str::to_string);
+            tokens.push(Token{secret: secret.to_string(), scopes, name});
+        }
+        Ok(Tokens{tokens})
+    }
+
+    /// Checks the first line of a client for a token with the scope, and
+    /// returns the token's name for the log.
+    pub fn check(&self, line: &str, scope: Scope) -> Result<&str, String> {
+        let secret = line.trim_end().strip_prefix("auth ").ok_or("expected auth TOKEN")?;
+        let token = self.tokens.iter().find(|t| same(&t.secret, secret)).ok_or("unknown token")?;
+        if !token.scopes.contains(&scope) {
+            return Err(format!("token {} doesn't have the {} scope", token.name, scope.name()))
+        }
+        Ok(&token.name)
+    }
+}
@@ -0,0 +1,56 @@
+use crate::inventory;
+use crate::sysfs;
+use crate::{AutosuspendArgs, DeviceID, Filter};
+
+/// What makes the setting stick across replugs and reboots, since sysfs
+/// forgets it as soon as the device goes away.
+fn udev_rule(id: &DeviceID, control: &str) -> String {
+    format!("ACTION==\"add\", SUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{:04x}\", ATTR{{idProduct}}==\"{:04x}\", \
+        TEST==\"power/control\", ATTR{{power/control}}=\"{}\"", id.vid, id.pid, control)
+}
+
+/// Shows or changes runtime power management of the matching devices
+/// through their power/control and power/autosuspend_delay_ms attributes:
+/// `on` keeps a device from being suspended, `auto` lets the kernel
+/// suspend it once it has been idle for the delay. Returns whether every
+/// device was changed.
+pub fn run(args: &AutosuspendArgs) -> rusb::Result<bool> {
+    let control = match (args.disable, args.enable) {
+        (true, _) => Some("on"),
+        (_, true) => Some("auto"),
+        _ => None,
+    };
+    // a rule is for devices whether or not they are plugged in now
+    if args.udev_rule && !args.filter.id.is_empty() {
+        for id in &args.filter.id {
+            println!("{}", udev_rule(id, control.unwrap_or("on")));
+        }
+        return Ok(true)
+    }
+    let ctx = rusb::Context::new()?;
+    let devices = inventory::scan(&ctx, &Filter::new(&args.filter))?;
+    if args.udev_rule {
+        let mut ids: Vec<&DeviceID> = devices.iter().map(|e| &e.id).collect();
+        ids.sort_by_key(|id| (id.vid, id.pid));
+        ids.dedup();
+        for id in ids {
+            println!("{}", udev_rule(id, control.unwrap_or("on")));
+        }
+        return Ok(true)
+    }
+    let mut ok = true;
+    for entry in devices.iter().filter(|e| !e.port.is_empty()) {
+        let mut changes = Vec::new();
+        changes.extend(control.map(|value| ("control", value.to_string())));
+        changes.extend(args.delay.map(|delay| ("autosuspend_delay_ms", delay.as_millis().to_string())));
+        for (attribute, value) in changes {
+            if let Err(e) = sysfs::set_power(&entry.port, attribute, &value) {
+                eprintln!("{}: failed to set power/{} to {}: {}", entry, attribute, value, e);
+                ok = false;
+            }
+        }
+        let get = |attribute| sysfs::power(&entry.port, attribute).unwrap_or_else(|| String::from("-"));
+        println!("{} {} control={} delay={}ms status={}", entry, entry.port, get("control"), get("autosuspend_delay_ms"), get("runtime_status"));
+    }
+    Ok(ok)
+}
@@ -0,0 +1,60 @@
+//! Just enough CBOR (RFC 8949) for events: maps of text strings.
+
+/// The head of a data item: the major type and the length or count.
+fn head(out: &mut Vec<u8>, major: u8, len: usize) {
+    let major = major << 5;
+    match len {
+        0..=23 => out.push(major | len as u8),
+        24..=0xff => out.extend([major | 24, len as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((len as u16).to_be_bytes());
+        },
+        _ => {
+            out.push(major | 26);
+            out.extend((len as u32).to_be_bytes());
+        },
+    }
+}
+
+fn text(out: &mut Vec<u8>, s: &str) {
+    head(out, 3, s.len());
+    out.extend(s.as_bytes());
+}
+
+/// A map of text keys to text values. Each one is a complete data item,
+/// so a stream of them needs no framing.
+pub fn map(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    head(&mut out, 5, fields.len());
+    for (key, value) in fields {
+        text(&mut out, key);
+        text(&mut out, value);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 8949, appendix A
+    #[test]
+    fn known_encodings() {
+        assert_eq!(map(&[]), [0xa0]);
+        assert_eq!(
+            map(&[("a", "A"), ("b", "B"), ("c", "C"), ("d", "D"), ("e", "E")]),
+            [0xa5, 0x61, 0x61, 0x61, 0x41, 0x61, 0x62, 0x61, 0x42, 0x61, 0x63, 0x61, 0x43, 0x61, 0x64, 0x61, 0x44, 0x61, 0x65, 0x61, 0x45]);
+        assert_eq!(map(&[("", "IETF")]), [0xa1, 0x60, 0x64, 0x49, 0x45, 0x54, 0x46]);
+    }
+
+    #[test]
+    fn longer_heads() {
+        for (len, head) in [(23, vec![0x77]), (24, vec![0x78, 24]), (0x100, vec![0x79, 0x01, 0x00]), (0x10000, vec![0x7a, 0, 1, 0, 0])] {
+            let value = "x".repeat(len);
+            let encoded = map(&[("", &value)]);
+            assert_eq!(encoded[2..2 + head.len()], head[..], "length {}", len);
+            assert_eq!(encoded.len(), 2 + head.len() + len);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+use std::fmt::Write;
+use std::time::Duration;
+
+use rusb::UsbContext;
+
+use crate::descriptors::{field, le16, le32, Descriptors};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+const CLASS_SMART_CARD: u8 = 0x0b;
+const CCID_DESCRIPTOR: u8 = 0x21;
+
+const PC_TO_RDR_GET_SLOT_STATUS: u8 = 0x65;
+const RDR_TO_PC_SLOT_STATUS: u8 = 0x81;
+
+/// What a reader's class descriptor says about it.
+#[derive(Debug, Clone)]
+pub struct Reader {
+    pub slots: u16,
+    pub protocols: u32,
+}
+
+impl Reader {
+    /// The names of the card protocols the reader speaks.
+    pub fn protocol_names(&self) -> Vec<&'static str> {
+        let mut protocols = Vec::new();
+        if self.protocols & 0x01 != 0 {
+            protocols.push("T=0");
+        }
+        if self.protocols & 0x02 != 0 {
+            protocols.push("T=1");
+        }
+        protocols
+    }
+}
+
+impl fmt::Display for Reader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} slot(s), protocols {}", self.slots, self.protocol_names().join(" "))
+    }
+}
+
+struct Interface {
+    number: u8,
+    ep_in: u8,
+    ep_out: u8,
+    descriptor: Vec<u8>,
+}
+
+fn find_interface<T: UsbContext>(device: &rusb::Device<T>) -> Option<Interface> {
+    let config = device.active_config_descriptor().ok()?;
+    for iface in config.interfaces() {
+        for alt in iface.descriptors() {
+            if alt.class_code() != CLASS_SMART_CARD {
+                continue;
+            }
+            // some readers put the class descriptor after the endpoints
+            let endpoints: Vec<rusb::EndpointDescriptor> = alt.endpoint_descriptors().collect();
+            let mut blobs = vec![alt.extra()];
+            blobs.extend(endpoints.iter().filter_map(|ep| ep.extra()));
+            let descriptor = blobs
+                .into_iter()
+                .flat_map(Descriptors::new)
+                .find(|d| d.len() >= 54 && d[1] == CCID_DESCRIPTOR)?
+                .to_vec();
+            let bulk = |dir| alt.endpoint_descriptors()
+                .find(|ep| ep.transfer_type() == rusb::TransferType::Bulk && ep.direction() == dir)
+                .map(|ep| ep.address());
+            return Some(Interface{
+                number: alt.interface_number(),
+                ep_in: bulk(rusb::Direction::In)?,
+                ep_out: bulk(rusb::Direction::Out)?,
+                descriptor,
+            })
+        }
+    }
+    None
+}
+
+/// Returns the reader description if the device has a CCID interface.
+pub fn reader<T: UsbContext>(device: &rusb::Device<T>) -> Option<Reader> {
+    let iface = find_interface(device)?;
+    Some(Reader{slots: u16::from(iface.descriptor[4]) + 1, protocols: le32(&iface.descriptor, 6)})
+}
+
+/// Asks every slot of the reader whether a card is inserted. This needs
+/// the interface, so it fails while another program such as pcscd holds it.
+pub fn card_present<T: UsbContext>(device: &rusb::Device<T>) -> rusb::Result<bool> {
+    let iface = match find_interface(device) {
+        Some(iface) => iface,
+        None => return Ok(false),
+    };
+    let mut handle = device.open()?;
+    _ = handle.set_auto_detach_kernel_driver(true);
+    handle.claim_interface(iface.number)?;
+    let result = (0..=iface.descriptor[4]).try_fold(false, |found, slot| {
+        Ok(found || slot_status(&handle, &iface, slot)?)
+    });
+    _ = handle.release_interface(iface.number);
+    result
+}
+
+fn slot_status<T: UsbContext>(handle: &rusb::DeviceHandle<T>, iface: &Interface, slot: u8) -> rusb::Result<bool> {
+    let request = [PC_TO_RDR_GET_SLOT_STATUS, 0, 0, 0, 0, slot, slot, 0, 0, 0];
+    handle.write_bulk(iface.ep_out, &request, TIMEOUT)?;
+    let mut response = [0u8; 64];
+    let n = handle.read_bulk(iface.ep_in, &mut response, TIMEOUT)?;
+    if n < 10 || response[0] != RDR_TO_PC_SLOT_STATUS {
+        return Err(rusb::Error::Io)
+    }
+    // bmICCStatus: 0 present and active, 1 present and inactive, 2 absent
+    Ok(response[7] & 0x03 != 2)
+}
+
+/// Decodes the smart card class descriptor. Returns false for anything else.
+pub fn dump_interface(out: &mut String, bytes: &[u8]) -> bool {
+    if bytes.len() < 54 || bytes[1] != CCID_DESCRIPTOR {
+        return false
+    }
+    _ = writeln!(out, "      ChipCard Interface Descriptor:");
+    field(out, 8, "bLength", bytes[0]);
+    field(out, 8, "bDescriptorType", bytes[1]);
+    field(out, 8, "bcdCCID", format!("{:x}.{:02x}", bytes[3], bytes[2]));
+    field(out, 8, "nMaxSlotIndex", bytes[4]);
+    let voltages = ["5.0V", "3.0V", "1.8V"]
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| bytes[5] & (1 << bit) != 0)
+        .map(|(_, v)| *v)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    field(out, 8, "bVoltageSupport", format!("{}  {}", bytes[5], voltages));
+    let protocols = le32(bytes, 6);
+    let names = [(0x01, "T=0"), (0x02, "T=1")]
+        .iter()
+        .filter(|(bit, _)| protocols & bit != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    field(out, 8, "dwProtocols", format!("{}  {}", protocols, names));
+    field(out, 8, "dwDefaultClock", le32(bytes, 10));
+    field(out, 8, "dwMaxiumumClock", le32(bytes, 14));
+    field(out, 8, "bNumClockSupported", bytes[18]);
+    field(out, 8, "dwDataRate", format!("{} bps", le32(bytes, 19)));
+    field(out, 8, "dwMaxDataRate", format!("{} bps", le32(bytes, 23)));
+    field(out, 8, "bNumDataRatesSupp.", bytes[27]);
+    field(out, 8, "dwMaxIFSD", le32(bytes, 28));
+    field(out, 8, "dwSyncProtocols", format!("{:08X}", le32(bytes, 32)));
+    field(out, 8, "dwMechanical", format!("{:08X}", le32(bytes, 36)));
+    field(out, 8, "dwFeatures", format!("{:08X}", le32(bytes, 40)));
+    field(out, 8, "dwMaxCCIDMsgLen", le32(bytes, 44));
+    field(out, 8, "bClassGetResponse", format!("{:02X}", bytes[48]));
+    field(out, 8, "bClassEnvelope", format!("{:02X}", bytes[49]));
+    field(out, 8, "wlcdLayout", format!("0x{:04x}", le16(bytes, 50)));
+    field(out, 8, "bPINSupport", bytes[52]);
+    field(out, 8, "bMaxCCIDBusySlots", bytes[53]);
+    true
+}
@@ -0,0 +1,102 @@
+use std::fmt::Write;
+
+use rusb::UsbContext;
+
+use crate::descriptors::{field, field_desc, le16, le32, string};
+
+const CS_INTERFACE: u8 = 0x24;
+
+fn capabilities(out: &mut String, bits: u8, names: &[&str]) {
+    for (bit, name) in names.iter().enumerate() {
+        if bits & (1 << bit) != 0 {
+            _ = writeln!(out, "          {}", name);
+        }
+    }
+}
+
+/// Decodes a CDC functional descriptor. Returns false when the descriptor
+/// isn't one this module knows.
+pub fn dump_interface<T: UsbContext>(
+    out: &mut String,
+    handle: Option<&rusb::DeviceHandle<T>>,
+    bytes: &[u8],
+) -> bool {
+    if bytes.len() < 3 || bytes[1] != CS_INTERFACE {
+        return false
+    }
+    let minimum = match bytes[2] {
+        0x00 => 5,
+        0x01 => 5,
+        0x02 => 4,
+        0x06 => 5,
+        0x0f => 13,
+        0x1a => 6,
+        0x1b => 12,
+        _ => return false,
+    };
+    if bytes.len() < minimum {
+        return false
+    }
+
+    match bytes[2] {
+        0x00 => {
+            _ = writeln!(out, "      CDC Header:");
+            field(out, 8, "bcdCDC", format!("{:x}.{:02x}", bytes[4], bytes[3]));
+        },
+        0x01 => {
+            _ = writeln!(out, "      CDC Call Management:");
+            field(out, 8, "bmCapabilities", format!("0x{:02x}", bytes[3]));
+            capabilities(out, bytes[3], &["call management", "use DataInterface"]);
+            field(out, 8, "bDataInterface", bytes[4]);
+        },
+        0x02 => {
+            _ = writeln!(out, "      CDC ACM:");
+            field(out, 8, "bmCapabilities", format!("0x{:02x}", bytes[3]));
+            capabilities(out, bytes[3], &[
+                "get/set/clear comm features",
+                "line coding and serial state",
+                "sends break",
+                "connection notifications",
+            ]);
+        },
+        0x06 => {
+            _ = writeln!(out, "      CDC Union:");
+            field(out, 8, "bMasterInterface", bytes[3]);
+            let slaves = bytes[4..].iter().map(|b| b.to_string()).collect::<Vec<String>>().join(" ");
+            field(out, 8, "bSlaveInterface", slaves);
+        },
+        0x0f => {
+            _ = writeln!(out, "      CDC Ethernet:");
+            let mac = Some(bytes[3]).filter(|i| *i != 0);
+            field_desc(out, 8, "iMacAddress", bytes[3], &string(handle, mac));
+            field(out, 8, "bmEthernetStatistics", format!("0x{:08x}", le32(bytes, 4)));
+            field(out, 8, "wMaxSegmentSize", le16(bytes, 8));
+            field(out, 8, "wNumberMCFilters", format!("0x{:04x}", le16(bytes, 10)));
+            field(out, 8, "bNumberPowerFilters", bytes[12]);
+        },
+        0x1a => {
+            _ = writeln!(out, "      CDC NCM:");
+            field(out, 8, "bcdNcmVersion", format!("{:x}.{:02x}", bytes[4], bytes[3]));
+            field(out, 8, "bmNetworkCapabilities", format!("0x{:02x}", bytes[5]));
+            capabilities(out, bytes[5], &[
+                "packet filter",
+                "net address",
+                "encapsulated commands",
+                "max datagram size",
+                "crc mode",
+                "8-byte ntb input size",
+            ]);
+        },
+        0x1b => {
+            _ = writeln!(out, "      CDC MBIM:");
+            field(out, 8, "bcdMBIMVersion", format!("{:x}.{:02x}", bytes[4], bytes[3]));
+            field(out, 8, "wMaxControlMessage", le16(bytes, 5));
+            field(out, 8, "bNumberFilters", bytes[7]);
+            field(out, 8, "bMaxFilterSize", bytes[8]);
+            field(out, 8, "wMaxSegmentSize", le16(bytes, 9));
+            field(out, 8, "bmNetworkCapabilities", format!("0x{:02x}", bytes[11]));
+        },
+        _ => {},
+    }
+    true
+}
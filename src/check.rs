@@ -0,0 +1,75 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::inventory::{self, Entry};
+use crate::{CheckArgs, DeviceID, Filter};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+            Status::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+fn missing(ids: &[DeviceID], devices: &[Entry]) -> Vec<DeviceID> {
+    ids.iter().filter(|id| !devices.iter().any(|e| &e.id == *id)).cloned().collect()
+}
+
+fn sample(ctx: &rusb::Context, filter: &Filter, window: Duration) -> rusb::Result<(Vec<Entry>, usize)> {
+    let start = Instant::now();
+    let mut devices = inventory::scan(ctx, filter)?;
+    let mut transitions = 0;
+    while start.elapsed() < window {
+        thread::sleep(SAMPLE_INTERVAL);
+        let current = inventory::scan(ctx, filter)?;
+        let (added, removed) = inventory::diff(&devices, &current);
+        transitions += added.len() + removed.len();
+        devices = current;
+    }
+    Ok((devices, transitions))
+}
+
+/// Runs a monitoring plugin style check, printing the one line status and
+/// returning the matching exit status.
+pub fn run(args: &CheckArgs) -> Status {
+    let filter = Filter::new(&args.filter);
+    let result = rusb::Context::new().and_then(|ctx| sample(&ctx, &filter, args.window));
+    let (devices, transitions) = match result {
+        Ok(sampled) => sampled,
+        Err(e) => {
+            println!("USB {} - {}", Status::Unknown.label(), e);
+            return Status::Unknown
+        },
+    };
+
+    let missing = missing(&args.filter.id, &devices);
+    let (status, text) = if !missing.is_empty() {
+        let ids = missing.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(" ");
+        (Status::Critical, format!("missing {}", ids))
+    } else if devices.is_empty() {
+        (Status::Critical, String::from("no matching device"))
+    } else if args.flap_critical.is_some_and(|n| transitions >= n) {
+        (Status::Critical, format!("{} transitions in {}s", transitions, args.window.as_secs()))
+    } else if args.flap_warning.is_some_and(|n| transitions >= n) {
+        (Status::Warning, format!("{} transitions in {}s", transitions, args.window.as_secs()))
+    } else {
+        let ids = devices.iter().map(|e| e.id.to_string()).collect::<Vec<String>>().join(" ");
+        (Status::Ok, format!("{} present", ids))
+    };
+    println!("USB {} - {} | devices={} transitions={}", status.label(), text, devices.len(), transitions);
+    status
+}
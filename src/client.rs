@@ -0,0 +1,288 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::inventory::Entry;
+use crate::control;
+use crate::errors;
+use crate::json;
+use crate::list;
+use crate::matcher::Matcher;
+use crate::{iterable_to_str, parse_device, print_ids, Args, DeviceID, Filter, ListArgs, ListFormat};
+use crate::zabbix;
+
+/// Sends a request to a running `watch --control` and returns the text
+/// after its `ok` with the stream for what follows. Requests are named
+/// after the capability they need.
+fn request(path: &Path, request: &str) -> io::Result<(String, BufReader<UnixStream>)> {
+    let capability = request.split_whitespace().next().unwrap_or_default();
+    let (result, reader) = control::open(path, capability, request)?;
+    result.map(|text| (text, reader)).map_err(io::Error::other)
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("malformed {} from the daemon", what))
+}
+
+/// Parses a `vid:pid bus:address port` line of a list answer.
+fn parse_entry(line: &str) -> Option<Entry> {
+    let mut words = line.split_whitespace();
+    let id = parse_device(words.next()?).ok()?;
+    let (bus, address) = words.next()?.split_once(':')?;
+    let port = words.next().unwrap_or_default().to_string();
+    Some(Entry{bus: bus.parse().ok()?, address: address.parse().ok()?, id, port})
+}
+
+/// The devices the daemon sees, with the sequence number of its last event.
+pub fn list(path: &Path) -> io::Result<(u64, Vec<Entry>)> {
+    let (text, mut reader) = request(path, "list")?;
+    let (seq, count) = text.split_once(' ').ok_or_else(|| invalid("list"))?;
+    let seq = seq.parse().map_err(|_| invalid("list"))?;
+    let count: usize = count.parse().map_err(|_| invalid("list"))?;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        entries.push(parse_entry(&line).ok_or_else(|| invalid("device"))?);
+    }
+    Ok((seq, entries))
+}
+
+/// Attach and detach events from the daemon as they happen.
+pub struct Subscription {
+    reader: BufReader<UnixStream>,
+}
+
+pub fn subscribe(path: &Path, since: u64) -> io::Result<Subscription> {
+    let (first, reader) = request(path, &format!("subscribe since={}", since))?;
+    if first.parse::<u64>().is_ok_and(|first| first > since + 1) {
+        // the snapshot is older than the buffer, so a list would be stale
+        return Err(io::Error::other("events were lost between listing and subscribing"))
+    }
+    Ok(Subscription{reader})
+}
+
+impl Subscription {
+    /// The next attach or detach, or None once the deadline has passed.
+    pub fn next(&mut self, deadline: Option<Instant>) -> io::Result<Option<(bool, Entry)>> {
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => Some(left),
+                    _ => return Ok(None),
+                },
+                None => None,
+            };
+            self.reader.get_ref().set_read_timeout(timeout)?;
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "the daemon went away")),
+                Ok(_) => {},
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            let attached = match json::get(&line, "type").as_deref() {
+                Some("attach") => true,
+                Some("detach") => false,
+                _ => continue,
+            };
+            // made up events don't change what is plugged in
+            if json::get(&line, "injected").is_some() {
+                continue
+            }
+            let entry = (|| Some(Entry{
+                bus: json::get(&line, "bus")?.parse().ok()?,
+                address: json::get(&line, "address")?.parse().ok()?,
+                id: parse_device(&json::get(&line, "id")?).ok()?,
+                port: json::get(&line, "port").unwrap_or_default(),
+            }))();
+            return Ok(Some((attached, entry.ok_or_else(|| invalid("event"))?)))
+        }
+    }
+}
+
+pub enum Waited {
+    /// With the id to print, if an event brought it about.
+    Done(Option<DeviceID>),
+    /// With the ids still outstanding.
+    TimedOut(Vec<DeviceID>),
+    /// --nowait and it hasn't happened yet.
+    NotYet,
+}
+
+/// Waits like the hotplug wait does, on the daemon's view of the bus: for
+/// any matching device to attach or every one to detach, or with `all`
+/// for each id.
+pub fn wait(path: &Path, filter: &Filter, attach: bool, all: bool, nowait: bool, deadline: Option<Instant>) -> io::Result<Waited> {
+    let matcher = Matcher::new(filter);
+    let outstanding = |present: &[Entry]| -> Vec<DeviceID> {
+        filter.ids.iter().filter(|id| present.iter().any(|e| &e.id == *id) != attach).cloned().collect()
+    };
+    let done = |present: &[Entry]| match all {
+        true => outstanding(present).is_empty(),
+        false => present.iter().any(|e| matcher.matches_id(&e.id)) == attach,
+    };
+    let (seq, mut present) = list(path)?;
+    if done(&present) {
+        let id = present.iter().find(|e| matcher.matches_id(&e.id)).map(|e| e.id.clone());
+        return Ok(Waited::Done(id.filter(|_| attach)))
+    }
+    if nowait {
+        return Ok(Waited::NotYet)
+    }
+    let mut events = subscribe(path, seq)?;
+    loop {
+        let (attached, entry) = match events.next(deadline)? {
+            Some(event) => event,
+            None => return Ok(Waited::TimedOut(outstanding(&present))),
+        };
+        present.retain(|e| e != &entry);
+        if attached {
+            present.push(entry.clone());
+        }
+        if done(&present) {
+            return Ok(Waited::Done(Some(entry.id)))
+        }
+    }
+}
+
+fn failed(e: io::Error) -> rusb::Error {
+    errors::report("daemon", &format!("daemon: {}", e), &[]);
+    rusb::Error::Other
+}
+
+/// Checks that the filter can be decided from ids alone, since the device
+/// itself isn't looked at through the daemon.
+fn require_ids(filter: &Filter) {
+    if Matcher::new(filter).needs_device() {
+        eprintln!("--class, --speed, --max-power, --wait-dfu and --wait-card look at the device and don't work with --via-daemon");
+        std::process::exit(2);
+    }
+}
+
+/// `wait --via-daemon`.
+pub fn run_wait(path: &Path, filter: &Filter, args: &Args) -> rusb::Result<()> {
+    require_ids(filter);
+    let attach = !args.detach;
+    let deadline = args.timeout.map(|timeout| Instant::now() + timeout);
+    match wait(path, filter, attach, args.all, args.nowait, deadline).map_err(failed)? {
+        Waited::Done(id) => {
+            if let Some(id) = id {
+                print_ids(&id, filter, args);
+            }
+            Ok(())
+        },
+        Waited::NotYet => Err(rusb::Error::NoDevice),
+        Waited::TimedOut(left) => {
+            let op = if attach { "attach" } else { "detach" };
+            let message = if left.is_empty() {
+                format!("timed out waiting for a device to {}", op)
+            } else {
+                format!("timed out waiting for {} to {}", iterable_to_str(left.iter()), op)
+            };
+            let left: Vec<String> = left.iter().map(DeviceID::to_string).collect();
+            errors::report("timeout", &message, &[("outstanding", left.join(","))]);
+            Err(rusb::Error::Timeout)
+        },
+    }
+}
+
+/// `list --via-daemon`, for the formats that need no more than the ids.
+pub fn run_list(path: &Path, args: &ListArgs) -> rusb::Result<()> {
+    let filter = Filter::new(&args.filter);
+    require_ids(&filter);
+    let matcher = Matcher::new(&filter);
+    let (_, mut devices) = list(path).map_err(failed)?;
+    devices.retain(|entry| matcher.matches_id(&entry.id));
+    if let Some(by) = args.sort {
+        list::sort::<rusb::Context>(&mut devices, by, None);
+    }
+    match args.format {
+        ListFormat::Text => {
+            for entry in &devices {
+                println!("{:03}:{:03} {}", entry.bus, entry.address, entry.id);
+            }
+        },
+        ListFormat::Yaml => {
+            if devices.is_empty() {
+                println!("[]");
+            }
+            for entry in &devices {
+                print!("{}", list::yaml_item::<rusb::Context>(entry, None));
+            }
+        },
+        ListFormat::Json => {
+            let items: Vec<String> = devices.iter().map(|entry| list::json_item::<rusb::Context>(entry, None)).collect();
+            println!("{}", json::array(&items));
+        },
+        ListFormat::ZabbixPresence => println!("{}", zabbix::presence(&args.filter.id, &devices)),
+        ListFormat::Lsusb => {
+            for entry in &devices {
+                println!("{}", list::lsusb_line(entry));
+            }
+        },
+        ListFormat::Protobuf => {
+            let mut stdout = io::stdout();
+            for entry in &devices {
+                stdout.write_all(&list::protobuf_item::<rusb::Context>(entry, None)).map_err(|_| rusb::Error::Io)?;
+            }
+        },
+        ListFormat::ZabbixDiscovery => {
+            eprintln!("zabbix-discovery reads device strings and doesn't work with --via-daemon");
+            std::process::exit(2);
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::{Command, Control};
+    use crate::replay::Replay;
+    use std::thread;
+    use std::time::Duration;
+
+    /// A real control socket with nothing plugged in, where a device
+    /// attaches once a client has subscribed, as a watch would see it.
+    fn serve(path: &Path, id: &DeviceID) {
+        let mut control = Control::bind(path).unwrap();
+        let mut replay = Replay::new(16);
+        let id = id.to_string();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        thread::spawn(move || {
+            let mut subscribed = false;
+            while Instant::now() < deadline {
+                // polled again after the subscription, as the watch loop does
+                let requests = control.requests();
+                if subscribed {
+                    let fields = [("id", id.clone()), ("bus", String::from("1")), ("address", String::from("4")), ("port", String::from("1-4"))];
+                    replay.record("attach", &format!("+ {} 001:004", id), &fields);
+                    subscribed = false;
+                }
+                for mut request in requests {
+                    match request.command {
+                        Command::List => request.reply_lines(format!("{} 0", replay.sequence()), &[]),
+                        Command::Subscribe{since} => {
+                            replay.subscribe(request.into_stream(), since);
+                            subscribed = true;
+                        },
+                        Command::Inject{..} => request.reply(Ok(String::new())),
+                    }
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+    }
+
+    #[test]
+    fn wait_sees_an_attach_through_the_daemon() {
+        let path = std::env::temp_dir().join(format!("usbmon-wait-{}.sock", std::process::id()));
+        let id = DeviceID{vid: 0x1d50, pid: 0x6018};
+        serve(&path, &id);
+        let filter = Filter{ids: vec![id.clone()], ..Default::default()};
+        let waited = wait(&path, &filter, true, false, false, Some(Instant::now() + Duration::from_secs(3))).unwrap();
+        assert!(matches!(waited, Waited::Done(Some(got)) if got == id));
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::inventory::Entry;
+use crate::DeviceID;
+
+/// Drops repeats of the same event for the same device that arrive within
+/// a short window, as some hubs report one transition several times. A
+/// device is its port and vid:pid, as a replug in between gets a new
+/// address; without a port its address will have to do.
+pub struct Coalescer {
+    window: Duration,
+    seen: HashMap<(bool, String, DeviceID), Instant>,
+}
+
+impl Coalescer {
+    pub fn new(window: Duration) -> Coalescer {
+        Coalescer{window, seen: HashMap::new()}
+    }
+
+    /// Whether an attach or detach of the entry is news rather than a
+    /// repeat of one reported less than the window ago.
+    pub fn fresh(&mut self, attached: bool, entry: &Entry, now: Instant) -> bool {
+        if self.window.is_zero() {
+            return true
+        }
+        self.seen.retain(|_, at| now.duration_since(*at) < self.window);
+        let port = match entry.port.as_str() {
+            "" => format!("{:03}:{:03}", entry.bus, entry.address),
+            port => port.to_string(),
+        };
+        let key = (attached, port, entry.id.clone());
+        if self.seen.contains_key(&key) {
+            return false
+        }
+        self.seen.insert(key, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(port: &str, address: u8) -> Entry {
+        Entry{bus: 1, address, id: DeviceID{vid: 0x1d50, pid: 0x6018}, port: String::from(port)}
+    }
+
+    #[test]
+    fn repeats_on_a_port_within_the_window_are_dropped() {
+        let mut coalescer = Coalescer::new(Duration::from_millis(200));
+        let now = Instant::now();
+        // the hub reports the same attach again, with the address of a replug
+        assert!(coalescer.fresh(true, &entry("1-4", 5), now));
+        assert!(!coalescer.fresh(true, &entry("1-4", 6), now + Duration::from_millis(50)));
+        // a detach, another port or another device is news
+        assert!(coalescer.fresh(false, &entry("1-4", 6), now + Duration::from_millis(60)));
+        assert!(coalescer.fresh(true, &entry("1-5", 7), now + Duration::from_millis(70)));
+        let mut other = entry("1-4", 8);
+        other.id.pid = 0x6019;
+        assert!(coalescer.fresh(true, &other, now + Duration::from_millis(80)));
+    }
+
+    #[test]
+    fn window_expires() {
+        let mut coalescer = Coalescer::new(Duration::from_millis(200));
+        let now = Instant::now();
+        assert!(coalescer.fresh(true, &entry("1-4", 5), now));
+        assert!(!coalescer.fresh(true, &entry("1-4", 5), now + Duration::from_millis(199)));
+        assert!(coalescer.fresh(true, &entry("1-4", 5), now + Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn zero_window_keeps_everything() {
+        let mut coalescer = Coalescer::new(Duration::ZERO);
+        let now = Instant::now();
+        assert!(coalescer.fresh(true, &entry("1-4", 5), now));
+        assert!(coalescer.fresh(true, &entry("1-4", 5), now));
+    }
+
+    #[test]
+    fn without_a_port_the_address_tells_devices_apart() {
+        let mut coalescer = Coalescer::new(Duration::from_millis(200));
+        let now = Instant::now();
+        assert!(coalescer.fresh(true, &entry("", 5), now));
+        assert!(coalescer.fresh(true, &entry("", 6), now));
+        assert!(!coalescer.fresh(true, &entry("", 5), now));
+    }
+}
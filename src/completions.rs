@@ -0,0 +1,239 @@
+use std::path::Path;
+
+use clap::Command;
+
+use crate::config::Config;
+use crate::inventory;
+use crate::state::{self, State};
+use crate::{format_duration, parse_device, DeviceID, Filter};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Lists the vid:pids --id completes to, a tab and what each is.
+const IDS: &str = "usbmon completions --ids 2>/dev/null";
+
+struct Flag {
+    long: Option<String>,
+    short: Option<char>,
+    help: String,
+    /// None for switches, otherwise what a value completes to
+    values: Option<Values>,
+}
+
+enum Values {
+    Ids,
+    Choices(Vec<String>),
+    Files,
+    Other,
+}
+
+fn flags(cmd: &Command) -> Vec<Flag> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .map(|arg| {
+            let values = if !arg.get_action().takes_values() {
+                None
+            } else if arg.get_id() == "id" {
+                Some(Values::Ids)
+            } else {
+                let choices: Vec<String> = arg
+                    .get_possible_values()
+                    .iter()
+                    .filter(|value| !value.is_hide_set())
+                    .map(|value| value.get_name().to_string())
+                    .collect();
+                let file = arg.get_value_names().is_some_and(|names| names.iter().any(|name| name == "FILE"));
+                Some(if !choices.is_empty() {
+                    Values::Choices(choices)
+                } else if file {
+                    Values::Files
+                } else {
+                    Values::Other
+                })
+            };
+            let help = arg.get_help().map(|help| help.to_string()).unwrap_or_default();
+            Flag{
+                long: arg.get_long().map(String::from),
+                short: arg.get_short(),
+                help: help.lines().next().unwrap_or_default().to_string(),
+                values,
+            }
+        })
+        .collect()
+}
+
+fn spellings(flag: &Flag) -> Vec<String> {
+    flag.long.iter().map(|long| format!("--{}", long)).chain(flag.short.map(|short| format!("-{}", short))).collect()
+}
+
+fn bash_case(cmd: &Command, subcommands: &[&str]) -> String {
+    let flags = flags(cmd);
+    let mut text = String::from("            case \"$prev\" in\n");
+    for flag in &flags {
+        let reply = match &flag.values {
+            None => continue,
+            Some(Values::Ids) => format!("COMPREPLY=($(compgen -W \"$({} | cut -f1)\" -- \"$cur\"))", IDS),
+            Some(Values::Choices(choices)) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", choices.join(" ")),
+            Some(Values::Files) => String::from("COMPREPLY=($(compgen -f -- \"$cur\"))"),
+            Some(Values::Other) => String::from("COMPREPLY=()"),
+        };
+        text.push_str(&format!("                {}) {}; return;;\n", spellings(flag).join("|"), reply));
+    }
+    text.push_str("            esac\n");
+    let words: Vec<String> = flags.iter().flat_map(spellings).chain(subcommands.iter().map(|s| s.to_string())).collect();
+    text.push_str(&format!("            opts=\"{}\"\n", words.join(" ")));
+    text
+}
+
+fn bash(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let subcommands: Vec<&str> = cmd.get_subcommands().map(|sub| sub.get_name()).collect();
+    let mut text = format!("_{}() {{\n", name);
+    text.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\" sub= opts\n");
+    text.push_str("    for word in \"${COMP_WORDS[@]:1:COMP_CWORD-1}\"; do\n");
+    text.push_str(&format!("        case \"$word\" in {}) sub=\"$word\"; break;; esac\n", subcommands.join("|")));
+    text.push_str("    done\n    case \"$sub\" in\n");
+    for sub in cmd.get_subcommands() {
+        text.push_str(&format!("        {})\n{}            ;;\n", sub.get_name(), bash_case(sub, &[])));
+    }
+    text.push_str(&format!("        *)\n{}            ;;\n", bash_case(cmd, &subcommands)));
+    text.push_str("    esac\n    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n}\n");
+    text.push_str(&format!("complete -F _{} {}\n", name, name));
+    text
+}
+
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish_flags(name: &str, cmd: &Command, condition: &str) -> String {
+    let mut text = String::new();
+    for flag in flags(cmd) {
+        let mut line = format!("complete -c {} -n '{}'", name, condition);
+        if let Some(long) = &flag.long {
+            line.push_str(&format!(" -l {}", long));
+        }
+        if let Some(short) = flag.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        match &flag.values {
+            None => {},
+            Some(Values::Ids) => line.push_str(&format!(" -x -a '({})'", IDS)),
+            Some(Values::Choices(choices)) => line.push_str(&format!(" -x -a '{}'", choices.join(" "))),
+            Some(Values::Files) => line.push_str(" -r -F"),
+            Some(Values::Other) => line.push_str(" -x"),
+        }
+        line.push_str(&format!(" -d '{}'\n", fish_escape(&flag.help)));
+        text.push_str(&line);
+    }
+    text
+}
+
+fn fish(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let mut text = fish_flags(name, cmd, "__fish_use_subcommand");
+    for sub in cmd.get_subcommands() {
+        let about = sub.get_about().map(|about| about.to_string()).unwrap_or_default();
+        text.push_str(&format!("complete -c {} -n '__fish_use_subcommand' -f -a {} -d '{}'\n",
+            name, sub.get_name(), fish_escape(&about)));
+    }
+    for sub in cmd.get_subcommands() {
+        text.push_str(&fish_flags(name, sub, &format!("__fish_seen_subcommand_from {}", sub.get_name())));
+    }
+    text
+}
+
+/// What --id completes to, each id once with what it is: the devices
+/// connected now, the names the config's `[aliases]` section gives ids,
+/// then what the state file has seen, most recently seen first.
+pub fn candidates(connected: &[DeviceID], config: Option<&Config>, state: Option<&State>, now: i64) -> Vec<(DeviceID, String)> {
+    let mut ids: Vec<(DeviceID, String)> = connected.iter().map(|id| (id.clone(), String::from("connected"))).collect();
+    let aliases = config.and_then(|config| config.section("aliases")).map(|section| section.entries()).unwrap_or_default();
+    for (name, id) in aliases {
+        if let Ok(id) = parse_device(id) {
+            ids.push((id, name.to_string()));
+        }
+    }
+    let mut records: Vec<&state::Record> = state.map(|state| state.records().iter().collect()).unwrap_or_default();
+    records.sort_by_key(|record| -record.seen);
+    for record in records {
+        ids.push((record.id.clone(), format!("seen {} ago", format_duration(now.saturating_sub(record.seen).max(0) as u64))));
+    }
+    let mut seen = Vec::new();
+    ids.retain(|(id, _)| {
+        let new = !seen.contains(id);
+        seen.push(id.clone());
+        new
+    });
+    ids
+}
+
+/// Prints the candidates of --id as `vid:pid<TAB>what`, for the scripts
+/// to call. What can't be read is left out, as a completion has nowhere to
+/// say why.
+pub fn print_ids(config: Option<&Path>, state: Option<&Path>) {
+    let connected: Vec<DeviceID> = rusb::Context::new()
+        .and_then(|ctx| inventory::scan(&ctx, &Filter::default()))
+        .map(|devices| devices.into_iter().map(|entry| entry.id).collect())
+        .unwrap_or_default();
+    let config = config.and_then(|path| Config::load(path).ok());
+    let state = state.and_then(|path| State::load(path).ok());
+    for (id, what) in candidates(&connected, config.as_ref(), state.as_ref(), state::now()) {
+        println!("{}\t{}", id, what);
+    }
+}
+
+/// A completion script for the shell. --id completes to the vid:pids of
+/// the devices connected at the time, the config's aliases and the state
+/// file's devices, other options to their choices.
+pub fn generate(shell: Shell, cmd: &mut Command) -> String {
+    cmd.build();
+    match shell {
+        Shell::Bash => bash(cmd),
+        // zsh runs bash completion functions through bashcompinit
+        Shell::Zsh => format!("autoload -U +X bashcompinit && bashcompinit\n{}", bash(cmd)),
+        Shell::Fish => fish(cmd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use std::fs;
+
+    #[test]
+    fn ids_come_from_devices_aliases_and_history() {
+        let base = std::env::temp_dir().join(format!("usbmon-completions-{}", std::process::id()));
+        let (conf, known) = (base.with_extension("conf"), base.with_extension("state"));
+        fs::write(&conf, "[aliases]\nflasher = 0483:df11\nbroken = 0483\nkey = 1050:0407\n").unwrap();
+        fs::write(&known, "046d:c52b absent 1000\n1050:0407 absent 4000\n1d50:6018 present 7000 500\n").unwrap();
+        let config = Config::load(&conf).unwrap();
+        let state = State::load(&known).unwrap();
+        _ = fs::remove_file(&conf);
+        _ = fs::remove_file(&known);
+        let connected = [parse_device("1d50:6018").unwrap()];
+        let ids: Vec<String> = candidates(&connected, Some(&config), Some(&state), 7200)
+            .into_iter()
+            .map(|(id, what)| format!("{} {}", id, what))
+            .collect();
+        assert_eq!(ids, ["1d50:6018 connected", "483:df11 flasher", "1050:407 key", "46d:c52b seen 1h 43m ago"]);
+        assert!(candidates(&[], None, None, 0).is_empty());
+    }
+
+    #[test]
+    fn scripts_complete_ids_from_usbmon() {
+        let mut cmd = crate::Args::command();
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = generate(shell, &mut cmd);
+            assert!(script.contains(IDS), "{:?}", shell);
+            let rest = script.replace(IDS, "");
+            assert!(!rest.contains("--ids") && !rest.contains("-l ids"), "{:?} offers the hidden flag", shell);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::parse_duration;
+
+/// An INI style file of `key = value` lines grouped under `[section]`
+/// headers. Lines starting with `#` are comments.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// In file order, which matters for rules.
+    sections: Vec<Section>,
+}
+
+#[derive(Debug, Default)]
+pub struct Section {
+    name: String,
+    values: HashMap<String, String>,
+}
+
+pub fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Config> {
+        let text = fs::read_to_string(path)?;
+        let mut config = Config::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+                if config.section(&name).is_some() {
+                    return Err(invalid(format!("{}:{}: duplicate section [{}]", path.display(), n + 1, name)))
+                }
+                config.sections.push(Section{name, ..Default::default()});
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("{}:{}: expected key = value", path.display(), n + 1)))?;
+            if config.sections.is_empty() {
+                config.sections.push(Section::default());
+            }
+            config.sections
+                .last_mut()
+                .unwrap()
+                .values
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+        Ok(config)
+    }
+
+    /// Narrows the config to one profile. `[profile.NAME]` holds the
+    /// profile's filter, found as `[profile]` afterwards, and sections
+    /// named `[profile.NAME KIND ...]` take the place of the shared
+    /// `[KIND ...]` section of that name, or are added after the shared
+    /// ones. Sections of other profiles are left out.
+    ///
+    /// ```text
+    /// [profile.ci]
+    /// vid = 0483
+    ///
+    /// [profile.ci output events]
+    /// target = /var/log/usbmon/ci.json
+    /// ```
+    pub fn select(self, profile: &str) -> io::Result<Config> {
+        let own = format!("profile.{}", profile);
+        let prefix = format!("{} ", own);
+        if !self.sections.iter().any(|s| s.name == own || s.name.starts_with(&prefix)) {
+            return Err(invalid(format!("there is no [{}] profile", own)))
+        }
+        let mut config = Config::default();
+        let mut overrides = Vec::new();
+        for mut section in self.sections {
+            if section.name == own {
+                section.name = String::from("profile");
+                overrides.push(section);
+            } else if let Some(name) = section.name.strip_prefix(&prefix) {
+                section.name = name.to_string();
+                overrides.push(section);
+            } else if section.name != "profile" && !section.name.starts_with("profile.") {
+                config.sections.push(section);
+            }
+        }
+        for section in overrides {
+            match config.sections.iter_mut().find(|s| s.name == section.name) {
+                Some(shared) => *shared = section,
+                None => config.sections.push(section),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Sections named `[<kind> <name>]`, such as `[rule flasher]`, in file
+    /// order along with their names.
+    pub fn sections<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = (&'a str, &'a Section)> + 'a {
+        self.sections.iter().filter_map(move |s| {
+            let name = s.name.strip_prefix(kind)?.strip_prefix(' ')?;
+            Some((name, s))
+        })
+    }
+}
+
+impl Section {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Every key and value, sorted by key.
+    pub fn entries(&self) -> Vec<(&str, &str)> {
+        let mut entries: Vec<(&str, &str)> = self.values.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        entries.sort();
+        entries
+    }
+
+    pub fn require(&self, key: &str) -> io::Result<&str> {
+        self.get(key).ok_or_else(|| invalid(format!("[{}] needs {}", self.name, key)))
+    }
+
+    /// A comma separated value, empty if the key is missing.
+    pub fn list(&self, key: &str) -> Vec<String> {
+        self.get(key)
+            .map(|v| v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn duration(&self, key: &str) -> io::Result<Option<Duration>> {
+        match self.get(key) {
+            Some(value) => parse_duration(value).map(Some).map_err(|e| invalid(format!("[{}] {}: {}", self.name, key, e))),
+            None => Ok(None),
+        }
+    }
+}
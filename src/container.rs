@@ -0,0 +1,67 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+use rusb::UsbContext;
+
+use crate::udev;
+
+/// The container runtime we run under, if any, from the marker files and
+/// environment runtimes leave behind or the cgroup of pid 1.
+pub fn detect() -> Option<String> {
+    if Path::new("/.dockerenv").exists() {
+        return Some(String::from("docker"))
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some(String::from("podman"))
+    }
+    if let Ok(runtime) = env::var("container") {
+        return Some(runtime)
+    }
+    let cgroup = fs::read_to_string("/proc/1/cgroup").unwrap_or_default();
+    ["kubepods", "docker", "lxc", "containerd"]
+        .into_iter()
+        .find(|name| cgroup.contains(name))
+        .map(String::from)
+}
+
+pub enum Access {
+    /// The node can be opened, or only file permissions stand in the way.
+    Allowed,
+    /// /dev/bus/usb wasn't passed into the container, or not this device.
+    Missing,
+    /// The node is there but the device cgroup refuses it.
+    Denied,
+}
+
+/// Whether the device's usbfs node is usable from here. The device cgroup
+/// refuses opens with EPERM, while file permissions give EACCES.
+pub fn access<T: UsbContext>(device: &rusb::Device<T>) -> Access {
+    let node = udev::node(device);
+    if !node.exists() {
+        return Access::Missing
+    }
+    match OpenOptions::new().read(true).open(&node) {
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) => Access::Denied,
+        _ => Access::Allowed,
+    }
+}
+
+/// The docker run argument that passes the device in.
+pub fn docker_flag<T: UsbContext>(device: &rusb::Device<T>) -> String {
+    format!("--device={}", udev::node(device).display())
+}
+
+/// Warns on stderr when the device can't be used from inside the
+/// container, with what to pass to the runtime instead.
+pub fn report<T: UsbContext>(device: &rusb::Device<T>, runtime: &str) {
+    let node = udev::node(device);
+    let problem = match access(device) {
+        Access::Allowed => return,
+        Access::Missing => "isn't visible",
+        Access::Denied => "is refused by the device cgroup",
+    };
+    eprintln!("running under {}, and {} {}; pass it in with {}, or for devices that get replugged \
+        with -v /dev/bus/usb:/dev/bus/usb --device-cgroup-rule='c 189:* rmw'",
+        runtime, node.display(), problem, docker_flag(device));
+}
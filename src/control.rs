@@ -0,0 +1,492 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::mem;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::acl::{self, Acl};
+use crate::{parse_device, DeviceID};
+
+/// The version of the control protocol. A client starts with
+///
+/// ```text
+/// hello VERSION [CAPABILITY...]
+/// ```
+///
+/// naming the newest version it speaks and the requests it needs, and is
+/// answered `ok VERSION CAPABILITY...` with the version both sides speak
+/// and every request this side serves, or an error if a capability is
+/// missing. Requests without a hello are taken as version 1.
+pub const PROTOCOL: u32 = 1;
+
+pub const CAPABILITIES: [&str; 3] = ["inject", "list", "subscribe"];
+
+/// How long a client that doesn't read its answer may hold up the watch.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The longest request line. Anyone may connect once there is an [acl],
+/// so a client is dropped rather than buffered for without end.
+const MAX_LINE: usize = 4096;
+
+/// Writes all of the bytes to a nonblocking stream, waiting for it to be
+/// writable again whenever its buffer is full, but no longer than the
+/// timeout in all. The stream stays nonblocking, as the other handles to
+/// the same socket expect.
+pub fn write_within(mut stream: &UnixStream, mut bytes: &[u8], timeout: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    while !bytes.is_empty() {
+        match stream.write(bytes) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => bytes = &bytes[n..],
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(ErrorKind::TimedOut.into())
+                }
+                let mut fd = libc::pollfd{fd: stream.as_raw_fd(), events: libc::POLLOUT, revents: 0};
+                // SAFETY: polls one descriptor we hold for the call
+                if unsafe { libc::poll(&mut fd, 1, left.as_millis().max(1) as libc::c_int) } < 0 {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != ErrorKind::Interrupted {
+                        return Err(e)
+                    }
+                }
+            },
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Whether a client with these rights, None for all, may make a request.
+fn permitted(allowed: &Option<Vec<String>>, capability: &str) -> bool {
+    allowed.as_ref().is_none_or(|allowed| allowed.iter().any(|a| a == capability))
+}
+
+/// Answers a hello, or None if the line isn't one. Only the requests the
+/// client is allowed are offered.
+fn hello(line: &str, allowed: &Option<Vec<String>>) -> Option<Result<String, String>> {
+    let mut words = line.split_whitespace();
+    if words.next()? != "hello" {
+        return None
+    }
+    let version: u32 = match words.next().map(str::parse) {
+        Some(Ok(version)) if version >= 1 => version,
+        _ => return Some(Err(String::from("expected hello VERSION with VERSION 1 or later"))),
+    };
+    for capability in words {
+        if !CAPABILITIES.contains(&capability) {
+            return Some(Err(format!("unsupported {}", capability)))
+        }
+        if !permitted(allowed, capability) {
+            return Some(Err(format!("not permitted {}", capability)))
+        }
+    }
+    let offered: Vec<&str> = CAPABILITIES.into_iter().filter(|c| permitted(allowed, c)).collect();
+    Some(Ok(format!("{} {}", version.min(PROTOCOL), offered.join(" "))))
+}
+
+/// What a client can ask a running watch for, one request per line:
+///
+/// ```text
+/// inject attach 1a2b:5678 [BUS:ADDRESS]
+/// inject detach 1a2b:5678 [BUS:ADDRESS]
+/// list
+/// subscribe [since=SEQ]
+/// ```
+///
+/// Each request is answered with a line starting with `ok` or `error`.
+pub enum Command {
+    /// A made up event, handled like a real one except that it doesn't
+    /// touch the device list.
+    Inject { attached: bool, id: DeviceID, bus: u8, address: u8 },
+    /// The devices present, answered `ok SEQ N` with the sequence number
+    /// of the last event and then one `vid:pid bus:address port` line per
+    /// device, so a subscription since SEQ picks up right after.
+    List,
+    /// Streams every event from now on, after those buffered since the
+    /// given sequence number.
+    Subscribe { since: Option<u64> },
+}
+
+impl Command {
+    /// The capability a client needs for the request.
+    fn capability(&self) -> &'static str {
+        match self {
+            Command::Inject{..} => "inject",
+            Command::List => "list",
+            Command::Subscribe{..} => "subscribe",
+        }
+    }
+}
+
+fn parse(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["inject", event, id, location @ ..] if location.len() <= 1 => {
+            let attached = match *event {
+                "attach" => true,
+                "detach" => false,
+                _ => return Err(format!("unknown event {}, expected attach or detach", event)),
+            };
+            let id = parse_device(id).map_err(|e| e.to_string())?;
+            let (bus, address) = match location.first() {
+                Some(location) => {
+                    let (bus, address) = location.split_once(':').ok_or("expected BUS:ADDRESS")?;
+                    let bus = bus.parse().map_err(|_| format!("invalid bus {}", bus))?;
+                    let address = address.parse().map_err(|_| format!("invalid address {}", address))?;
+                    (bus, address)
+                },
+                None => (0, 0),
+            };
+            Ok(Command::Inject{attached, id, bus, address})
+        },
+        ["list"] => Ok(Command::List),
+        ["subscribe"] => Ok(Command::Subscribe{since: None}),
+        ["subscribe", since] => {
+            let since = since.strip_prefix("since=").ok_or("expected since=SEQ")?;
+            let since = since.parse().map_err(|_| format!("invalid sequence number {}", since))?;
+            Ok(Command::Subscribe{since: Some(since)})
+        },
+        [] => Err(String::from("empty request")),
+        _ => Err(format!("unknown request {}", line.trim())),
+    }
+}
+
+struct Client {
+    stream: UnixStream,
+    pending: Vec<u8>,
+    /// What the [acl] lets the client ask for, None for everything.
+    allowed: Option<Vec<String>>,
+}
+
+/// A request with the client that sent it, for the reply.
+pub struct Request {
+    pub command: Command,
+    stream: UnixStream,
+}
+
+fn status_line(result: Result<String, String>) -> String {
+    let (status, text) = match result {
+        Ok(text) => ("ok", text),
+        Err(text) => ("error", text),
+    };
+    if text.is_empty() { format!("{}\n", status) } else { format!("{} {}\n", status, text) }
+}
+
+fn reply(stream: &UnixStream, result: Result<String, String>) {
+    _ = write_within(stream, status_line(result).as_bytes(), REPLY_TIMEOUT);
+}
+
+impl Request {
+    pub fn reply(&mut self, result: Result<String, String>) {
+        reply(&self.stream, result);
+    }
+
+    /// Answers with the `ok` line followed by more lines, all of them or,
+    /// if the client stops reading for longer than the reply timeout, as
+    /// many as it took.
+    pub fn reply_lines(&mut self, text: String, lines: &[String]) {
+        let mut answer = status_line(Ok(text));
+        for line in lines {
+            answer.push_str(line);
+            answer.push('\n');
+        }
+        _ = write_within(&self.stream, answer.as_bytes(), REPLY_TIMEOUT);
+    }
+
+    /// The client's stream, for a subscription. It is the client's own,
+    /// which the control socket no longer reads requests from, and it is
+    /// nonblocking.
+    pub fn into_stream(self) -> UnixStream {
+        self.stream
+    }
+}
+
+/// The listening control socket. Watch waits on it between scans and
+/// handles requests as they come, so nothing here blocks.
+pub struct Control {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client>,
+    acl: Option<Acl>,
+    /// The socket's permissions as bound.
+    mode: u32,
+}
+
+impl Control {
+    /// Binds the socket, replacing a stale one a killed instance left
+    /// behind. The pid file is what keeps two instances apart.
+    pub fn bind(path: &Path) -> io::Result<Control> {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(ErrorKind::AddrInUse, "another instance is listening"))
+        }
+        _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        let mode = fs::metadata(path)?.permissions().mode();
+        Ok(Control{path: path.to_path_buf(), listener, clients: Vec::new(), acl: None, mode})
+    }
+
+    /// Limits what clients that connect from now on may ask for. With an
+    /// ACL anyone may connect, so the socket is opened up to everyone and
+    /// the ACL decides; without one the socket's permissions do, as the
+    /// umask left them.
+    pub fn set_acl(&mut self, acl: Option<Acl>) -> io::Result<()> {
+        let mode = if acl.is_some() { 0o666 } else { self.mode };
+        fs::set_permissions(&self.path, fs::Permissions::from_mode(mode))?;
+        self.acl = acl;
+        Ok(())
+    }
+
+    /// Waits until a client connects or sends something, a signal arrives
+    /// or the timeout passes, whichever is first.
+    pub fn wait(&self, timeout: Duration) {
+        let mut fds: Vec<libc::pollfd> = std::iter::once(self.listener.as_raw_fd())
+            .chain(self.clients.iter().map(|client| client.stream.as_raw_fd()))
+            .map(|fd| libc::pollfd{fd, events: libc::POLLIN, revents: 0})
+            .collect();
+        // SAFETY: polls descriptors we hold for the call; an error, EINTR
+        // from a signal included, only ends the wait early
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout.as_millis().max(1) as libc::c_int) };
+    }
+
+    /// Accepts new clients and returns every complete request they sent.
+    /// Malformed requests are answered right away.
+    pub fn requests(&mut self) -> Vec<Request> {
+        while let Ok((stream, _)) = self.listener.accept() {
+            // clients whose credentials can't be told get nothing
+            let allowed = match &self.acl {
+                Some(acl) => acl::peer(&stream).map_or(Some(Vec::new()), |peer| acl.allowed(&peer)),
+                None => None,
+            };
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client{stream, pending: Vec::new(), allowed});
+            }
+        }
+        let mut requests = Vec::new();
+        for mut client in mem::take(&mut self.clients) {
+            let mut buf = [0u8; 1024];
+            let mut open = loop {
+                // the rest waits until the lines read so far are handled
+                if client.pending.len() >= MAX_LINE {
+                    break true
+                }
+                match client.stream.read(&mut buf) {
+                    Ok(0) => break false,
+                    Ok(n) => client.pending.extend_from_slice(&buf[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break true,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(_) => break false,
+                }
+            };
+            let mut subscribed = None;
+            while let Some(end) = client.pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = client.pending.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                if let Some(result) = hello(&line, &client.allowed) {
+                    reply(&client.stream, result);
+                    continue
+                }
+                match parse(&line) {
+                    Ok(command) if !permitted(&client.allowed, command.capability()) => {
+                        reply(&client.stream, Err(format!("not permitted {}", command.capability())));
+                    },
+                    // the stream goes to the subscription, and whatever
+                    // the client sends after it is of no interest
+                    Ok(command @ Command::Subscribe{..}) => {
+                        subscribed = Some(command);
+                        break
+                    },
+                    Ok(command) => match client.stream.try_clone() {
+                        Ok(stream) => requests.push(Request{command, stream}),
+                        Err(_) => open = false,
+                    },
+                    Err(e) => reply(&client.stream, Err(e)),
+                }
+            }
+            if subscribed.is_none() && client.pending.len() >= MAX_LINE {
+                reply(&client.stream, Err(format!("request longer than {} bytes", MAX_LINE)));
+                open = false;
+            }
+            match subscribed {
+                Some(command) => requests.push(Request{command, stream: client.stream}),
+                None if open => self.clients.push(client),
+                None => {},
+            }
+        }
+        requests
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        _ = fs::remove_file(&self.path);
+    }
+}
+
+fn read_reply(reader: &mut BufReader<UnixStream>) -> io::Result<Result<String, String>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let line = line.trim_end();
+    if let Some(text) = line.strip_prefix("ok") {
+        Ok(Ok(text.trim_start().to_string()))
+    } else if let Some(text) = line.strip_prefix("error") {
+        Ok(Err(text.trim_start().to_string()))
+    } else {
+        Err(io::Error::new(ErrorKind::InvalidData, format!("unexpected reply {}", line)))
+    }
+}
+
+/// Connects, says hello needing the capability and sends the request in
+/// one go, then returns the answer to the request with the stream for
+/// whatever follows it. A watch from before the handshake refuses the
+/// hello as an unknown request and is spoken to as version 1.
+pub fn open(path: &Path, capability: &str, request: &str) -> io::Result<(Result<String, String>, BufReader<UnixStream>)> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(format!("hello {} {}\n{}\n", PROTOCOL, capability, request).as_bytes())?;
+    let mut reader = BufReader::new(stream);
+    match read_reply(&mut reader)? {
+        Err(e) if !e.starts_with("unknown request") => {
+            return Err(io::Error::new(ErrorKind::Unsupported, format!("handshake refused: {}", e)))
+        },
+        _ => {},
+    }
+    Ok((read_reply(&mut reader)?, reader))
+}
+
+/// Sends one request to a running watch and returns its answer.
+pub fn send(path: &Path, capability: &str, request: &str) -> io::Result<Result<String, String>> {
+    open(path, capability, request).map(|(result, _)| result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::Replay;
+    use std::thread;
+
+    fn device(n: usize) -> String {
+        format!("1d50:{:04x} 001:{:03} 1-{}", n, n % 128, n)
+    }
+
+    /// Binds a socket of its own and serves it the way watch does for a
+    /// few seconds, listing this many devices.
+    fn serve(name: &str, devices: usize) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("usbmon-{}-{}.sock", name, std::process::id()));
+        let mut control = Control::bind(&path).unwrap();
+        let mut replay = Replay::new(16);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        thread::spawn(move || while Instant::now() < deadline {
+            for mut request in control.requests() {
+                match request.command {
+                    Command::List => {
+                        let lines: Vec<String> = (0..devices).map(device).collect();
+                        request.reply_lines(format!("0 {}", devices), &lines);
+                    },
+                    Command::Subscribe{since} => replay.subscribe(request.into_stream(), since),
+                    Command::Inject{..} => request.reply(Ok(String::new())),
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        });
+        path
+    }
+
+    #[test]
+    fn list_is_answered_while_another_client_is_subscribed() {
+        let path = serve("subscribed", 1);
+        let (first, _subscriber) = open(&path, "subscribe", "subscribe").unwrap();
+        assert_eq!(first, Ok(String::from("1")));
+        // the subscriber sends nothing more, which mustn't hold up the watch
+        let stream = UnixStream::connect(&path).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        (&stream).write_all(b"list\n").unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "ok 0 1\n");
+    }
+
+    #[test]
+    fn wait_ends_when_a_request_comes() {
+        let path = std::env::temp_dir().join(format!("usbmon-poll-{}.sock", std::process::id()));
+        let mut control = Control::bind(&path).unwrap();
+        let client = UnixStream::connect(&path).unwrap();
+        let start = Instant::now();
+        control.wait(Duration::from_secs(5));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(control.requests().is_empty());
+        // a connected client that sends nothing doesn't end it
+        let start = Instant::now();
+        control.wait(Duration::from_millis(200));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            (&client).write_all(b"list\n").unwrap();
+            client
+        });
+        let start = Instant::now();
+        control.wait(Duration::from_secs(5));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(control.requests().len(), 1);
+        drop(writer.join());
+    }
+
+    #[test]
+    fn oversized_request_drops_the_client() {
+        let path = serve("oversized", 1);
+        let stream = UnixStream::connect(&path).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        // all of it is read, so the hangup that follows is no reset
+        (&stream).write_all(&[b'x'; MAX_LINE]).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, format!("error request longer than {} bytes\n", MAX_LINE));
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+    }
+
+    #[test]
+    fn requests_sent_together_are_all_answered() {
+        let path = serve("together", 1);
+        let stream = UnixStream::connect(&path).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        // more than a line's worth in all, each of them short
+        let count = 2 * MAX_LINE / 5;
+        (&stream).write_all("list\n".repeat(count).as_bytes()).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "ok 0 1\n");
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+    }
+
+    #[test]
+    fn long_list_arrives_whole() {
+        // far more than the socket buffer holds before the client reads
+        let path = serve("long", 50_000);
+        let stream = UnixStream::connect(&path).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        (&stream).write_all(b"list\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "ok 0 50000\n");
+        for n in 0..50_000 {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line.trim_end(), device(n));
+        }
+    }
+}
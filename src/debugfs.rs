@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use crate::inventory::Entry;
+use crate::DeviceID;
+
+/// Where the kernel's usbmon text interface is, with debugfs mounted.
+pub const DIR: &str = "/sys/kernel/debug/usb/usbmon";
+
+/// Statuses of URBs that were given back because the device is gone.
+const ENODEV: i32 = -19;
+const ESHUTDOWN: i32 = -108;
+
+/// One line of a usbmon text stream, what happened to a URB:
+///
+/// ```text
+/// ffff9a26c4b0e000 3575914555 C Ci:1:005:0 0 18 = 12010002 00000040 6b1d0200 ...
+/// ```
+///
+/// with the tag, the time in microseconds, S, C or E for submission,
+/// callback or error, the transfer type and direction, bus, device and
+/// endpoint, then the status, or `s` and a setup packet for control
+/// submissions, the length and the first bytes of data after `=`.
+#[derive(Debug)]
+pub struct Urb {
+    pub event: char,
+    /// C, Z, I or B for control, isochronous, interrupt or bulk.
+    pub transfer: char,
+    pub inbound: bool,
+    pub bus: u8,
+    pub device: u8,
+    pub endpoint: u8,
+    /// None for control submissions, which carry a setup packet instead.
+    pub status: Option<i32>,
+    pub data: Vec<u8>,
+}
+
+pub fn parse(line: &str) -> Option<Urb> {
+    let mut words = line.split_whitespace().skip(2);
+    let event = words.next()?.chars().next()?;
+    let mut address = words.next()?.split(':');
+    let mut kind = address.next()?.chars();
+    let (transfer, direction) = (kind.next()?, kind.next()?);
+    let bus = address.next()?.parse().ok()?;
+    let device = address.next()?.parse().ok()?;
+    let endpoint = address.next()?.parse().ok()?;
+    let status = match words.next()? {
+        "s" => {
+            // the five words of the setup packet
+            words.by_ref().take(5).for_each(drop);
+            None
+        },
+        // isochronous and interrupt statuses go on with :interval and such
+        status => Some(status.split(':').next()?.parse().ok()?),
+    };
+    let _length = words.next();
+    let data = match words.next() {
+        Some("=") => words
+            .flat_map(|word| (0..word.len() / 2).map(move |i| u8::from_str_radix(&word[2 * i..2 * i + 2], 16)))
+            .collect::<Result<Vec<u8>, _>>()
+            .ok()?,
+        _ => Vec::new(),
+    };
+    Some(Urb{event, transfer, inbound: direction == 'i', bus, device, endpoint, status, data})
+}
+
+/// Turns URBs into the attach and detach of the devices they are for. A
+/// device attaches when its device descriptor is first read at its
+/// address and detaches when its URBs start coming back because it is
+/// gone. A driver letting go of a device flushes its URBs the same way,
+/// so that reads as a detach too, until the next descriptor read. Devices
+/// that were there before the stream started aren't known, so they don't
+/// detach either.
+#[derive(Default)]
+pub struct Tracker {
+    devices: HashMap<(u8, u8), DeviceID>,
+}
+
+impl Tracker {
+    pub fn track(&mut self, urb: &Urb) -> Option<(bool, Entry)> {
+        let key = (urb.bus, urb.device);
+        let entry = |id: DeviceID| Entry{bus: urb.bus, address: urb.device, id, port: String::new()};
+        let descriptor = urb.event == 'C' && urb.transfer == 'C' && urb.inbound && urb.endpoint == 0 && urb.status == Some(0)
+            && urb.data.len() >= 12 && urb.data[..2] == [18, 1];
+        // address 0 is where every device starts, before it gets its own
+        if descriptor && urb.device != 0 {
+            let id = DeviceID{
+                vid: u16::from_le_bytes([urb.data[8], urb.data[9]]),
+                pid: u16::from_le_bytes([urb.data[10], urb.data[11]]),
+            };
+            // read again after a reset
+            if self.devices.insert(key, id.clone()).as_ref() == Some(&id) {
+                return None
+            }
+            return Some((true, entry(id)))
+        }
+        if matches!(urb.status, Some(ENODEV) | Some(ESHUTDOWN)) && urb.event != 'S' {
+            return self.devices.remove(&key).map(|id| (false, entry(id)))
+        }
+        None
+    }
+}
@@ -0,0 +1,454 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use rusb::UsbContext;
+
+use crate::ccid;
+use crate::cdc;
+use crate::dfu;
+use crate::hid;
+use crate::inventory;
+use crate::sysfs;
+use crate::uac;
+use crate::uvc;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Walks a descriptor blob, yielding each sub-descriptor by its bLength.
+pub struct Descriptors<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Descriptors<'a> {
+    pub fn new(bytes: &'a [u8]) -> Descriptors<'a> {
+        Descriptors { bytes }
+    }
+}
+
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.bytes.is_empty() {
+            return None
+        }
+        let len = self.bytes[0] as usize;
+        // a zero or overlong bLength would loop forever, hand back the rest
+        let len = if len < 2 || len > self.bytes.len() { self.bytes.len() } else { len };
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Some(head)
+    }
+}
+
+pub fn class_name(class: u8) -> &'static str {
+    match class {
+        0x00 => "(Defined at Interface level)",
+        0x01 => "Audio",
+        0x02 => "Communications",
+        0x03 => "Human Interface Device",
+        0x05 => "Physical Interface Device",
+        0x06 => "Imaging",
+        0x07 => "Printer",
+        0x08 => "Mass Storage",
+        0x09 => "Hub",
+        0x0a => "CDC Data",
+        0x0b => "Chip/SmartCard",
+        0x0d => "Content Security",
+        0x0e => "Video",
+        0x0f => "Personal Healthcare",
+        0x10 => "Audio/Video",
+        0x11 => "Billboard",
+        0x12 => "Type-C Bridge",
+        0xdc => "Diagnostic",
+        0xe0 => "Wireless",
+        0xef => "Miscellaneous Device",
+        0xfe => "Application Specific Interface",
+        0xff => "Vendor Specific Class",
+        _ => "",
+    }
+}
+
+/// Writes `name` and `value` so that short values line up in the same
+/// column, the way lsusb -v lays out its fields.
+pub fn field(out: &mut String, indent: usize, name: &str, value: impl std::fmt::Display) {
+    let value = value.to_string();
+    let width = 25usize.saturating_sub(name.len()).max(value.len() + 1);
+    _ = writeln!(out, "{:indent$}{}{:>width$}", "", name, value, indent = indent, width = width);
+}
+
+pub fn field_desc(out: &mut String, indent: usize, name: &str, value: impl std::fmt::Display, desc: &str) {
+    let value = format!("{}", value);
+    let width = 25usize.saturating_sub(name.len()).max(value.len() + 1);
+    _ = writeln!(out, "{:indent$}{}{:>width$} {}", "", name, value, desc, indent = indent, width = width);
+}
+
+pub fn le16(bytes: &[u8], at: usize) -> u16 {
+    bytes.get(at..at + 2).map_or(0, |b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+pub fn le24(bytes: &[u8], at: usize) -> u32 {
+    bytes.get(at..at + 3).map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], 0]))
+}
+
+pub fn le32(bytes: &[u8], at: usize) -> u32 {
+    bytes.get(at..at + 4).map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(" ")
+}
+
+pub fn unrecognized(out: &mut String, indent: usize, bytes: &[u8]) {
+    _ = writeln!(out, "{:indent$}** UNRECOGNIZED:  {}", "", hex_bytes(bytes), indent = indent);
+}
+
+/// Offset, hex and ASCII, 16 bytes a line like `hexdump -C`.
+pub fn hex_dump(out: &mut String, indent: usize, bytes: &[u8]) {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let (first, second) = hex.split_at(hex.len().min(8));
+        let ascii: String = line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+        _ = writeln!(out, "{:indent$}{:04x}  {:<23}  {:<23}  |{}|", "", i * 16, first.join(" "), second.join(" "), ascii, indent = indent);
+    }
+}
+
+fn bcd(version: rusb::Version) -> String {
+    format!("{}.{}{}", version.major(), version.minor(), version.sub_minor())
+}
+
+pub fn string<T: UsbContext>(handle: Option<&rusb::DeviceHandle<T>>, index: Option<u8>) -> String {
+    match (handle, index) {
+        (Some(handle), Some(index)) => handle.read_string_descriptor_ascii(index).unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+fn endpoint_length(iface: &rusb::InterfaceDescriptor) -> usize {
+    // UAC1 streaming endpoints carry bRefresh and bSynchAddress
+    if iface.class_code() == 0x01 && iface.sub_class_code() == 0x02 && iface.protocol_code() == 0x00 {
+        9
+    } else {
+        7
+    }
+}
+
+fn total_length(config: &rusb::ConfigDescriptor) -> usize {
+    let mut total = 9 + config.extra().len();
+    for iface in config.interfaces() {
+        for alt in iface.descriptors() {
+            total += 9 + alt.extra().len();
+            for ep in alt.endpoint_descriptors() {
+                total += endpoint_length(&alt) + ep.extra().map_or(0, |e| e.len());
+            }
+        }
+    }
+    total
+}
+
+/// Produces an lsusb -v style dump of every descriptor the device exposes.
+/// Class-specific descriptors this module doesn't decode are printed as raw hex.
+pub fn dump<T: UsbContext>(device: &rusb::Device<T>) -> rusb::Result<String> {
+    let desc = device.device_descriptor()?;
+    let handle = device.open().ok();
+    let handle = handle.as_ref();
+    let mut out = String::new();
+
+    _ = writeln!(out, "Bus {:03} Device {:03}: ID {:04x}:{:04x} {} {}",
+        device.bus_number(), device.address(), desc.vendor_id(), desc.product_id(),
+        string(handle, desc.manufacturer_string_index()),
+        string(handle, desc.product_string_index()));
+    if handle.is_none() {
+        _ = writeln!(out, "Couldn't open device, some information will be missing");
+    }
+    _ = writeln!(out, "Device Descriptor:");
+    field(&mut out, 2, "bLength", 18);
+    field(&mut out, 2, "bDescriptorType", 1);
+    field(&mut out, 2, "bcdUSB", bcd(desc.usb_version()));
+    field_desc(&mut out, 2, "bDeviceClass", desc.class_code(), class_name(desc.class_code()));
+    field(&mut out, 2, "bDeviceSubClass", desc.sub_class_code());
+    field(&mut out, 2, "bDeviceProtocol", desc.protocol_code());
+    field(&mut out, 2, "bMaxPacketSize0", desc.max_packet_size());
+    field(&mut out, 2, "idVendor", format!("0x{:04x}", desc.vendor_id()));
+    field(&mut out, 2, "idProduct", format!("0x{:04x}", desc.product_id()));
+    field(&mut out, 2, "bcdDevice", bcd(desc.device_version()));
+    field_desc(&mut out, 2, "iManufacturer", desc.manufacturer_string_index().unwrap_or(0),
+        &string(handle, desc.manufacturer_string_index()));
+    field_desc(&mut out, 2, "iProduct", desc.product_string_index().unwrap_or(0),
+        &string(handle, desc.product_string_index()));
+    field_desc(&mut out, 2, "iSerial", desc.serial_number_string_index().unwrap_or(0),
+        &string(handle, desc.serial_number_string_index()));
+    field(&mut out, 2, "bNumConfigurations", desc.num_configurations());
+
+    for index in 0..desc.num_configurations() {
+        let config = device.config_descriptor(index)?;
+        dump_config(&mut out, handle, &config, device.speed());
+    }
+    Ok(out)
+}
+
+fn dump_config<T: UsbContext>(
+    out: &mut String,
+    handle: Option<&rusb::DeviceHandle<T>>,
+    config: &rusb::ConfigDescriptor,
+    speed: rusb::Speed,
+) {
+    let mut attributes = 0x80;
+    if config.self_powered() {
+        attributes |= 0x40;
+    }
+    if config.remote_wakeup() {
+        attributes |= 0x20;
+    }
+
+    _ = writeln!(out, "  Configuration Descriptor:");
+    field(out, 4, "bLength", 9);
+    field(out, 4, "bDescriptorType", 2);
+    field(out, 4, "wTotalLength", format!("0x{:04x}", total_length(config)));
+    field(out, 4, "bNumInterfaces", config.num_interfaces());
+    field(out, 4, "bConfigurationValue", config.number());
+    field_desc(out, 4, "iConfiguration", config.description_string_index().unwrap_or(0),
+        &string(handle, config.description_string_index()));
+    field(out, 4, "bmAttributes", format!("0x{:02x}", attributes));
+    if config.self_powered() {
+        _ = writeln!(out, "      Self Powered");
+    } else {
+        _ = writeln!(out, "      (Bus Powered)");
+    }
+    if config.remote_wakeup() {
+        _ = writeln!(out, "      Remote Wakeup");
+    }
+    // as lsusb -v and list power= count it
+    _ = writeln!(out, "    MaxPower{:>17}mA", inventory::milliamps(config, speed));
+
+    for bytes in Descriptors::new(config.extra()) {
+        if bytes.len() >= 8 && bytes[1] == 0x0b {
+            dump_association(out, handle, bytes);
+        } else {
+            unrecognized(out, 4, bytes);
+        }
+    }
+
+    for iface in config.interfaces() {
+        for alt in iface.descriptors() {
+            dump_interface(out, handle, &alt);
+        }
+    }
+}
+
+fn dump_association<T: UsbContext>(out: &mut String, handle: Option<&rusb::DeviceHandle<T>>, bytes: &[u8]) {
+    _ = writeln!(out, "    Interface Association:");
+    field(out, 6, "bLength", bytes[0]);
+    field(out, 6, "bDescriptorType", bytes[1]);
+    field(out, 6, "bFirstInterface", bytes[2]);
+    field(out, 6, "bInterfaceCount", bytes[3]);
+    field_desc(out, 6, "bFunctionClass", bytes[4], class_name(bytes[4]));
+    field(out, 6, "bFunctionSubClass", bytes[5]);
+    field(out, 6, "bFunctionProtocol", bytes[6]);
+    let index = if bytes[7] == 0 { None } else { Some(bytes[7]) };
+    field_desc(out, 6, "iFunction", bytes[7], &string(handle, index));
+}
+
+fn dump_interface<T: UsbContext>(
+    out: &mut String,
+    handle: Option<&rusb::DeviceHandle<T>>,
+    alt: &rusb::InterfaceDescriptor,
+) {
+    _ = writeln!(out, "    Interface Descriptor:");
+    field(out, 6, "bLength", 9);
+    field(out, 6, "bDescriptorType", 4);
+    field(out, 6, "bInterfaceNumber", alt.interface_number());
+    field(out, 6, "bAlternateSetting", alt.setting_number());
+    field(out, 6, "bNumEndpoints", alt.num_endpoints());
+    field_desc(out, 6, "bInterfaceClass", alt.class_code(), class_name(alt.class_code()));
+    field(out, 6, "bInterfaceSubClass", alt.sub_class_code());
+    field(out, 6, "bInterfaceProtocol", alt.protocol_code());
+    field_desc(out, 6, "iInterface", alt.description_string_index().unwrap_or(0),
+        &string(handle, alt.description_string_index()));
+
+    for bytes in Descriptors::new(alt.extra()) {
+        let decoded = match alt.class_code() {
+            0x01 => uac::dump_interface(out, handle, alt, bytes),
+            0x02 => cdc::dump_interface(out, handle, bytes),
+            0x03 => dump_hid(out, handle, alt.interface_number(), bytes),
+            0x0b => ccid::dump_interface(out, bytes),
+            0x0e => uvc::dump_interface(out, handle, alt, bytes),
+            0xfe => dfu::dump_interface(out, alt, bytes),
+            _ => false,
+        };
+        if !decoded {
+            unrecognized(out, 6, bytes);
+        }
+    }
+
+    for ep in alt.endpoint_descriptors() {
+        dump_endpoint(out, alt, &ep);
+    }
+}
+
+fn dump_endpoint(out: &mut String, alt: &rusb::InterfaceDescriptor, ep: &rusb::EndpointDescriptor) {
+    let direction = match ep.direction() {
+        rusb::Direction::In => "IN",
+        rusb::Direction::Out => "OUT",
+    };
+    let (transfer, transfer_bits) = match ep.transfer_type() {
+        rusb::TransferType::Control => ("Control", 0),
+        rusb::TransferType::Isochronous => ("Isochronous", 1),
+        rusb::TransferType::Bulk => ("Bulk", 2),
+        rusb::TransferType::Interrupt => ("Interrupt", 3),
+    };
+    let (sync, sync_bits) = match ep.sync_type() {
+        rusb::SyncType::NoSync => ("None", 0),
+        rusb::SyncType::Asynchronous => ("Asynchronous", 1),
+        rusb::SyncType::Adaptive => ("Adaptive", 2),
+        rusb::SyncType::Synchronous => ("Synchronous", 3),
+    };
+    let (usage, usage_bits) = match ep.usage_type() {
+        rusb::UsageType::Data => ("Data", 0),
+        rusb::UsageType::Feedback => ("Feedback", 1),
+        rusb::UsageType::FeedbackData => ("Implicit feedback Data", 2),
+        rusb::UsageType::Reserved => ("Reserved", 3),
+    };
+    let length = endpoint_length(alt);
+    let packet = ep.max_packet_size();
+
+    _ = writeln!(out, "      Endpoint Descriptor:");
+    field(out, 8, "bLength", length);
+    field(out, 8, "bDescriptorType", 5);
+    field_desc(out, 8, "bEndpointAddress", format!("0x{:02x}", ep.address()),
+        &format!(" EP {} {}", ep.number(), direction));
+    field(out, 8, "bmAttributes", transfer_bits | sync_bits << 2 | usage_bits << 4);
+    _ = writeln!(out, "          Transfer Type            {}", transfer);
+    _ = writeln!(out, "          Synch Type               {}", sync);
+    _ = writeln!(out, "          Usage Type               {}", usage);
+    field_desc(out, 8, "wMaxPacketSize", format!("0x{:04x}", packet),
+        &format!(" {}x {} bytes", ((packet >> 11) & 3) + 1, packet & 0x7ff));
+    field(out, 8, "bInterval", ep.interval());
+    if length == 9 {
+        field(out, 8, "bRefresh", ep.refresh());
+        field(out, 8, "bSynchAddress", ep.synch_address());
+    }
+
+    if let Some(extra) = ep.extra() {
+        for bytes in Descriptors::new(extra) {
+            let decoded = match alt.class_code() {
+                0x01 => uac::dump_endpoint(out, alt, bytes),
+                0x0b => ccid::dump_interface(out, bytes),
+                _ => false,
+            };
+            if !decoded {
+                unrecognized(out, 8, bytes);
+            }
+        }
+    }
+}
+
+fn dump_hid<T: UsbContext>(
+    out: &mut String,
+    handle: Option<&rusb::DeviceHandle<T>>,
+    iface: u8,
+    bytes: &[u8],
+) -> bool {
+    if bytes.len() < 6 || bytes[1] != 0x21 {
+        return false
+    }
+    _ = writeln!(out, "        HID Device Descriptor:");
+    field(out, 10, "bLength", bytes[0]);
+    field(out, 10, "bDescriptorType", bytes[1]);
+    field(out, 10, "bcdHID", format!("{:x}.{:02x}", bytes[3], bytes[2]));
+    field(out, 10, "bCountryCode", bytes[4]);
+    field(out, 10, "bNumDescriptors", bytes[5]);
+
+    for class in bytes[6..].chunks_exact(3) {
+        let kind = class[0];
+        let length = u16::from_le_bytes([class[1], class[2]]);
+        field_desc(out, 10, "bDescriptorType", kind, if kind == 0x22 { "Report" } else { "" });
+        field(out, 10, "wDescriptorLength", length);
+        if kind != 0x22 {
+            continue;
+        }
+        match handle.map(|h| read_report_descriptor(h, iface, length)) {
+            Some(Ok(report)) => {
+                _ = writeln!(out, "          Report Descriptor: (length is {})", report.len());
+                hid::dump(out, 12, &report);
+            },
+            _ => _ = writeln!(out, "          Report Descriptors: \n            ** UNAVAILABLE **"),
+        }
+    }
+    true
+}
+
+/// A descriptor of the type and index, read whole: the header first when
+/// the total length is in wTotalLength rather than bLength.
+fn read_descriptor<T: UsbContext>(handle: &rusb::DeviceHandle<T>, kind: u8, index: u8, header: usize) -> rusb::Result<Vec<u8>> {
+    let request_type = rusb::request_type(rusb::Direction::In, rusb::RequestType::Standard, rusb::Recipient::Device);
+    let value = (kind as u16) << 8 | index as u16;
+    let mut buf = vec![0u8; header];
+    let n = handle.read_control(request_type, 0x06, value, 0, &mut buf, TIMEOUT)?;
+    if kind != 0x01 && n >= 4 {
+        buf = vec![0u8; le16(&buf, 2) as usize];
+        let n = handle.read_control(request_type, 0x06, value, 0, &mut buf, TIMEOUT)?;
+        buf.truncate(n);
+        return Ok(buf)
+    }
+    buf.truncate(n);
+    Ok(buf)
+}
+
+/// The raw bytes of the device, configuration and BOS descriptors, for
+/// bug reports where the decoded fields of `dump` hide what was sent.
+/// Devices that can't be opened are dumped from what the kernel read at
+/// enumeration, which has no BOS.
+pub fn dump_hex<T: UsbContext>(device: &rusb::Device<T>) -> rusb::Result<String> {
+    let desc = device.device_descriptor()?;
+    let mut out = String::new();
+    _ = writeln!(out, "Bus {:03} Device {:03}: ID {:04x}:{:04x}", device.bus_number(), device.address(), desc.vendor_id(), desc.product_id());
+    let mut blobs = Vec::new();
+    match device.open() {
+        Ok(handle) => {
+            blobs.push((String::from("Device Descriptor"), read_descriptor(&handle, 0x01, 0, 18)?));
+            for index in 0..desc.num_configurations() {
+                blobs.push((format!("Configuration Descriptor {}", index), read_descriptor(&handle, 0x02, index, 9)?));
+            }
+            // BOS came with USB 2.01, older devices may stall on it
+            let version = desc.usb_version();
+            if (version.major(), version.minor(), version.sub_minor()) >= (2, 0, 1) {
+                match read_descriptor(&handle, 0x0f, 0, 5) {
+                    Ok(bos) => blobs.push((String::from("BOS Descriptor"), bos)),
+                    Err(e) => _ = writeln!(out, "No BOS descriptor: {}", e),
+                }
+            }
+        },
+        Err(e) => {
+            let bytes = sysfs::descriptors(device).ok_or(e)?;
+            _ = writeln!(out, "Couldn't open device ({}), dumping what the kernel read at enumeration", e);
+            let (device, mut rest) = bytes.split_at(bytes.len().min(18));
+            blobs.push((String::from("Device Descriptor"), device.to_vec()));
+            let mut index = 0;
+            while rest.len() >= 4 {
+                let len = (le16(rest, 2) as usize).clamp(4, rest.len());
+                let (config, tail) = rest.split_at(len);
+                blobs.push((format!("Configuration Descriptor {}", index), config.to_vec()));
+                rest = tail;
+                index += 1;
+            }
+        },
+    }
+    for (name, bytes) in blobs {
+        _ = writeln!(out, "{} ({} bytes):", name, bytes.len());
+        hex_dump(&mut out, 2, &bytes);
+    }
+    Ok(out)
+}
+
+pub fn read_report_descriptor<T: UsbContext>(
+    handle: &rusb::DeviceHandle<T>,
+    iface: u8,
+    length: u16,
+) -> rusb::Result<Vec<u8>> {
+    let request_type = rusb::request_type(rusb::Direction::In, rusb::RequestType::Standard, rusb::Recipient::Interface);
+    let mut buf = vec![0u8; length as usize];
+    let n = handle.read_control(request_type, 0x06, 0x22 << 8, iface as u16, &mut buf, TIMEOUT)?;
+    buf.truncate(n);
+    Ok(buf)
+}
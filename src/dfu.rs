@@ -0,0 +1,73 @@
+use std::fmt;
+use std::fmt::Write;
+
+use rusb::UsbContext;
+
+use crate::descriptors::{field, le16};
+
+const CLASS_APPLICATION: u8 = 0xfe;
+const SUBCLASS_DFU: u8 = 0x01;
+
+const PROTOCOL_RUNTIME: u8 = 0x01;
+const PROTOCOL_DFU: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Runtime,
+    Dfu,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mode::Runtime => write!(f, "runtime"),
+            Mode::Dfu => write!(f, "DFU"),
+        }
+    }
+}
+
+/// Looks for a DFU interface in the active configuration and reports which
+/// side of the runtime/DFU split the device is currently on.
+pub fn mode<T: UsbContext>(device: &rusb::Device<T>) -> Option<Mode> {
+    let config = device.active_config_descriptor().ok()?;
+    let mut mode = None;
+    for iface in config.interfaces() {
+        for alt in iface.descriptors() {
+            if alt.class_code() != CLASS_APPLICATION || alt.sub_class_code() != SUBCLASS_DFU {
+                continue;
+            }
+            match alt.protocol_code() {
+                PROTOCOL_DFU => return Some(Mode::Dfu),
+                PROTOCOL_RUNTIME => mode = Some(Mode::Runtime),
+                _ => {},
+            }
+        }
+    }
+    mode
+}
+
+/// Decodes the DFU functional descriptor. Returns false for anything else.
+pub fn dump_interface(out: &mut String, alt: &rusb::InterfaceDescriptor, bytes: &[u8]) -> bool {
+    if alt.sub_class_code() != SUBCLASS_DFU || bytes.len() < 7 || bytes[1] != 0x21 {
+        return false
+    }
+    _ = writeln!(out, "      Device Firmware Upgrade Interface Descriptor:");
+    field(out, 8, "bLength", bytes[0]);
+    field(out, 8, "bDescriptorType", bytes[1]);
+    field(out, 8, "bmAttributes", bytes[2]);
+    let attributes = [
+        ("Download Supported", "No Download"),
+        ("Upload Supported", "No Upload"),
+        ("Manifestation Tolerant", "Not Manifestation Tolerant"),
+        ("Will Detach", "Will Not Detach"),
+    ];
+    for (bit, (set, clear)) in attributes.iter().enumerate() {
+        _ = writeln!(out, "          {}", if bytes[2] & (1 << bit) != 0 { set } else { clear });
+    }
+    field(out, 8, "wDetachTimeout", format!("{} milliseconds", le16(bytes, 3)));
+    field(out, 8, "wTransferSize", format!("{} bytes", le16(bytes, 5)));
+    if bytes.len() >= 9 {
+        field(out, 8, "bcdDFUVersion", format!("{:x}.{:02x}", bytes[8], bytes[7]));
+    }
+    true
+}
@@ -0,0 +1,81 @@
+use rusb::UsbContext;
+
+use crate::udev;
+
+/// The libusb name of the error, as its documentation and forums use it.
+pub fn constant(e: &rusb::Error) -> &'static str {
+    match e {
+        rusb::Error::Io => "LIBUSB_ERROR_IO",
+        rusb::Error::InvalidParam => "LIBUSB_ERROR_INVALID_PARAM",
+        rusb::Error::Access => "LIBUSB_ERROR_ACCESS",
+        rusb::Error::NoDevice => "LIBUSB_ERROR_NO_DEVICE",
+        rusb::Error::NotFound => "LIBUSB_ERROR_NOT_FOUND",
+        rusb::Error::Busy => "LIBUSB_ERROR_BUSY",
+        rusb::Error::Timeout => "LIBUSB_ERROR_TIMEOUT",
+        rusb::Error::Overflow => "LIBUSB_ERROR_OVERFLOW",
+        rusb::Error::Pipe => "LIBUSB_ERROR_PIPE",
+        rusb::Error::Interrupted => "LIBUSB_ERROR_INTERRUPTED",
+        rusb::Error::NoMem => "LIBUSB_ERROR_NO_MEM",
+        rusb::Error::NotSupported => "LIBUSB_ERROR_NOT_SUPPORTED",
+        rusb::Error::BadDescriptor => "bad descriptor",
+        rusb::Error::Other => "LIBUSB_ERROR_OTHER",
+    }
+}
+
+/// The errno the backend most likely got from the kernel.
+fn errno(e: &rusb::Error) -> Option<&'static str> {
+    match e {
+        rusb::Error::Io => Some("EIO"),
+        rusb::Error::InvalidParam => Some("EINVAL"),
+        rusb::Error::Access => Some("EACCES or EPERM"),
+        rusb::Error::NoDevice => Some("ENODEV"),
+        rusb::Error::NotFound => Some("ENOENT"),
+        rusb::Error::Busy => Some("EBUSY"),
+        rusb::Error::Timeout => Some("ETIMEDOUT"),
+        rusb::Error::Overflow => Some("EOVERFLOW"),
+        rusb::Error::Pipe => Some("EPIPE"),
+        rusb::Error::Interrupted => Some("EINTR"),
+        rusb::Error::NoMem => Some("ENOMEM"),
+        rusb::Error::NotSupported => Some("ENOSYS"),
+        rusb::Error::BadDescriptor | rusb::Error::Other => None,
+    }
+}
+
+/// What usually fixes the error.
+fn remedy(e: &rusb::Error) -> &'static str {
+    match e {
+        rusb::Error::Access => "give your user access to the device node, usbmon doctor --id VID:PID suggests a udev rule",
+        rusb::Error::NoDevice => "the device went away, check the cable and the hub's power",
+        rusb::Error::Busy => "another process or a kernel driver has the interface claimed, see usbmon who-has",
+        rusb::Error::NotFound => "the device or interface isn't there, or has no usable driver (WinUSB on Windows)",
+        rusb::Error::Timeout => "the device didn't answer, replug it or try another port",
+        rusb::Error::Pipe => "the device stalled the request, it may not support it in its current mode",
+        rusb::Error::Io | rusb::Error::Overflow => "the transfer failed on the bus, replug the device or try a shorter cable",
+        rusb::Error::NotSupported => "this libusb backend can't do it on this platform",
+        rusb::Error::NoMem => "raise the usbfs memory limit, /sys/module/usbcore/parameters/usbfs_memory_mb",
+        rusb::Error::Other => "in a container, pass /dev/bus/usb in and mount /sys; usbmon doctor checks both",
+        rusb::Error::InvalidParam | rusb::Error::Interrupted | rusb::Error::BadDescriptor => "run again; if it persists, report it",
+    }
+}
+
+/// Which libusb and platform backend are in use, e.g. `libusb 1.0.26 (linux)`.
+fn backend() -> String {
+    let version = rusb::version();
+    format!("libusb {}.{}.{} ({})", version.major(), version.minor(), version.micro(), std::env::consts::OS)
+}
+
+/// Explains a failure on stderr with everything needed to make sense of
+/// it, for --verbose: what failed and on which device, the backend, the
+/// libusb error and errno it stands for, and what to try.
+pub fn report<T: UsbContext>(what: &str, device: Option<&rusb::Device<T>>, e: &rusb::Error) {
+    let mut lines = vec![format!("{} failed: {} ({})", what, e, constant(e)), format!("  backend: {}", backend())];
+    if let Some(device) = device {
+        let id = device.device_descriptor().map(|d| format!(" {:04x}:{:04x}", d.vendor_id(), d.product_id())).unwrap_or_default();
+        lines.push(format!("  device: {:03}:{:03}{} {}", device.bus_number(), device.address(), id, udev::node(device).display()));
+    }
+    if let Some(errno) = errno(e) {
+        lines.push(format!("  errno: {}", errno));
+    }
+    lines.push(format!("  try: {}", remedy(e)));
+    eprintln!("{}", lines.join("\n"));
+}
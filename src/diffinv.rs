@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::Path;
+
+use crate::json;
+use crate::DiffInventoryArgs;
+
+/// A device of a saved `list --format json`.
+#[derive(Debug)]
+struct Saved {
+    id: String,
+    port: String,
+    serial: String,
+    revision: String,
+    name: String,
+}
+
+fn load(path: &Path) -> Result<Vec<Saved>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    json::objects(&text)
+        .into_iter()
+        .map(|object| {
+            let field = |key: &str| json::get(object, key).unwrap_or_default();
+            match json::get(object, "id") {
+                Some(id) => Ok(Saved{id, port: field("port"), serial: field("serial"), revision: field("revision"), name: field("name")}),
+                None => Err(format!("{}: a device without an id", path.display())),
+            }
+        })
+        .collect()
+}
+
+/// Pairs the devices of the new inventory with those of the old one, by
+/// id and serial where there is one, which survives moving the device to
+/// another port, and then by id and port.
+fn pair<'a>(old: &'a [Saved], new: &'a [Saved]) -> (Vec<(&'a Saved, &'a Saved)>, Vec<&'a Saved>, Vec<&'a Saved>) {
+    let mut left: Vec<&Saved> = old.iter().collect();
+    let mut pairs = Vec::new();
+    let passes: [fn(&Saved, &Saved) -> bool; 2] = [
+        |a, b| a.id == b.id && !a.serial.is_empty() && a.serial == b.serial,
+        |a, b| a.id == b.id && a.port == b.port,
+    ];
+    let mut unpaired: Vec<&Saved> = new.iter().collect();
+    for same in passes {
+        unpaired.retain(|b| match left.iter().position(|a| same(a, b)) {
+            Some(i) => {
+                pairs.push((left.remove(i), *b));
+                false
+            },
+            None => true,
+        });
+    }
+    (pairs, unpaired, left)
+}
+
+fn describe(device: &Saved) -> String {
+    let mut text = format!("{} {}", device.id, if device.port.is_empty() { "-" } else { &device.port });
+    if !device.name.is_empty() {
+        text.push_str(&format!(" {}", device.name));
+    }
+    text
+}
+
+/// Prints `+` for devices only in the new inventory, `-` for those only in
+/// the old one and `~` with what changed for those in both. Returns
+/// whether the two match.
+pub fn run(args: &DiffInventoryArgs) -> Result<bool, String> {
+    let old = load(&args.old)?;
+    let new = load(&args.new)?;
+    let (pairs, added, removed) = pair(&old, &new);
+    for device in &removed {
+        println!("- {}", describe(device));
+    }
+    for device in &added {
+        println!("+ {}", describe(device));
+    }
+    let mut changed = 0;
+    for (a, b) in pairs {
+        let changes: Vec<String> = [("port", &a.port, &b.port), ("serial", &a.serial, &b.serial), ("revision", &a.revision, &b.revision)]
+            .into_iter()
+            .filter(|(_, before, after)| before != after)
+            .map(|(what, before, after)| format!("{} {} -> {}", what, before, after))
+            .collect();
+        if !changes.is_empty() {
+            println!("~ {}: {}", describe(b), changes.join(", "));
+            changed += 1;
+        }
+    }
+    Ok(removed.is_empty() && added.is_empty() && changed == 0)
+}
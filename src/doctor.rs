@@ -0,0 +1,168 @@
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use rusb::UsbContext;
+
+use crate::container;
+use crate::{DeviceID, DoctorArgs};
+
+const DEV_BUS_USB: &str = "/dev/bus/usb";
+const SYSFS_USB: &str = "/sys/bus/usb/devices";
+const UDEV_RULES: [&str; 4] = ["/etc/udev/rules.d", "/run/udev/rules.d", "/usr/lib/udev/rules.d", "/lib/udev/rules.d"];
+
+/// Programs some options run, with what needs them.
+const TOOLS: [(&str, &str); 5] = [
+    ("udevadm", "--settle"),
+    ("udisksctl", "--mount"),
+    ("curl", "HTTPS webhooks"),
+    ("openssl", "tls: outputs and hub --tls-cert"),
+    ("gzip", "--log-gzip"),
+];
+
+struct Report {
+    failed: usize,
+}
+
+impl Report {
+    fn ok(&self, what: &str, detail: &str) {
+        println!("ok    {}: {}", what, detail);
+    }
+
+    fn warn(&self, what: &str, detail: &str, fix: &str) {
+        println!("warn  {}: {}", what, detail);
+        println!("      fix: {}", fix);
+    }
+
+    fn fail(&mut self, what: &str, detail: &str, fix: &str) {
+        self.failed += 1;
+        println!("FAIL  {}: {}", what, detail);
+        println!("      fix: {}", fix);
+    }
+}
+
+fn accessible(path: &Path) -> bool {
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    // SAFETY: a valid NUL terminated path
+    unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) == 0 }
+}
+
+fn on_path(program: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?).map(|dir| dir.join(program)).find(|path| path.is_file())
+}
+
+fn rule(id: &DeviceID) -> String {
+    format!("SUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{:04x}\", ATTR{{idProduct}}==\"{:04x}\", TAG+=\"uaccess\"", id.vid, id.pid)
+}
+
+/// Rules files that mention the vendor id.
+fn udev_rules(id: &DeviceID) -> Vec<PathBuf> {
+    let vendor = format!("{:04x}", id.vid);
+    UDEV_RULES
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| fs::read_to_string(path).is_ok_and(|rules| rules.to_lowercase().contains(&vendor)))
+        .collect()
+}
+
+fn device_nodes(report: &mut Report, ctx: &rusb::Context, ids: &[DeviceID]) {
+    if !Path::new(DEV_BUS_USB).is_dir() {
+        report.fail("device nodes", &format!("{} is missing", DEV_BUS_USB),
+            "mount devtmpfs on /dev, or pass /dev/bus/usb into the container");
+        return
+    }
+    let devices = match ctx.devices() {
+        Ok(devices) => devices,
+        Err(e) => return report.fail("enumeration", &e.to_string(), "check that usbfs is available to libusb"),
+    };
+    let mut denied = 0;
+    let mut total = 0;
+    for dev in devices.iter() {
+        let node = Path::new(DEV_BUS_USB).join(format!("{:03}", dev.bus_number())).join(format!("{:03}", dev.address()));
+        let id = match dev.device_descriptor() {
+            Ok(desc) => DeviceID{vid: desc.vendor_id(), pid: desc.product_id()},
+            Err(_) => continue,
+        };
+        total += 1;
+        if accessible(&node) {
+            continue
+        }
+        denied += 1;
+        if ids.contains(&id) && matches!(container::access(&dev), container::Access::Denied) {
+            report.fail(&format!("{}", id), &format!("the device cgroup refuses {}", node.display()),
+                &format!("start the container with {} or --device-cgroup-rule='c 189:* rmw'", container::docker_flag(&dev)));
+        } else if ids.contains(&id) {
+            report.fail(&format!("{}", id), &format!("no read/write access to {}", node.display()),
+                &format!("add a udev rule such as '{}' and replug, or join the group owning the node", rule(&id)));
+        }
+    }
+    if denied == 0 {
+        report.ok("device nodes", &format!("all {} are readable and writable", total));
+    } else {
+        report.warn("device nodes", &format!("{} of {} can't be opened read/write", denied, total),
+            "descriptors still enumerate, but --inquiry, strings and card checks need access; run as root or add udev rules");
+    }
+}
+
+/// Checks the things most often wrong with an environment and prints a
+/// fix for each problem. Returns whether nothing failed.
+pub fn run(args: &DoctorArgs) -> bool {
+    let mut report = Report{failed: 0};
+    match container::detect() {
+        Some(runtime) => report.ok("container", &format!("running under {}; devices need passing in, \
+            see --print-docker-flag", runtime)),
+        None => report.ok("container", "not in a container"),
+    }
+    let version = rusb::version();
+    report.ok("libusb", &format!("{}.{}.{}", version.major(), version.minor(), version.micro()));
+
+    // the remaining checks don't need libusb and still tell why it failed
+    match rusb::Context::new() {
+        Ok(ctx) => {
+            if rusb::has_hotplug() {
+                report.ok("hotplug", "supported");
+            } else {
+                report.warn("hotplug", "not supported by this libusb",
+                    "waiting needs hotplug; use `usbmon watch`, which polls, or a libusb built with udev or netlink support");
+            }
+            device_nodes(&mut report, &ctx, &args.filter.id);
+        },
+        Err(e) => report.fail("libusb", &format!("can't be initialized: {}", e),
+            "check that /dev/bus/usb exists and, in a container, that sysfs and udev data are mounted"),
+    }
+    if Path::new(SYSFS_USB).is_dir() {
+        report.ok("sysfs", SYSFS_USB);
+    } else {
+        report.warn("sysfs", &format!("{} is missing", SYSFS_USB),
+            "mount sysfs; --settle, --netif, --alsa, --video, --wait-mount and authorize rules read it");
+    }
+
+    for id in &args.filter.id {
+        let rules = udev_rules(id);
+        if rules.is_empty() {
+            report.warn(&format!("udev {}", id), "no rules file mentions the vendor id",
+                &format!("add '{}' to /etc/udev/rules.d/70-usbmon.rules", rule(id)));
+        } else {
+            let files: Vec<String> = rules.iter().map(|path| path.display().to_string()).collect();
+            report.ok(&format!("udev {}", id), &files.join(", "));
+        }
+    }
+
+    for (tool, needed_by) in TOOLS {
+        match on_path(tool) {
+            Some(path) => report.ok(tool, &path.display().to_string()),
+            None => report.warn(tool, &format!("not found, {} won't work", needed_by),
+                &format!("install {} or add it to PATH", tool)),
+        }
+    }
+
+    report.failed == 0
+}
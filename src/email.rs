@@ -0,0 +1,164 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::config::Section;
+use crate::ratelimit::RateLimit;
+use crate::template::{self, Fields};
+use crate::workers::Workers;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends notifications through an SMTP relay, configured by the `[email]`
+/// section:
+///
+/// ```text
+/// [email]
+/// server = localhost:25
+/// from = usbmon@lab1
+/// to = oncall@example.com, lab@example.com
+/// events = detach, absent
+/// subject = {id} {event} on {host}
+/// template = {name} ({id}, serial {serial}) reported {event} on {host}
+/// rate_limit = 1/10m
+/// cooldown = 30m
+/// ```
+///
+/// There is no TLS or authentication, relay through a local MTA for that.
+/// Mail is sent in the background, one message after another, so a slow
+/// relay doesn't hold up watching.
+pub struct Mailer {
+    server: String,
+    from: String,
+    to: Vec<String>,
+    events: Vec<String>,
+    subject: String,
+    template: String,
+    limit: Option<RateLimit>,
+    worker: Workers,
+}
+
+impl Mailer {
+    pub fn new(section: &Section) -> io::Result<Mailer> {
+        let to = section.list("to");
+        if to.is_empty() {
+            section.require("to")?;
+        }
+        let events = section.list("events");
+        Ok(Mailer{
+            server: section.get("server").unwrap_or("localhost:25").to_string(),
+            from: section.require("from")?.to_string(),
+            to,
+            events: if events.is_empty() { vec![String::from("detach"), String::from("absent")] } else { events },
+            subject: section.get("subject").unwrap_or("usbmon: {id} {event} on {host}").to_string(),
+            template: section.get("template").unwrap_or("{id} {event} on {host}").to_string(),
+            limit: RateLimit::new(section)?,
+            worker: Workers::new(1),
+        })
+    }
+
+    /// Mails the event if it is one of the configured ones.
+    pub fn notify(&mut self, fields: &Fields) {
+        if self.events.iter().any(|e| e == template::get(fields, "event")) {
+            self.deliver(fields);
+        }
+    }
+
+    /// Mails an event unless the rate limit holds it back. Suppressed
+    /// events are counted in the next mail that does go out.
+    pub fn deliver(&mut self, fields: &Fields) {
+        let mut suppressed = 0;
+        if let Some(limit) = self.limit.as_mut() {
+            if !limit.allow(Instant::now()) {
+                return
+            }
+            suppressed = limit.take_suppressed();
+        }
+        let subject = template::render(&self.subject, fields);
+        let mut body = template::render(&self.template, fields);
+        if suppressed > 0 {
+            body.push_str(&format!("\n\n{} earlier event(s) not mailed because of the rate limit.", suppressed));
+        }
+        let (server, from, to) = (self.server.clone(), self.from.clone(), self.to.clone());
+        self.worker.run("email", move || {
+            if let Err(e) = send(&server, &from, &to, &subject, &body) {
+                eprintln!("email: {}: {}", server, e);
+            }
+        });
+    }
+}
+
+fn send(server: &str, from: &str, to: &[String], subject: &str, body: &str) -> io::Result<()> {
+    let addr = server.to_socket_addrs()?.next().ok_or(io::ErrorKind::NotFound)?;
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut smtp = Smtp{reader: BufReader::new(stream.try_clone()?), writer: stream};
+    smtp.expect(220)?;
+    smtp.command(&format!("EHLO {}", template::hostname()), 250)?;
+    smtp.command(&format!("MAIL FROM:<{}>", from), 250)?;
+    for to in to {
+        smtp.command(&format!("RCPT TO:<{}>", to), 250)?;
+    }
+    smtp.command("DATA", 354)?;
+    smtp.command(&message(from, to, subject, body), 250)?;
+    smtp.command("QUIT", 221)
+}
+
+/// The message as sent after DATA, up to the dot that ends it.
+fn message(from: &str, to: &[String], subject: &str, body: &str) -> String {
+    // a device's strings can end up in the subject, and must not start
+    // headers of their own
+    let subject = subject.replace(['\r', '\n'], " ");
+    let mut message = format!("From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n", from, to.join(", "), subject);
+    for line in body.lines() {
+        // a lone dot would end the message early
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        // and a bare CR is no line end in SMTP
+        message.push_str(&line.replace('\r', ""));
+        message.push_str("\r\n");
+    }
+    message.push('.');
+    message
+}
+
+struct Smtp {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Smtp {
+    fn command(&mut self, line: &str, code: u16) -> io::Result<()> {
+        write!(self.writer, "{}\r\n", line)?;
+        self.expect(code)
+    }
+
+    /// Reads a possibly multiline reply and checks its code.
+    fn expect(&mut self, code: u16) -> io::Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into())
+            }
+            if line.len() < 4 || line.as_bytes()[3] != b'-' {
+                return match line.get(..3).and_then(|c| c.parse::<u16>().ok()) {
+                    Some(got) if got == code => Ok(()),
+                    _ => Err(io::Error::other(line.trim_end().to_string())),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_has_no_injected_headers() {
+        let to = [String::from("oncall@example.com")];
+        let message = message("usbmon@lab1", &to, "usbmon: x\r\nBcc: evil@example.com", "a\r\n.b\rc");
+        assert_eq!(message, "From: usbmon@lab1\r\nTo: oncall@example.com\r\nSubject: usbmon: x  Bcc: evil@example.com\r\n\r\na\r\n..bc\r\n.");
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use crate::diagnose;
+use crate::json;
+use crate::ErrorFormat;
+
+static FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Sets how failures are reported for the rest of the run.
+pub fn configure(format: ErrorFormat) {
+    _ = FORMAT.set(format);
+}
+
+/// The stable code of a libusb error. Codes are only ever added.
+pub fn code(e: &rusb::Error) -> &'static str {
+    match e {
+        rusb::Error::Timeout => "timeout",
+        rusb::Error::Access => "permission_denied",
+        rusb::Error::Busy => "device_busy",
+        rusb::Error::NoDevice => "no_device",
+        rusb::Error::NotFound => "not_found",
+        rusb::Error::NotSupported => "not_supported",
+        rusb::Error::Io | rusb::Error::Overflow | rusb::Error::Pipe => "io",
+        rusb::Error::Interrupted => "interrupted",
+        rusb::Error::NoMem => "no_memory",
+        rusb::Error::InvalidParam | rusb::Error::BadDescriptor | rusb::Error::Other => "usb",
+    }
+}
+
+/// Reports why the run is failing on stderr: the message as is, or with
+/// `--errors json` one object with its `code`, the message and any other
+/// fields. Only the first object is written, so the cause isn't buried.
+pub fn report(code: &str, message: &str, fields: &[(&str, String)]) {
+    match FORMAT.get().copied().unwrap_or(ErrorFormat::Text) {
+        ErrorFormat::Text => eprintln!("{}", message),
+        ErrorFormat::Json if REPORTED.swap(true, Ordering::Relaxed) => {},
+        ErrorFormat::Json => {
+            let mut object = vec![("code", json::string(code)), ("message", json::string(message))];
+            object.extend(fields.iter().map(|(name, value)| (*name, json::string(value))));
+            eprintln!("{}", json::object(&object));
+        },
+    }
+}
+
+/// Reports a failure and exits with status 1.
+pub fn fail(code: &str, message: &str) -> ! {
+    report(code, message, &[]);
+    std::process::exit(1)
+}
+
+/// Exits with status 1 for an error that made it out of main, as the
+/// default error handling of main prints it.
+pub fn exit(e: &rusb::Error) -> ! {
+    report(code(e), &format!("Error: {:?}", e), &[("libusb", diagnose::constant(e).to_string())]);
+    std::process::exit(1)
+}
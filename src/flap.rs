@@ -0,0 +1,47 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::DeviceID;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Counts attach/detach transitions per vid:pid over a sliding minute.
+pub struct FlapDetector {
+    limit: usize,
+    transitions: HashMap<DeviceID, VecDeque<Instant>>,
+    flapping: HashMap<DeviceID, bool>,
+    tripped: bool,
+}
+
+impl FlapDetector {
+    pub fn new(limit: usize) -> FlapDetector {
+        FlapDetector{limit, transitions: HashMap::new(), flapping: HashMap::new(), tripped: false}
+    }
+
+    /// Records a transition and returns the rate when it has just gone over
+    /// the limit. A device warns once until its rate drops back down.
+    pub fn record(&mut self, id: &DeviceID, now: Instant) -> Option<usize> {
+        let times = self.transitions.entry(id.clone()).or_default();
+        times.push_back(now);
+        while times.front().is_some_and(|t| now.duration_since(*t) > WINDOW) {
+            times.pop_front();
+        }
+        let rate = times.len();
+        let flapping = self.flapping.entry(id.clone()).or_insert(false);
+        if rate <= self.limit {
+            *flapping = false;
+            return None
+        }
+        if *flapping {
+            return None
+        }
+        *flapping = true;
+        self.tripped = true;
+        Some(rate)
+    }
+
+    /// False once any device has gone over the limit.
+    pub fn healthy(&self) -> bool {
+        !self.tripped
+    }
+}
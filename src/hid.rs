@@ -0,0 +1,398 @@
+use std::fmt::Write;
+
+use rusb::UsbContext;
+
+use crate::descriptors::{le16, read_report_descriptor, Descriptors};
+use crate::sysfs;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemType {
+    Main,
+    Global,
+    Local,
+    Reserved,
+    Long,
+}
+
+/// A single short or long item of a HID report descriptor.
+#[derive(Debug, Clone, Copy)]
+pub struct Item<'a> {
+    pub kind: ItemType,
+    pub tag: u8,
+    pub data: &'a [u8],
+}
+
+impl<'a> Item<'a> {
+    /// Item data as an unsigned little-endian value.
+    pub fn value(&self) -> u32 {
+        self.data.iter().rev().fold(0, |a, b| a << 8 | *b as u32)
+    }
+
+    /// Item data sign-extended from its encoded width, as used by the
+    /// logical and physical extents.
+    pub fn signed(&self) -> i32 {
+        match self.data.len() {
+            1 => self.data[0] as i8 as i32,
+            2 => i16::from_le_bytes([self.data[0], self.data[1]]) as i32,
+            4 => self.value() as i32,
+            _ => 0,
+        }
+    }
+}
+
+pub struct Items<'a> {
+    bytes: &'a [u8],
+}
+
+/// Iterates over the items of a report descriptor, stopping at the first
+/// truncated item.
+pub fn items(report: &[u8]) -> Items<'_> {
+    Items { bytes: report }
+}
+
+impl<'a> Iterator for Items<'a> {
+    type Item = Item<'a>;
+
+    fn next(&mut self) -> Option<Item<'a>> {
+        let prefix = *self.bytes.first()?;
+        let (kind, tag, start, size) = if prefix == 0xfe {
+            let size = *self.bytes.get(1)? as usize;
+            let tag = *self.bytes.get(2)?;
+            (ItemType::Long, tag, 3, size)
+        } else {
+            let size = match prefix & 0x03 {
+                3 => 4,
+                n => n as usize,
+            };
+            let kind = match (prefix >> 2) & 0x03 {
+                0 => ItemType::Main,
+                1 => ItemType::Global,
+                2 => ItemType::Local,
+                _ => ItemType::Reserved,
+            };
+            (kind, prefix >> 4, 1, size)
+        };
+        if self.bytes.len() < start + size {
+            self.bytes = &[];
+            return None
+        }
+        let data = &self.bytes[start..start + size];
+        self.bytes = &self.bytes[start + size..];
+        Some(Item { kind, tag, data })
+    }
+}
+
+fn item_name(item: &Item) -> &'static str {
+    match (item.kind, item.tag) {
+        (ItemType::Main, 0x8) => "Input",
+        (ItemType::Main, 0x9) => "Output",
+        (ItemType::Main, 0xa) => "Collection",
+        (ItemType::Main, 0xb) => "Feature",
+        (ItemType::Main, 0xc) => "End Collection",
+        (ItemType::Global, 0x0) => "Usage Page",
+        (ItemType::Global, 0x1) => "Logical Minimum",
+        (ItemType::Global, 0x2) => "Logical Maximum",
+        (ItemType::Global, 0x3) => "Physical Minimum",
+        (ItemType::Global, 0x4) => "Physical Maximum",
+        (ItemType::Global, 0x5) => "Unit Exponent",
+        (ItemType::Global, 0x6) => "Unit",
+        (ItemType::Global, 0x7) => "Report Size",
+        (ItemType::Global, 0x8) => "Report ID",
+        (ItemType::Global, 0x9) => "Report Count",
+        (ItemType::Global, 0xa) => "Push",
+        (ItemType::Global, 0xb) => "Pop",
+        (ItemType::Local, 0x0) => "Usage",
+        (ItemType::Local, 0x1) => "Usage Minimum",
+        (ItemType::Local, 0x2) => "Usage Maximum",
+        (ItemType::Local, 0x3) => "Designator Index",
+        (ItemType::Local, 0x4) => "Designator Minimum",
+        (ItemType::Local, 0x5) => "Designator Maximum",
+        (ItemType::Local, 0x7) => "String Index",
+        (ItemType::Local, 0x8) => "String Minimum",
+        (ItemType::Local, 0x9) => "String Maximum",
+        (ItemType::Local, 0xa) => "Delimiter",
+        (ItemType::Long, _) => "Long Item",
+        _ => "Unknown",
+    }
+}
+
+pub fn usage_page_name(page: u32) -> String {
+    let name = match page {
+        0x01 => "Generic Desktop Controls",
+        0x02 => "Simulation Controls",
+        0x03 => "VR Controls",
+        0x04 => "Sport Controls",
+        0x05 => "Game Controls",
+        0x06 => "Generic Device Controls",
+        0x07 => "Keyboard",
+        0x08 => "LEDs",
+        0x09 => "Buttons",
+        0x0a => "Ordinal",
+        0x0b => "Telephony",
+        0x0c => "Consumer",
+        0x0d => "Digitizer",
+        0x0f => "PID Page",
+        0x10 => "Unicode",
+        0x14 => "Alphanumeric Display",
+        0x40 => "Medical Instruments",
+        0x59 => "Lighting And Illumination",
+        0x80 => "Monitor",
+        0x84 => "Power Device",
+        0x85 => "Battery System",
+        0x8c => "Bar Code Scanner",
+        0x8d => "Scale",
+        0x8e => "Magnetic Stripe Reader",
+        0x90 => "Camera Control",
+        0x91 => "Arcade",
+        0xf1d0 => "FIDO Alliance",
+        0xff00..=0xffff => "Vendor Defined",
+        _ => "",
+    };
+    if name.is_empty() {
+        format!("Page 0x{:04x}", page)
+    } else {
+        name.to_string()
+    }
+}
+
+pub fn usage_name(page: u32, usage: u32) -> String {
+    let name = match (page, usage) {
+        (0x01, 0x01) => "Pointer",
+        (0x01, 0x02) => "Mouse",
+        (0x01, 0x04) => "Joystick",
+        (0x01, 0x05) => "Gamepad",
+        (0x01, 0x06) => "Keyboard",
+        (0x01, 0x07) => "Keypad",
+        (0x01, 0x08) => "Multi-axis Controller",
+        (0x01, 0x30) => "Direction-X",
+        (0x01, 0x31) => "Direction-Y",
+        (0x01, 0x32) => "Direction-Z",
+        (0x01, 0x33) => "Rotate-X",
+        (0x01, 0x34) => "Rotate-Y",
+        (0x01, 0x35) => "Rotate-Z",
+        (0x01, 0x36) => "Slider",
+        (0x01, 0x37) => "Dial",
+        (0x01, 0x38) => "Wheel",
+        (0x01, 0x39) => "Hat Switch",
+        (0x01, 0x80) => "System Control",
+        (0x01, 0x81) => "System Power Down",
+        (0x01, 0x82) => "System Sleep",
+        (0x01, 0x83) => "System Wake Up",
+        (0x08, 0x01) => "NumLock",
+        (0x08, 0x02) => "CapsLock",
+        (0x08, 0x03) => "Scroll Lock",
+        (0x08, 0x04) => "Compose",
+        (0x08, 0x05) => "Kana",
+        (0x0c, 0x01) => "Consumer Control",
+        (0x0c, 0xb5) => "Scan Next Track",
+        (0x0c, 0xb6) => "Scan Previous Track",
+        (0x0c, 0xb7) => "Stop",
+        (0x0c, 0xcd) => "Play/Pause",
+        (0x0c, 0xe2) => "Mute",
+        (0x0c, 0xe9) => "Volume Increment",
+        (0x0c, 0xea) => "Volume Decrement",
+        (0x0c, 0x238) => "AC Pan",
+        (0x0d, 0x01) => "Digitizer",
+        (0x0d, 0x02) => "Pen",
+        (0x0d, 0x04) => "Touch Screen",
+        (0x0d, 0x05) => "Touch Pad",
+        (0x0d, 0x22) => "Finger",
+        (0x0d, 0x42) => "Tip Switch",
+        (0x0d, 0x51) => "Contact Identifier",
+        (0xf1d0, 0x01) => "U2F Authenticator Device",
+        (0xf1d0, 0x20) => "Input Report Data",
+        (0xf1d0, 0x21) => "Output Report Data",
+        _ => "",
+    };
+    if !name.is_empty() {
+        return name.to_string()
+    }
+    match page {
+        0x07 => format!("Key 0x{:02x}", usage),
+        0x09 => format!("Button {}", usage),
+        0x0a => format!("Instance {}", usage),
+        _ => format!("Usage 0x{:02x}", usage),
+    }
+}
+
+fn collection_name(kind: u32) -> &'static str {
+    match kind {
+        0x00 => "Physical",
+        0x01 => "Application",
+        0x02 => "Logical",
+        0x03 => "Report",
+        0x04 => "Named Array",
+        0x05 => "Usage Switch",
+        0x06 => "Usage Modifier",
+        0x80..=0xff => "Vendor Defined",
+        _ => "Reserved",
+    }
+}
+
+fn main_flags(value: u32, output: bool) -> String {
+    let flags = [
+        ("Data", "Constant"),
+        ("Array", "Variable"),
+        ("Absolute", "Relative"),
+        ("No_Wrap", "Wrap"),
+        ("Linear", "Non_Linear"),
+        ("Preferred_State", "No_Preferred_State"),
+        ("No_Null_Position", "Null_State"),
+        (if output { "Non_Volatile" } else { "Reserved" }, if output { "Volatile" } else { "Reserved" }),
+        ("Bitfield", "Buffered Bytes"),
+    ];
+    flags
+        .iter()
+        .enumerate()
+        .map(|(bit, (clear, set))| if value & (1 << bit) == 0 { *clear } else { *set })
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Decodes a report descriptor the way lsusb -v does, one item per line,
+/// naming usage pages and usages and indenting collections.
+pub fn dump(out: &mut String, indent: usize, report: &[u8]) {
+    let mut page: u32 = 0;
+    let mut depth: usize = 0;
+    let mut stack: Vec<u32> = Vec::new();
+
+    for item in items(report) {
+        let data = item.data.iter().map(|b| format!("0x{:02x}", b)).collect::<Vec<String>>().join(" ");
+        if item.kind == ItemType::Main && item.tag == 0xc {
+            depth = depth.saturating_sub(1);
+        }
+        let pad = indent + depth * 2;
+        let kind = format!("{:?}", item.kind);
+        _ = writeln!(out, "{:pad$}Item({:<6}): {}, data= [ {} ] {}", "",
+            kind, item_name(&item), data, item.value(), pad = pad);
+
+        let detail = match (item.kind, item.tag) {
+            (ItemType::Global, 0x0) => {
+                page = item.value();
+                Some(usage_page_name(page))
+            },
+            (ItemType::Global, 0xa) => {
+                stack.push(page);
+                None
+            },
+            (ItemType::Global, 0xb) => {
+                page = stack.pop().unwrap_or(page);
+                None
+            },
+            (ItemType::Global, 0x1..=0x4) => Some(format!("{}", item.signed())),
+            (ItemType::Local, 0x0..=0x2) => {
+                // four byte usages carry their own page in the high word
+                let value = item.value();
+                if item.data.len() == 4 {
+                    Some(format!("{} ({})", usage_name(value >> 16, value & 0xffff), usage_page_name(value >> 16)))
+                } else {
+                    Some(usage_name(page, value))
+                }
+            },
+            (ItemType::Main, 0x8) | (ItemType::Main, 0xb) => Some(main_flags(item.value(), false)),
+            (ItemType::Main, 0x9) => Some(main_flags(item.value(), true)),
+            (ItemType::Main, 0xa) => Some(collection_name(item.value()).to_string()),
+            _ => None,
+        };
+        if let Some(detail) = detail {
+            _ = writeln!(out, "{:pad$}{}", "", detail, pad = pad + 16);
+        }
+
+        if item.kind == ItemType::Main && item.tag == 0xa {
+            depth += 1;
+        }
+    }
+}
+
+const CLASS_HID: u8 = 0x03;
+const USAGE_PAGE_FIDO: u32 = 0xf1d0;
+
+/// Report descriptors of every HID interface, taken from sysfs where the
+/// kernel has already parsed them and read from the device otherwise.
+pub fn report_descriptors<T: UsbContext>(device: &rusb::Device<T>) -> Vec<Vec<u8>> {
+    let config = match device.active_config_descriptor() {
+        Ok(config) => config,
+        Err(_) => return Vec::new(),
+    };
+    let mut handle = None;
+    let mut reports = Vec::new();
+    for iface in config.interfaces() {
+        let alt = match iface.descriptors().next() {
+            Some(alt) if alt.class_code() == CLASS_HID => alt,
+            _ => continue,
+        };
+        let found = sysfs::hid_report_descriptors(device, alt.interface_number());
+        if !found.is_empty() {
+            reports.extend(found);
+            continue;
+        }
+        let length = Descriptors::new(alt.extra())
+            .find(|d| d.len() >= 9 && d[1] == 0x21 && d[6] == 0x22)
+            .map(|d| le16(d, 7));
+        if let Some(length) = length {
+            if handle.is_none() {
+                handle = device.open().ok();
+            }
+            if let Some(Ok(report)) = handle.as_ref().map(|h| read_report_descriptor(h, alt.interface_number(), length)) {
+                reports.push(report);
+            }
+        }
+    }
+    reports
+}
+
+/// FIDO U2F and CTAP2 authenticators announce themselves with a dedicated
+/// usage page in their report descriptor.
+pub fn is_fido<T: UsbContext>(device: &rusb::Device<T>) -> bool {
+    report_descriptors(device).iter().any(|report| {
+        items(report).any(|item| item.kind == ItemType::Global && item.tag == 0x0 && item.value() == USAGE_PAGE_FIDO)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the boot mouse of the HID spec, appendix E.10
+    const MOUSE: [u8; 50] = [
+        0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29, 0x03,
+        0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01, 0x75, 0x05, 0x81, 0x01,
+        0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f, 0x75, 0x08, 0x95, 0x02, 0x81, 0x06,
+        0xc0, 0xc0,
+    ];
+
+    #[test]
+    fn boot_mouse_items() {
+        let items: Vec<Item> = items(&MOUSE).collect();
+        assert_eq!(items.len(), 26);
+        assert_eq!((items[0].kind, items[0].tag, items[0].value()), (ItemType::Global, 0x0, 1));
+        // Logical Minimum (-127)
+        assert_eq!((items[19].kind, items[19].tag, items[19].signed()), (ItemType::Global, 0x1, -127));
+        assert_eq!((items[25].kind, items[25].tag, items[25].data.len()), (ItemType::Main, 0xc, 0));
+        let mut out = String::new();
+        dump(&mut out, 0, &MOUSE);
+        assert!(out.contains("Generic Desktop") && out.contains("-127"), "{}", out);
+    }
+
+    #[test]
+    fn truncated_items_are_dropped() {
+        // Usage Page with its data byte missing
+        assert_eq!(items(&[0x05]).count(), 0);
+        assert_eq!(items(&[0x05, 0x01, 0x26, 0xff]).count(), 1);
+        // a four byte item with three
+        assert_eq!(items(&[0x27, 0x01, 0x02, 0x03]).count(), 0);
+        // long items, short of their header and of their data
+        assert_eq!(items(&[0xfe, 0x04]).count(), 0);
+        assert_eq!(items(&[0xfe, 0x04, 0xf0, 0x01, 0x02]).count(), 0);
+        assert_eq!(items(&[0xfe, 0x02, 0xf0, 0x01, 0x02]).next().map(|item| item.kind), Some(ItemType::Long));
+    }
+
+    #[test]
+    fn truncated_descriptors_dont_panic() {
+        for len in 0..MOUSE.len() {
+            dump(&mut String::new(), 0, &MOUSE[..len]);
+        }
+        // more ends than collections, and a pop without a push
+        dump(&mut String::new(), 0, &[0xc0, 0xc0, 0xb4]);
+    }
+}
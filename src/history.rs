@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::json;
+use crate::matcher::Matcher;
+use crate::parquet;
+use crate::retention::Retention;
+use crate::rules::Rule;
+use crate::parse_device;
+use crate::{CountBy, ExportArgs, ExportFormat, Filter, HistoryArgs, HistoryCommand, HistoryFormat, HistoryQuery, PruneArgs, TimeBucket};
+
+/// The event log, which clap requires unless export is given.
+fn file(query: &HistoryQuery) -> &Path {
+    query.file.as_deref().expect("--file is required")
+}
+
+/// The events of a JSON event log, as watch writes with `--format json`
+/// to a file or to an `[output]` sink, that fall in the window and pass
+/// the filter and the rule. Lines that aren't events are skipped, so a log
+/// that was cut short mid-line or shared with other output still reads.
+/// Events without a device, such as heartbeats, only pass when the filter
+/// is empty.
+pub fn events<'a>(args: &'a HistoryQuery, filter: &'a Filter, rule: Option<&'a Rule>) -> io::Result<impl Iterator<Item = io::Result<String>> + 'a> {
+    let file = File::open(file(args))?;
+    let matcher = Matcher::new(filter);
+    Ok(BufReader::new(file).lines().filter(move |line| {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return true,
+        };
+        let time = match json::get(line, "time") {
+            Some(time) => time,
+            None => return false,
+        };
+        let raw_id = json::get(line, "id").unwrap_or_default();
+        let id = parse_device(&raw_id).ok();
+        let kind = json::get(line, "type").unwrap_or_default();
+        args.since.as_ref().is_none_or(|since| &time >= since)
+            && args.until.as_ref().is_none_or(|until| &time <= until)
+            && (args.r#type.is_empty() || args.r#type.contains(&kind))
+            && (filter.is_empty() || id.is_some_and(|id| matcher.matches_id(&id)))
+            && rule.is_none_or(|rule| rule.matches_logged(&kind, &raw_id))
+    }))
+}
+
+/// What an event is counted under.
+fn key(line: &str, by: CountBy) -> String {
+    let field = match by {
+        CountBy::Device => "id",
+        CountBy::Port => "port",
+        CountBy::Type => "type",
+        CountBy::Source => "source",
+    };
+    json::get(line, field).filter(|value| !value.is_empty()).unwrap_or_else(|| String::from("-"))
+}
+
+/// The start of the hour or day of an event's time.
+fn bucket(time: &str, by: TimeBucket) -> String {
+    match by {
+        TimeBucket::Hour => format!("{}:00", time.get(..13).unwrap_or(time)),
+        TimeBucket::Day => time.get(..10).unwrap_or(time).to_string(),
+    }
+}
+
+/// Counts the events per --count-by key, per --group-by period, or per
+/// key within each period. Periods come in time order, keys most events
+/// first.
+fn aggregate(events: impl Iterator<Item = io::Result<String>>, args: &HistoryArgs, out: &mut impl Write) -> io::Result<()> {
+    let mut counts: BTreeMap<(String, String), u64> = BTreeMap::new();
+    for line in events {
+        let line = line?;
+        let period = args.group_by.map(|by| bucket(&json::get(&line, "time").unwrap_or_default(), by)).unwrap_or_default();
+        let key = args.count_by.map(|by| key(&line, by)).unwrap_or_default();
+        *counts.entry((period, key)).or_default() += 1;
+    }
+    let mut rows: Vec<((String, String), u64)> = counts.into_iter().collect();
+    rows.sort_by(|((period_a, key_a), a), ((period_b, key_b), b)| period_a.cmp(period_b).then(b.cmp(a)).then(key_a.cmp(key_b)));
+    for ((period, key), count) in rows {
+        let columns: Vec<String> = [period, count.to_string(), key].into_iter().filter(|c| !c.is_empty()).collect();
+        writeln!(out, "{}", columns.join(" "))?;
+    }
+    Ok(())
+}
+
+/// The columns of an export, named after the event fields they hold.
+const COLUMNS: [&str; 10] = ["time", "type", "id", "bus", "address", "port", "source", "session", "host", "text"];
+
+/// Quotes a CSV field if it holds a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes the events as a table with a column per field, empty where an
+/// event doesn't have it. Parquet needs the row count up front, so its
+/// rows are held until the log has been read.
+fn export(events: impl Iterator<Item = io::Result<String>>, args: &ExportArgs, out: &mut impl Write) -> io::Result<()> {
+    let rows = events.map(|line| {
+        let line = line?;
+        Ok(COLUMNS.iter().map(|column| json::get(&line, column).unwrap_or_default()).collect::<Vec<String>>())
+    });
+    match args.format {
+        ExportFormat::Csv => {
+            writeln!(out, "{}", COLUMNS.join(","))?;
+            for row in rows {
+                let row: Vec<String> = row?.iter().map(|value| csv_field(value)).collect();
+                writeln!(out, "{}", row.join(","))?;
+            }
+        },
+        ExportFormat::Parquet => parquet::write(&COLUMNS, &rows.collect::<io::Result<Vec<_>>>()?, out)?,
+    }
+    out.flush()
+}
+
+/// Prunes the log and says how much of it is left.
+fn prune(args: &PruneArgs) -> io::Result<()> {
+    let (kept, dropped) = Retention{max_age: args.max_age, max_rows: args.max_rows}.prune(&args.file)?;
+    println!("dropped {} event(s), kept {}", dropped, kept);
+    Ok(())
+}
+
+/// Rejects filters that look at the device, which an event log can't.
+fn check(filter: &Filter) -> io::Result<()> {
+    if Matcher::new(filter).needs_device() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "events only record ids, --class, --speed and --max-power don't apply"))
+    }
+    Ok(())
+}
+
+/// The --rule of the query, refused if it looks at the device.
+fn rule(query: &HistoryQuery) -> Result<Option<Rule>, (PathBuf, io::Error)> {
+    let (name, path) = match (&query.rule, &query.config) {
+        (Some(name), Some(path)) => (name, path),
+        _ => return Ok(None),
+    };
+    let rule = Config::load(path).and_then(|config| Rule::named(&config, name)).map_err(|e| (path.clone(), e))?;
+    if rule.needs_device() {
+        let e = io::Error::new(io::ErrorKind::InvalidInput, format!("[rule {}] has class, speed or max_power, which events don't record", name));
+        return Err((path.clone(), e))
+    }
+    Ok(Some(rule))
+}
+
+/// Prints the recorded events between --since and --until that pass the
+/// filter, oldest first, or how many there were of each kind, or exports
+/// or prunes them. Errors come with the file they are about.
+pub fn run(args: &HistoryArgs) -> Result<(), (PathBuf, io::Error)> {
+    if let Some(HistoryCommand::Prune(prune_args)) = &args.command {
+        return prune(prune_args).map_err(|e| (prune_args.file.clone(), e))
+    }
+    if let Some(HistoryCommand::Export(export_args)) = &args.command {
+        let query = &export_args.query;
+        let filter = Filter::new(&query.filter);
+        check(&filter).map_err(|e| (file(query).to_path_buf(), e))?;
+        let rule = rule(query)?;
+        let events = events(query, &filter, rule.as_ref()).map_err(|e| (file(query).to_path_buf(), e))?;
+        let written = match &export_args.output {
+            Some(path) => File::create(path)
+                .and_then(|file| export(events, export_args, &mut BufWriter::new(file)))
+                .map_err(|e| (path.clone(), e)),
+            None => export(events, export_args, &mut io::stdout().lock()).map_err(|e| (PathBuf::from("stdout"), e)),
+        };
+        return written
+    }
+    let query = &args.query;
+    let rule = rule(query)?;
+    show(args, query, rule.as_ref()).map_err(|e| (file(query).to_path_buf(), e))
+}
+
+fn show(args: &HistoryArgs, query: &HistoryQuery, rule: Option<&Rule>) -> io::Result<()> {
+    let filter = Filter::new(&query.filter);
+    check(&filter)?;
+    let mut out = io::stdout().lock();
+    let events = events(query, &filter, rule)?;
+    if args.count_by.is_some() || args.group_by.is_some() {
+        return aggregate(events, args, &mut out)
+    }
+    for line in events {
+        let line = line?;
+        match args.format {
+            HistoryFormat::Text => {
+                let time = json::get(&line, "time").unwrap_or_default();
+                writeln!(out, "{} {}", time, json::get(&line, "text").unwrap_or_default())?;
+            },
+            HistoryFormat::Json => writeln!(out, "{}", line)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::fs;
+
+    const LOG: &str = concat!(
+        "{\"time\":\"2024-01-31T08:00:00\",\"type\":\"attach\",\"id\":\"483:df11\",\"text\":\"+ 483:df11\"}\n",
+        "{\"time\":\"2024-01-31T08:05:00\",\"type\":\"detach\",\"id\":\"483:df11\",\"text\":\"- 483:df11\"}\n",
+        "{\"time\":\"2024-01-31T09:00:00\",\"type\":\"attach\",\"id\":\"1d50:6018\",\"text\":\"+ 1d50:6018\"}\n",
+        "{\"time\":\"2024-01-31T09:30:00\",\"type\":\"heartbeat\",\"text\":\"heartbeat\"}\n",
+        "cut short",
+    );
+
+    /// The text of the events history reads from LOG with these arguments
+    /// and a config of these lines.
+    fn texts(name: &str, config: &str, args: &[&str]) -> Result<Vec<String>, String> {
+        let base = std::env::temp_dir().join(format!("usbmon-history-{}-{}", name, std::process::id()));
+        let (log, conf) = (base.with_extension("json"), base.with_extension("conf"));
+        fs::write(&log, LOG).unwrap();
+        fs::write(&conf, config).unwrap();
+        let (log_arg, conf_arg) = (log.display().to_string(), conf.display().to_string());
+        let argv = [&["usbmon", "history", "--file", &log_arg, "--config", &conf_arg][..], args].concat();
+        let query = match crate::Args::parse_from(argv).command {
+            Some(crate::Command::History(history)) => history.query,
+            _ => unreachable!(),
+        };
+        let filter = Filter::new(&query.filter);
+        let result = rule(&query).map_err(|(_, e)| e.to_string()).map(|rule| {
+            events(&query, &filter, rule.as_ref())
+                .unwrap()
+                .map(|line| json::get(&line.unwrap(), "text").unwrap_or_default())
+                .collect()
+        });
+        _ = fs::remove_file(&log);
+        _ = fs::remove_file(&conf);
+        result
+    }
+
+    #[test]
+    fn flags_filter_by_id_type_and_time() {
+        assert_eq!(texts("all", "", &[]).unwrap(), ["+ 483:df11", "- 483:df11", "+ 1d50:6018", "heartbeat"]);
+        assert_eq!(texts("vid", "", &["--vid", "483"]).unwrap(), ["+ 483:df11", "- 483:df11"]);
+        assert_eq!(texts("type", "", &["--type", "detach,heartbeat"]).unwrap(), ["- 483:df11", "heartbeat"]);
+        assert_eq!(texts("window", "", &["--since", "2024-01-31T08:01", "--until", "2024-01-31T09:00"]).unwrap(), ["- 483:df11", "+ 1d50:6018"]);
+    }
+
+    #[test]
+    fn rule_filters_as_watch_applies_it() {
+        let config = "[rule flasher]\nvid = 0483\nevents = attach, detach\nexec = true\n\n[rule any]\nlog = {id}\n\n[rule fast]\nspeed = high\n";
+        assert_eq!(texts("flasher", config, &["--rule", "flasher"]).unwrap(), ["+ 483:df11", "- 483:df11"]);
+        // attach only, like a rule without events
+        assert_eq!(texts("any", config, &["--rule", "any"]).unwrap(), ["+ 483:df11", "+ 1d50:6018"]);
+        assert_eq!(texts("both", config, &["--rule", "any", "--pid", "6018"]).unwrap(), ["+ 1d50:6018"]);
+        assert!(texts("fast", config, &["--rule", "fast"]).unwrap_err().contains("don't record"));
+        assert_eq!(texts("none", config, &["--rule", "none"]).unwrap_err(), "there is no [rule none] section");
+    }
+}
@@ -0,0 +1,345 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::auth::{Scope, Tokens};
+use crate::json;
+use crate::logfile;
+use crate::template;
+use crate::tls::{self, Certificates};
+use crate::HubArgs;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a slow client of the merged stream may hold up the hub before
+/// it is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The token agents present, from watch's --hub-token.
+static TOKEN: OnceLock<String> = OnceLock::new();
+
+pub fn configure(token: Option<String>) {
+    if let Some(token) = token {
+        _ = TOKEN.set(token);
+    }
+}
+
+/// The `--output tcp:HOST:PORT` or `tls:HOST:PORT` end of an agent:
+/// connects on the first event and again after the hub went away,
+/// introducing itself with
+///
+/// ```text
+/// [auth TOKEN]
+/// agent HOSTNAME
+/// ```
+///
+/// before it sends event lines. Events while the hub can't be reached
+/// are reported as output errors and lost.
+pub struct Uplink {
+    addr: String,
+    tls: bool,
+    stream: Option<Box<dyn Write>>,
+}
+
+impl Uplink {
+    pub fn new(addr: &str, tls: bool) -> Uplink {
+        Uplink{addr: addr.to_string(), tls, stream: None}
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn Write>> {
+        let mut stream: Box<dyn Write> = if self.tls {
+            Box::new(tls::connect(&self.addr)?)
+        } else {
+            let addr = self.addr.to_socket_addrs()?.next().ok_or(ErrorKind::NotFound)?;
+            let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            Box::new(stream)
+        };
+        if let Some(token) = TOKEN.get() {
+            stream.write_all(format!("auth {}\n", token).as_bytes())?;
+        }
+        stream.write_all(format!("agent {}\n", template::hostname()).as_bytes())?;
+        Ok(stream)
+    }
+}
+
+impl Write for Uplink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => self.stream.insert(self.connect()?),
+        };
+        let written = stream.write(buf);
+        if written.is_err() {
+            self.stream = None;
+        }
+        written
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.as_mut().map_or(Ok(()), |stream| stream.flush())
+    }
+}
+
+/// Adds the host an event came from, unless it already says, as one that
+/// passed through another hub does. Lines that aren't JSON, from agents
+/// writing text, become message events.
+pub fn tag(host: &str, line: &str) -> String {
+    let line = line.trim_end();
+    match line.strip_prefix('{') {
+        Some(_) if json::get(line, "host").is_some() => line.to_string(),
+        Some(rest) => {
+            let comma = if rest.trim_start().starts_with('}') { "" } else { "," };
+            format!("{{\"host\":{}{}{}", json::string(host), comma, rest)
+        },
+        None => json::object(&[
+            ("host", json::string(host)),
+            ("time", json::string(&logfile::timestamp())),
+            ("type", json::string("message")),
+            ("text", json::string(line)),
+        ]),
+    }
+}
+
+/// What the threads tell the hub about.
+enum Message {
+    Connected(String, SocketAddr),
+    Event(String, String),
+    Disconnected(String, SocketAddr),
+    Client(Box<dyn ReadWrite>),
+}
+
+/// Reads one agent's events until it goes away. The host is the name the
+/// agent gave, or its address if it started sending events straight away.
+/// With --tokens the agent has to authenticate first, and is the host its
+/// token is named for: another name, or events of another host, are
+/// refused.
+fn agent(stream: impl Read, peer: SocketAddr, messages: Sender<Message>, tokens: Option<&Tokens>) {
+    let mut lines = BufReader::new(stream).lines();
+    let token = match tokens {
+        Some(tokens) => {
+            let line = lines.next().and_then(Result::ok).unwrap_or_default();
+            match tokens.check(&line, Scope::Push) {
+                Ok(name) => Some(name.to_string()),
+                Err(e) => return eprintln!("hub: {}: {}", peer, e),
+            }
+        },
+        None => None,
+    };
+    let first = match lines.next() {
+        Some(Ok(line)) => line,
+        _ => return,
+    };
+    let (named, first) = match first.strip_prefix("agent ") {
+        Some(name) if !name.trim().is_empty() => (Some(name.trim().to_string()), None),
+        _ => (None, Some(first)),
+    };
+    let host = match (token, named) {
+        (Some(token), Some(name)) if name != token => {
+            return eprintln!("hub: {}: agent {} has the token of {}", peer, name, token)
+        },
+        (Some(host), _) | (None, Some(host)) => host,
+        (None, None) => peer.ip().to_string(),
+    };
+    let checked = tokens.is_some();
+    if messages.send(Message::Connected(host.clone(), peer)).is_err() {
+        return
+    }
+    for line in first.into_iter().map(Ok).chain(lines) {
+        match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) if checked && json::get(&line, "host").is_some_and(|other| other != host) => {
+                eprintln!("hub: {}: refused an event of another host from {}", peer, host);
+            },
+            Ok(line) => if messages.send(Message::Event(host.clone(), line)).is_err() {
+                return
+            },
+            Err(_) => break,
+        }
+    }
+    _ = messages.send(Message::Disconnected(host, peer));
+}
+
+/// The client's stream, through TLS if the hub has a certificate.
+fn open(stream: TcpStream, tls: Option<&Certificates>) -> io::Result<Box<dyn ReadWrite>> {
+    match tls {
+        Some(certs) => {
+            let session = tls::accept(stream, certs)?;
+            session.set_nonblocking()?;
+            Ok(Box::new(session))
+        },
+        None => {
+            stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            Ok(Box::new(stream))
+        },
+    }
+}
+
+trait ReadWrite: Read + Write + Send {}
+
+impl<T: Read + Write + Send> ReadWrite for T {}
+
+/// Lets a client of the merged stream in once it sent a token with the
+/// read scope, answering `ok` or `error` like the control socket does.
+fn authenticate(stream: Box<dyn ReadWrite>, tokens: &Tokens) -> Result<Box<dyn ReadWrite>, String> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    let mut stream = reader.into_inner();
+    match tokens.check(&line, Scope::Read) {
+        Ok(_) => {
+            stream.write_all(b"ok\n").map_err(|e| e.to_string())?;
+            Ok(stream)
+        },
+        Err(e) => {
+            _ = stream.write_all(format!("error {}\n", e).as_bytes());
+            Err(e)
+        },
+    }
+}
+
+/// Accepts agents, or with agents false clients of the merged stream,
+/// each in a thread of its own so a slow handshake holds up no one else.
+fn accept(listener: TcpListener, messages: Sender<Message>, tls: Option<Certificates>, tokens: Option<Arc<Tokens>>, agents: bool) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("hub: {}", e);
+                continue
+            },
+        };
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
+            Err(_) => continue,
+        };
+        let messages = messages.clone();
+        let (tls, tokens) = (tls.clone(), tokens.clone());
+        thread::spawn(move || {
+            let stream = match open(stream, tls.as_ref()) {
+                Ok(stream) => stream,
+                Err(e) => return eprintln!("hub: {}: {}", peer, e),
+            };
+            if agents {
+                return agent(stream, peer, messages, tokens.as_deref())
+            }
+            let stream = match tokens {
+                Some(tokens) => match authenticate(stream, &tokens) {
+                    Ok(stream) => stream,
+                    Err(e) => return eprintln!("hub: {}: {}", peer, e),
+                },
+                None => stream,
+            };
+            _ = messages.send(Message::Client(stream));
+        });
+    }
+}
+
+/// A connect or disconnect of an agent, in the merged stream so a
+/// dashboard knows which hosts it is hearing from.
+fn presence(kind: &str, host: &str, peer: SocketAddr) -> String {
+    let sign = if kind == "connect" { '+' } else { '-' };
+    json::object(&[
+        ("host", json::string(host)),
+        ("time", json::string(&logfile::timestamp())),
+        ("type", json::string(kind)),
+        ("peer", json::string(&peer.to_string())),
+        ("text", json::string(&format!("{} agent {} ({})", sign, host, peer))),
+    ])
+}
+
+/// Takes event streams from agents on --listen and writes them merged,
+/// each event tagged with its host, to stdout and to every client of
+/// --serve. With --tls-cert both listeners only speak TLS, and with
+/// --tokens they only take clients with a token. Runs until killed.
+pub fn run(args: &HubArgs) -> io::Result<()> {
+    let tls = args.tls_cert.as_ref().map(|cert| Certificates{
+        cert: Some(cert.clone()),
+        key: args.tls_key.clone(),
+        ca: args.tls_ca.clone(),
+    });
+    if tls.is_some() {
+        tls::check()?;
+    }
+    // openssl only reads them once a client connects
+    for path in tls.iter().flat_map(|certs| [&certs.cert, &certs.key, &certs.ca]).flatten() {
+        fs::File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    }
+    let tokens = match &args.tokens {
+        Some(path) => Some(Arc::new(Tokens::load(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?)),
+        None => None,
+    };
+    let (messages, received) = mpsc::channel();
+    let listener = TcpListener::bind(&args.listen)?;
+    let serve = args.serve.as_ref().map(TcpListener::bind).transpose()?;
+    {
+        let (messages, tls, tokens) = (messages.clone(), tls.clone(), tokens.clone());
+        thread::spawn(move || accept(listener, messages, tls, tokens, true));
+    }
+    if let Some(serve) = serve {
+        let messages = messages.clone();
+        thread::spawn(move || accept(serve, messages, tls, tokens, false));
+    }
+    drop(messages);
+    let mut clients: Vec<Box<dyn ReadWrite>> = Vec::new();
+    let mut stdout = io::stdout();
+    for message in received {
+        let line = match message {
+            Message::Client(stream) => {
+                clients.push(stream);
+                continue
+            },
+            Message::Connected(host, peer) => presence("connect", &host, peer),
+            Message::Disconnected(host, peer) => presence("disconnect", &host, peer),
+            Message::Event(host, line) => tag(&host, &line),
+        };
+        let line = format!("{}\n", line);
+        clients.retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+        stdout.write_all(line.as_bytes())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(name: &str, sent: &str) -> Vec<(String, String)> {
+        let path = std::env::temp_dir().join(format!("usbmon-tokens-{}-{}", name, std::process::id()));
+        fs::write(&path, "s3cret push lab1\n").unwrap();
+        let tokens = Tokens::load(&path).unwrap();
+        _ = fs::remove_file(&path);
+        let (tx, rx) = mpsc::channel();
+        agent(sent.as_bytes(), "192.0.2.1:4000".parse().unwrap(), tx, Some(&tokens));
+        rx.into_iter()
+            .filter_map(|message| match message {
+                Message::Connected(host, _) => Some((host, String::from("connect"))),
+                Message::Event(host, line) => Some((host, line)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn agent_is_the_host_of_its_token() {
+        let got = events("host", "auth s3cret\nagent lab1\n{\"type\":\"attach\"}\n{\"host\":\"lab2\",\"type\":\"attach\"}\n");
+        assert_eq!(got, [
+            (String::from("lab1"), String::from("connect")),
+            (String::from("lab1"), String::from("{\"type\":\"attach\"}")),
+        ]);
+        // sending events straight away, it is still its token's host
+        let got = events("unnamed", "auth s3cret\n{\"type\":\"detach\"}\n");
+        assert_eq!(got[0], (String::from("lab1"), String::from("connect")));
+    }
+
+    #[test]
+    fn agent_claiming_another_host_is_refused() {
+        assert!(events("other", "auth s3cret\nagent lab2\n{\"type\":\"attach\"}\n").is_empty());
+        assert!(events("wrong", "auth guess\nagent lab1\n").is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+
+use rusb::UsbContext;
+
+use crate::matcher::Matcher;
+use crate::sysfs;
+use crate::usbids;
+use crate::{DeviceID, Filter};
+
+/// A connected device as seen by one enumeration pass. Bus and address
+/// tell apart several devices sharing a vid:pid. The port path, e.g.
+/// `1-4.2`, stays the same when the device comes back on the same port
+/// with a new address, so it identifies the device across replugs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub bus: u8,
+    pub address: u8,
+    pub id: DeviceID,
+    pub port: String,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:03}:{:03}", self.id, self.bus, self.address)
+    }
+}
+
+/// Enumerates the devices currently matching the filter.
+pub fn scan<T: UsbContext>(ctx: &T, filter: &Filter) -> rusb::Result<Vec<Entry>> {
+    let matcher = Matcher::new(filter);
+    let mut entries = Vec::new();
+    for dev in ctx.devices()?.iter().filter(|dev| matcher.matches(dev)) {
+        let desc = dev.device_descriptor()?;
+        entries.push(Entry{
+            bus: dev.bus_number(),
+            address: dev.address(),
+            id: DeviceID{vid: desc.vendor_id(), pid: desc.product_id()},
+            port: sysfs::device_name(&dev).unwrap_or_default(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Splits the change between two scans into added and removed entries.
+pub fn diff(old: &[Entry], new: &[Entry]) -> (Vec<Entry>, Vec<Entry>) {
+    let added = new.iter().filter(|e| !old.contains(e)).cloned().collect();
+    let removed = old.iter().filter(|e| !new.contains(e)).cloned().collect();
+    (added, removed)
+}
+
+/// Manufacturer and product strings, with the usb.ids names for those the
+/// device doesn't report or when it can't be opened. Empty if neither has
+/// a name.
+pub fn name<T: UsbContext>(dev: &rusb::Device<T>) -> String {
+    let desc = match dev.device_descriptor() {
+        Ok(desc) => desc,
+        Err(_) => return String::new(),
+    };
+    let (mut manufacturer, mut product) = match dev.open() {
+        Ok(handle) => (
+            handle.read_manufacturer_string_ascii(&desc).unwrap_or_default(),
+            handle.read_product_string_ascii(&desc).unwrap_or_default(),
+        ),
+        Err(_) => (String::new(), String::new()),
+    };
+    if manufacturer.trim().is_empty() || product.trim().is_empty() {
+        let (vendor, model) = usbids::lookup(&DeviceID{vid: desc.vendor_id(), pid: desc.product_id()});
+        if manufacturer.trim().is_empty() {
+            manufacturer = vendor.unwrap_or_default();
+        }
+        if product.trim().is_empty() {
+            product = model.unwrap_or_default();
+        }
+    }
+    format!("{} {}", manufacturer.trim(), product.trim()).trim().to_string()
+}
+
+/// The firmware revision from bcdDevice, e.g. `1.02`.
+pub fn revision<T: UsbContext>(dev: &rusb::Device<T>) -> String {
+    dev.device_descriptor()
+        .map(|desc| desc.device_version())
+        .map(|v| format!("{}.{}{}", v.major(), v.minor(), v.sub_minor()))
+        .unwrap_or_default()
+}
+
+/// The bMaxPower of the active configuration in mA, or of the first one
+/// while unconfigured. SuperSpeed devices count it in units of 8 mA, the
+/// others in units of 2.
+pub fn max_power<T: UsbContext>(dev: &rusb::Device<T>) -> Option<u16> {
+    let config = dev.active_config_descriptor().or_else(|_| dev.config_descriptor(0)).ok()?;
+    Some(milliamps(&config, dev.speed()))
+}
+
+/// A configuration's bMaxPower in mA at the speed the device runs at;
+/// rusb's max_power always counts units of 2.
+pub fn milliamps(config: &rusb::ConfigDescriptor, speed: rusb::Speed) -> u16 {
+    let units = config.max_power() / 2;
+    match speed {
+        rusb::Speed::Super | rusb::Speed::SuperPlus => units * 8,
+        _ => units * 2,
+    }
+}
+
+/// Looks a scanned entry back up in a fresh device list.
+pub fn find<T: UsbContext>(ctx: &T, entry: &Entry) -> Option<rusb::Device<T>> {
+    ctx.devices().ok()?.iter().find(|dev| dev.bus_number() == entry.bus && dev.address() == entry.address)
+}
+
+/// Serial number string, or an empty string if there is none or the
+/// device can't be opened.
+pub fn serial<T: UsbContext>(dev: &rusb::Device<T>) -> String {
+    let desc = match dev.device_descriptor() {
+        Ok(desc) => desc,
+        Err(_) => return String::new(),
+    };
+    match dev.open() {
+        Ok(handle) => handle.read_serial_number_string_ascii(&desc).unwrap_or_default(),
+        Err(_) => String::new(),
+    }
+}
+
+/// The device instance id Windows gives the device, e.g.
+/// `USB\VID_1A2B&PID_5678\0001`, as devcon and pnputil take it. Windows
+/// only builds it from the serial number when there is a usable one and
+/// makes up a location based one otherwise, which can't be derived here.
+pub fn instance_id<T: UsbContext>(dev: &rusb::Device<T>) -> Option<String> {
+    let desc = dev.device_descriptor().ok()?;
+    let serial = serial(dev);
+    if serial.is_empty() || serial.chars().any(|c| !(' '..='\x7f').contains(&c) || c == ',') {
+        return None
+    }
+    Some(format!("USB\\VID_{:04X}&PID_{:04X}\\{}", desc.vendor_id(), desc.product_id(), serial))
+}
@@ -0,0 +1,121 @@
+/// Quotes and escapes a string for inclusion in JSON output.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Renders `key: value` pairs, whose values are already JSON, as an object.
+pub fn object<K: AsRef<str>>(fields: &[(K, String)]) -> String {
+    let body = fields
+        .iter()
+        .map(|(key, value)| format!("{}:{}", string(key.as_ref()), value))
+        .collect::<Vec<String>>()
+        .join(",");
+    format!("{{{}}}", body)
+}
+
+/// Renders already formatted JSON values as an array.
+pub fn array(values: &[String]) -> String {
+    format!("[{}]", values.join(","))
+}
+
+/// The string value of a key in a flat object as `object` renders it with
+/// `string` values. Values are escaped, so a key can't be faked by one.
+pub fn get(object: &str, key: &str) -> Option<String> {
+    let prefix = format!("{}:\"", string(key));
+    let start = object.find(&prefix)? + prefix.len();
+    let mut value = String::new();
+    let mut chars = object[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                },
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// The objects of an array of flat objects, such as `list --format json`
+/// prints, as slices to read with `get`.
+pub fn objects(array: &str) -> Vec<&str> {
+    let mut objects = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (None, false, false);
+    for (i, c) in array.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '{' if !quoted => start = Some(i),
+            '}' if !quoted => {
+                if let Some(start) = start.take() {
+                    objects.push(&array[start..=i]);
+                }
+            },
+            _ => {},
+        }
+    }
+    objects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_reads_back_what_object_wrote() {
+        let line = object(&[
+            ("id", string("1d50:6018")),
+            ("text", string("a \"quoted\"\tline\\\n\u{1}")),
+            ("empty", string("")),
+        ]);
+        assert_eq!(get(&line, "id").as_deref(), Some("1d50:6018"));
+        assert_eq!(get(&line, "text").as_deref(), Some("a \"quoted\"\tline\\\n\u{1}"));
+        assert_eq!(get(&line, "empty").as_deref(), Some(""));
+        assert_eq!(get(&line, "missing"), None);
+        // a value that isn't a string
+        assert_eq!(get("{\"seq\":3}", "seq"), None);
+        assert_eq!(get("{\"id\":\"cut", "id"), None);
+    }
+
+    #[test]
+    fn get_finds_escaped_keys_and_not_fakes() {
+        let line = object(&[("a\"b", string("quoted key")), ("text", string("\"id\":\"dead:beef\""))]);
+        assert_eq!(get(&line, "a\"b").as_deref(), Some("quoted key"));
+        assert_eq!(get(&line, "id"), None);
+        let line = object(&[("text", string("\"id\":\"dead:beef\"")), ("id", string("1d50:6018"))]);
+        assert_eq!(get(&line, "id").as_deref(), Some("1d50:6018"));
+    }
+
+    #[test]
+    fn objects_split_an_array() {
+        let first = object(&[("name", string("{not} an object"))]);
+        let second = object(&[("name", string("back\\slash \"}\""))]);
+        let text = array(&[first.clone(), second.clone()]);
+        assert_eq!(objects(&text), [first.as_str(), second.as_str()]);
+        assert!(objects("[]").is_empty());
+        assert_eq!(objects(" [ {\"a\":\"1\"} ,\n{\"b\":\"2\"} ] "), ["{\"a\":\"1\"}", "{\"b\":\"2\"}"]);
+    }
+}
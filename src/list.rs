@@ -0,0 +1,203 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+
+use crate::ccid;
+use crate::dfu;
+use crate::inventory::{self, Entry};
+use crate::json;
+use crate::protobuf;
+use crate::sysfs;
+use crate::report;
+use crate::usbids;
+use crate::yaml;
+use crate::zabbix;
+use crate::{Class, Filter, GroupBy, ListArgs, ListFormat, SortBy, Speed};
+
+/// The fields of a device as a JSON object, which `diff-inventory` reads
+/// back. What needs the device itself is empty when it's gone.
+pub fn json_item<T: rusb::UsbContext>(entry: &Entry, dev: Option<&rusb::Device<T>>) -> String {
+    let detail = |f: fn(&rusb::Device<T>) -> String| json::string(&dev.map(f).unwrap_or_default());
+    json::object(&[
+        ("id", json::string(&entry.id.to_string())),
+        ("vid", json::string(&format!("{:04x}", entry.id.vid))),
+        ("pid", json::string(&format!("{:04x}", entry.id.pid))),
+        ("bus", json::string(&format!("{:03}", entry.bus))),
+        ("address", json::string(&format!("{:03}", entry.address))),
+        ("port", json::string(&entry.port)),
+        ("name", detail(inventory::name)),
+        ("serial", detail(inventory::serial)),
+        ("revision", detail(inventory::revision)),
+        ("max_power", detail(|dev| inventory::max_power(dev).map(|ma| ma.to_string()).unwrap_or_default())),
+    ])
+}
+
+/// A device as a length-delimited Device message, with what needs the
+/// device itself empty when it's gone.
+pub fn protobuf_item<T: rusb::UsbContext>(entry: &Entry, dev: Option<&rusb::Device<T>>) -> Vec<u8> {
+    let detail = |f: fn(&rusb::Device<T>) -> String| dev.map(f).unwrap_or_default();
+    let max_power = dev.and_then(inventory::max_power);
+    protobuf::device(entry, &detail(inventory::name), &detail(inventory::serial), &detail(inventory::revision), max_power)
+}
+
+/// The fields of a device as a YAML sequence item. What needs the device
+/// itself is left out when it's gone or was never looked at.
+pub fn yaml_item<T: rusb::UsbContext>(entry: &Entry, dev: Option<&rusb::Device<T>>) -> String {
+    let mut fields = vec![
+        ("id", yaml::string(&entry.id.to_string())),
+        ("vid", yaml::string(&format!("{:04x}", entry.id.vid))),
+        ("pid", yaml::string(&format!("{:04x}", entry.id.pid))),
+        ("bus", entry.bus.to_string()),
+        ("address", entry.address.to_string()),
+        ("port", yaml::string(&entry.port)),
+    ];
+    if let Some(dev) = dev {
+        let drivers = drivers(dev);
+        fields.push(("name", yaml::string(&inventory::name(dev))));
+        fields.push(("serial", yaml::string(&inventory::serial(dev))));
+        if let Some(ma) = inventory::max_power(dev) {
+            fields.push(("max_power", ma.to_string()));
+        }
+        fields.push(("drivers", yaml::flow(&drivers.iter().map(|d| yaml::string(d)).collect::<Vec<String>>())));
+    }
+    yaml::item(&fields)
+}
+
+/// A device the way lsusb lists it: usb.ids names first, then the strings
+/// the device reported, with the spaces lsusb leaves when there are none.
+pub fn lsusb_line(entry: &Entry) -> String {
+    let (vendor, product) = usbids::lookup(&entry.id);
+    let vendor = vendor.or_else(|| sysfs::attribute(&entry.port, "manufacturer")).unwrap_or_default();
+    let product = product.or_else(|| sysfs::attribute(&entry.port, "product")).unwrap_or_default();
+    format!("Bus {:03} Device {:03}: ID {:04x}:{:04x} {} {}", entry.bus, entry.address, entry.id.vid, entry.id.pid, vendor, product)
+}
+
+/// The drivers bound to the device's interfaces, each once.
+fn drivers<T: rusb::UsbContext>(dev: &rusb::Device<T>) -> Vec<String> {
+    let mut drivers: Vec<String> = sysfs::interface_drivers(dev).into_iter().filter_map(|(_, driver)| driver).collect();
+    drivers.sort();
+    drivers.dedup();
+    drivers
+}
+
+fn text_line<T: rusb::UsbContext>(entry: &Entry, dev: Option<&rusb::Device<T>>) -> String {
+    let fido = dev.is_some_and(|dev| Class::Fido.matches(dev));
+    let label = if fido { " fido" } else { "" };
+    let drivers = dev.map(drivers).unwrap_or_default();
+    let drivers = if drivers.is_empty() { String::new() } else { format!(" driver={}", drivers.join(",")) };
+    let power = dev.and_then(inventory::max_power).map(|ma| format!(" power={}mA", ma)).unwrap_or_default();
+    let dfu = match dev.and_then(dfu::mode) {
+        Some(dfu::Mode::Runtime) => " dfu=runtime",
+        Some(dfu::Mode::Dfu) => " dfu=dfu",
+        None => "",
+    };
+    let reader = dev.and_then(ccid::reader)
+        .map(|r| format!(" ccid slots={} protocols={}", r.slots, r.protocol_names().join(",")))
+        .unwrap_or_default();
+    format!("{:03}:{:03} {}{}{}{}{}{}", entry.bus, entry.address, entry.id, label, dfu, reader, power, drivers)
+}
+
+/// The heading a device is listed under, and what orders the headings.
+fn group<T: rusb::UsbContext>(by: GroupBy, entry: &Entry, dev: Option<&rusb::Device<T>>, devices: &[Entry]) -> (Vec<u32>, String) {
+    match by {
+        GroupBy::Bus => (vec![entry.bus as u32], format!("bus {:03}", entry.bus)),
+        GroupBy::Hub => {
+            let parent = match entry.port.rsplit_once('.').or_else(|| entry.port.split_once('-')) {
+                // the last port of a chain, or the root hub of a top level device
+                Some((parent, _)) if entry.port.contains('.') => parent.to_string(),
+                Some((bus, _)) => format!("usb{}", bus),
+                None => return (Vec::new(), String::from("root hubs")),
+            };
+            let hub = devices.iter().find(|e| e.port == parent).map(|e| format!(" {}", e.id)).unwrap_or_default();
+            (report::topology_key(&parent), format!("hub {}{}", parent, hub))
+        },
+        GroupBy::Class => {
+            // the specific classes first, so a FIDO key isn't listed as HID
+            let classes = [Class::Fido, Class::Dfu].into_iter()
+                .chain(Class::value_variants().iter().copied().filter(|c| !matches!(c, Class::Fido | Class::Dfu)));
+            match dev.and_then(|dev| classes.enumerate().find(|(_, class)| class.matches(dev))) {
+                Some((i, class)) => (vec![i as u32], format!("class {}", class.to_possible_value().map_or_else(String::new, |v| v.get_name().to_string()))),
+                None => (vec![u32::MAX], String::from("class other")),
+            }
+        },
+    }
+}
+
+/// Orders the devices by the key, then by where they are plugged in and
+/// their id, so the order doesn't depend on enumeration. Names and speeds
+/// need the devices; without a context the ties decide.
+pub fn sort<T: rusb::UsbContext>(devices: &mut [Entry], by: SortBy, ctx: Option<&T>) {
+    let place = |e: &Entry| (e.bus, report::topology_key(&e.port), e.id.vid, e.id.pid, e.address);
+    let dev = |e: &Entry| ctx.and_then(|ctx| inventory::find(ctx, e));
+    match by {
+        SortBy::Vid => devices.sort_by_key(|e| (e.id.vid, e.id.pid, place(e))),
+        SortBy::Pid => devices.sort_by_key(|e| (e.id.pid, e.id.vid, place(e))),
+        SortBy::Bus => devices.sort_by_key(place),
+        SortBy::Name => devices.sort_by_cached_key(|e| (dev(e).map(|d| inventory::name(&d)).unwrap_or_default(), place(e))),
+        // unknown speeds last
+        SortBy::Speed => devices.sort_by_cached_key(|e| (dev(e).and_then(|d| Speed::of(d.speed())).map_or(u8::MAX, |s| s as u8), place(e))),
+    }
+}
+
+pub fn run(args: &ListArgs) -> rusb::Result<()> {
+    let filter = Filter::new(&args.filter);
+    let ctx = rusb::Context::new()?;
+    let mut devices = inventory::scan(&ctx, &filter)?;
+    if let Some(by) = args.sort {
+        sort(&mut devices, by, Some(&ctx));
+    }
+    match args.format {
+        ListFormat::Text => match args.group_by {
+            None => {
+                for entry in &devices {
+                    println!("{}", text_line(entry, inventory::find(&ctx, entry).as_ref()));
+                }
+            },
+            Some(by) => {
+                let mut lines: Vec<((Vec<u32>, String), String)> = devices
+                    .iter()
+                    .map(|entry| {
+                        let dev = inventory::find(&ctx, entry);
+                        (group(by, entry, dev.as_ref(), &devices), text_line(entry, dev.as_ref()))
+                    })
+                    .collect();
+                // stable, so devices keep their order within a group
+                lines.sort_by(|a, b| a.0.cmp(&b.0));
+                let mut heading = None;
+                for ((key, title), line) in lines {
+                    if heading.as_ref() != Some(&key) {
+                        println!("{}", title);
+                        heading = Some(key);
+                    }
+                    println!("  {}", line);
+                }
+            },
+        },
+        ListFormat::Yaml => {
+            if devices.is_empty() {
+                println!("[]");
+            }
+            for entry in &devices {
+                print!("{}", yaml_item(entry, inventory::find(&ctx, entry).as_ref()));
+            }
+        },
+        ListFormat::Json => {
+            let items: Vec<String> = devices.iter().map(|entry| json_item(entry, inventory::find(&ctx, entry).as_ref())).collect();
+            println!("{}", json::array(&items));
+        },
+        ListFormat::ZabbixDiscovery => println!("{}", zabbix::discovery(&ctx, &devices)),
+        ListFormat::ZabbixPresence => println!("{}", zabbix::presence(&args.filter.id, &devices)),
+        ListFormat::Lsusb => {
+            for entry in &devices {
+                println!("{}", lsusb_line(entry));
+            }
+        },
+        ListFormat::Protobuf => {
+            let mut stdout = io::stdout();
+            for entry in &devices {
+                stdout.write_all(&protobuf_item(entry, inventory::find(&ctx, entry).as_ref())).map_err(|_| rusb::Error::Io)?;
+            }
+        },
+    }
+    Ok(())
+}
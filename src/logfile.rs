@@ -0,0 +1,121 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Local time as `2024-01-31T23:59:59`.
+pub fn timestamp() -> String {
+    // SAFETY: time only reads the clock
+    local_time(unsafe { libc::time(std::ptr::null_mut()) })
+}
+
+/// Seconds since the epoch as local time, in the format of timestamp.
+pub fn local_time(secs: libc::time_t) -> String {
+    // SAFETY: localtime_r writes into the tm we own
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec)
+    }
+}
+
+/// Seconds since the epoch of a time in the format of timestamp, read as
+/// local time the way it was written. In the hour a DST change repeats,
+/// mktime picks one of the two.
+pub fn epoch(time: &str) -> Option<libc::time_t> {
+    let number = |range: std::ops::Range<usize>| time.get(range)?.parse::<libc::c_int>().ok();
+    if time.len() != 19 || !time.is_char_boundary(10) || &time[4..5] != "-" || &time[7..8] != "-" || &time[10..11] != "T" {
+        return None
+    }
+    // SAFETY: mktime reads and normalizes the tm we own
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        tm.tm_year = number(0..4)? - 1900;
+        tm.tm_mon = number(5..7)? - 1;
+        tm.tm_mday = number(8..10)?;
+        tm.tm_hour = number(11..13)?;
+        tm.tm_min = number(14..16)?;
+        tm.tm_sec = number(17..19)?;
+        tm.tm_isdst = -1;
+        match libc::mktime(&mut tm) {
+            -1 => None,
+            secs => Some(secs),
+        }
+    }
+}
+
+/// When a log file is rotated and what happens to the old ones.
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub keep: usize,
+    pub gzip: bool,
+}
+
+/// A timestamped log that rotates itself: once the file grows past
+/// max_size or gets older than max_age it becomes `<path>.1`, the older
+/// ones shift up and all but the newest keep are deleted.
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: Instant,
+    rotation: Rotation,
+}
+
+fn open(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+impl LogFile {
+    pub fn new(path: &Path, rotation: Rotation) -> io::Result<LogFile> {
+        let (file, size) = open(path)?;
+        Ok(LogFile{path: path.to_path_buf(), file, size, opened: Instant::now(), rotation})
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let suffix = if self.rotation.gzip { ".gz" } else { "" };
+        PathBuf::from(format!("{}.{}{}", self.path.display(), n, suffix))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        _ = fs::remove_file(self.rotated(self.rotation.keep));
+        for n in (1..self.rotation.keep).rev() {
+            _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+        }
+        if self.rotation.keep > 0 {
+            let first = PathBuf::from(format!("{}.1", self.path.display()));
+            fs::rename(&self.path, &first)?;
+            if self.rotation.gzip {
+                let status = Command::new("gzip").arg("-f").arg(&first).status();
+                if !status.is_ok_and(|s| s.success()) {
+                    eprintln!("log: failed to gzip {}", first.display());
+                }
+            }
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        let (file, size) = open(&self.path)?;
+        self.file = file;
+        self.size = size;
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let due = self.rotation.max_size.is_some_and(|max| self.size >= max)
+            || self.rotation.max_age.is_some_and(|max| self.opened.elapsed() >= max);
+        if due && self.size > 0 {
+            self.rotate()?;
+        }
+        let record = format!("{} {}\n", timestamp(), line);
+        self.file.write_all(record.as_bytes())?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+}
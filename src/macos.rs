@@ -0,0 +1,37 @@
+use std::process::Command;
+
+use rusb::UsbContext;
+
+/// The IOKit location id, e.g. `0x14200000`: the bus in the top byte and
+/// one nibble per port below it, as ioreg and system_profiler show it.
+pub fn location_id<T: UsbContext>(dev: &rusb::Device<T>) -> Option<u32> {
+    let ports = dev.port_numbers().ok()?;
+    let mut location = (dev.bus_number() as u32) << 24;
+    for (i, port) in ports.iter().take(6).enumerate() {
+        location |= ((*port as u32) & 0xf) << (20 - 4 * i);
+    }
+    Some(location)
+}
+
+/// The registry path of the device in the IOUSB plane, found by its
+/// location id in the ioreg tree, whose entries are named `Name@location`.
+pub fn registry_path(location: u32) -> Option<String> {
+    let output = Command::new("ioreg").args(["-p", "IOUSB", "-w0"]).output().ok()?;
+    let suffix = format!("@{:08x}", location);
+    let mut stack: Vec<String> = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let start = match line.find("+-o ") {
+            Some(start) => start,
+            None => continue,
+        };
+        let name = line[start + 4..].split("  <").next().unwrap_or_default().trim().to_string();
+        // every level of the tree is indented by two more columns
+        stack.truncate(start / 2);
+        stack.push(name);
+        if stack.last().is_some_and(|name| name.ends_with(&suffix)) {
+            // the root entry isn't part of the path
+            return Some(format!("IOUSB:/{}", stack[1..].join("/")))
+        }
+    }
+    None
+}
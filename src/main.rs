@@ -1,10 +1,102 @@
+use std::collections::HashSet;
 use std::fmt;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use rusb::UsbContext;
-use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod acl;
+mod audit;
+mod auth;
+mod autosuspend;
+mod cbor;
+mod ccid;
+mod check;
+mod client;
+mod cdc;
+mod coalesce;
+mod completions;
+mod config;
+mod container;
+mod control;
+mod debugfs;
+mod descriptors;
+mod dfu;
+mod diagnose;
+mod diffinv;
+mod doctor;
+mod email;
+mod errors;
+mod flap;
+mod hid;
+mod hub;
+mod history;
+mod inventory;
+mod json;
+mod list;
+mod logfile;
+mod macos;
+mod man;
+mod matcher;
+mod mount;
+mod netif;
+mod notify;
+mod mqtt;
+mod msgpack;
+mod otlp;
+mod overcurrent;
+mod output;
+mod parquet;
+mod pidfile;
+mod power;
+mod plugin;
+mod ratelimit;
+mod privileges;
+mod progress;
+mod protobuf;
+mod queue;
+mod replay;
+mod retention;
+mod retry;
+mod report;
+mod rules;
+#[cfg(feature = "sandbox")]
+mod sandbox;
+mod script;
+mod selftest;
+mod sha256;
+mod signal;
+mod sources;
+mod state;
+mod statsd;
+mod status;
+mod storage;
+mod sysfs;
+mod template;
+mod tls;
+mod tui;
+mod udev;
+mod uac;
+mod usbids;
+mod uvc;
+mod v4l2;
+mod vm;
+mod wakeup;
+mod watch;
+mod webhook;
+mod whohas;
+mod windows;
+mod workers;
+mod yaml;
+mod zabbix;
+
+const CARD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
 
 struct HotPlugHandler<T: rusb::UsbContext> {
-    sender: mpsc::Sender<rusb::Device<T>>,
+    queue: Arc<queue::Queue<rusb::Device<T>>>,
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -14,6 +106,9 @@ enum Error {
     MissingSeparator,
     InvalidVID(String),
     InvalidPID(String),
+    InvalidDuration(String),
+    InvalidSize(String),
+    InvalidTime(String),
 }
 
 impl fmt::Display for Error {
@@ -22,13 +117,16 @@ impl fmt::Display for Error {
             Error::MissingSeparator => write!(f, "missing : separator"),
             Error::InvalidVID(s) => write!(f, "invalid hex VID {}", s),
             Error::InvalidPID(s) => write!(f, "invalid hex PID {}", s),
+            Error::InvalidDuration(s) => write!(f, "invalid duration {}, expected e.g. 500ms, 2s, 5m, 7d", s),
+            Error::InvalidSize(s) => write!(f, "invalid size {}, expected e.g. 512K, 10M, 1G", s),
+            Error::InvalidTime(s) => write!(f, "invalid time {}, expected e.g. 2024-01-31, 2024-01-31T23:59, -24h or now", s),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct DeviceID {
     vid: u16,
     pid: u16,
@@ -71,84 +169,1589 @@ fn parse_device(arg: &str) -> Result<DeviceID> {
     Ok(DeviceID{vid, pid})
 }
 
+fn parse_vid(arg: &str) -> Result<u16> {
+    u16::from_str_radix(arg, 16).map_err(|_| Error::InvalidVID(arg.to_string()))
+}
+
+fn parse_pid(arg: &str) -> Result<u16> {
+    u16::from_str_radix(arg, 16).map_err(|_| Error::InvalidPID(arg.to_string()))
+}
+
+fn parse_duration(arg: &str) -> Result<Duration> {
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let number: u64 = match number.parse() {
+        Err(_) => return Err(Error::InvalidDuration(arg.to_string())),
+        Ok(number) => number,
+    };
+    let secs = |per: u64| number.checked_mul(per).map(Duration::from_secs).ok_or_else(|| Error::InvalidDuration(arg.to_string()));
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => secs(60),
+        "h" => secs(3600),
+        "d" => secs(86400),
+        _ => Err(Error::InvalidDuration(arg.to_string())),
+    }
+}
+
+/// A point in local time, in the `2024-01-31T23:59:59` form of event
+/// timestamps, which then compare as strings. Takes that form with the
+/// time or part of it left out, a space for the T, `now`, or a duration
+/// back from now such as `-24h`.
+fn parse_time(arg: &str) -> Result<String> {
+    let invalid = || Error::InvalidTime(arg.to_string());
+    if arg == "now" {
+        return Ok(logfile::timestamp())
+    }
+    if let Some(back) = arg.strip_prefix('-') {
+        let back = parse_duration(back).map_err(|_| invalid())?;
+        // SAFETY: time only reads the clock
+        let now = unsafe { libc::time(std::ptr::null_mut()) };
+        let then = libc::time_t::try_from(back.as_secs()).ok().and_then(|back| now.checked_sub(back)).ok_or_else(invalid)?;
+        return Ok(logfile::local_time(then))
+    }
+    let time = arg.replacen(' ', "T", 1);
+    let full = "0000-00-00T00:00:00";
+    let shape = |c: char| if c.is_ascii_digit() { '0' } else { c };
+    // a date, to the minute or to the second
+    if ![10, 16, 19].contains(&time.len()) || !time.chars().map(shape).eq(full[..time.len()].chars()) {
+        return Err(invalid())
+    }
+    Ok(format!("{}{}", time, &full[time.len()..]))
+}
+
+/// Seconds as people say them, in the largest units that matter, e.g.
+/// `3d 4h`, `2h 5m`, `12m` or `45s`.
+fn format_duration(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", secs),
+        (0, 0, _) => format!("{}m", minutes),
+        (0, _, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+fn parse_size(arg: &str) -> Result<u64> {
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let number: u64 = match number.parse() {
+        Err(_) => return Err(Error::InvalidSize(arg.to_string())),
+        Ok(number) => number,
+    };
+    match unit {
+        "" => Ok(number),
+        "K" | "k" => Ok(number << 10),
+        "M" => Ok(number << 20),
+        "G" => Ok(number << 30),
+        _ => Err(Error::InvalidSize(arg.to_string())),
+    }
+}
+
 impl<T: rusb::UsbContext> rusb::Hotplug<T> for HotPlugHandler<T> {
     fn device_arrived(&mut self, device: rusb::Device<T>) {
-        _ = self.sender.send(device);
+        self.queue.push(device);
     }
 
     fn device_left(&mut self, device: rusb::Device<T>) {
-        _ = self.sender.send(device);
+        self.queue.push(device);
     }
 }
 
-fn is_connected<T: rusb::UsbContext>(
-    devices: rusb::Result<rusb::DeviceList<T>>, 
-    ids: &Vec<DeviceID>
-) -> Option<DeviceID> {
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum Class {
+    Audio,
+    Cdc,
+    Hid,
+    Storage,
+    Hub,
+    Video,
+    Smartcard,
+    Printer,
+    Vendor,
+    /// Any device with a DFU interface, runtime or DFU mode
+    Dfu,
+    /// FIDO U2F/CTAP authenticators
+    Fido,
+}
+
+/// The speed a device enumerated at, slowest first.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Speed {
+    /// 1.5 Mbit/s
+    Low,
+    /// 12 Mbit/s
+    Full,
+    /// 480 Mbit/s
+    High,
+    /// 5 Gbit/s
+    Super,
+    /// 10 Gbit/s
+    SuperPlus,
+}
+
+impl Speed {
+    fn of(speed: rusb::Speed) -> Option<Speed> {
+        match speed {
+            rusb::Speed::Low => Some(Speed::Low),
+            rusb::Speed::Full => Some(Speed::Full),
+            rusb::Speed::High => Some(Speed::High),
+            rusb::Speed::Super => Some(Speed::Super),
+            rusb::Speed::SuperPlus => Some(Speed::SuperPlus),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = clap::ValueEnum::to_possible_value(self).unwrap();
+        write!(f, "{}", value.get_name())
+    }
+}
+
+impl Class {
+    fn code(&self) -> Option<u8> {
+        match self {
+            Class::Audio => Some(0x01),
+            Class::Cdc => Some(0x02),
+            Class::Hid => Some(0x03),
+            Class::Printer => Some(0x07),
+            Class::Storage => Some(0x08),
+            Class::Hub => Some(0x09),
+            Class::Smartcard => Some(0x0b),
+            Class::Video => Some(0x0e),
+            Class::Vendor => Some(0xff),
+            Class::Dfu | Class::Fido => None,
+        }
+    }
+
+    fn matches<T: rusb::UsbContext>(&self, dev: &rusb::Device<T>) -> bool {
+        match self {
+            Class::Dfu => dfu::mode(dev).is_some(),
+            // only HID devices have report descriptors to look in
+            Class::Fido => Class::Hid.matches(dev) && hid::is_fido(dev),
+            _ => has_class(dev, self.code().unwrap()),
+        }
+    }
+}
+
+/// Whether the device or any interface of its active configuration has the class.
+fn has_class<T: rusb::UsbContext>(dev: &rusb::Device<T>, code: u8) -> bool {
+    if dev.device_descriptor().is_ok_and(|desc| desc.class_code() == code) {
+        return true
+    }
+    match dev.active_config_descriptor() {
+        Ok(config) => config.interfaces().any(|iface| iface.descriptors().any(|alt| alt.class_code() == code)),
+        Err(_) => false,
+    }
+}
+
+/// Everything a device has to satisfy to count as the one being waited for.
+#[derive(Default, Clone)]
+struct Filter {
+    ids: Vec<DeviceID>,
+    /// Together a vid:pid pattern with wildcards, alongside the ids
+    vids: Vec<u16>,
+    pids: Vec<u16>,
+    classes: Vec<Class>,
+    speeds: Vec<Speed>,
+    /// In mA, the least a device may ask for
+    max_power: Option<u16>,
+    dfu: bool,
+    card: bool,
+}
+
+impl Filter {
+    fn new(args: &FilterArgs) -> Filter {
+        Filter{
+            ids: args.id.clone(),
+            vids: args.vid.clone(),
+            pids: args.pid.clone(),
+            classes: args.class.clone(),
+            speeds: args.speed.clone(),
+            max_power: args.max_power,
+            ..Default::default()
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.vids.is_empty() && self.pids.is_empty() && self.classes.is_empty()
+    }
+}
+
+fn find_device<T: rusb::UsbContext>(
+    devices: rusb::Result<rusb::DeviceList<T>>,
+    filter: &Filter
+) -> Option<rusb::Device<T>> {
     match devices {
         Err(_) =>  None,
         Ok(devices) => {
-            let result = devices
-                .iter()
-                .find(|dev| {
-                    let desc = dev.device_descriptor().unwrap();
-                    ids.iter().find(|id| desc.vendor_id() == id.vid && desc.product_id() == id.pid).is_some()
-                });
-            match result {
-                Some(dev) => {
-                    let desc = dev.device_descriptor().unwrap();
-                    return Some(DeviceID{vid: desc.vendor_id(), pid: desc.product_id()})
-                },
-                None => None
-            }
+            let matcher = matcher::Matcher::new(filter);
+            devices.iter().find(|dev| matcher.matches(dev))
         },
     }
 }
 
+fn is_connected<T: rusb::UsbContext>(
+    devices: rusb::Result<rusb::DeviceList<T>>,
+    filter: &Filter
+) -> Option<DeviceID> {
+    find_device(devices, filter).and_then(|dev| {
+        let desc = retry::run(|| dev.device_descriptor()).ok()?;
+        Some(DeviceID{vid: desc.vendor_id(), pid: desc.product_id()})
+    })
+}
+
+/// The ids of the filter that aren't attached yet, or when waiting for
+/// detach, the ones still attached.
+fn outstanding<T: rusb::UsbContext>(
+    devices: rusb::Result<rusb::DeviceList<T>>,
+    filter: &Filter,
+    attach: bool
+) -> Vec<DeviceID> {
+    let devices = match devices {
+        Err(_) => return filter.ids.clone(),
+        Ok(devices) => devices,
+    };
+    // one pass with the checks other than the id, then a lookup per id
+    let rest = matcher::Matcher::new(&Filter{
+        classes: filter.classes.clone(),
+        speeds: filter.speeds.clone(),
+        max_power: filter.max_power,
+        dfu: filter.dfu,
+        card: filter.card,
+        ..Default::default()
+    });
+    let present: HashSet<DeviceID> = devices
+        .iter()
+        .filter(|dev| rest.matches(dev))
+        .filter_map(|dev| dev.device_descriptor().ok())
+        .map(|desc| DeviceID{vid: desc.vendor_id(), pid: desc.product_id()})
+        .collect();
+    filter.ids.iter().filter(|id| present.contains(id) == attach).cloned().collect()
+}
+
+/// Gives up when a device the filter asks for is there at a speed it
+/// doesn't allow, such as a SuperSpeed device that fell back to High, as
+/// it won't change speed without being plugged in again.
+fn check_speed<T: rusb::UsbContext>(devices: rusb::Result<rusb::DeviceList<T>>, filter: &Filter) {
+    if filter.speeds.is_empty() {
+        return
+    }
+    let devices = match devices {
+        Ok(devices) => devices,
+        Err(_) => return,
+    };
+    let any = matcher::Matcher::new(&Filter{speeds: Vec::new(), ..filter.clone()});
+    let wanted = matcher::Matcher::new(filter);
+    let slow = match devices.iter().find(|dev| any.matches(dev) && !wanted.matches(dev)) {
+        Some(dev) => dev,
+        None => return,
+    };
+    let desc = match slow.device_descriptor() {
+        Ok(desc) => desc,
+        Err(_) => return,
+    };
+    let speed = Speed::of(slow.speed()).map_or_else(|| String::from("unknown"), |speed| speed.to_string());
+    let wanted: Vec<String> = filter.speeds.iter().map(Speed::to_string).collect();
+    errors::fail("wrong_speed", &format!("{:04x}:{:04x} is connected at {} speed, not {}",
+        desc.vendor_id(), desc.product_id(), speed, wanted.join(" or ")));
+}
+
+/// Gives up when Windows has a device the filter allows in a problem
+/// state, having failed to install or start its driver, since waiting
+/// longer won't make it usable.
+fn check_driver(filter: &Filter) {
+    if let Some(problem) = windows::problem(&matcher::Matcher::new(filter)) {
+        let id = problem.id().map(|id| id.to_string()).unwrap_or_default();
+        errors::fail("driver_problem", &format!("{} is present with a driver problem, code {} {}: {} ({})",
+            id, problem.code, problem.reason, problem.name, problem.instance_id));
+    }
+}
+
+/// Lists on stderr where each id of the filter is connected, or that it
+/// is missing, for --expect-present.
+fn report_presence<T: rusb::UsbContext>(devices: rusb::Result<rusb::DeviceList<T>>, filter: &Filter) {
+    let devices: Vec<rusb::Device<T>> = devices.map(|list| list.iter().collect()).unwrap_or_default();
+    for id in &filter.ids {
+        let matcher = matcher::Matcher::new(&Filter{ids: vec![id.clone()], ..filter.clone()});
+        let found: Vec<&rusb::Device<T>> = devices.iter().filter(|dev| matcher.matches(dev)).collect();
+        if found.is_empty() {
+            eprintln!("missing {}", id);
+        }
+        for dev in found {
+            let port = sysfs::device_name(dev).unwrap_or_default();
+            eprintln!("present {} {:03}:{:03} {}", id, dev.bus_number(), dev.address(), port);
+        }
+    }
+}
+
+/// Whether the wait is over. With --all every id has to get there and
+/// the progress list follows along, otherwise any matching device will do.
+fn is_done<T: rusb::UsbContext>(
+    devices: rusb::Result<rusb::DeviceList<T>>,
+    connected: &Option<DeviceID>,
+    filter: &Filter,
+    attach: bool,
+    progress: &mut Option<progress::Progress>
+) -> bool {
+    match progress {
+        Some(progress) => {
+            let left = outstanding(devices, filter, attach);
+            progress.update(&left);
+            left.is_empty()
+        },
+        None => connected.is_some() ^ !attach,
+    }
+}
+
+/// Reports what still hadn't happened when --timeout ran out.
+fn timed_out<T: rusb::UsbContext>(devices: rusb::Result<rusb::DeviceList<T>>, filter: &Filter, attach: bool) -> rusb::Error {
+    let op = if attach { "attach" } else { "detach" };
+    let left = if filter.ids.is_empty() { Vec::new() } else { outstanding(devices, filter, attach) };
+    let message = if left.is_empty() {
+        format!("timed out waiting for a device to {}", op)
+    } else {
+        format!("timed out waiting for {} to {}", iterable_to_str(left.iter()), op)
+    };
+    let left: Vec<String> = left.iter().map(DeviceID::to_string).collect();
+    errors::report("timeout", &message, &[("outstanding", left.join(","))]);
+    rusb::Error::Timeout
+}
+
+/// Waits for whatever the matched device was asked to settle into and
+/// prints whatever extra information was asked for about it.
+fn print_details<T: rusb::UsbContext>(devices: rusb::Result<rusb::DeviceList<T>>, filter: &Filter, args: &Args) {
+    let dev = match find_device(devices, filter) {
+        Some(dev) => dev,
+        None => return,
+    };
+    // most of what follows opens the device, which is where access fails
+    if args.verbose {
+        if let Err(e) = dev.open() {
+            diagnose::report("opening the device", Some(&dev), &e);
+        }
+    }
+    if let Some(runtime) = container::detect() {
+        container::report(&dev, &runtime);
+    }
+    if args.print_docker_flag {
+        println!("{}", container::docker_flag(&dev));
+    }
+    if args.settle && !udev::settle(&dev, SETTLE_TIMEOUT) {
+        errors::fail("settle_timeout", &format!("udev didn't settle within {}s", SETTLE_TIMEOUT.as_secs()));
+    }
+    if let Some(driver) = &args.wait_driver {
+        let bound = sysfs::wait_for(SETTLE_TIMEOUT, || {
+            sysfs::interface_drivers(&dev).into_iter().filter(|(_, d)| d.as_ref() == Some(driver)).collect()
+        });
+        if bound.is_empty() {
+            let drivers: Vec<String> = sysfs::interface_drivers(&dev)
+                .into_iter()
+                .map(|(iface, d)| format!("{}:{}", iface, d.unwrap_or_else(|| String::from("-"))))
+                .collect();
+            errors::fail("driver_timeout", &format!("{} didn't bind within {}s, interfaces have {}",
+                driver, SETTLE_TIMEOUT.as_secs(), drivers.join(" ")));
+        }
+    }
+    if args.drivers {
+        for (iface, driver) in sysfs::interface_drivers(&dev) {
+            println!("{} {}", iface, driver.as_deref().unwrap_or("-"));
+        }
+    }
+    if args.wait_mount {
+        for (source, target) in mount::wait(&dev, args.mount) {
+            println!("{} {}", source, target);
+        }
+    }
+    if args.netif {
+        let ifaces = sysfs::wait_for(SETTLE_TIMEOUT, || sysfs::net_interfaces(&dev));
+        if args.wait_link_up {
+            netif::wait_up(&ifaces);
+        }
+        for iface in ifaces {
+            println!("{}", iface);
+        }
+    }
+    if args.alsa {
+        for (index, id) in sysfs::wait_for(SETTLE_TIMEOUT, || sysfs::sound_cards(&dev)) {
+            println!("hw:{} {}", index, id);
+        }
+    }
+    if args.instance_id {
+        match inventory::instance_id(&dev) {
+            Some(id) => println!("{}", id),
+            None => eprintln!("no usable serial number, Windows makes up the instance id from the port"),
+        }
+    }
+    if args.location_id {
+        if let Some(location) = macos::location_id(&dev) {
+            match macos::registry_path(location) {
+                Some(path) => println!("0x{:08x} {}", location, path),
+                None => println!("0x{:08x}", location),
+            }
+        }
+    }
+    if args.video {
+        for (node, kind) in sysfs::wait_for(SETTLE_TIMEOUT, || v4l2::nodes(&dev)) {
+            println!("{} {}", node, kind);
+        }
+    }
+    if args.inquiry {
+        match storage::inquiry(&dev) {
+            Ok(Some(inquiry)) => println!("{}", inquiry),
+            Ok(None) => {},
+            Err(e) if args.verbose => diagnose::report("INQUIRY", Some(&dev), &e),
+            Err(e) => eprintln!("INQUIRY failed: {}", e),
+        }
+    }
+    if args.verbose_descriptors {
+        match descriptors::dump(&dev) {
+            Ok(dump) => print!("{}", dump),
+            Err(e) if args.verbose => diagnose::report("reading descriptors", Some(&dev), &e),
+            Err(e) => eprintln!("failed to read descriptors: {}", e),
+        }
+    }
+    if args.dump_hex {
+        match descriptors::dump_hex(&dev) {
+            Ok(dump) => print!("{}", dump),
+            Err(e) if args.verbose => diagnose::report("reading descriptors", Some(&dev), &e),
+            Err(e) => eprintln!("failed to read descriptors: {}", e),
+        }
+    }
+}
+
+/// What is being waited for, e.g. `1d50:6018, hid to attach`.
+fn summary(args: &Args) -> String {
+    let mut parts: Vec<String> = args.filter.id.iter().map(|id| id.to_string()).collect();
+    match (args.filter.vid.as_slice(), args.filter.pid.as_slice()) {
+        ([], []) => {},
+        (vids, []) => parts.extend(vids.iter().map(|vid| format!("{:x}:*", vid))),
+        ([], pids) => parts.extend(pids.iter().map(|pid| format!("*:{:x}", pid))),
+        (vids, pids) => parts.extend(vids.iter().flat_map(|vid| pids.iter().map(move |pid| format!("{:x}:{:x}", vid, pid)))),
+    }
+    parts.extend(args.filter.class
+        .iter()
+        .filter_map(clap::ValueEnum::to_possible_value)
+        .map(|value| value.get_name().to_string()));
+    let op = if args.detach { "detach" } else { "attach" };
+    format!("waiting for {} to {}", parts.join(", "), op)
+}
+
+/// Waits for the next hotplug event, turning the spinner meanwhile.
+/// Returns None once the deadline has passed.
+fn next_event<T>(queue: &queue::Queue<T>, deadline: Option<Instant>, spinner: &mut Option<progress::Spinner>) -> Option<T> {
+    loop {
+        let mut wait = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if spinner.is_some() {
+            wait = Some(wait.map_or(SPINNER_INTERVAL, |wait| wait.min(SPINNER_INTERVAL)));
+        }
+        let wait = match wait {
+            None => return Some(queue.pop()),
+            Some(wait) => wait,
+        };
+        if let Some(item) = queue.pop_timeout(wait) {
+            return Some(item)
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return None
+        }
+        if let Some(spinner) = spinner {
+            spinner.tick();
+        }
+    }
+}
+
+/// Prints the id of the device the wait ended on, or with --all and
+/// attach, every id that was waited for, labelled with --report-match.
+fn print_ids(id: &DeviceID, filter: &Filter, args: &Args) {
+    let matcher = matcher::Matcher::new(filter);
+    let print = |id: &DeviceID| match matcher.label(id).filter(|_| args.report_match) {
+        Some(label) => println!("{} {}", id, label),
+        None => println!("{}", id),
+    };
+    if args.all && !args.detach {
+        filter.ids.iter().for_each(print);
+    } else {
+        print(id);
+    }
+}
+
+fn print_capabilities<T: rusb::UsbContext>(devices: rusb::Result<rusb::DeviceList<T>>, ids: &[DeviceID]) {
+    let filter = Filter{ids: ids.to_vec(), ..Default::default()};
+    if let Some(dev) = find_device(devices, &filter) {
+        let desc = match retry::run(|| dev.device_descriptor()) {
+            Ok(desc) => desc,
+            Err(_) => return,
+        };
+        if let Some(mode) = dfu::mode(&dev) {
+            eprintln!("{:x}:{:x} is in {} mode", desc.vendor_id(), desc.product_id(), mode);
+        }
+        if let Some(reader) = ccid::reader(&dev) {
+            eprintln!("{:x}:{:x} is a smart card reader with {}", desc.vendor_id(), desc.product_id(), reader);
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct FilterArgs {
+   /// Device id, vid:pid
+   #[arg(short, long, num_args = 1.., value_delimiter = ',', env = "USBMON_ID", value_parser=parse_device)]
+   id: Vec<DeviceID>,
+
+   /// Any device of this vendor, in hex; with --pid only that product
+   #[arg(long, num_args = 1.., value_delimiter = ',', env = "USBMON_VID", value_parser = parse_vid)]
+   vid: Vec<u16>,
+
+   /// Any device with this product id, in hex, whatever the vendor
+   #[arg(long, num_args = 1.., value_delimiter = ',', env = "USBMON_PID", value_parser = parse_pid)]
+   pid: Vec<u16>,
+
+   /// Device or interface class
+   #[arg(long, value_enum, value_delimiter = ',', env = "USBMON_CLASS")]
+   class: Vec<Class>,
+
+   /// Only devices that enumerated at one of these speeds; waiting fails
+   /// right away when the device comes up at another
+   #[arg(long, value_enum, value_delimiter = ',', env = "USBMON_SPEED")]
+   speed: Vec<Speed>,
+
+   /// Only devices whose configuration asks for at least this many mA
+   #[arg(long, value_name = "MA", env = "USBMON_MAX_POWER")]
+   max_power: Option<u16>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum ListFormat {
+    Text,
+    /// A YAML sequence with names, serials and drivers
+    Yaml,
+    /// A JSON array with names, serials and revisions, to save for
+    /// diff-inventory
+    Json,
+    /// Zabbix low-level discovery JSON
+    ZabbixDiscovery,
+    /// JSON object of how many devices of each id are present, for Zabbix
+    /// dependent items
+    ZabbixPresence,
+    /// `Bus 001 Device 004: ID 1a2b:5678 Vendor Product`, for scripts
+    /// written against lsusb
+    Lsusb,
+    /// Length-delimited Device messages of usbmon --proto
+    Protobuf,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum GroupBy {
+    /// The hub each device is plugged into
+    Hub,
+    Bus,
+    /// The most specific --class each device has
+    Class,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum SortBy {
+    Vid,
+    Pid,
+    /// Bus, then port
+    Bus,
+    Name,
+    /// Slowest first
+    Speed,
+}
+
+#[derive(clap::Args, Debug)]
+struct ListArgs {
+   #[command(flatten)]
+   filter: FilterArgs,
+
+   /// Output format
+   #[arg(long, value_enum, default_value = "text", env = "USBMON_LIST_FORMAT")]
+   format: ListFormat,
+
+   /// List text output under a heading per hub, bus or class
+   #[arg(long, value_enum)]
+   group_by: Option<GroupBy>,
+
+   /// Order devices by this, then by bus and port, the same on every run
+   #[arg(long, value_enum)]
+   sort: Option<SortBy>,
+
+   /// Ask the watch listening on this control socket instead of USB
+   #[arg(long, value_name = "FILE", env = "USBMON_VIA_DAEMON")]
+   via_daemon: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct WatchArgs {
+   #[command(flatten)]
+   filter: FilterArgs,
+
+   /// Time between scans
+   #[arg(long, default_value = "2s", env = "USBMON_INTERVAL", value_parser = parse_duration)]
+   interval: Duration,
+
+   /// Warn when a device changes state more than N times a minute,
+   /// and exit nonzero when stopped
+   #[arg(long, value_name = "N")]
+   flap_limit: Option<usize>,
+
+   /// Print a "still alive" line this often, even without events
+   #[arg(long, value_parser = parse_duration)]
+   heartbeat: Option<Duration>,
+
+   /// Report hub ports that trip their overcurrent protection, which
+   /// otherwise looks like the device on the port detaching (Linux)
+   #[arg(long)]
+   overcurrent: bool,
+
+   /// Report devices the kernel runtime suspends and resumes, from their
+   /// power/runtime_status (Linux)
+   #[arg(long)]
+   power_state: bool,
+
+   /// Keep the last seen time of every matching device in this file,
+   /// for usbmon status
+   #[arg(long, value_name = "FILE", env = "USBMON_STATE")]
+   state: Option<std::path::PathBuf>,
+
+   /// Report the matching devices already connected at startup as attach
+   /// events, marked existing, so a consumer can build its state from the
+   /// event stream alone
+   #[arg(long)]
+   enumerate_existing: bool,
+
+   /// Start with a snapshot record (=) of the matching devices connected
+   /// now, which the events that follow change
+   #[arg(long, conflicts_with = "enumerate_existing")]
+   initial_state: bool,
+
+   /// Exit with status 2 when a device has been missing for longer than
+   /// --max-absence
+   #[arg(long)]
+   require_present: bool,
+
+   /// How long a required device may be missing
+   #[arg(long, default_value = "0s", value_parser = parse_duration, requires = "require_present")]
+   max_absence: Duration,
+
+   /// Run this shell command instead of exiting when a required device
+   /// is missing for too long; $USBMON_ID names the device
+   #[arg(long, value_name = "CMD", requires = "require_present")]
+   on_absence: Option<String>,
+
+   /// Send attach/detach counters and presence gauges to this statsd
+   /// server, host:port
+   #[arg(long, value_name = "ADDR")]
+   statsd: Option<String>,
+
+   /// Prefix for statsd metric names
+   #[arg(long, default_value = "usbmon", requires = "statsd")]
+   statsd_prefix: String,
+
+   /// Export event counters and replug spans to this OTLP/HTTP collector,
+   /// e.g. http://localhost:4318. Spans join the trace in $TRACEPARENT.
+   #[arg(long, value_name = "URL")]
+   otlp: Option<String>,
+
+   /// Notifier settings; an [email] section mails events through SMTP,
+   /// a [webhook] section posts them to Slack or Discord, an [mqtt]
+   /// section publishes presence with optional Home Assistant discovery
+   /// and [rule NAME] sections pair filters with actions; [plugins]
+   /// lists programs that get every event as JSON on stdin and [audit]
+   /// keeps a hash-chained log of every event; [output NAME] sections
+   /// add more outputs with a target and format of their own; [aliases]
+   /// names vid:pids, `name = vid:pid`, for shell completion of --id.
+   /// SIGHUP reloads the file.
+   #[arg(long, value_name = "FILE", env = "USBMON_CONFIG")]
+   config: Option<std::path::PathBuf>,
+
+   /// Use the config's [profile.NAME] sections, with their filter when
+   /// none is given here and their own sinks and rules
+   #[arg(long, value_name = "NAME", env = "USBMON_PROFILE", requires = "config")]
+   profile: Option<String>,
+
+   /// Write the pid to this file and lock it, refusing to start if
+   /// another instance holds the lock. It is removed on exit, which with
+   /// --user needs its directory to be writable by the user; otherwise it
+   /// is left empty
+   #[arg(long, value_name = "FILE")]
+   pidfile: Option<std::path::PathBuf>,
+
+   /// Listen for requests such as `usbmon inject` on this Unix socket
+   #[arg(long, value_name = "FILE", env = "USBMON_CONTROL")]
+   control: Option<std::path::PathBuf>,
+
+   /// Keep this many recent events for control socket subscribers that
+   /// reconnect with `subscribe since=SEQ`
+   #[arg(long, value_name = "N", default_value_t = 1024, requires = "control")]
+   replay_buffer: usize,
+
+   /// Merge events from this source, labelled, as LABEL=SOURCE: local for
+   /// this host, native for it without usbip imports, usbip for only
+   /// those, debugfs or debugfs:BUS for what enumerates in the kernel's
+   /// usbmon text stream, or exec:COMMAND for the text output of another
+   /// watch, e.g. exec:'ssh host usbmon watch'. Repeatable; the first local source
+   /// covering a device reports it. Only local devices count for
+   /// --require-present and presence.
+   #[arg(long, value_name = "LABEL=SOURCE", value_parser = sources::parse_source)]
+   source: Vec<sources::Spec>,
+
+   /// Once the USB context is open, switch to this user, by name or uid
+   #[arg(long)]
+   user: Option<String>,
+
+   /// Once the USB context is open, switch to this group, by name or
+   /// gid; defaults to the --user's primary group
+   #[arg(long)]
+   group: Option<String>,
+
+   /// Confine the process with landlock and seccomp to USB device nodes,
+   /// sysfs and the files it was given; actions and plugins inherit this
+   #[cfg(feature = "sandbox")]
+   #[arg(long)]
+   sandbox: bool,
+
+   /// Write event lines to stdout (-), an inherited descriptor (fd:N), a
+   /// usbmon hub (tcp:HOST:PORT, or tls:HOST:PORT for one with
+   /// --tls-cert) or a file, flushing each line
+   #[arg(long, value_name = "TARGET", default_value = "-", env = "USBMON_OUTPUT", value_parser = output::parse_target)]
+   output: output::Target,
+
+   /// Report the same event of the same device only once within this
+   /// window, e.g. 200ms
+   #[arg(long, value_name = "DURATION", default_value = "0s", value_parser = parse_duration)]
+   coalesce: Duration,
+
+   /// Format of the --output events
+   #[arg(long, value_enum, default_value = "text", env = "USBMON_FORMAT")]
+   format: output::Format,
+
+   /// Also append every event line, timestamped, to this file
+   #[arg(long, value_name = "FILE")]
+   log_file: Option<std::path::PathBuf>,
+
+   /// Rotate the log file once it grows past this size, e.g. 10M
+   #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "log_file")]
+   log_max_size: Option<u64>,
+
+   /// Rotate the log file this often, e.g. 24h
+   #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "log_file")]
+   log_max_age: Option<Duration>,
+
+   /// How many rotated log files to keep
+   #[arg(long, value_name = "N", default_value = "5", requires = "log_file")]
+   log_keep: usize,
+
+   /// Compress rotated log files with gzip
+   #[arg(long, requires = "log_file")]
+   log_gzip: bool,
+
+   /// Check the certificate of a tls: hub against this PEM CA rather
+   /// than the system's
+   #[arg(long, value_name = "FILE", env = "USBMON_TLS_CA")]
+   tls_ca: Option<std::path::PathBuf>,
+
+   /// Present this PEM certificate to a tls: hub that asks for one
+   #[arg(long, value_name = "FILE", env = "USBMON_TLS_CERT", requires = "tls_key")]
+   tls_cert: Option<std::path::PathBuf>,
+
+   /// The PEM private key of --tls-cert
+   #[arg(long, value_name = "FILE", env = "USBMON_TLS_KEY", requires = "tls_cert")]
+   tls_key: Option<std::path::PathBuf>,
+
+   /// Authenticate to a hub with --tokens with this token, better given
+   /// in the environment than on the command line
+   #[arg(long, value_name = "TOKEN", env = "USBMON_HUB_TOKEN", hide_env_values = true)]
+   hub_token: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct CheckArgs {
+   #[command(flatten)]
+   filter: FilterArgs,
+
+   /// Watch for this long to count transitions
+   #[arg(long, default_value = "0s", value_parser = parse_duration)]
+   window: Duration,
+
+   /// Warn at this many transitions within the window
+   #[arg(long, value_name = "N")]
+   flap_warning: Option<usize>,
+
+   /// Go critical at this many transitions within the window
+   #[arg(long, value_name = "N")]
+   flap_critical: Option<usize>,
+}
+
+/// How failures are reported on stderr.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    /// A line of English
+    Text,
+    /// One JSON object with a stable code, e.g. timeout, permission_denied
+    /// or device_busy
+    Json,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReportArgs {
+   #[command(flatten)]
+   filter: FilterArgs,
+
+   /// Document format
+   #[arg(long, value_enum, default_value = "markdown")]
+   format: ReportFormat,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum HistoryFormat {
+    /// The time and the text of each event
+    Text,
+    /// The events as they were recorded
+    Json,
+}
+
+/// What history events are counted under.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum CountBy {
+    /// vid:pid
+    Device,
+    /// Kernel port path
+    Port,
+    /// attach, detach, flap and so on
+    Type,
+    /// The --source label
+    Source,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum TimeBucket {
+    Hour,
+    Day,
+}
+
+/// Which events of a history are looked at.
+#[derive(clap::Args, Debug)]
+struct HistoryQuery {
+   /// An event log written by watch --format json
+   // an Option, as history's own query is filled in even for export
+   #[arg(long, value_name = "FILE", env = "USBMON_HISTORY", required = true)]
+   file: Option<std::path::PathBuf>,
+
+   #[command(flatten)]
+   filter: FilterArgs,
+
+   /// Only events of these types, e.g. attach,detach
+   #[arg(long, value_delimiter = ',')]
+   r#type: Vec<String>,
+
+   /// Only events from this time on, e.g. 2024-01-31, 2024-01-31T08:00
+   /// or -24h
+   #[arg(long, value_name = "TIME", value_parser = parse_time, allow_hyphen_values = true)]
+   since: Option<String>,
+
+   /// Only events up to this time
+   #[arg(long, value_name = "TIME", value_parser = parse_time, allow_hyphen_values = true)]
+   until: Option<String>,
+
+   /// Only events the [rule NAME] section of --config fires on, by its
+   /// id, vid, pid and events keys as watch applies them
+   #[arg(long, value_name = "NAME", requires = "config")]
+   rule: Option<String>,
+
+   /// The config file --rule is in
+   #[arg(long, value_name = "FILE", env = "USBMON_CONFIG")]
+   config: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct HistoryArgs {
+   #[command(subcommand)]
+   command: Option<HistoryCommand>,
+
+   #[command(flatten)]
+   query: HistoryQuery,
+
+   /// Print how many events there were for each of these
+   #[arg(long, value_enum)]
+   count_by: Option<CountBy>,
+
+   /// Print how many events there were in each hour or day, or with
+   /// --count-by for each in each
+   #[arg(long, value_enum)]
+   group_by: Option<TimeBucket>,
+
+   /// Output format
+   #[arg(long, value_enum, default_value = "text")]
+   format: HistoryFormat,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum HistoryCommand {
+    /// Write the events as a table for analytics tools, one row per event
+    Export(Box<ExportArgs>),
+    /// Drop old events from the log, for logs that no [output] section
+    /// prunes as it writes them
+    Prune(PruneArgs),
+}
+
+#[derive(clap::Args, Debug)]
+#[command(group(clap::ArgGroup::new("retention").required(true).multiple(true)))]
+struct PruneArgs {
+   /// An event log written by watch --format json
+   #[arg(long, value_name = "FILE", env = "USBMON_HISTORY")]
+   file: std::path::PathBuf,
+
+   /// Drop the events older than this, e.g. 90d
+   #[arg(long, value_name = "DURATION", value_parser = parse_duration, group = "retention")]
+   max_age: Option<Duration>,
+
+   /// Keep no more than the newest N events
+   #[arg(long, value_name = "N", group = "retention")]
+   max_rows: Option<usize>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum ExportFormat {
+    /// Comma separated, with a header row
+    Csv,
+    /// Apache Parquet, string columns
+    Parquet,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+   #[command(flatten)]
+   query: HistoryQuery,
+
+   /// Table format
+   #[arg(long, value_enum, default_value = "csv")]
+   format: ExportFormat,
+
+   /// Write the table to FILE rather than stdout
+   #[arg(long, short = 'o', value_name = "FILE")]
+   output: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct HubArgs {
+   /// Take events from agents, watches with --output tcp:HOST:PORT and
+   /// --format json, on this address, e.g. 0.0.0.0:7010
+   #[arg(long, value_name = "ADDR", env = "USBMON_HUB_LISTEN")]
+   listen: String,
+
+   /// Stream the merged events to every client that connects here
+   #[arg(long, value_name = "ADDR", env = "USBMON_HUB_SERVE")]
+   serve: Option<String>,
+
+   /// Speak only TLS on both addresses, with this PEM certificate
+   #[arg(long, value_name = "FILE", env = "USBMON_TLS_CERT", requires = "tls_key")]
+   tls_cert: Option<std::path::PathBuf>,
+
+   /// The PEM private key of --tls-cert
+   #[arg(long, value_name = "FILE", env = "USBMON_TLS_KEY", requires = "tls_cert")]
+   tls_key: Option<std::path::PathBuf>,
+
+   /// Only take clients with a certificate this PEM CA signed
+   #[arg(long, value_name = "FILE", env = "USBMON_TLS_CA", requires = "tls_cert")]
+   tls_ca: Option<std::path::PathBuf>,
+
+   /// Only take clients that start with `auth TOKEN` for a token of this
+   /// file, one `TOKEN read|push[,...] [NAME]` per line: read for the
+   /// merged stream, push for agents, which are the host the token is
+   /// named for
+   #[arg(long, value_name = "FILE", env = "USBMON_HUB_TOKENS")]
+   tokens: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatusArgs {
+   #[command(flatten)]
+   filter: FilterArgs,
+
+   /// The state file of the watch
+   #[arg(long, value_name = "FILE", env = "USBMON_STATE")]
+   state: std::path::PathBuf,
+
+   /// Print times as seconds since the epoch, as the state file has them,
+   /// rather than how long ago they were
+   #[arg(long)]
+   raw: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct AutosuspendArgs {
+   #[command(flatten)]
+   filter: FilterArgs,
+
+   /// Keep the devices powered, writing "on" to power/control
+   #[arg(long, conflicts_with = "enable")]
+   disable: bool,
+
+   /// Let the kernel suspend the devices when idle, writing "auto"
+   #[arg(long)]
+   enable: bool,
+
+   /// How long a device has to be idle before it is suspended
+   #[arg(long, value_parser = parse_duration)]
+   delay: Option<Duration>,
+
+   /// Print a udev rule that applies the setting on every attach
+   /// instead of changing it now
+   #[arg(long, conflicts_with = "delay")]
+   udev_rule: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct WakeupArgs {
+   #[command(flatten)]
+   filter: FilterArgs,
+
+   /// Let the devices wake the machine
+   #[arg(long, conflicts_with = "disable")]
+   enable: bool,
+
+   /// Keep the devices from waking the machine
+   #[arg(long)]
+   disable: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffInventoryArgs {
+   /// The reference inventory, saved with list --format json
+   old: std::path::PathBuf,
+
+   /// The inventory to check against it
+   new: std::path::PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyAuditArgs {
+   /// The log written by the [audit] section of a watch config
+   path: std::path::PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct DoctorArgs {
+   /// Also check access and udev rules for these devices
+   #[command(flatten)]
+   filter: FilterArgs,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(group(clap::ArgGroup::new("event").required(true)))]
+struct InjectArgs {
+   /// Control socket of the running watch
+   #[arg(long, value_name = "FILE", env = "USBMON_CONTROL")]
+   control: std::path::PathBuf,
+
+   /// Pretend this device was attached
+   #[arg(long, value_name = "ID", value_parser = parse_device, group = "event")]
+   attach: Option<DeviceID>,
+
+   /// Pretend this device was detached
+   #[arg(long, value_name = "ID", value_parser = parse_device, group = "event")]
+   detach: Option<DeviceID>,
+
+   /// Bus and address to report for the device, e.g. 001:042
+   #[arg(long, value_name = "BUS:ADDRESS")]
+   at: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(group(clap::ArgGroup::new("vm").required(true)))]
+struct VmAttachArgs {
+   #[command(flatten)]
+   filter: FilterArgs,
+
+   /// libvirt domain to attach the device to; prints the hostdev XML
+   #[arg(long, group = "vm")]
+   domain: Option<String>,
+
+   /// QEMU monitor socket to attach the device through; prints the QMP
+   /// command
+   #[arg(long, value_name = "SOCKET", group = "vm")]
+   qmp: Option<std::path::PathBuf>,
+
+   /// Attach the device with virsh or over QMP instead of printing
+   #[arg(long)]
+   apply: bool,
+
+   /// Give up if the device hasn't shown up after this long
+   #[arg(long, value_name = "DURATION", env = "USBMON_TIMEOUT", value_parser = parse_duration)]
+   timeout: Option<Duration>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+   /// List connected devices
+   List(ListArgs),
+
+   /// Periodically print devices that were added (+) or removed (-)
+   Watch(Box<WatchArgs>),
+
+   /// Nagios/Icinga compatible presence check
+   Check(CheckArgs),
+
+   /// Print the events recorded in a JSON event log, optionally only
+   /// those of a time window
+   History(HistoryArgs),
+
+   /// Tell when devices were last connected, from the state file a watch
+   /// --state keeps, brought up to date with the devices there now
+   Status(StatusArgs),
+
+   /// Live dashboard of devices and recent events
+   Tui {
+      #[command(flatten)]
+      filter: FilterArgs,
+   },
+
+   /// Send a made up attach or detach event to a running watch, to try
+   /// out rules and sinks without touching hardware
+   Inject(InjectArgs),
+
+   /// List the processes that have a matching device's nodes open
+   WhoHas {
+      #[command(flatten)]
+      filter: FilterArgs,
+   },
+
+   /// Wait for a device and attach it to a libvirt or QEMU virtual machine
+   VmAttach(VmAttachArgs),
+
+   /// Show or change whether the kernel may autosuspend matching devices,
+   /// the usual fix for a device that keeps dropping off; exits 1 when a
+   /// setting couldn't be written
+   Autosuspend(AutosuspendArgs),
+
+   /// Show or change whether matching devices, such as keyboards and
+   /// their receivers, may wake the machine from sleep; exits 1 when a
+   /// setting couldn't be made
+   Wakeup(WakeupArgs),
+
+   /// Print an inventory of the connected devices with names, serials,
+   /// firmware revisions and topology, for audit documents
+   Report(ReportArgs),
+
+   /// Merge the event streams of watches on other machines, tagging
+   /// each event with its host
+   Hub(HubArgs),
+
+   /// Compare two inventories saved with list --format json, listing
+   /// devices added, removed or changed in port, serial or revision; exits
+   /// 1 when they differ
+   DiffInventory(DiffInventoryArgs),
+
+   /// Check the hash chain of an audit log, naming the first line that was
+   /// edited, added or removed; exits 1 when it is broken
+   VerifyAudit(VerifyAuditArgs),
+
+   /// Check the environment for common problems and suggest fixes
+   Doctor(DoctorArgs),
+
+   /// Exercise enumeration, opening devices and poll latency, for
+   /// validating a new machine
+   SelfTest,
+
+   /// Print a shell completion script; --id completes to connected
+   /// devices, the config's [aliases] and the devices the state file has
+   /// seen
+   Completions {
+      #[arg(value_enum, required_unless_present = "ids")]
+      shell: Option<completions::Shell>,
+
+      /// List what --id completes to, for the scripts
+      #[arg(long, hide = true, conflicts_with = "shell")]
+      ids: bool,
+
+      #[arg(long, hide = true, env = "USBMON_CONFIG")]
+      config: Option<std::path::PathBuf>,
+
+      #[arg(long, hide = true, env = "USBMON_STATE")]
+      state: Option<std::path::PathBuf>,
+   },
+
+   /// Print the manual page in roff, e.g. for usbmon.1
+   Man,
+}
+
 #[derive(Parser, Debug)]
-#[command(version, long_about = None)]
+#[command(version, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+   #[command(subcommand)]
+   command: Option<Command>,
+
    /// To watch for detach events
    #[arg(short, long)]
    detach: bool,
 
-   /// Device id, vid:pid
-   #[arg(short, long, num_args = 1.., value_parser=parse_device)]
-   id: Vec<DeviceID>,
+   #[command(flatten)]
+   filter: FilterArgs,
 
    /// Return immediately
    #[arg(short, long)]
    nowait: bool,
 
+   /// Wait for every --id rather than any one of them, showing which
+   /// are still outstanding on stderr
+   #[arg(long, requires = "id")]
+   all: bool,
+
+   /// Start by listing on stderr which --id are present, and where, and
+   /// which are missing, then wait for the missing ones as with --all,
+   /// or with --nowait fail if there are any
+   #[arg(long, requires = "id", conflicts_with_all = ["detach", "via_daemon"])]
+   expect_present: bool,
+
+   /// Give up after this long, naming the ids still outstanding
+   #[arg(long, value_name = "DURATION", env = "USBMON_TIMEOUT", value_parser = parse_duration)]
+   timeout: Option<Duration>,
+
+   /// Follow the events of the watch listening on this control socket
+   /// rather than opening USB, so many waits share one listener
+   #[arg(long, value_name = "FILE", env = "USBMON_VIA_DAEMON")]
+   via_daemon: Option<std::path::PathBuf>,
+
+   /// Follow the printed vid:pid with the filter that matched it, e.g.
+   /// id#2 for the second --id
+   #[arg(long)]
+   report_match: bool,
+
+   /// Show a spinner with the elapsed time and the filter on stderr
+   /// while waiting
+   #[arg(long, conflicts_with = "verbose")]
+   spinner: bool,
+
+   /// Print the JSON Schema of the JSON events and lists, then exit
+   #[arg(long)]
+   schema: bool,
+
+   /// Print the protobuf definition of the protobuf events and lists, then
+   /// exit
+   #[arg(long)]
+   proto: bool,
+
+   /// Try libusb calls that fail while a device is being replugged this
+   /// many more times before giving up
+   #[arg(long, value_name = "N", default_value_t = 3)]
+   retries: u32,
+
+   /// Wait this long before the first retry, twice as long before each
+   /// one after
+   #[arg(long, value_name = "DURATION", default_value = "50ms", value_parser = parse_duration)]
+   retry_backoff: Duration,
+
+   /// How failures are reported on stderr
+   #[arg(long, value_enum, default_value = "text", env = "USBMON_ERRORS")]
+   errors: ErrorFormat,
+
    /// Print out extra information
    #[arg(short, long)]
    verbose: bool,
+
+   /// Dump all descriptors of the attached device, like lsusb -v
+   #[arg(long)]
+   verbose_descriptors: bool,
+
+   /// Dump the raw bytes of the device, configuration and BOS descriptors
+   /// of the attached device, for firmware bug reports
+   #[arg(long)]
+   dump_hex: bool,
+
+   /// Identify attached mass-storage devices with a SCSI INQUIRY, or from
+   /// sysfs while the kernel driver has them
+   #[arg(long)]
+   inquiry: bool,
+
+   /// Only match devices that have enumerated in DFU mode
+   #[arg(long)]
+   wait_dfu: bool,
+
+   /// Only match smart card readers with a card inserted
+   #[arg(long)]
+   wait_card: bool,
+
+   /// Wait until hotplug events have stopped arriving for this long
+   /// before judging the outcome, e.g. 200ms
+   #[arg(long, value_name = "DURATION", default_value = "0s", value_parser = parse_duration)]
+   coalesce: Duration,
+
+   /// Hotplug events held while the main loop is busy
+   #[arg(long, value_name = "N", default_value_t = 64)]
+   queue_size: usize,
+
+   /// What to do with hotplug events arriving at a full queue
+   #[arg(long, value_enum, default_value = "drop-oldest")]
+   overflow: queue::Overflow,
+
+   /// After attach, wait until udev has created the device nodes and
+   /// applied their permissions
+   #[arg(long)]
+   settle: bool,
+
+   /// After attach, wait until this kernel driver has bound to one of
+   /// the interfaces, and fail if it doesn't
+   #[arg(long, value_name = "NAME")]
+   wait_driver: Option<String>,
+
+   /// Print the kernel driver of each interface, - for unclaimed ones
+   #[arg(long)]
+   drivers: bool,
+
+   /// After attach, wait until a filesystem of the storage device is
+   /// mounted and print the device node and mountpoint
+   #[arg(long)]
+   wait_mount: bool,
+
+   /// Mount the storage device with udisksctl instead of waiting for
+   /// something else to
+   #[arg(long, requires = "wait_mount")]
+   mount: bool,
+
+   /// Print the network interfaces a USB network adapter created
+   #[arg(long)]
+   netif: bool,
+
+   /// Wait until those interfaces are up before printing them
+   #[arg(long, requires = "netif")]
+   wait_link_up: bool,
+
+   /// Print the ALSA cards of a USB audio device as hw:N and card id
+   #[arg(long)]
+   alsa: bool,
+
+   /// Print the docker run --device= argument that passes the device into
+   /// a container
+   #[arg(long)]
+   print_docker_flag: bool,
+
+   /// Print the device instance id Windows uses, for devcon and pnputil
+   #[arg(long)]
+   instance_id: bool,
+
+   /// Print the macOS location id and, where ioreg is there, the IOUSB
+   /// registry path
+   #[arg(long)]
+   location_id: bool,
+
+   /// Print the /dev/videoN nodes of a camera, telling capture and
+   /// metadata nodes apart
+   #[arg(long)]
+   video: bool,
+}
+
+/// Exits with a usage error unless the arguments select some devices.
+fn require_filter(args: &FilterArgs) {
+    if Filter::new(args).is_empty() {
+        Args::command()
+            .error(clap::error::ErrorKind::MissingRequiredArgument, "--id, --vid, --pid or --class is required")
+            .exit();
+    }
 }
 
-fn main() -> rusb::Result<()> {
-    let args = Args::parse();
+fn main() {
+    let mut args = Args::parse();
+    args.all |= args.expect_present;
+    errors::configure(args.errors);
+    if let Err(e) = run(args) {
+        errors::exit(&e);
+    }
+}
+
+fn run(args: Args) -> rusb::Result<()> {
+
+    if args.schema {
+        print!("{}", output::SCHEMA);
+        return Ok(())
+    }
+    if args.proto {
+        print!("{}", protobuf::PROTO);
+        return Ok(())
+    }
+
+    match &args.command {
+        Some(Command::List(list)) => match &list.via_daemon {
+            Some(path) => return client::run_list(path, list),
+            None => return list::run(list),
+        },
+        Some(Command::Watch(watch)) => {
+            match watch::run(watch)? {
+                watch::Outcome::Stopped{healthy: true} => return Ok(()),
+                watch::Outcome::Stopped{healthy: false} => std::process::exit(1),
+                watch::Outcome::Absent => std::process::exit(2),
+            }
+        },
+        Some(Command::Check(check)) => std::process::exit(check::run(check) as i32),
+        Some(Command::Tui{filter}) => return tui::run(Filter::new(filter)),
+        Some(Command::Inject(inject)) => {
+            let (event, id) = match (&inject.attach, &inject.detach) {
+                (Some(id), _) => ("attach", id),
+                (_, Some(id)) => ("detach", id),
+                _ => unreachable!("clap requires one of them"),
+            };
+            let mut request = format!("inject {} {:04x}:{:04x}", event, id.vid, id.pid);
+            if let Some(at) = &inject.at {
+                request.push_str(&format!(" {}", at));
+            }
+            match control::send(&inject.control, "inject", &request) {
+                Ok(Ok(_)) => return Ok(()),
+                Ok(Err(e)) => eprintln!("{}: {}", inject.control.display(), e),
+                Err(e) => eprintln!("{}: {}", inject.control.display(), e),
+            }
+            std::process::exit(1);
+        },
+        Some(Command::WhoHas{filter}) => {
+            require_filter(filter);
+            std::process::exit(if whohas::run(filter)? { 0 } else { 1 })
+        },
+        Some(Command::VmAttach(vm)) => {
+            require_filter(&vm.filter);
+            std::process::exit(if vm::run(vm)? { 0 } else { 1 })
+        },
+        Some(Command::Autosuspend(autosuspend)) => {
+            require_filter(&autosuspend.filter);
+            std::process::exit(if autosuspend::run(autosuspend)? { 0 } else { 1 })
+        },
+        Some(Command::History(history)) => {
+            if let Err((file, e)) = history::run(history) {
+                eprintln!("{}: {}", file.display(), e);
+                std::process::exit(1);
+            }
+            return Ok(())
+        },
+        Some(Command::Status(status)) => return status::run(status),
+        Some(Command::Wakeup(wakeup)) => {
+            require_filter(&wakeup.filter);
+            std::process::exit(if wakeup::run(wakeup)? { 0 } else { 1 })
+        },
+        Some(Command::Report(report)) => return report::run(report),
+        Some(Command::Hub(hub)) => {
+            if let Err(e) = hub::run(hub) {
+                eprintln!("hub: {}", e);
+                std::process::exit(1);
+            }
+            return Ok(())
+        },
+        Some(Command::DiffInventory(diff)) => match diffinv::run(diff) {
+            Ok(same) => std::process::exit(if same { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            },
+        },
+        Some(Command::VerifyAudit(verify)) => match audit::run(verify) {
+            Ok(intact) => std::process::exit(if intact { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            },
+        },
+        Some(Command::Doctor(doctor)) => std::process::exit(if doctor::run(doctor) { 0 } else { 1 }),
+        Some(Command::SelfTest) => std::process::exit(if selftest::run() { 0 } else { 1 }),
+        Some(Command::Man) => {
+            print!("{}", man::generate(&mut Args::command()));
+            return Ok(())
+        },
+        Some(Command::Completions{shell, config, state, ..}) => {
+            match shell {
+                Some(shell) => print!("{}", completions::generate(*shell, &mut Args::command())),
+                None => completions::print_ids(config.as_deref(), state.as_deref()),
+            }
+            return Ok(())
+        },
+        None => {},
+    }
+
+    require_filter(&args.filter);
+    retry::configure(retry::Policy{retries: args.retries, backoff: args.retry_backoff});
+    let mut filter = Filter::new(&args.filter);
+    filter.dfu = args.wait_dfu;
+    filter.card = args.wait_card;
+
+    if let Some(path) = &args.via_daemon {
+        return client::run_wait(path, &filter, &args)
+    }
 
     // check if device is already connected
 
     if args.verbose {
         let op = if args.detach { "detach" } else { "attach" };
-        eprintln!("Waiting for {} to {}...", iterable_to_str(args.id.iter()), op);
+        eprintln!("Waiting for {} to {}...", iterable_to_str(args.filter.id.iter()), op);
     }
 
     let attach = !args.detach;
 
-    let connected = is_connected(rusb::devices(), &args.id);
-    if connected.is_some()  ^ !attach {
+    if args.verbose {
+        // later scans treat a failure as no device, so it is explained once here
+        if let Err(e) = retry::devices() {
+            diagnose::report::<rusb::GlobalContext>("enumerating devices", None, &e);
+        }
+        print_capabilities(retry::devices(), &args.filter.id);
+    }
+
+    let deadline = args.timeout.map(|timeout| Instant::now() + timeout);
+    let mut progress = if args.all { Some(progress::Progress::new(&filter.ids)) } else { None };
+
+    if args.expect_present {
+        report_presence(retry::devices(), &filter);
+    }
+    // the device may be there for Windows yet missing for libusb
+    if attach {
+        check_driver(&filter);
+    }
+    let connected = is_connected(retry::devices(), &filter);
+    if is_done(retry::devices(), &connected, &filter, attach, &mut progress) {
         if let Some(id) = connected {
-            println!("{}", id);
+            print_ids(&id, &filter, &args);
+            print_details(retry::devices(), &filter, &args);
         }
         return Ok(())
     }
+    if attach {
+        check_speed(retry::devices(), &filter);
+    }
 
     if args.nowait {
         return Err(rusb::Error::NoDevice)
     }
 
+    let mut spinner = args.spinner.then(|| progress::Spinner::new(summary(&args)));
+
+    // card insertion isn't a USB event, so readers have to be polled
+    if args.wait_card {
+        loop {
+            thread::sleep(CARD_POLL_INTERVAL);
+            if let Some(spinner) = spinner.as_mut() {
+                spinner.tick();
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                drop(spinner.take());
+                return Err(timed_out(retry::devices(), &filter, attach))
+            }
+            let connected = is_connected(retry::devices(), &filter);
+            if is_done(retry::devices(), &connected, &filter, attach, &mut progress) {
+                drop(spinner.take());
+                if let Some(id) = connected {
+                    print_ids(&id, &filter, &args);
+                    print_details(retry::devices(), &filter, &args);
+                }
+                return Ok(())
+            }
+        }
+    }
+
     if args.verbose {
         eprintln!("Waiting for USB events...");
     }
@@ -156,32 +1759,67 @@ fn main() -> rusb::Result<()> {
     // wait for device to be attached or detached
 
     if rusb::has_hotplug() {
-        let ctx = rusb::Context::new()?;
-        let (tx, rx) = mpsc::channel::<rusb::Device<rusb::Context>>();
+        let ctx = retry::run(rusb::Context::new).inspect_err(|e| if args.verbose {
+            diagnose::report::<rusb::Context>("creating a libusb context", None, e);
+        })?;
+        let queue = Arc::new(queue::Queue::new(args.queue_size, args.overflow));
         let mut reg = Some(
             rusb::HotplugBuilder::new()
                 .enumerate(false)                                
-                .register(&ctx, Box::new(HotPlugHandler{sender: tx}))?,
+                .register(&ctx, Box::new(HotPlugHandler{queue: queue.clone()}))?,
         );
 
+        // callbacks run where events are handled, so that happens on its
+        // own thread for a blocked queue to hold back only the callbacks
+        let events = ctx.clone();
+        thread::spawn(move || loop {
+            _ = events.handle_events(None);
+        });
+
         loop {
             if args.verbose {
                 eprintln!("Loop...");
             }
-            ctx.handle_events(None).unwrap();
-            let dev = rx.recv().unwrap();
-            let desc = dev.device_descriptor().unwrap();
-            let connected = is_connected(ctx.devices(), &args.id);
+            let dev = match next_event(&queue, deadline, &mut spinner) {
+                Some(dev) => dev,
+                None => {
+                    drop(spinner.take());
+                    ctx.unregister_callback(reg.take().unwrap());
+                    return Err(timed_out(retry::run(|| ctx.devices()), &filter, attach))
+                },
+            };
+            // let repeated callbacks for the same transition arrive and
+            // judge the state they leave behind
+            if !args.coalesce.is_zero() {
+                while queue.pop_timeout(args.coalesce).is_some() {}
+            }
+            let dropped = queue.take_dropped();
+            if dropped > 0 && (args.verbose || args.overflow == queue::Overflow::CountAndReport) {
+                eprintln!("Dropped {} hotplug event(s), queue full", dropped);
+            }
+            // gone again before it could be looked at, as happens mid-replug
+            let desc = match retry::run(|| dev.device_descriptor()) {
+                Ok(desc) => desc,
+                Err(_) => continue,
+            };
+            let connected = is_connected(retry::run(|| ctx.devices()), &filter);
             if args.verbose {
                 eprintln!("Event from {:x}:{:x}, connected: {:?}", 
                     desc.vendor_id(), desc.product_id(), connected);
+                print_capabilities(retry::run(|| ctx.devices()), &args.filter.id);
             }
-            if connected.is_some() ^ !attach {
+            if is_done(retry::run(|| ctx.devices()), &connected, &filter, attach, &mut progress) {
                 if let Some(reg) = reg.take() {
+                    drop(spinner.take());
                     ctx.unregister_callback(reg);
-                    println!("{:x}:{:x}", desc.vendor_id(), desc.product_id());
+                    print_ids(&DeviceID{vid: desc.vendor_id(), pid: desc.product_id()}, &filter, &args);
+                    if attach {
+                        print_details(retry::run(|| ctx.devices()), &filter, &args);
+                    }
                     break;
                 }
+            } else if attach {
+                check_speed(retry::run(|| ctx.devices()), &filter);
             }
         }
         Ok(())
@@ -190,3 +1828,49 @@ fn main() -> rusb::Result<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_units() {
+        let parse = |arg| parse_duration(arg).ok();
+        assert_eq!(parse("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse("2"), Some(Duration::from_secs(2)));
+        assert_eq!(parse("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse("7d"), Some(Duration::from_secs(7 * 86400)));
+        assert_eq!(parse("0s"), Some(Duration::ZERO));
+        assert_eq!(parse(&format!("{}", u64::MAX)), Some(Duration::from_secs(u64::MAX)));
+    }
+
+    #[test]
+    fn durations_refuse_what_they_cant_read() {
+        for arg in ["", "s", "-5s", "1.5h", "5 m", "5M", "2w", "1h30m", "99999999999999999999", "999999999999999999d"] {
+            assert!(parse_duration(arg).is_err(), "{:?}", arg);
+        }
+    }
+
+    #[test]
+    fn times_fill_in_what_is_left_out() {
+        let parse = |arg| parse_time(arg).ok();
+        assert_eq!(parse("2024-01-31").as_deref(), Some("2024-01-31T00:00:00"));
+        assert_eq!(parse("2024-01-31T08:30").as_deref(), Some("2024-01-31T08:30:00"));
+        assert_eq!(parse("2024-01-31 08:30:15").as_deref(), Some("2024-01-31T08:30:15"));
+        for arg in ["2024-1-31", "2024-01-31T8:30", "2024-01-31T08", "31/01/2024", "yesterday", "-", "-2x", "", "-18446744073709551615s"] {
+            assert!(parse_time(arg).is_err(), "{:?}", arg);
+        }
+    }
+
+    #[test]
+    fn times_back_from_now() {
+        // SAFETY: time only reads the clock
+        let now = unsafe { libc::time(std::ptr::null_mut()) };
+        let day = parse_time("-24h").unwrap();
+        // the clock may tick between the two reads
+        assert!([now - 86400, now - 86399].map(logfile::local_time).contains(&day), "{}", day);
+        assert!(parse_time("now").unwrap() >= logfile::local_time(now));
+    }
+}
@@ -0,0 +1,159 @@
+use clap::{Arg, Command};
+
+const SUMMARY: &str = "wait for USB devices to attach or detach, and watch them";
+
+/// Escapes text for roff, including a leading dot or quote that would
+/// otherwise start a request.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+/// Help text ends in a full stop so the notes after it read as sentences.
+fn sentence(text: &str) -> String {
+    let text = text.trim_end();
+    if text.ends_with(['.', '!', '?']) {
+        escape(text)
+    } else {
+        format!("{}.", escape(text))
+    }
+}
+
+fn usage(arg: &Arg) -> String {
+    let mut names = Vec::new();
+    if let Some(short) = arg.get_short() {
+        names.push(format!("\\fB\\-{}\\fR", short));
+    }
+    if let Some(long) = arg.get_long() {
+        names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    let mut usage = names.join(", ");
+    if arg.get_action().takes_values() {
+        let value = match arg.get_value_names() {
+            Some(names) => names.iter().map(|name| name.to_string()).collect::<Vec<_>>().join(" "),
+            None => arg.get_id().to_string().to_uppercase(),
+        };
+        usage.push_str(&format!(" \\fI{}\\fR", escape(&value)));
+    }
+    usage
+}
+
+fn options(cmd: &Command) -> String {
+    let mut text = String::new();
+    for arg in cmd.get_arguments().filter(|arg| !arg.is_positional() && !arg.is_hide_set()) {
+        text.push_str(&format!(".TP\n{}\n", usage(arg)));
+        if let Some(help) = arg.get_long_help().or(arg.get_help()) {
+            text.push_str(&format!("{}\n", sentence(&help.to_string())));
+        }
+        let values: Vec<String> = arg
+            .get_possible_values()
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| value.get_name().to_string())
+            .collect();
+        if !values.is_empty() {
+            text.push_str(&format!("One of: {}.\n", escape(&values.join(", "))));
+        }
+        if let Some(env) = arg.get_env() {
+            text.push_str(&format!("Also read from \\fB{}\\fR.\n", escape(&env.to_string_lossy())));
+        }
+        let defaults: Vec<String> = arg.get_default_values().iter().map(|v| v.to_string_lossy().to_string()).collect();
+        if !defaults.is_empty() && arg.get_action().takes_values() {
+            text.push_str(&format!("Defaults to {}.\n", escape(&defaults.join(", "))));
+        }
+    }
+    text
+}
+
+/// A section for every subcommand of cmd, and after each the sections of
+/// its own subcommands, headed by the words that run them.
+fn commands(page: &mut String, path: &str, cmd: &Command) {
+    for sub in cmd.get_subcommands().filter(|sub| sub.get_name() != "help" && !sub.is_hide_set()) {
+        let path = format!("{} {}", path, sub.get_name());
+        page.push_str(&format!(".SS {}\n", path));
+        if let Some(about) = sub.get_about() {
+            page.push_str(&format!("{}\n", sentence(&about.to_string())));
+        }
+        for positional in sub.get_positionals() {
+            let values: Vec<String> = positional.get_possible_values().iter().map(|v| v.get_name().to_string()).collect();
+            page.push_str(&format!(".TP\n\\fI{}\\fR\n", escape(&positional.get_id().to_string().to_uppercase())));
+            if !values.is_empty() {
+                page.push_str(&format!("One of: {}.\n", escape(&values.join(", "))));
+            }
+        }
+        page.push_str(&options(sub));
+        commands(page, &path, sub);
+    }
+}
+
+/// A roff manual page covering every subcommand and option.
+pub fn generate(cmd: &mut Command) -> String {
+    cmd.build();
+    let name = cmd.get_name().to_string();
+    let version = cmd.get_version().unwrap_or_default().to_string();
+    let mut page = format!(".TH {} 1 \"\" \"{} {}\"\n", name.to_uppercase(), name, version);
+    page.push_str(&format!(".SH NAME\n{} \\- {}\n", name, SUMMARY));
+    page.push_str(&format!(".SH SYNOPSIS\n\\fB{}\\fR [\\fIOPTIONS\\fR]\n.br\n\\fB{}\\fR \\fICOMMAND\\fR [\\fIOPTIONS\\fR]\n", name, name));
+    page.push_str(".SH DESCRIPTION\nWithout a command, waits until a device matching \\fB\\-\\-id\\fR or \\fB\\-\\-class\\fR \
+        is attached, or with \\fB\\-\\-detach\\fR detached, prints its vid:pid and exits. \
+        It exits with status 0 at once if the device is already in that state.\n");
+    page.push_str(&format!(".SH OPTIONS\n{}", options(cmd)));
+    page.push_str(".SH COMMANDS\n");
+    commands(&mut page, &name, cmd);
+    page.push_str(".SH EXIT STATUS\n0 once the device is in the awaited state, 1 on errors and timeouts. \
+        \\fBwatch\\fR exits with 2 when a required device stays absent, \\fBcheck\\fR with the Nagios status.\n");
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    /// The commands under cmd with the words that name them, depth first.
+    fn commands(cmd: &Command, path: &str, all: &mut Vec<(String, Command)>) {
+        for sub in cmd.get_subcommands().filter(|sub| sub.get_name() != "help" && !sub.is_hide_set()) {
+            let path = format!("{} {}", path, sub.get_name());
+            all.push((path.clone(), sub.clone()));
+            commands(sub, &path, all);
+        }
+    }
+
+    #[test]
+    fn page_has_every_command_and_option() {
+        let mut cmd = crate::Args::command();
+        let page = generate(&mut cmd);
+        let mut all = vec![(String::from("usbmon"), cmd.clone())];
+        commands(&cmd, "usbmon", &mut all);
+        let mut missing = Vec::new();
+        for (path, sub) in &all {
+            let section = if path == "usbmon" {
+                page.split(".SH COMMANDS\n").next().unwrap().to_string()
+            } else {
+                let heading = format!(".SS {}\n", path);
+                match page.split_once(&heading) {
+                    Some((_, rest)) => rest.split(".SS ").next().unwrap().to_string(),
+                    None => {
+                        missing.push(path.clone());
+                        continue
+                    },
+                }
+            };
+            for arg in sub.get_arguments().filter(|arg| !arg.is_hide_set() && arg.get_id() != "help" && arg.get_id() != "version") {
+                let usage = match arg.get_long() {
+                    Some(long) => format!("\\fB\\-\\-{}\\fR", escape(long)),
+                    None if arg.is_positional() => format!("\\fI{}\\fR", escape(&arg.get_id().to_string().to_uppercase())),
+                    None => format!("\\fB\\-{}\\fR", arg.get_short().unwrap()),
+                };
+                if !section.contains(&usage) {
+                    missing.push(format!("{} {}", path, usage));
+                }
+            }
+        }
+        assert!(missing.is_empty(), "not in the page: {:?}", missing);
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use rusb::UsbContext;
+
+use crate::inventory;
+use crate::{ccid, dfu, Class, DeviceID, Filter, Speed};
+
+/// A check that needs more of the device than its ids.
+enum Predicate {
+    All(Vec<Predicate>),
+    Any(Vec<Predicate>),
+    Class(Class),
+    Speed(Vec<Speed>),
+    /// At least this many mA
+    MaxPower(u16),
+    DfuMode,
+    CardPresent,
+}
+
+impl Predicate {
+    fn eval<T: UsbContext>(&self, dev: &rusb::Device<T>) -> bool {
+        match self {
+            Predicate::All(all) => all.iter().all(|p| p.eval(dev)),
+            Predicate::Any(any) => any.iter().any(|p| p.eval(dev)),
+            Predicate::Class(class) => class.matches(dev),
+            Predicate::Speed(speeds) => Speed::of(dev.speed()).is_some_and(|speed| speeds.contains(&speed)),
+            Predicate::MaxPower(least) => inventory::max_power(dev).is_some_and(|ma| ma >= *least),
+            // the runtime and DFU personalities often share a vid:pid
+            Predicate::DfuMode => dfu::mode(dev) == Some(dfu::Mode::Dfu),
+            Predicate::CardPresent => ccid::card_present(dev).unwrap_or(false),
+        }
+    }
+}
+
+/// A filter compiled once and then run against every device of a scan or
+/// event: a hash lookup for the ids, then the remaining checks from
+/// cheapest to dearest, so opening a reader to look for a card only
+/// happens for devices that passed everything else.
+pub struct Matcher {
+    /// Each with its 1-based position on the command line
+    ids: HashMap<DeviceID, usize>,
+    vids: HashMap<u16, usize>,
+    pids: HashMap<u16, usize>,
+    predicate: Predicate,
+    needs_device: bool,
+}
+
+impl Matcher {
+    pub fn new(filter: &Filter) -> Matcher {
+        let mut all = Vec::new();
+        if !filter.classes.is_empty() {
+            all.push(Predicate::Any(filter.classes.iter().map(|class| Predicate::Class(*class)).collect()));
+        }
+        if !filter.speeds.is_empty() {
+            all.push(Predicate::Speed(filter.speeds.clone()));
+        }
+        if let Some(least) = filter.max_power {
+            all.push(Predicate::MaxPower(least));
+        }
+        if filter.dfu {
+            all.push(Predicate::DfuMode);
+        }
+        if filter.card {
+            all.push(Predicate::CardPresent);
+        }
+        Matcher{
+            ids: filter.ids.iter().cloned().zip(1..).collect(),
+            vids: filter.vids.iter().copied().zip(1..).collect(),
+            pids: filter.pids.iter().copied().zip(1..).collect(),
+            needs_device: !all.is_empty(),
+            predicate: Predicate::All(all),
+        }
+    }
+
+    /// Whether the ids alone allow the device: one of the --id, or the
+    /// --vid and --pid given, each matching any counterpart when alone.
+    pub fn matches_id(&self, id: &DeviceID) -> bool {
+        let pattern = !self.vids.is_empty() || !self.pids.is_empty();
+        if self.ids.is_empty() && !pattern {
+            return true
+        }
+        self.ids.contains_key(id)
+            || pattern
+                && (self.vids.is_empty() || self.vids.contains_key(&id.vid))
+                && (self.pids.is_empty() || self.pids.contains_key(&id.pid))
+    }
+
+    /// Which filter let the id through, e.g. `id#2` for the second --id,
+    /// `vid#1,pid#3` for a --vid and --pid pair, or `class` when only
+    /// classes were given.
+    pub fn label(&self, id: &DeviceID) -> Option<String> {
+        if !self.matches_id(id) {
+            return None
+        }
+        if let Some(n) = self.ids.get(id) {
+            return Some(format!("id#{}", n))
+        }
+        let parts: Vec<String> = self.vids.get(&id.vid).map(|n| format!("vid#{}", n))
+            .into_iter()
+            .chain(self.pids.get(&id.pid).map(|n| format!("pid#{}", n)))
+            .collect();
+        Some(if parts.is_empty() { String::from("class") } else { parts.join(",") })
+    }
+
+    /// Whether anything beyond the ids is checked, which can only be done
+    /// while the device is there.
+    pub fn needs_device(&self) -> bool {
+        self.needs_device
+    }
+
+    pub fn matches<T: UsbContext>(&self, dev: &rusb::Device<T>) -> bool {
+        let desc = match dev.device_descriptor() {
+            Ok(desc) => desc,
+            Err(_) => return false,
+        };
+        self.matches_id(&DeviceID{vid: desc.vendor_id(), pid: desc.product_id()}) && self.predicate.eval(dev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(text: &str) -> DeviceID {
+        crate::parse_device(text).unwrap()
+    }
+
+    #[test]
+    fn empty_filter_allows_any_id() {
+        let matcher = Matcher::new(&Filter::default());
+        assert!(matcher.matches_id(&id("1d50:6018")));
+        assert!(!matcher.needs_device());
+    }
+
+    #[test]
+    fn ids_and_patterns() {
+        let filter = Filter{ids: vec![id("1d50:6018"), id("483:df11")], vids: vec![0x046d], ..Default::default()};
+        let matcher = Matcher::new(&filter);
+        assert!(matcher.matches_id(&id("483:df11")));
+        assert!(matcher.matches_id(&id("46d:c52b")));
+        assert!(!matcher.matches_id(&id("483:5740")));
+        assert_eq!(matcher.label(&id("483:df11")).as_deref(), Some("id#2"));
+        assert_eq!(matcher.label(&id("46d:c52b")).as_deref(), Some("vid#1"));
+        assert_eq!(matcher.label(&id("483:5740")), None);
+
+        // a vid and pid together only allow that product
+        let filter = Filter{vids: vec![0x0483, 0x046d], pids: vec![0xdf11], ..Default::default()};
+        let matcher = Matcher::new(&filter);
+        assert!(matcher.matches_id(&id("46d:df11")));
+        assert!(!matcher.matches_id(&id("483:5740")));
+        assert_eq!(matcher.label(&id("46d:df11")).as_deref(), Some("vid#2,pid#1"));
+
+        let matcher = Matcher::new(&Filter{pids: vec![0x6018], ..Default::default()});
+        assert!(matcher.matches_id(&id("1d50:6018")) && matcher.matches_id(&id("ffff:6018")));
+        assert!(!matcher.matches_id(&id("1d50:6017")));
+    }
+
+    #[test]
+    fn classes_need_the_device() {
+        let matcher = Matcher::new(&Filter{classes: vec![Class::Hid], ..Default::default()});
+        assert!(matcher.needs_device());
+        assert!(matcher.matches_id(&id("1d50:6018")));
+        assert_eq!(matcher.label(&id("1d50:6018")).as_deref(), Some("class"));
+        assert!(Matcher::new(&Filter{max_power: Some(500), ..Default::default()}).needs_device());
+        assert!(Matcher::new(&Filter{dfu: true, ..Default::default()}).needs_device());
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use rusb::UsbContext;
+
+use crate::sysfs;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Undoes the octal escapes /proc/self/mounts uses for blanks.
+fn unescape(field: &str) -> String {
+    field.replace("\\040", " ").replace("\\011", "\t").replace("\\012", "\n").replace("\\134", "\\")
+}
+
+/// Mountpoints of the given block devices, as (device node, mountpoint).
+fn mountpoints(blocks: &[String]) -> Vec<(String, String)> {
+    let mounts = fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            Some((fields.next()?, unescape(fields.next()?)))
+        })
+        .filter(|(source, _)| blocks.iter().any(|b| source.strip_prefix("/dev/") == Some(b)))
+        .map(|(source, target)| (source.to_string(), target))
+        .collect()
+}
+
+/// What can carry a filesystem: the partitions, or the whole disk if it
+/// has none.
+fn volumes(blocks: &[String]) -> Vec<String> {
+    let partitions: Vec<String> = blocks.iter().filter(|b| sysfs::is_partition(b)).cloned().collect();
+    if partitions.is_empty() {
+        blocks.to_vec()
+    } else {
+        partitions
+    }
+}
+
+/// Blocks until a filesystem of the storage device is mounted and returns
+/// the mountpoints. With automount, each volume is handed to udisks once
+/// as soon as it shows up, for hosts without a desktop automounter.
+pub fn wait<T: UsbContext>(device: &rusb::Device<T>, automount: bool) -> Vec<(String, String)> {
+    let mut tried: Vec<String> = Vec::new();
+    loop {
+        let blocks = sysfs::block_devices(device);
+        let mounted = mountpoints(&blocks);
+        if !mounted.is_empty() {
+            return mounted
+        }
+        if automount {
+            let fresh: Vec<String> = volumes(&blocks).into_iter().filter(|v| !tried.contains(v)).collect();
+            for volume in fresh {
+                let status = Command::new("udisksctl")
+                    .args(["mount", "--no-user-interaction", "-b"])
+                    .arg(format!("/dev/{}", volume))
+                    .stdout(Stdio::null())
+                    .status();
+                if let Err(e) = status {
+                    eprintln!("failed to run udisksctl: {}", e);
+                }
+                tried.push(volume);
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
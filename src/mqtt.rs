@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::config::Section;
+use crate::inventory::Entry;
+use crate::json;
+use crate::template;
+use crate::DeviceID;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Publishes device presence to an MQTT broker, configured by the `[mqtt]`
+/// section:
+///
+/// ```text
+/// [mqtt]
+/// server = broker:1883
+/// topic = usbmon/lab1
+/// username = usbmon
+/// password = secret
+/// discovery = true
+/// discovery_prefix = homeassistant
+/// ```
+///
+/// Each device's state goes to `<topic>/<vvvv_pppp>` as a retained `ON`
+/// or `OFF`, and `<topic>/status` says whether usbmon itself is online.
+/// With discovery on, a Home Assistant config payload announces every
+/// device as a connectivity binary sensor the first time it is seen.
+pub struct Publisher {
+    server: String,
+    topic: String,
+    username: Option<String>,
+    password: Option<String>,
+    discovery: Option<String>,
+    stream: Option<TcpStream>,
+    announced: HashSet<DeviceID>,
+}
+
+fn key(id: &DeviceID) -> String {
+    format!("{:04x}_{:04x}", id.vid, id.pid)
+}
+
+fn string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Frames a packet: control byte, variable length remaining length, body.
+fn packet(control: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![control];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+impl Publisher {
+    pub fn new(section: &Section) -> io::Result<Publisher> {
+        let host = template::hostname();
+        let discovery = match section.get("discovery") {
+            Some("true") | Some("yes") | Some("1") => Some(section.get("discovery_prefix").unwrap_or("homeassistant").to_string()),
+            _ => None,
+        };
+        Ok(Publisher{
+            server: section.require("server")?.to_string(),
+            topic: section.get("topic").map_or(format!("usbmon/{}", host), String::from),
+            username: section.get("username").map(String::from),
+            password: section.get("password").map(String::from),
+            discovery,
+            stream: None,
+            announced: HashSet::new(),
+        })
+    }
+
+    fn status_topic(&self) -> String {
+        format!("{}/status", self.topic)
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let addr = self.server.to_socket_addrs()?.next().ok_or(io::ErrorKind::NotFound)?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut body = Vec::new();
+        string(&mut body, "MQTT");
+        body.push(4);
+        // clean session, retained "offline" will
+        let mut flags = 0x02 | 0x04 | 0x20;
+        if self.username.is_some() {
+            flags |= 0x80;
+        }
+        if self.password.is_some() {
+            flags |= 0x40;
+        }
+        body.push(flags);
+        // no keep alive, the broker won't expect pings
+        body.extend_from_slice(&[0, 0]);
+        string(&mut body, &format!("usbmon-{}-{}", template::hostname(), std::process::id()));
+        string(&mut body, &self.status_topic());
+        string(&mut body, "offline");
+        if let Some(username) = &self.username {
+            string(&mut body, username);
+        }
+        if let Some(password) = &self.password {
+            string(&mut body, password);
+        }
+        stream.write_all(&packet(0x10, &body))?;
+
+        let mut connack = [0u8; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[3] != 0 {
+            return Err(io::Error::other(format!("connection refused, code {}", connack[3])))
+        }
+        let mut online = Vec::new();
+        string(&mut online, &self.status_topic());
+        online.extend_from_slice(b"online");
+        stream.write_all(&packet(0x31, &online))?;
+        Ok(stream)
+    }
+
+    /// Publishes a retained QoS 0 message, reconnecting if the previous
+    /// connection broke. A new connection announces every device again.
+    fn publish(&mut self, topic: &str, payload: &str) {
+        if self.stream.is_none() {
+            match self.connect() {
+                Ok(stream) => {
+                    self.stream = Some(stream);
+                    self.announced.clear();
+                },
+                Err(e) => {
+                    eprintln!("mqtt: {}: {}", self.server, e);
+                    return
+                },
+            }
+        }
+        let mut body = Vec::new();
+        string(&mut body, topic);
+        body.extend_from_slice(payload.as_bytes());
+        if let Some(Err(e)) = self.stream.as_mut().map(|s| s.write_all(&packet(0x31, &body))) {
+            eprintln!("mqtt: {}: {}", self.server, e);
+            self.stream = None;
+        }
+    }
+
+    fn announce(&mut self, id: &DeviceID, name: &str) {
+        let prefix = match &self.discovery {
+            Some(prefix) => prefix.clone(),
+            None => return,
+        };
+        if self.stream.is_some() && self.announced.contains(id) {
+            return
+        }
+        let unique = format!("usbmon_{}_{}", template::hostname(), key(id));
+        let label = if name.is_empty() { format!("USB {}", id) } else { name.to_string() };
+        let config = json::object(&[
+            ("name", json::string(&label)),
+            ("unique_id", json::string(&unique)),
+            ("device_class", json::string("connectivity")),
+            ("state_topic", json::string(&format!("{}/{}", self.topic, key(id)))),
+            ("availability_topic", json::string(&self.status_topic())),
+            ("payload_on", json::string("ON")),
+            ("payload_off", json::string("OFF")),
+        ]);
+        self.publish(&format!("{}/binary_sensor/{}/config", prefix, unique), &config);
+        self.announced.insert(id.clone());
+    }
+
+    /// Publishes the state of every id. Without ids every present device
+    /// is reported, and one that went away is reported OFF on its detach.
+    pub fn presence(&mut self, ids: &[DeviceID], devices: &[Entry], names: &dyn Fn(&Entry) -> String) {
+        let mut states: Vec<(DeviceID, bool, String)> = Vec::new();
+        if ids.is_empty() {
+            for entry in devices {
+                if !states.iter().any(|(id, _, _)| *id == entry.id) {
+                    states.push((entry.id.clone(), true, names(entry)));
+                }
+            }
+        }
+        for id in ids {
+            let entry = devices.iter().find(|e| &e.id == id);
+            states.push((id.clone(), entry.is_some(), entry.map(names).unwrap_or_default()));
+        }
+        for (id, present, name) in states {
+            self.announce(&id, &name);
+            self.publish(&format!("{}/{}", self.topic, key(&id)), if present { "ON" } else { "OFF" });
+        }
+    }
+
+    /// Reports a device OFF once no entry with its id is left.
+    pub fn detached(&mut self, id: &DeviceID, devices: &[Entry]) {
+        if !devices.iter().any(|e| &e.id == id) {
+            self.publish(&format!("{}/{}", self.topic, key(id)), "OFF");
+        }
+    }
+}
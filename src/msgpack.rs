@@ -0,0 +1,66 @@
+//! Just enough MessagePack for events: maps of strings.
+
+fn string(out: &mut Vec<u8>, s: &str) {
+    match s.len() {
+        len @ 0..=31 => out.push(0xa0 | len as u8),
+        len @ 32..=0xff => out.extend([0xd9, len as u8]),
+        len @ 0x100..=0xffff => {
+            out.push(0xda);
+            out.extend((len as u16).to_be_bytes());
+        },
+        len => {
+            out.push(0xdb);
+            out.extend((len as u32).to_be_bytes());
+        },
+    }
+    out.extend(s.as_bytes());
+}
+
+/// A map of string keys to string values. Each one is a complete object,
+/// so a stream of them needs no framing.
+pub fn map(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    match fields.len() {
+        len @ 0..=15 => out.push(0x80 | len as u8),
+        len @ 16..=0xffff => {
+            out.push(0xde);
+            out.extend((len as u16).to_be_bytes());
+        },
+        len => {
+            out.push(0xdf);
+            out.extend((len as u32).to_be_bytes());
+        },
+    }
+    for (key, value) in fields {
+        string(&mut out, key);
+        string(&mut out, value);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_encodings() {
+        assert_eq!(map(&[]), [0x80]);
+        assert_eq!(map(&[("a", "b")]), [0x81, 0xa1, 0x61, 0xa1, 0x62]);
+        assert_eq!(map(&[("", "")]), [0x81, 0xa0, 0xa0]);
+    }
+
+    // the smallest length of each format, from the spec's str and map families
+    #[test]
+    fn longer_formats() {
+        for (len, head) in [(31, vec![0xbf]), (32, vec![0xd9, 32]), (0x100, vec![0xda, 0x01, 0x00]), (0x10000, vec![0xdb, 0, 1, 0, 0])] {
+            let value = "x".repeat(len);
+            let encoded = map(&[("", &value)]);
+            assert_eq!(encoded[2..2 + head.len()], head[..], "length {}", len);
+            assert_eq!(encoded.len(), 2 + head.len() + len);
+        }
+        let keys: Vec<String> = (0..16).map(|i| format!("{:x}", i)).collect();
+        let fields: Vec<(&str, &str)> = keys.iter().map(|k| (k.as_str(), "")).collect();
+        assert_eq!(map(&fields[..15])[0], 0x8f);
+        assert_eq!(map(&fields)[..3], [0xde, 0x00, 0x10]);
+    }
+}
@@ -0,0 +1,13 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::sysfs;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Blocks until every interface reports operstate up.
+pub fn wait_up(ifaces: &[String]) {
+    while !ifaces.iter().all(|iface| sysfs::is_up(iface)) {
+        thread::sleep(POLL_INTERVAL);
+    }
+}
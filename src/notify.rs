@@ -0,0 +1,47 @@
+use std::io;
+
+use crate::config::Config;
+use crate::email::Mailer;
+use crate::template::Fields;
+use crate::webhook::Webhook;
+
+/// The notifiers the config file set up.
+#[derive(Default)]
+pub struct Notifiers {
+    mailer: Option<Mailer>,
+    webhook: Option<Webhook>,
+}
+
+impl Notifiers {
+    pub fn new(config: &Config) -> io::Result<Notifiers> {
+        Ok(Notifiers{
+            mailer: config.section("email").map(Mailer::new).transpose()?,
+            webhook: config.section("webhook").map(Webhook::new).transpose()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mailer.is_none() && self.webhook.is_none()
+    }
+
+    /// Passes the event to every notifier configured for its kind.
+    pub fn notify(&mut self, fields: &Fields) {
+        if let Some(mailer) = self.mailer.as_mut() {
+            mailer.notify(fields);
+        }
+        if let Some(webhook) = self.webhook.as_mut() {
+            webhook.notify(fields);
+        }
+    }
+
+    /// Sends the event through the named notifier whatever its events
+    /// setting says, for rules that ask for it. Returns false if that
+    /// notifier isn't configured.
+    pub fn deliver(&mut self, name: &str, fields: &Fields) -> bool {
+        match name {
+            "email" => self.mailer.as_mut().map(|m| m.deliver(fields)).is_some(),
+            "webhook" => self.webhook.as_mut().map(|w| w.deliver(fields)).is_some(),
+            _ => false,
+        }
+    }
+}
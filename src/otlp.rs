@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::inventory::Entry;
+use crate::json;
+use crate::DeviceID;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Exports event counters and replug spans as OTLP/HTTP JSON. Only plain
+/// http endpoints are supported, point it at a local collector.
+pub struct Exporter {
+    host: String,
+    path: String,
+    /// Trace and span id from $TRACEPARENT, so replugs during a test run
+    /// nest under the run's trace.
+    parent: Option<(String, String)>,
+    start: u64,
+    attaches: HashMap<DeviceID, u64>,
+    detaches: HashMap<DeviceID, u64>,
+}
+
+/// A device going away and coming back. Times are nanoseconds since the
+/// epoch; openable is when the device could be opened again, if ever.
+pub struct Replug {
+    pub detached: u64,
+    pub attached: u64,
+    pub openable: Option<u64>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    if File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut buf)).is_err() {
+        // unique enough for a span id
+        buf = now().to_be_bytes().iter().cycle().take(bytes).copied().collect();
+    }
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses a W3C traceparent, `00-<trace id>-<span id>-<flags>`.
+fn traceparent(value: &str) -> Option<(String, String)> {
+    let parts: Vec<&str> = value.split('-').collect();
+    match parts[..] {
+        [_, trace, span, _] if trace.len() == 32 && span.len() == 16 => Some((trace.to_string(), span.to_string())),
+        _ => None,
+    }
+}
+
+fn attribute(key: &str, value: &str) -> String {
+    json::object(&[
+        ("key", json::string(key)),
+        ("value", json::object(&[("stringValue", json::string(value))])),
+    ])
+}
+
+fn resource() -> String {
+    json::object(&[("attributes", json::array(&[attribute("service.name", "usbmon")]))])
+}
+
+fn scope() -> String {
+    json::object(&[("name", json::string("usbmon"))])
+}
+
+impl Exporter {
+    /// Takes the collector's base URL, e.g. `http://localhost:4318`.
+    pub fn new(endpoint: &str) -> io::Result<Exporter> {
+        let rest = endpoint.strip_prefix("http://").ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "only http:// endpoints are supported")
+        })?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let host = if host.contains(':') { host.to_string() } else { format!("{}:4318", host) };
+        Ok(Exporter{
+            host,
+            path: path.to_string(),
+            parent: env::var("TRACEPARENT").ok().and_then(|v| traceparent(&v)),
+            start: now(),
+            attaches: HashMap::new(),
+            detaches: HashMap::new(),
+        })
+    }
+
+    fn post(&self, signal: &str, body: &str) {
+        if let Err(e) = self.try_post(signal, body) {
+            eprintln!("otlp: {}: {}", self.host, e);
+        }
+    }
+
+    fn try_post(&self, signal: &str, body: &str) -> io::Result<()> {
+        let addr = self.host.to_socket_addrs()?.next().ok_or(io::ErrorKind::NotFound)?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        write!(stream,
+            "POST {}/v1/{} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path, signal, self.host, body.len(), body)?;
+        let mut status = [0u8; 12];
+        stream.read_exact(&mut status)?;
+        match &status[9..10] {
+            b"2" => Ok(()),
+            _ => Err(io::Error::other(String::from_utf8_lossy(&status[9..]).to_string())),
+        }
+    }
+
+    /// Counts an attach or detach and exports both counters for the device.
+    pub fn event(&mut self, attached: bool, entry: &Entry) {
+        let counts = if attached { &mut self.attaches } else { &mut self.detaches };
+        *counts.entry(entry.id.clone()).or_insert(0) += 1;
+        let time = now().to_string();
+        let metric = |name: &str, counts: &HashMap<DeviceID, u64>| {
+            let points: Vec<String> = counts
+                .iter()
+                .filter(|(id, _)| **id == entry.id)
+                .map(|(id, count)| json::object(&[
+                    ("attributes", json::array(&[attribute("usb.id", &id.to_string())])),
+                    ("startTimeUnixNano", json::string(&self.start.to_string())),
+                    ("timeUnixNano", json::string(&time)),
+                    ("asInt", json::string(&count.to_string())),
+                ]))
+                .collect();
+            json::object(&[
+                ("name", json::string(name)),
+                ("sum", json::object(&[
+                    ("aggregationTemporality", String::from("2")),
+                    ("isMonotonic", String::from("true")),
+                    ("dataPoints", json::array(&points)),
+                ])),
+            ])
+        };
+        let metrics = [metric("usb.attach", &self.attaches), metric("usb.detach", &self.detaches)];
+        let body = json::object(&[("resourceMetrics", json::array(&[json::object(&[
+            ("resource", resource()),
+            ("scopeMetrics", json::array(&[json::object(&[
+                ("scope", scope()),
+                ("metrics", json::array(&metrics)),
+            ])])),
+        ])]))]);
+        self.post("metrics", &body);
+    }
+
+    /// Exports a detach → reattach → openable span for one device.
+    pub fn replug(&self, entry: &Entry, replug: &Replug) {
+        let (trace, parent) = match &self.parent {
+            Some((trace, span)) => (trace.clone(), Some(span.clone())),
+            None => (random_hex(16), None),
+        };
+        let mut events = vec![json::object(&[
+            ("timeUnixNano", json::string(&replug.attached.to_string())),
+            ("name", json::string("reattach")),
+        ])];
+        if let Some(openable) = replug.openable {
+            events.push(json::object(&[
+                ("timeUnixNano", json::string(&openable.to_string())),
+                ("name", json::string("openable")),
+            ]));
+        }
+        let mut span = vec![
+            ("traceId", json::string(&trace)),
+            ("spanId", json::string(&random_hex(8))),
+            ("name", json::string("usb.replug")),
+            ("kind", String::from("1")),
+            ("startTimeUnixNano", json::string(&replug.detached.to_string())),
+            ("endTimeUnixNano", json::string(&replug.openable.unwrap_or(replug.attached).to_string())),
+            ("attributes", json::array(&[
+                attribute("usb.id", &entry.id.to_string()),
+                attribute("usb.bus", &format!("{:03}", entry.bus)),
+                attribute("usb.address", &format!("{:03}", entry.address)),
+            ])),
+            ("events", json::array(&events)),
+        ];
+        if let Some(parent) = parent {
+            span.push(("parentSpanId", json::string(&parent)));
+        }
+        let body = json::object(&[("resourceSpans", json::array(&[json::object(&[
+            ("resource", resource()),
+            ("scopeSpans", json::array(&[json::object(&[
+                ("scope", scope()),
+                ("spans", json::array(&[json::object(&span)])),
+            ])])),
+        ])]))]);
+        self.post("traces", &body);
+    }
+}
@@ -0,0 +1,243 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use crate::cbor;
+use crate::config::{invalid, Config};
+use crate::hub::Uplink;
+use crate::json;
+use crate::logfile::{self, LogFile};
+use crate::msgpack;
+use crate::protobuf;
+use crate::replay::Replay;
+use crate::retention::{Pruner, Retention};
+use crate::tls;
+use crate::udev;
+
+/// JSON Schema of every JSON output, versioned in its `$id`.
+pub const SCHEMA: &str = include_str!("schema.json");
+
+/// Where event lines are written, from `--output`.
+#[derive(Debug, Clone)]
+pub enum Target {
+    Stdout,
+    /// An inherited descriptor, `fd:N`.
+    Fd(i32),
+    /// A `usbmon hub`, `tcp:HOST:PORT`.
+    Tcp(String),
+    /// A `usbmon hub` with a certificate, `tls:HOST:PORT`.
+    Tls(String),
+    /// Anything else is a file path, appended to.
+    File(PathBuf),
+}
+
+pub fn parse_target(arg: &str) -> std::result::Result<Target, String> {
+    if arg == "-" || arg == "stdout" {
+        return Ok(Target::Stdout)
+    }
+    if let Some(addr) = arg.strip_prefix("tcp:") {
+        return Ok(Target::Tcp(addr.to_string()))
+    }
+    if let Some(addr) = arg.strip_prefix("tls:") {
+        return Ok(Target::Tls(addr.to_string()))
+    }
+    match arg.strip_prefix("fd:") {
+        Some(fd) => fd.parse().map(Target::Fd).map_err(|_| format!("invalid descriptor {}", fd)),
+        None => Ok(Target::File(PathBuf::from(arg))),
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// The +/-/! lines
+    Text,
+    /// One JSON object per line
+    Json,
+    /// Attach and detach as udevadm monitor --udev prints them
+    Udev,
+    /// The same with the properties block of udevadm monitor --property
+    UdevProperty,
+    /// One CBOR map of the JSON fields per event, for consumers that
+    /// can't afford to parse JSON
+    Cbor,
+    /// The same as MessagePack maps
+    Msgpack,
+    /// Length-delimited Event messages of usbmon --proto
+    Protobuf,
+}
+
+/// One destination for event lines, in its own format.
+pub struct Sink {
+    writer: Box<dyn Write>,
+    format: Format,
+    pruner: Option<Pruner>,
+    /// The SEQNUM and device paths of the uevents written in udev format.
+    uevents: udev::Monitor,
+}
+
+fn append(path: &std::path::Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Sink {
+    pub fn new(target: &Target, format: Format) -> io::Result<Sink> {
+        if matches!(format, Format::Cbor | Format::Msgpack | Format::Protobuf) && matches!(target, Target::Tcp(_) | Target::Tls(_)) {
+            return Err(invalid("a usbmon hub reads lines, use the text or json format".to_string()))
+        }
+        let writer: Box<dyn Write> = match target {
+            Target::Stdout => Box::new(io::stdout()),
+            Target::Fd(fd) => {
+                // SAFETY: only queries the descriptor's flags
+                if unsafe { libc::fcntl(*fd, libc::F_GETFD) } < 0 {
+                    return Err(io::Error::last_os_error())
+                }
+                // SAFETY: the descriptor is open and handed to us to own
+                Box::new(unsafe { File::from_raw_fd(*fd) })
+            },
+            Target::Tcp(addr) => Box::new(Uplink::new(addr, false)),
+            Target::Tls(addr) => {
+                tls::check()?;
+                Box::new(Uplink::new(addr, true))
+            },
+            Target::File(path) => Box::new(append(path)?),
+        };
+        Ok(Sink{writer, format, pruner: None, uevents: udev::Monitor::default()})
+    }
+
+    /// Sinks from `[output <name>]` sections:
+    ///
+    /// ```text
+    /// [output events]
+    /// target = /var/log/usbmon/events.json
+    /// format = json
+    /// max_age = 90d
+    /// max_rows = 1000000
+    /// ```
+    ///
+    /// A JSON file sink with max_age or max_rows is pruned as it grows.
+    pub fn configured(config: &Config) -> io::Result<Vec<Sink>> {
+        config
+            .sections("output")
+            .map(|(_, section)| {
+                let target = parse_target(section.require("target")?).map_err(invalid)?;
+                let format = match section.get("format").unwrap_or("text") {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    "udev" => Format::Udev,
+                    "udev-property" => Format::UdevProperty,
+                    "cbor" => Format::Cbor,
+                    "msgpack" => Format::Msgpack,
+                    "protobuf" => Format::Protobuf,
+                    format => return Err(invalid(format!("[{}] unknown format {}", section.name(), format))),
+                };
+                let max_rows = section.get("max_rows")
+                    .map(|n| n.parse().map_err(|_| invalid(format!("[{}] max_rows: invalid number {}", section.name(), n))))
+                    .transpose()?;
+                let retention = Retention{max_age: section.duration("max_age")?, max_rows};
+                // pruned before the sink opens what may be replaced
+                let pruner = match (&target, format) {
+                    _ if retention.is_empty() => None,
+                    (Target::File(path), Format::Json) => Some(Pruner::new(path, retention)?),
+                    _ => return Err(invalid(format!("[{}] max_age and max_rows need a file target and format = json", section.name()))),
+                };
+                Ok(Sink{pruner, ..Sink::new(&target, format)?})
+            })
+            .collect()
+    }
+
+    fn write(&mut self, kind: &str, text: &str, fields: &[(&str, String)]) -> io::Result<()> {
+        match self.format {
+            Format::Text => writeln!(self.writer, "{}", text)?,
+            Format::Json => {
+                let mut object = vec![("time", json::string(&logfile::timestamp())), ("type", json::string(kind))];
+                object.extend(fields.iter().map(|(name, value)| (*name, json::string(value))));
+                object.push(("text", json::string(text)));
+                writeln!(self.writer, "{}", json::object(&object))?;
+            },
+            Format::Udev | Format::UdevProperty => {
+                if let Some(event) = self.uevents.event(kind, fields, self.format == Format::UdevProperty) {
+                    write!(self.writer, "{}", event)?;
+                }
+            },
+            Format::Cbor | Format::Msgpack => {
+                let time = logfile::timestamp();
+                let mut map = vec![("time", time.as_str()), ("type", kind)];
+                map.extend(fields.iter().map(|(name, value)| (*name, value.as_str())));
+                map.push(("text", text));
+                let bytes = if self.format == Format::Cbor { cbor::map(&map) } else { msgpack::map(&map) };
+                self.writer.write_all(&bytes)?;
+            },
+            Format::Protobuf => self.writer.write_all(&protobuf::event(&logfile::timestamp(), kind, text, fields))?,
+        }
+        self.writer.flush()?;
+        if let Some(pruner) = self.pruner.as_mut() {
+            if pruner.written()? {
+                self.writer = Box::new(append(pruner.path())?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where watch's event lines go: the --output sink, the sinks of the
+/// config file and the log file if there is one. Every line is flushed as
+/// soon as it is written, so a consumer on the other end of a pipe sees
+/// events as they happen.
+pub struct Output {
+    main: Sink,
+    configured: Vec<Sink>,
+    log: Option<LogFile>,
+    replay: Option<Replay>,
+}
+
+impl Output {
+    pub fn new(main: Sink, log: Option<LogFile>) -> Output {
+        Output{main, configured: Vec::new(), log, replay: None}
+    }
+
+    /// Buffers events for subscribers of the control socket.
+    pub fn set_replay(&mut self, replay: Replay) {
+        self.replay = Some(replay);
+    }
+
+    /// The sequence number of the last buffered event, 0 before the first.
+    pub fn sequence(&self) -> u64 {
+        self.replay.as_ref().map_or(0, Replay::sequence)
+    }
+
+    pub fn subscribe(&mut self, stream: UnixStream, since: Option<u64>) {
+        if let Some(replay) = self.replay.as_mut() {
+            replay.subscribe(stream, since);
+        }
+    }
+
+    /// Replaces the sinks that came from the config file.
+    pub fn set_configured(&mut self, sinks: Vec<Sink>) {
+        self.configured = sinks;
+    }
+
+    /// Writes an event everywhere: text sinks get the text, JSON sinks
+    /// the kind and fields as well.
+    pub fn emit(&mut self, kind: &str, text: &str, fields: &[(&str, String)]) {
+        for sink in std::iter::once(&mut self.main).chain(self.configured.iter_mut()) {
+            if let Err(e) = sink.write(kind, text, fields) {
+                eprintln!("output: {}", e);
+            }
+        }
+        if let Some(log) = self.log.as_mut() {
+            if let Err(e) = log.write_line(text) {
+                eprintln!("log: {}", e);
+            }
+        }
+        if let Some(replay) = self.replay.as_mut() {
+            replay.record(kind, text, fields);
+        }
+    }
+
+    /// Writes a line that isn't about a particular event.
+    pub fn line(&mut self, text: &str) {
+        self.emit("message", text, &[]);
+    }
+}
@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+
+use crate::sysfs;
+
+/// Follows the overcurrent counters of hub ports. The kernel powers a
+/// port down when it trips, so the device on it simply detaches; the
+/// counter is what tells the two apart.
+pub struct Overcurrent {
+    counts: HashMap<String, u64>,
+}
+
+impl Overcurrent {
+    /// Starts from the counts now, so conditions from before aren't reported.
+    pub fn new() -> Overcurrent {
+        Overcurrent{counts: sysfs::overcurrent_counts().into_iter().collect()}
+    }
+
+    /// The ports that tripped since the last check, with their count since boot.
+    pub fn check(&mut self) -> Vec<(String, u64)> {
+        let mut tripped = Vec::new();
+        for (port, count) in sysfs::overcurrent_counts() {
+            let last = self.counts.insert(port.clone(), count);
+            // a hub plugged in since then starts from its own count
+            if last.is_some_and(|last| count > last) {
+                tripped.push((port, count));
+            }
+        }
+        tripped.sort();
+        tripped
+    }
+}
@@ -0,0 +1,337 @@
+use std::io::{self, Write};
+
+const MAGIC: &[u8] = b"PAR1";
+
+// Thrift compact protocol field types
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+// Parquet enums
+const BYTE_ARRAY: i64 = 6;
+const REQUIRED: i64 = 0;
+const UTF8: i64 = 0;
+const PLAIN: i64 = 0;
+const RLE: i64 = 3;
+const UNCOMPRESSED: i64 = 0;
+const DATA_PAGE: i64 = 0;
+
+/// Just enough of the Thrift compact protocol for Parquet's metadata:
+/// structs of integers, strings and lists, written field by field.
+#[derive(Default)]
+struct Thrift {
+    out: Vec<u8>,
+    /// The last field id of each struct being written.
+    last: Vec<i16>,
+}
+
+impl Thrift {
+    fn varint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.out.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.out.push(n as u8);
+    }
+
+    fn zigzag(&mut self, n: i64) {
+        self.varint(((n << 1) ^ (n >> 63)) as u64);
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let last = self.last.last_mut().expect("a field outside a struct");
+        let delta = id - std::mem::replace(last, id);
+        if (1..=15).contains(&delta) {
+            self.out.push((delta as u8) << 4 | kind);
+        } else {
+            self.out.push(kind);
+            self.zigzag(id as i64);
+        }
+    }
+
+    fn begin(&mut self) {
+        self.last.push(0);
+    }
+
+    fn end(&mut self) {
+        self.out.push(0);
+        self.last.pop();
+    }
+
+    fn int(&mut self, id: i16, kind: u8, n: i64) {
+        self.field(id, kind);
+        self.zigzag(n);
+    }
+
+    fn string(&mut self, id: i16, s: &str) {
+        self.field(id, BINARY);
+        self.varint(s.len() as u64);
+        self.out.extend_from_slice(s.as_bytes());
+    }
+
+    fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, LIST);
+        if len < 15 {
+            self.out.push((len as u8) << 4 | kind);
+        } else {
+            self.out.push(0xf0 | kind);
+            self.varint(len as u64);
+        }
+    }
+
+    /// A struct field whose fields the closure writes.
+    fn nested(&mut self, id: i16, fields: impl FnOnce(&mut Thrift)) {
+        self.field(id, STRUCT);
+        self.begin();
+        fields(self);
+        self.end();
+    }
+
+    /// A struct as an element of a list.
+    fn element(&mut self, fields: impl FnOnce(&mut Thrift)) {
+        self.begin();
+        fields(self);
+        self.end();
+    }
+}
+
+/// Where a column's one page ended up.
+struct Chunk {
+    offset: u64,
+    size: u64,
+}
+
+/// Writes a Parquet file of required string columns in one row group,
+/// one uncompressed PLAIN page per column, which every reader handles.
+pub fn write(names: &[&str], rows: &[Vec<String>], out: &mut impl Write) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    let mut offset = MAGIC.len() as u64;
+    let mut chunks = Vec::with_capacity(names.len());
+    for column in 0..names.len() {
+        let mut data = Vec::new();
+        for row in rows {
+            let value = row.get(column).map_or("", String::as_str);
+            data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            data.extend_from_slice(value.as_bytes());
+        }
+        let mut header = Thrift::default();
+        header.begin();
+        header.int(1, I32, DATA_PAGE);
+        header.int(2, I32, data.len() as i64);
+        header.int(3, I32, data.len() as i64);
+        header.nested(5, |page| {
+            page.int(1, I32, rows.len() as i64);
+            page.int(2, I32, PLAIN);
+            page.int(3, I32, RLE);
+            page.int(4, I32, RLE);
+        });
+        header.end();
+        out.write_all(&header.out)?;
+        out.write_all(&data)?;
+        let size = (header.out.len() + data.len()) as u64;
+        chunks.push(Chunk{offset, size});
+        offset += size;
+    }
+    let mut meta = Thrift::default();
+    meta.begin();
+    meta.int(1, I32, 1);
+    meta.list(2, STRUCT, names.len() + 1);
+    meta.element(|root| {
+        root.string(4, "schema");
+        root.int(5, I32, names.len() as i64);
+    });
+    for name in names {
+        meta.element(|column| {
+            column.int(1, I32, BYTE_ARRAY);
+            column.int(3, I32, REQUIRED);
+            column.string(4, name);
+            column.int(6, I32, UTF8);
+        });
+    }
+    meta.int(3, I64, rows.len() as i64);
+    meta.list(4, STRUCT, 1);
+    meta.element(|group| {
+        group.list(1, STRUCT, names.len());
+        for (name, chunk) in names.iter().zip(&chunks) {
+            group.element(|column| {
+                column.int(2, I64, chunk.offset as i64);
+                column.nested(3, |data| {
+                    data.int(1, I32, BYTE_ARRAY);
+                    data.list(2, I32, 1);
+                    data.zigzag(PLAIN);
+                    data.list(3, BINARY, 1);
+                    data.varint(name.len() as u64);
+                    data.out.extend_from_slice(name.as_bytes());
+                    data.int(4, I32, UNCOMPRESSED);
+                    data.int(5, I64, rows.len() as i64);
+                    data.int(6, I64, chunk.size as i64);
+                    data.int(7, I64, chunk.size as i64);
+                    data.int(9, I64, chunk.offset as i64);
+                });
+            });
+        }
+        group.int(2, I64, chunks.iter().map(|c| c.size).sum::<u64>() as i64);
+        group.int(3, I64, rows.len() as i64);
+    });
+    meta.string(6, concat!("usbmon version ", env!("CARGO_PKG_VERSION")));
+    meta.end();
+    out.write_all(&meta.out)?;
+    out.write_all(&(meta.out.len() as u32).to_le_bytes())?;
+    out.write_all(MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Thrift compact protocol value, read back without knowing the
+    /// schema.
+    #[derive(Debug)]
+    enum Value {
+        Int(i64),
+        Binary(Vec<u8>),
+        List(Vec<Value>),
+        Struct(Vec<(i16, Value)>),
+    }
+
+    impl Value {
+        fn field(&self, id: i16) -> &Value {
+            match self {
+                Value::Struct(fields) => &fields.iter().find(|(i, _)| *i == id).unwrap_or_else(|| panic!("no field {}", id)).1,
+                _ => panic!("not a struct"),
+            }
+        }
+
+        fn int(&self) -> i64 {
+            match self {
+                Value::Int(n) => *n,
+                _ => panic!("not an integer"),
+            }
+        }
+
+        fn string(&self) -> &str {
+            match self {
+                Value::Binary(bytes) => std::str::from_utf8(bytes).unwrap(),
+                _ => panic!("not binary"),
+            }
+        }
+
+        fn list(&self) -> &[Value] {
+            match self {
+                Value::List(values) => values,
+                _ => panic!("not a list"),
+            }
+        }
+    }
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        at: usize,
+    }
+
+    impl Reader<'_> {
+        fn byte(&mut self) -> u8 {
+            self.at += 1;
+            self.bytes[self.at - 1]
+        }
+
+        fn varint(&mut self) -> u64 {
+            let (mut n, mut shift) = (0, 0);
+            loop {
+                let b = self.byte();
+                n |= ((b & 0x7f) as u64) << shift;
+                if b < 0x80 {
+                    return n
+                }
+                shift += 7;
+            }
+        }
+
+        fn zigzag(&mut self) -> i64 {
+            let n = self.varint();
+            (n >> 1) as i64 ^ -((n & 1) as i64)
+        }
+
+        fn value(&mut self, kind: u8) -> Value {
+            match kind {
+                I32 | I64 => Value::Int(self.zigzag()),
+                BINARY => {
+                    let len = self.varint() as usize;
+                    self.at += len;
+                    Value::Binary(self.bytes[self.at - len..self.at].to_vec())
+                },
+                LIST => {
+                    let head = self.byte();
+                    let len = match head >> 4 {
+                        15 => self.varint() as usize,
+                        len => len as usize,
+                    };
+                    Value::List((0..len).map(|_| self.value(head & 0x0f)).collect())
+                },
+                STRUCT => {
+                    let (mut fields, mut last) = (Vec::new(), 0);
+                    loop {
+                        let head = self.byte();
+                        if head == 0 {
+                            return Value::Struct(fields)
+                        }
+                        last = match head >> 4 {
+                            0 => self.zigzag() as i16,
+                            delta => last + delta as i16,
+                        };
+                        fields.push((last, self.value(head & 0x0f)));
+                    }
+                },
+                kind => panic!("unexpected type {}", kind),
+            }
+        }
+    }
+
+    #[test]
+    fn written_file_reads_back() {
+        let names = ["time", "event", "id"];
+        let rows: Vec<Vec<String>> = (0..20)
+            .map(|i| vec![format!("2026-10-14T10:00:{:02}", i), String::from(if i % 2 == 0 { "attach" } else { "detach" }), String::from("1d50:6018")])
+            .collect();
+        let mut file = Vec::new();
+        write(&names, &rows, &mut file).unwrap();
+
+        assert_eq!(&file[..4], MAGIC);
+        assert_eq!(&file[file.len() - 4..], MAGIC);
+        let footer = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap()) as usize;
+        let start = file.len() - 8 - footer;
+        let mut reader = Reader{bytes: &file, at: start};
+        let meta = reader.value(STRUCT);
+        // the footer length covers the metadata exactly
+        assert_eq!(reader.at, file.len() - 8);
+
+        assert_eq!(meta.field(3).int(), rows.len() as i64);
+        let schema = meta.field(2).list();
+        assert_eq!(schema[0].field(5).int(), names.len() as i64);
+        let columns: Vec<&str> = schema[1..].iter().map(|c| c.field(4).string()).collect();
+        assert_eq!(columns, names);
+
+        let groups = meta.field(4).list();
+        assert_eq!(groups.len(), 1);
+        for (column, chunk) in groups[0].field(1).list().iter().enumerate() {
+            let data = chunk.field(3);
+            assert_eq!(data.field(3).list()[0].string(), names[column]);
+            assert_eq!(data.field(5).int(), rows.len() as i64);
+            let offset = data.field(9).int() as usize;
+            let mut reader = Reader{bytes: &file, at: offset};
+            let header = reader.value(STRUCT);
+            assert_eq!(header.field(5).field(1).int(), rows.len() as i64);
+            let size = header.field(3).int() as usize;
+            assert_eq!((reader.at - offset + size) as i64, data.field(6).int());
+            let mut page = &file[reader.at..reader.at + size];
+            for row in &rows {
+                let len = u32::from_le_bytes(page[..4].try_into().unwrap()) as usize;
+                assert_eq!(std::str::from_utf8(&page[4..4 + len]).unwrap(), row[column]);
+                page = &page[4 + len..];
+            }
+            assert!(page.is_empty());
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// A locked pid file. The lock lives as long as the open file, so it goes
+/// away with the process however it ends; the file itself is removed on
+/// drop.
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+/// Whether the path still names the file that was opened. An instance
+/// exiting removes the file before its lock goes, so one that opened it
+/// just before then gets the lock of a file nobody else will see.
+fn is_current(file: &File, path: &Path) -> io::Result<bool> {
+    let opened = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(named) => Ok(named.dev() == opened.dev() && named.ino() == opened.ino()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl PidFile {
+    /// Locks the file and writes our pid to it. Fails with AlreadyExists
+    /// naming the running instance if another process holds the lock.
+    pub fn create(path: &Path) -> io::Result<PidFile> {
+        loop {
+            let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+            // SAFETY: flock on a descriptor we own
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != ErrorKind::WouldBlock {
+                    return Err(err)
+                }
+                let pid = fs::read_to_string(path).unwrap_or_default();
+                return Err(io::Error::new(ErrorKind::AlreadyExists,
+                    format!("already running as pid {}", pid.trim())))
+            }
+            if !is_current(&file, path)? {
+                continue
+            }
+            file.set_len(0)?;
+            writeln!(file, "{}", std::process::id())?;
+            return Ok(PidFile{path: path.to_path_buf(), file})
+        }
+    }
+}
+
+impl Drop for PidFile {
+    /// Removes the file while it is still locked. After --user the
+    /// directory is often not ours to change; the file is then left empty,
+    /// which the next instance takes over like a missing one.
+    fn drop(&mut self) {
+        match fs::remove_file(&self.path) {
+            Ok(()) => {},
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => {
+                let left = if self.file.set_len(0).is_ok() { ", left it empty" } else { "" };
+                eprintln!("{}: can't remove: {}{}", self.path.display(), e, left);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("usbmon-pid-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn second_instance_is_refused_until_the_first_goes() {
+        let path = path("second");
+        let first = PidFile::create(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+        let err = PidFile::create(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(err.to_string(), format!("already running as pid {}", std::process::id()));
+        drop(first);
+        assert!(!path.exists());
+        drop(PidFile::create(&path).unwrap());
+    }
+
+    #[test]
+    fn removed_file_is_not_current() {
+        let path = path("removed");
+        let file = OpenOptions::new().write(true).create(true).truncate(false).open(&path).unwrap();
+        assert!(is_current(&file, &path).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(!is_current(&file, &path).unwrap());
+        // another instance made a new file in its place
+        let pidfile = PidFile::create(&path).unwrap();
+        assert!(!is_current(&file, &path).unwrap());
+        assert!(is_current(&pidfile.file, &path).unwrap());
+    }
+}
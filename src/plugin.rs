@@ -0,0 +1,155 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::config::Config;
+use crate::json;
+use crate::template::Fields;
+
+pub const PROTOCOL: u32 = 1;
+
+/// What a plugin can ask for, one per line on its stdout:
+///
+/// ```text
+/// log <message>
+/// notify <email|webhook> <message>
+/// authorize <bus>:<address> <0|1>
+/// ```
+#[derive(Debug)]
+pub enum Request {
+    Log(String),
+    Notify(String, String),
+    Authorize(u8, u8, bool),
+}
+
+fn parse(line: &str) -> Option<Request> {
+    let (verb, rest) = line.split_once(' ').unwrap_or((line, ""));
+    match verb {
+        "log" => Some(Request::Log(rest.to_string())),
+        "notify" => {
+            let (via, message) = rest.split_once(' ')?;
+            Some(Request::Notify(via.to_string(), message.to_string()))
+        },
+        "authorize" => {
+            let (location, allow) = rest.split_once(' ')?;
+            let (bus, address) = location.split_once(':')?;
+            let allow = match allow {
+                "1" => true,
+                "0" => false,
+                _ => return None,
+            };
+            Some(Request::Authorize(bus.parse().ok()?, address.parse().ok()?, allow))
+        },
+        _ => None,
+    }
+}
+
+struct Plugin {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+}
+
+/// Long running programs from the `[plugins]` section, one `name = command`
+/// line each. Every plugin gets a hello line and then one JSON object per
+/// event on its stdin:
+///
+/// ```text
+/// {"type":"hello","protocol":1}
+/// {"type":"event","event":"attach","id":"1a2b:5678",...}
+/// ```
+///
+/// The event object carries the same fields as notification templates.
+/// A plugin that exits or stops reading is dropped.
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+    requests: Option<mpsc::Receiver<(String, String)>>,
+}
+
+impl Plugins {
+    pub fn new(config: &Config) -> io::Result<Plugins> {
+        let section = match config.section("plugins") {
+            Some(section) => section,
+            None => return Ok(Plugins::default()),
+        };
+        let (tx, rx) = mpsc::channel();
+        let mut plugins = Vec::new();
+        for (name, command) in section.entries() {
+            let mut child = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()?;
+            let stdin = child.stdin.take().ok_or(io::ErrorKind::BrokenPipe)?;
+            let stdout = child.stdout.take().ok_or(io::ErrorKind::BrokenPipe)?;
+            let tx = tx.clone();
+            let reader_name = name.to_string();
+            thread::spawn(move || {
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    if tx.send((reader_name.clone(), line)).is_err() {
+                        break;
+                    }
+                }
+            });
+            plugins.push(Plugin{name: name.to_string(), child, stdin});
+        }
+        let mut plugins = Plugins{plugins, requests: Some(rx)};
+        let hello = json::object(&[("type", json::string("hello")), ("protocol", PROTOCOL.to_string())]);
+        plugins.broadcast(&hello);
+        Ok(plugins)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    fn broadcast(&mut self, line: &str) {
+        self.plugins.retain_mut(|plugin| match writeln!(plugin.stdin, "{}", line).and_then(|_| plugin.stdin.flush()) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("plugin {}: {}, dropping it", plugin.name, e);
+                _ = plugin.child.kill();
+                _ = plugin.child.wait();
+                false
+            },
+        });
+    }
+
+    /// Sends an event to every plugin.
+    pub fn send(&mut self, fields: &Fields) {
+        let mut object: Vec<(&str, String)> = vec![("type", json::string("event"))];
+        object.extend(fields.iter().map(|(name, value)| (*name, json::string(value))));
+        self.broadcast(&json::object(&object));
+    }
+
+    /// Whatever the plugins asked for since the last call, along with the
+    /// name of the plugin asking. Lines that aren't requests are reported
+    /// and skipped.
+    pub fn requests(&self) -> Vec<(String, Request)> {
+        let rx = match &self.requests {
+            Some(rx) => rx,
+            None => return Vec::new(),
+        };
+        rx.try_iter()
+            .filter_map(|(name, line)| match parse(&line) {
+                Some(request) => Some((name, request)),
+                None => {
+                    eprintln!("plugin {}: unknown request {}", name, line);
+                    None
+                },
+            })
+            .collect()
+    }
+}
+
+impl Drop for Plugins {
+    fn drop(&mut self) {
+        for plugin in &mut self.plugins {
+            _ = plugin.child.kill();
+            _ = plugin.child.wait();
+        }
+    }
+}
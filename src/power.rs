@@ -0,0 +1,35 @@
+use crate::inventory::Entry;
+use crate::sysfs;
+
+/// Follows the runtime power state the kernel keeps for each device, so a
+/// device that autosuspended doesn't look like one that went away.
+#[derive(Default)]
+pub struct PowerStates {
+    suspended: Vec<(Entry, bool)>,
+}
+
+impl PowerStates {
+    /// The devices that suspended (true) or resumed (false) since the last
+    /// check. Devices show up without an event the first time, and those in
+    /// the middle of a transition are checked again next time.
+    pub fn check(&mut self, devices: &[Entry]) -> Vec<(Entry, bool)> {
+        self.suspended.retain(|(entry, _)| devices.contains(entry));
+        let mut changed = Vec::new();
+        for entry in devices.iter().filter(|e| !e.port.is_empty()) {
+            let suspended = match sysfs::power(&entry.port, "runtime_status").as_deref() {
+                Some("suspended") => true,
+                Some("active") => false,
+                _ => continue,
+            };
+            match self.suspended.iter_mut().find(|(e, _)| e == entry) {
+                Some((_, was)) if *was != suspended => {
+                    *was = suspended;
+                    changed.push((entry.clone(), suspended));
+                },
+                Some(_) => {},
+                None => self.suspended.push((entry.clone(), suspended)),
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,68 @@
+use std::ffi::CString;
+use std::io;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Looks up a user by name or number, returning its uid and primary gid.
+pub fn user(name: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let cname = CString::new(name).map_err(|_| invalid(format!("invalid user {}", name)))?;
+    // SAFETY: getpwnam returns a pointer to static storage or NULL, read
+    // right away in this single threaded setup phase
+    unsafe {
+        let pw = libc::getpwnam(cname.as_ptr());
+        if !pw.is_null() {
+            return Ok(((*pw).pw_uid, (*pw).pw_gid))
+        }
+        let uid: libc::uid_t = name.parse().map_err(|_| invalid(format!("no such user {}", name)))?;
+        let pw = libc::getpwuid(uid);
+        Ok((uid, if pw.is_null() { uid } else { (*pw).pw_gid }))
+    }
+}
+
+/// Looks up a group by name or number.
+pub fn group(name: &str) -> io::Result<libc::gid_t> {
+    let cname = CString::new(name).map_err(|_| invalid(format!("invalid group {}", name)))?;
+    // SAFETY: as for getpwnam above
+    unsafe {
+        let gr = libc::getgrnam(cname.as_ptr());
+        if !gr.is_null() {
+            return Ok((*gr).gr_gid)
+        }
+    }
+    name.parse().map_err(|_| invalid(format!("no such group {}", name)))
+}
+
+/// Switches to the user and group for good, dropping supplementary groups.
+/// Without a group the user's primary group is used. Devices already
+/// opened stay usable, anything opened later needs the new identity's
+/// permissions.
+pub fn drop(user_name: Option<&str>, group_name: Option<&str>) -> io::Result<()> {
+    let (uid, primary) = match user_name {
+        Some(name) => {
+            let (uid, gid) = user(name)?;
+            (Some(uid), Some(gid))
+        },
+        None => (None, None),
+    };
+    let gid = match group_name {
+        Some(name) => Some(group(name)?),
+        None => primary,
+    };
+    // SAFETY: plain system calls on ids; group before user, since setting
+    // the user first gives up the right to change the group
+    unsafe {
+        if let Some(gid) = gid {
+            if libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 {
+                return Err(io::Error::last_os_error())
+            }
+        }
+        if let Some(uid) = uid {
+            if libc::setuid(uid) != 0 {
+                return Err(io::Error::last_os_error())
+            }
+        }
+    }
+    Ok(())
+}
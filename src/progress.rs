@@ -0,0 +1,76 @@
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crate::DeviceID;
+
+/// A ✓/✗ line per awaited id on stderr, redrawn in place as devices come
+/// and go. Stays silent when stderr isn't a terminal.
+pub struct Progress {
+    ids: Vec<DeviceID>,
+    interactive: bool,
+    drawn: usize,
+}
+
+impl Progress {
+    pub fn new(ids: &[DeviceID]) -> Progress {
+        // SAFETY: isatty only inspects the descriptor
+        let interactive = unsafe { libc::isatty(libc::STDERR_FILENO) } == 1;
+        Progress{ids: ids.to_vec(), interactive, drawn: 0}
+    }
+
+    pub fn update(&mut self, outstanding: &[DeviceID]) {
+        if !self.interactive {
+            return
+        }
+        let mut text = String::new();
+        if self.drawn > 0 {
+            text.push_str(&format!("\x1b[{}A", self.drawn));
+        }
+        for id in &self.ids {
+            let mark = if outstanding.contains(id) { "\x1b[31m✗\x1b[0m" } else { "\x1b[32m✓\x1b[0m" };
+            text.push_str(&format!("\r\x1b[K{} {}\n", mark, id));
+        }
+        let mut stderr = io::stderr();
+        _ = stderr.write_all(text.as_bytes());
+        _ = stderr.flush();
+        self.drawn = self.ids.len();
+    }
+}
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// A spinner with the elapsed time and what is being waited for, kept on
+/// the last line of stderr and cleared when dropped.
+pub struct Spinner {
+    summary: String,
+    start: Instant,
+    frame: usize,
+    interactive: bool,
+}
+
+impl Spinner {
+    pub fn new(summary: String) -> Spinner {
+        // SAFETY: isatty only inspects the descriptor
+        let interactive = unsafe { libc::isatty(libc::STDERR_FILENO) } == 1;
+        Spinner{summary, start: Instant::now(), frame: 0, interactive}
+    }
+
+    pub fn tick(&mut self) {
+        if !self.interactive {
+            return
+        }
+        let frame = FRAMES[self.frame % FRAMES.len()];
+        self.frame += 1;
+        let mut stderr = io::stderr();
+        _ = write!(stderr, "\r\x1b[K{} {}s {}", frame, self.start.elapsed().as_secs(), self.summary);
+        _ = stderr.flush();
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if self.interactive && self.frame > 0 {
+            _ = write!(io::stderr(), "\r\x1b[K");
+        }
+    }
+}
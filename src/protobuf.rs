@@ -0,0 +1,167 @@
+//! Just enough of the protobuf wire format to write the messages of
+//! usbmon.proto.
+
+use crate::inventory::Entry;
+
+/// The definition of what is written, printed by --proto.
+pub const PROTO: &str = include_str!("usbmon.proto");
+
+/// Event.type, in the order of EventType from 1.
+const TYPES: [&str; 13] = [
+    "attach", "detach", "flap", "absent", "snapshot", "heartbeat", "reload", "message", "overcurrent", "suspend",
+    "resume", "connect", "disconnect",
+];
+
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Uint,
+    Bool,
+}
+
+/// The Event fields the JSON fields go in, the rest go in extra.
+const EVENT_FIELDS: [(&str, u32, Kind); 15] = [
+    ("host", 4, Kind::String),
+    ("peer", 5, Kind::String),
+    ("id", 6, Kind::String),
+    ("bus", 7, Kind::Uint),
+    ("address", 8, Kind::Uint),
+    ("port", 9, Kind::String),
+    ("sessions", 10, Kind::Uint),
+    ("session", 11, Kind::Uint),
+    ("source", 12, Kind::String),
+    ("existing", 13, Kind::Bool),
+    ("injected", 14, Kind::Bool),
+    ("overcurrents", 15, Kind::Uint),
+    ("transitions", 16, Kind::Uint),
+    ("devices", 17, Kind::Uint),
+    ("present", 18, Kind::String),
+];
+
+const EXTRA: u32 = 19;
+
+/// A message being encoded. Fields with their default value are left out,
+/// as proto3 does.
+#[derive(Default)]
+struct Message {
+    bytes: Vec<u8>,
+}
+
+impl Message {
+    fn varint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.bytes.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.bytes.push(n as u8);
+    }
+
+    fn uint(&mut self, field: u32, n: u64) {
+        if n != 0 {
+            self.varint((field as u64) << 3);
+            self.varint(n);
+        }
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            self.varint((field as u64) << 3 | 2);
+            self.varint(bytes.len() as u64);
+            self.bytes.extend(bytes);
+        }
+    }
+
+    fn string(&mut self, field: u32, s: &str) {
+        self.bytes(field, s.as_bytes());
+    }
+
+    /// The message with its length in front, so a stream of them can be
+    /// split up again.
+    fn delimited(self) -> Vec<u8> {
+        let mut out = Message::default();
+        out.varint(self.bytes.len() as u64);
+        out.bytes.extend(self.bytes);
+        out.bytes
+    }
+}
+
+/// An Event, length-delimited. Numbers that don't parse are left out.
+pub fn event(time: &str, kind: &str, text: &str, fields: &[(&str, String)]) -> Vec<u8> {
+    let mut message = Message::default();
+    message.string(1, time);
+    message.uint(2, TYPES.iter().position(|t| *t == kind).map_or(0, |i| i as u64 + 1));
+    message.string(3, text);
+    for (name, value) in fields {
+        match EVENT_FIELDS.iter().find(|(field, ..)| field == name) {
+            Some((_, number, Kind::String)) => message.string(*number, value),
+            Some((_, number, Kind::Uint)) => message.uint(*number, value.parse().unwrap_or(0)),
+            Some((_, number, Kind::Bool)) => message.uint(*number, (value == "true") as u64),
+            None => {
+                let mut entry = Message::default();
+                entry.string(1, name);
+                entry.string(2, value);
+                message.bytes(EXTRA, &entry.bytes);
+            },
+        }
+    }
+    message.delimited()
+}
+
+/// A Device, length-delimited. What needs the device itself is empty when
+/// it's gone.
+pub fn device(entry: &Entry, name: &str, serial: &str, revision: &str, max_power: Option<u16>) -> Vec<u8> {
+    let mut message = Message::default();
+    message.string(1, &entry.id.to_string());
+    message.uint(2, entry.id.vid as u64);
+    message.uint(3, entry.id.pid as u64);
+    message.uint(4, entry.bus as u64);
+    message.uint(5, entry.address as u64);
+    message.string(6, &entry.port);
+    message.string(7, name);
+    message.string(8, serial);
+    message.string(9, revision);
+    message.uint(10, max_power.unwrap_or(0) as u64);
+    message.delimited()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceID;
+
+    // the examples of the protobuf encoding guide: 150 as a varint, and
+    // "testing" as a string field
+    #[test]
+    fn known_encodings() {
+        assert_eq!(event("", "attach", "", &[("bus", String::from("150"))]), [5, 0x10, 1, 0x38, 0x96, 0x01]);
+        assert_eq!(
+            event("", "", "", &[("id", String::from("testing"))]),
+            [9, 0x32, 7, b't', b'e', b's', b't', b'i', b'n', b'g']);
+    }
+
+    #[test]
+    fn defaults_are_left_out() {
+        assert_eq!(event("", "", "", &[("bus", String::from("0")), ("existing", String::from("false")), ("port", String::new())]), [0]);
+        // a number that doesn't parse is as good as none
+        assert_eq!(event("", "", "", &[("bus", String::from("x"))]), [0]);
+    }
+
+    #[test]
+    fn unknown_fields_go_in_extra() {
+        // field 19 takes a two byte tag, and each entry is a nested message
+        assert_eq!(
+            event("", "", "", &[("vm", String::from("a"))]),
+            [10, 0x9a, 0x01, 7, 0x0a, 2, b'v', b'm', 0x12, 1, b'a']);
+    }
+
+    #[test]
+    fn device_fields() {
+        let entry = Entry{bus: 1, address: 2, id: DeviceID{vid: 0x1d50, pid: 0x6018}, port: String::from("1-4")};
+        let bytes = device(&entry, "", "", "", Some(500));
+        let mut expected = vec![0x0a, 9];
+        expected.extend(b"1d50:6018");
+        expected.extend([0x10, 0xd0, 0x3a, 0x18, 0x98, 0xc0, 0x01, 0x20, 1, 0x28, 2, 0x32, 3, b'1', b'-', b'4', 0x50, 0xf4, 0x03]);
+        assert_eq!(bytes[0] as usize, expected.len());
+        assert_eq!(bytes[1..], expected[..]);
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// What a full queue does with another item.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Make room by dropping the oldest queued item
+    DropOldest,
+    /// Wait for the consumer to make room
+    Block,
+    /// Drop the new item and report how many were lost
+    CountAndReport,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    dropped: usize,
+}
+
+/// A bounded queue between one producer, the libusb event thread, and
+/// one consumer.
+pub struct Queue<T> {
+    capacity: usize,
+    overflow: Overflow,
+    state: Mutex<State<T>>,
+    changed: Condvar,
+}
+
+impl<T> Queue<T> {
+    pub fn new(capacity: usize, overflow: Overflow) -> Queue<T> {
+        Queue{
+            capacity: capacity.max(1),
+            overflow,
+            state: Mutex::new(State{items: VecDeque::new(), dropped: 0}),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        let mut state = self.state.lock().unwrap();
+        while state.items.len() >= self.capacity {
+            match self.overflow {
+                Overflow::DropOldest => {
+                    state.items.pop_front();
+                    state.dropped += 1;
+                },
+                Overflow::Block => state = self.changed.wait(state).unwrap(),
+                Overflow::CountAndReport => {
+                    state.dropped += 1;
+                    return
+                },
+            }
+        }
+        state.items.push_back(item);
+        self.changed.notify_all();
+    }
+
+    /// Waits for the next item.
+    pub fn pop(&self) -> T {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.changed.notify_all();
+                return item
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Waits up to timeout for the next item.
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        let state = self.state.lock().unwrap();
+        let (mut state, _) = self.changed.wait_timeout_while(state, timeout, |s| s.items.is_empty()).unwrap();
+        let item = state.items.pop_front();
+        if item.is_some() {
+            self.changed.notify_all();
+        }
+        item
+    }
+
+    /// How many items were dropped since the last call.
+    pub fn take_dropped(&self) -> usize {
+        std::mem::take(&mut self.state.lock().unwrap().dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
+
+    fn drain(queue: &Queue<u32>) -> Vec<u32> {
+        std::iter::from_fn(|| queue.pop_timeout(Duration::ZERO)).collect()
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_newest() {
+        let queue = Queue::new(2, Overflow::DropOldest);
+        (1..=5).for_each(|n| queue.push(n));
+        assert_eq!(queue.take_dropped(), 3);
+        assert_eq!(queue.take_dropped(), 0);
+        assert_eq!(drain(&queue), [4, 5]);
+    }
+
+    #[test]
+    fn count_and_report_keeps_the_oldest() {
+        let queue = Queue::new(2, Overflow::CountAndReport);
+        (1..=5).for_each(|n| queue.push(n));
+        assert_eq!(queue.take_dropped(), 3);
+        assert_eq!(drain(&queue), [1, 2]);
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let queue = Arc::new(Queue::new(1, Overflow::Block));
+        queue.push(1);
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.push(2))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.pop(), 1);
+        assert_eq!(queue.pop(), 2);
+        producer.join().unwrap();
+        assert_eq!(queue.take_dropped(), 0);
+    }
+
+    #[test]
+    fn pop_timeout_gives_up() {
+        let queue: Queue<u32> = Queue::new(0, Overflow::DropOldest);
+        let start = Instant::now();
+        assert_eq!(queue.pop_timeout(Duration::from_millis(50)), None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        // a capacity of 0 still holds one
+        queue.push(7);
+        assert_eq!(queue.pop_timeout(Duration::from_millis(50)), Some(7));
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::config::{invalid, Section};
+use crate::parse_duration;
+
+/// Caps how often a notifier sends, from its section's keys:
+///
+/// ```text
+/// rate_limit = 5/10m
+/// cooldown = 30m
+/// ```
+///
+/// rate_limit allows at most N messages in any window of the given
+/// length, a bare duration meaning one. Once the limit is hit, cooldown
+/// keeps the notifier quiet for that long after the last message, so a
+/// flapping device produces one burst instead of a steady trickle.
+pub struct RateLimit {
+    max: usize,
+    window: Duration,
+    cooldown: Duration,
+    sent: VecDeque<Instant>,
+    quiet_until: Option<Instant>,
+    suppressed: usize,
+}
+
+impl RateLimit {
+    /// None if the section sets no limit.
+    pub fn new(section: &Section) -> io::Result<Option<RateLimit>> {
+        let limit = match section.get("rate_limit") {
+            Some(limit) => limit,
+            None => return Ok(None),
+        };
+        let error = |e: String| invalid(format!("[{}] rate_limit: {}", section.name(), e));
+        let (max, window) = match limit.split_once('/') {
+            Some((max, window)) => (
+                max.trim().parse().map_err(|_| error(format!("invalid count {}", max)))?,
+                parse_duration(window.trim()).map_err(|e| error(e.to_string()))?,
+            ),
+            None => (1, parse_duration(limit).map_err(|e| error(e.to_string()))?),
+        };
+        Ok(Some(RateLimit{
+            max,
+            window,
+            cooldown: section.duration("cooldown")?.unwrap_or(Duration::ZERO),
+            sent: VecDeque::new(),
+            quiet_until: None,
+            suppressed: 0,
+        }))
+    }
+
+    /// Whether a message may go out now, counting it if so and counting
+    /// it as suppressed if not.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        while self.sent.front().is_some_and(|t| now.duration_since(*t) >= self.window) {
+            self.sent.pop_front();
+        }
+        if self.quiet_until.is_some_and(|until| now < until) || self.sent.len() >= self.max {
+            self.suppressed += 1;
+            return false
+        }
+        self.sent.push_back(now);
+        if self.sent.len() >= self.max && !self.cooldown.is_zero() {
+            self.quiet_until = Some(now + self.cooldown);
+        }
+        true
+    }
+
+    /// How many messages were held back since the last call.
+    pub fn take_suppressed(&mut self) -> usize {
+        std::mem::take(&mut self.suppressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::fs;
+
+    /// The limit of a [webhook] section of these lines.
+    fn limit(name: &str, lines: &str) -> io::Result<Option<RateLimit>> {
+        let path = std::env::temp_dir().join(format!("usbmon-ratelimit-{}-{}.conf", name, std::process::id()));
+        fs::write(&path, format!("[webhook]\n{}", lines)).unwrap();
+        let config = Config::load(&path);
+        _ = fs::remove_file(&path);
+        RateLimit::new(config?.section("webhook").unwrap())
+    }
+
+    #[test]
+    fn limit_needs_a_count_and_a_window() {
+        assert!(limit("unset", "url = x\n").unwrap().is_none());
+        let bare = limit("bare", "rate_limit = 10m\n").unwrap().unwrap();
+        assert_eq!((bare.max, bare.window), (1, Duration::from_secs(600)));
+        assert!(limit("count", "rate_limit = x/10m\n").is_err());
+        assert!(limit("window", "rate_limit = 5/soon\n").is_err());
+        assert!(limit("cooldown", "rate_limit = 5/10m\ncooldown = later\n").is_err());
+    }
+
+    #[test]
+    fn window_expires() {
+        let mut limit = limit("window", "rate_limit = 2/10s\n").unwrap().unwrap();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(limit.allow(at(0)));
+        assert!(limit.allow(at(4)));
+        assert!(!limit.allow(at(9)));
+        // the first has left the window, the second hasn't
+        assert!(limit.allow(at(10)));
+        assert!(!limit.allow(at(13)));
+        assert!(limit.allow(at(14)));
+        assert_eq!(limit.take_suppressed(), 2);
+        assert_eq!(limit.take_suppressed(), 0);
+    }
+
+    #[test]
+    fn cooldown_outlasts_the_window() {
+        let mut limit = limit("quiet", "rate_limit = 2/10s\ncooldown = 60s\n").unwrap().unwrap();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(limit.allow(at(0)));
+        assert!(limit.allow(at(1)));
+        assert!(!limit.allow(at(20)));
+        assert!(!limit.allow(at(60)));
+        assert!(limit.allow(at(61)));
+        assert_eq!(limit.take_suppressed(), 2);
+    }
+}
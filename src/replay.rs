@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use crate::control;
+use crate::json;
+use crate::logfile;
+
+/// How long a slow subscriber may hold up the event loop before it is
+/// dropped; it can reconnect and catch up from the buffer.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The most recent events with their sequence numbers, and the control
+/// socket clients subscribed to new ones. Every event is one JSON object
+/// per line with a `seq` that grows by one per event, so a subscriber
+/// that reconnects with the last one it saw gets what it missed.
+pub struct Replay {
+    events: VecDeque<(u64, String)>,
+    capacity: usize,
+    next: u64,
+    subscribers: Vec<UnixStream>,
+}
+
+impl Replay {
+    pub fn new(capacity: usize) -> Replay {
+        Replay{events: VecDeque::with_capacity(capacity), capacity, next: 1, subscribers: Vec::new()}
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.next - 1
+    }
+
+    pub fn record(&mut self, kind: &str, text: &str, fields: &[(&str, String)]) {
+        let mut object = vec![
+            ("seq", self.next.to_string()),
+            ("time", json::string(&logfile::timestamp())),
+            ("type", json::string(kind)),
+        ];
+        object.extend(fields.iter().map(|(name, value)| (*name, json::string(value))));
+        object.push(("text", json::string(text)));
+        let line = format!("{}\n", json::object(&object));
+        self.subscribers.retain(|stream| control::write_within(stream, line.as_bytes(), WRITE_TIMEOUT).is_ok());
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        if self.capacity > 0 {
+            self.events.push_back((self.next, line));
+        }
+        self.next += 1;
+    }
+
+    /// Answers `ok SEQ` with the first sequence number that follows, then
+    /// replays the buffered events after `since` and keeps the stream for
+    /// new ones. A SEQ past since + 1 tells the subscriber it lost events
+    /// that no longer fit in the buffer. The stream is the subscriber's
+    /// alone and stays nonblocking; every write waits for it at most the
+    /// write timeout.
+    pub fn subscribe(&mut self, stream: UnixStream, since: Option<u64>) {
+        let backlog: Vec<&(u64, String)> = match since {
+            Some(since) => self.events.iter().filter(|(seq, _)| *seq > since).collect(),
+            None => Vec::new(),
+        };
+        let first = backlog.first().map_or(self.next, |(seq, _)| *seq);
+        let sent = std::iter::once(format!("ok {}\n", first))
+            .chain(backlog.into_iter().map(|(_, line)| line.clone()))
+            .all(|line| control::write_within(&stream, line.as_bytes(), WRITE_TIMEOUT).is_ok());
+        if sent {
+            self.subscribers.push(stream);
+        }
+    }
+}
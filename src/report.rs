@@ -0,0 +1,118 @@
+use rusb::UsbContext;
+
+use crate::inventory;
+use crate::logfile;
+use crate::template;
+use crate::{Filter, ReportArgs, ReportFormat};
+
+/// What the report says about one device.
+struct Row {
+    port: String,
+    id: String,
+    name: String,
+    serial: String,
+    revision: String,
+    power: String,
+}
+
+/// The kernel's port path sorts parents before their children once the
+/// numbers in it are compared as numbers, e.g. 1-2 < 1-2.1 < 1-10.
+pub fn topology_key(port: &str) -> Vec<u32> {
+    let port = port.strip_prefix("usb").map_or_else(|| port.to_string(), |bus| format!("{}-0", bus));
+    port.split(['-', '.']).map(|n| n.parse().unwrap_or(u32::MAX)).collect()
+}
+
+/// Hub depth, 0 for root hubs.
+fn depth(port: &str) -> usize {
+    if port.starts_with("usb") || port.is_empty() {
+        0
+    } else {
+        port.matches('.').count() + 1
+    }
+}
+
+fn rows<T: UsbContext>(ctx: &T, filter: &Filter) -> rusb::Result<Vec<Row>> {
+    let mut rows: Vec<Row> = inventory::scan(ctx, filter)?
+        .iter()
+        .map(|entry| {
+            let dev = inventory::find(ctx, entry);
+            Row{
+                port: entry.port.clone(),
+                id: entry.id.to_string(),
+                name: dev.as_ref().map(inventory::name).unwrap_or_default(),
+                serial: dev.as_ref().map(inventory::serial).unwrap_or_default(),
+                revision: dev.as_ref().map(inventory::revision).unwrap_or_default(),
+                power: dev.as_ref().and_then(inventory::max_power).map(|ma| format!("{} mA", ma)).unwrap_or_default(),
+            }
+        })
+        .collect();
+    rows.sort_by_key(|row| topology_key(&row.port));
+    Ok(rows)
+}
+
+/// Escapes what would break out of a table cell.
+fn markdown_cell(text: &str) -> String {
+    if text.is_empty() {
+        return String::from("-")
+    }
+    text.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn markdown(rows: &[Row]) -> String {
+    let mut out = format!("# USB inventory of {}\n\nTaken {}, {} device(s).\n\n", template::hostname(), logfile::timestamp(), rows.len());
+    out.push_str("| Port | ID | Name | Serial | Revision | Max power |\n|---|---|---|---|---|---|\n");
+    for row in rows {
+        out.push_str(&format!("| {} | {} | {} | {} | {} | {} |\n",
+            markdown_cell(&row.port), row.id, markdown_cell(&row.name), markdown_cell(&row.serial), markdown_cell(&row.revision),
+            markdown_cell(&row.power)));
+    }
+    out.push_str("\n## Topology\n\n");
+    for row in rows {
+        let name = if row.name.is_empty() { String::new() } else { format!(" {}", row.name) };
+        out.push_str(&format!("{}- `{}` {}{}\n", "  ".repeat(depth(&row.port)), row.port, row.id, name));
+    }
+    out
+}
+
+fn html_text(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn html(rows: &[Row]) -> String {
+    let host = html_text(&template::hostname());
+    let mut out = format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>USB inventory of {}</title>\n</head>\n<body>\n\
+        <h1>USB inventory of {}</h1>\n<p>Taken {}, {} device(s).</p>\n", host, host, logfile::timestamp(), rows.len());
+    out.push_str("<table>\n<tr><th>Port</th><th>ID</th><th>Name</th><th>Serial</th><th>Revision</th><th>Max power</th></tr>\n");
+    for row in rows {
+        out.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            html_text(&row.port), row.id, html_text(&row.name), html_text(&row.serial), html_text(&row.revision), row.power));
+    }
+    out.push_str("</table>\n<h2>Topology</h2>\n");
+    // nested lists, opened and closed as the depth changes
+    let mut open = 0;
+    for row in rows {
+        let level = depth(&row.port) + 1;
+        while open < level {
+            out.push_str("<ul>\n");
+            open += 1;
+        }
+        while open > level {
+            out.push_str("</ul>\n");
+            open -= 1;
+        }
+        out.push_str(&format!("<li><code>{}</code> {} {}</li>\n", html_text(&row.port), row.id, html_text(&row.name)));
+    }
+    out.push_str(&"</ul>\n".repeat(open));
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+pub fn run(args: &ReportArgs) -> rusb::Result<()> {
+    let ctx = rusb::Context::new()?;
+    let rows = rows(&ctx, &Filter::new(&args.filter))?;
+    match args.format {
+        ReportFormat::Markdown => print!("{}", markdown(&rows)),
+        ReportFormat::Html => print!("{}", html(&rows)),
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::json;
+use crate::logfile;
+use crate::state;
+
+/// How often a log with a max age is pruned while it is written.
+const INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How long a JSON event log keeps its events and how many it keeps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Retention {
+    pub max_age: Option<Duration>,
+    pub max_rows: Option<usize>,
+}
+
+impl Retention {
+    pub fn is_empty(&self) -> bool {
+        self.max_age.is_none() && self.max_rows.is_none()
+    }
+
+    /// Rewrites the log without the events older than max_age and all but
+    /// the newest max_rows, aside and renamed into place so the space is
+    /// given back and a reader never sees half of it. Ages are taken in
+    /// seconds since the epoch, so they hold across DST changes. Lines
+    /// without a time only go by count. Returns how many lines were kept
+    /// and dropped.
+    pub fn prune(&self, path: &Path) -> io::Result<(usize, usize)> {
+        self.prune_at(path, state::now())
+    }
+
+    fn prune_at(&self, path: &Path, now: i64) -> io::Result<(usize, usize)> {
+        let cutoff = self.max_age.map(|age| (now - age.as_secs() as i64) as libc::time_t);
+        let mut read = 0;
+        let mut lines = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            read += 1;
+            let expired = cutoff.is_some_and(|cutoff| json::get(&line, "time").and_then(|time| logfile::epoch(&time)).is_some_and(|time| time < cutoff));
+            if !expired {
+                lines.push(line);
+            }
+        }
+        let excess = self.max_rows.map_or(0, |max| lines.len().saturating_sub(max));
+        let kept = &lines[excess..];
+        if kept.len() < read {
+            let partial = PathBuf::from(format!("{}.partial", path.display()));
+            let mut file = File::create(&partial)?;
+            for line in kept {
+                writeln!(file, "{}", line)?;
+            }
+            file.sync_all()?;
+            fs::rename(&partial, path)?;
+        }
+        Ok((kept.len(), read - kept.len()))
+    }
+}
+
+/// Prunes a log as it is appended to: on open, once it has a tenth more
+/// rows than it keeps, and hourly when events expire.
+pub struct Pruner {
+    path: PathBuf,
+    retention: Retention,
+    rows: usize,
+    pruned: Instant,
+}
+
+impl Pruner {
+    pub fn new(path: &Path, retention: Retention) -> io::Result<Pruner> {
+        let mut pruner = Pruner{path: path.to_path_buf(), retention, rows: 0, pruned: Instant::now()};
+        pruner.prune()?;
+        Ok(pruner)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn prune(&mut self) -> io::Result<()> {
+        self.rows = match self.retention.prune(&self.path) {
+            Ok((kept, _)) => kept,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        self.pruned = Instant::now();
+        Ok(())
+    }
+
+    /// Counts a written line and prunes when due. True if the file was
+    /// replaced, and whoever appends to it has to reopen it.
+    pub fn written(&mut self) -> io::Result<bool> {
+        self.rows += 1;
+        let full = self.retention.max_rows.is_some_and(|max| self.rows > max + max / 10);
+        let stale = self.retention.max_age.is_some() && self.pruned.elapsed() >= INTERVAL;
+        if full || stale {
+            self.prune()?;
+            return Ok(true)
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(time: i64, n: usize) -> String {
+        format!("{{\"time\":\"{}\",\"type\":\"attach\",\"text\":\"{}\"}}", logfile::local_time(time as libc::time_t), n)
+    }
+
+    /// Prunes a log of these lines and returns what was kept.
+    fn prune(name: &str, retention: Retention, lines: &[String], now: i64) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("usbmon-retention-{}-{}", name, std::process::id()));
+        fs::write(&path, lines.iter().map(|line| format!("{}\n", line)).collect::<String>()).unwrap();
+        let (kept, dropped) = retention.prune_at(&path, now).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        _ = fs::remove_file(&path);
+        assert_eq!(kept + dropped, lines.len());
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn max_age_drops_by_epoch_seconds() {
+        let now = 1_700_000_000;
+        let lines: Vec<String> = (0..4).map(|n| event(now - 3600 * (3 - n as i64), n)).chain([String::from("no time")]).collect();
+        let retention = Retention{max_age: Some(Duration::from_secs(3600 + 1)), max_rows: None};
+        assert_eq!(prune("age", retention, &lines, now), &lines[2..]);
+    }
+
+    #[test]
+    fn max_rows_keeps_the_newest() {
+        let lines: Vec<String> = (0..5).map(|n| event(1_700_000_000 + n as i64, n)).collect();
+        let retention = Retention{max_age: None, max_rows: Some(2)};
+        assert_eq!(prune("rows", retention, &lines, 0), &lines[3..]);
+        assert_eq!(prune("none", Retention::default(), &lines, 0), lines);
+    }
+
+    #[test]
+    fn times_read_back_as_written() {
+        let times: [libc::time_t; 3] = [0, 1_700_000_000, 1_720_000_000];
+        for secs in times {
+            assert_eq!(logfile::epoch(&logfile::local_time(secs)), Some(secs));
+        }
+        assert_eq!(logfile::epoch("2024-01-31"), None);
+        assert_eq!(logfile::epoch("2024-01-31 08:00:00"), None);
+        assert_eq!(logfile::epoch("2024-01-31T08:00:xx"), None);
+    }
+}
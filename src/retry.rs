@@ -0,0 +1,47 @@
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+/// How often and how patiently a libusb call is tried again.
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    /// Tries after the first.
+    pub retries: u32,
+    /// Before the first retry, doubling for each one after.
+    pub backoff: Duration,
+}
+
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+/// Sets the policy for the rest of the run. Until then nothing is retried.
+pub fn configure(policy: Policy) {
+    _ = POLICY.set(policy);
+}
+
+/// Errors a device being replugged or reset causes for a moment, which are
+/// gone by the next try.
+fn transient(e: &rusb::Error) -> bool {
+    matches!(e, rusb::Error::NoDevice | rusb::Error::Io | rusb::Error::Busy | rusb::Error::Interrupted)
+}
+
+/// Runs a libusb call, trying again with backoff while it fails with a
+/// transient error. The last error is returned once the retries run out.
+pub fn run<R>(mut call: impl FnMut() -> rusb::Result<R>) -> rusb::Result<R> {
+    let policy = POLICY.get().copied().unwrap_or(Policy{retries: 0, backoff: Duration::ZERO});
+    let mut delay = policy.backoff;
+    for _ in 0..policy.retries {
+        match call() {
+            Err(e) if transient(&e) => {
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+            },
+            result => return result,
+        }
+    }
+    call()
+}
+
+/// The device list of the default context, with retries.
+pub fn devices() -> rusb::Result<rusb::DeviceList<rusb::GlobalContext>> {
+    run(rusb::devices)
+}
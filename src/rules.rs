@@ -0,0 +1,227 @@
+use std::io;
+use std::process::Command;
+
+use rusb::UsbContext;
+
+use crate::config::{invalid, Config, Section};
+use crate::descriptors;
+use crate::json;
+use crate::matcher::Matcher;
+use crate::notify::Notifiers;
+use crate::output::Output;
+use crate::script;
+use crate::sysfs;
+use crate::template::{self, Fields};
+use crate::workers::Workers;
+use crate::{parse_device, parse_pid, parse_vid, Class, Filter, Speed};
+
+const DEFAULT_WORKERS: usize = 4;
+
+/// What to do when a device matching the rule's filter has an event,
+/// from a `[rule <name>]` section:
+///
+/// ```text
+/// [rule flasher]
+/// id = 0483:df11
+/// vid = 0483
+/// class = dfu
+/// speed = high, super
+/// max_power = 500
+/// events = attach
+/// exec = dfu-util -a 0 -D /srv/firmware.bin
+/// script = python3 /etc/usbmon/flash.py
+/// log = flashing {id} on {bus}:{address}
+/// notify = webhook
+/// authorize = true
+/// ```
+///
+/// exec runs through `sh -c` with every field in the environment as
+/// `USBMON_<FIELD>`. script runs a program the same way, in whatever
+/// language it is written in, for logic that is more than a command line:
+/// it gets the event and its descriptors as JSON on stdin and can ask for
+/// exec and http_post on stdout; see script::run. Nothing is embedded, it
+/// is a subprocess like exec.
+/// authorize writes the device's sysfs authorized
+/// attribute, so `authorize = false` keeps a device from binding drivers
+/// where USB authorization is enabled. Classes can only be checked while
+/// the device is there, so a rule with a class never matches detach or
+/// absent events.
+///
+/// Commands and scripts run on a pool of worker threads, four unless a `[rules]`
+/// section sets `workers`, so a slow one doesn't hold up the events after
+/// it. Those for the same vid:pid still run one at a time, in order.
+pub struct Rule {
+    name: String,
+    matcher: Matcher,
+    events: Vec<String>,
+    exec: Option<String>,
+    script: Option<String>,
+    log: Option<String>,
+    notify: Vec<String>,
+    authorize: Option<bool>,
+}
+
+fn boolean(section: &Section, key: &str) -> io::Result<Option<bool>> {
+    match section.get(key) {
+        None => Ok(None),
+        Some("true") | Some("yes") | Some("1") => Ok(Some(true)),
+        Some("false") | Some("no") | Some("0") => Ok(Some(false)),
+        Some(value) => Err(invalid(format!("[{}] {}: expected true or false, not {}", section.name(), key, value))),
+    }
+}
+
+/// The filter of a section's `id`, `vid`, `pid` and `class` keys.
+pub fn filter(section: &Section) -> io::Result<Filter> {
+    let ids = section
+        .list("id")
+        .iter()
+        .map(|id| parse_device(id).map_err(|e| invalid(format!("[{}] id: {}", section.name(), e))))
+        .collect::<io::Result<Vec<_>>>()?;
+    let hex = |key: &str, parse: fn(&str) -> crate::Result<u16>| section
+        .list(key)
+        .iter()
+        .map(|value| parse(value).map_err(|e| invalid(format!("[{}] {}: {}", section.name(), key, e))))
+        .collect::<io::Result<Vec<u16>>>();
+    let (vids, pids) = (hex("vid", parse_vid)?, hex("pid", parse_pid)?);
+    let classes = section
+        .list("class")
+        .iter()
+        .map(|class| <Class as clap::ValueEnum>::from_str(class, true)
+            .map_err(|_| invalid(format!("[{}] class: unknown class {}", section.name(), class))))
+        .collect::<io::Result<Vec<_>>>()?;
+    let speeds = section
+        .list("speed")
+        .iter()
+        .map(|speed| <Speed as clap::ValueEnum>::from_str(speed, true)
+            .map_err(|_| invalid(format!("[{}] speed: unknown speed {}", section.name(), speed))))
+        .collect::<io::Result<Vec<_>>>()?;
+    let max_power = section
+        .get("max_power")
+        .map(|ma| ma.parse().map_err(|_| invalid(format!("[{}] max_power: {} isn't a number of mA", section.name(), ma))))
+        .transpose()?;
+    Ok(Filter{ids, vids, pids, classes, speeds, max_power, ..Default::default()})
+}
+
+impl Rule {
+    fn new(name: &str, section: &Section, config: &Config) -> io::Result<Rule> {
+        let notify = section.list("notify");
+        if let Some(missing) = notify.iter().find(|n| config.section(n).is_none()) {
+            return Err(invalid(format!("[{}] notify: there is no [{}] section", section.name(), missing)))
+        }
+        let events = section.list("events");
+        Ok(Rule{
+            name: name.to_string(),
+            matcher: Matcher::new(&filter(section)?),
+            events: if events.is_empty() { vec![String::from("attach")] } else { events },
+            exec: section.get("exec").map(String::from),
+            script: section.get("script").map(String::from),
+            log: section.get("log").map(String::from),
+            notify,
+            authorize: boolean(section, "authorize")?,
+        })
+    }
+
+    /// The rule of the config's `[rule NAME]` section.
+    pub fn named(config: &Config, name: &str) -> io::Result<Rule> {
+        match config.sections("rule").find(|(n, _)| *n == name) {
+            Some((name, section)) => Rule::new(name, section, config),
+            None => Err(invalid(format!("there is no [rule {}] section", name))),
+        }
+    }
+
+    /// Whether the rule looks at more than the ids and the event.
+    pub fn needs_device(&self) -> bool {
+        self.matcher.needs_device()
+    }
+
+    /// Whether the rule fires for an event of a log, which has no device
+    /// to look at, as for a live detach.
+    pub fn matches_logged(&self, kind: &str, id: &str) -> bool {
+        let fields: Fields = vec![("event", kind.to_string()), ("id", id.to_string())];
+        self.matches::<rusb::GlobalContext>(&fields, None)
+    }
+
+    fn matches<T: UsbContext>(&self, fields: &Fields, device: Option<&rusb::Device<T>>) -> bool {
+        if !self.events.iter().any(|e| e == template::get(fields, "event")) {
+            return false
+        }
+        match device {
+            Some(device) => self.matcher.matches(device),
+            None => !self.matcher.needs_device()
+                && parse_device(template::get(fields, "id")).is_ok_and(|id| self.matcher.matches_id(&id)),
+        }
+    }
+
+    fn apply<T: UsbContext>(&self, fields: &Fields, device: Option<&rusb::Device<T>>, workers: &Workers, notifiers: &mut Notifiers, out: &mut Output) {
+        if let Some(log) = &self.log {
+            out.line(&format!("* {}: {}", self.name, template::render(log, fields)));
+        }
+        if let (Some(authorize), Some(device)) = (self.authorize, device) {
+            if let Err(e) = sysfs::authorize(device, authorize) {
+                eprintln!("rule {}: failed to authorize {}: {}", self.name, template::get(fields, "id"), e);
+            }
+        }
+        let env: Vec<(String, String)> = fields
+            .iter()
+            .map(|(name, value)| (format!("USBMON_{}", name.to_uppercase()), value.clone()))
+            .collect();
+        if let Some(cmd) = &self.exec {
+            let (name, cmd, env) = (self.name.clone(), cmd.clone(), env.clone());
+            workers.run(template::get(fields, "id"), move || {
+                let status = Command::new("sh").arg("-c").arg(&cmd).envs(env).status();
+                if let Err(e) = status {
+                    eprintln!("rule {}: failed to run {}: {}", name, cmd, e);
+                }
+            });
+        }
+        if let Some(path) = &self.script {
+            let mut object: Vec<(&str, String)> = vec![("type", json::string("event"))];
+            object.extend(fields.iter().map(|(name, value)| (*name, json::string(value))));
+            // as the kernel read them, empty once the device is gone
+            let raw = device.and_then(sysfs::descriptors).unwrap_or_default();
+            object.push(("descriptors", json::string(&descriptors::hex_bytes(&raw).replace(' ', ""))));
+            let (name, path, event) = (self.name.clone(), path.clone(), json::object(&object));
+            workers.run(template::get(fields, "id"), move || {
+                if let Err(e) = script::run(&name, &path, &event, &env) {
+                    eprintln!("rule {}: script: {}", name, e);
+                }
+            });
+        }
+        for name in &self.notify {
+            notifiers.deliver(name, fields);
+        }
+    }
+}
+
+/// Every rule of the config file, evaluated in file order.
+pub struct Rules {
+    rules: Vec<Rule>,
+    workers: Workers,
+}
+
+
+impl Rules {
+    pub fn new(config: &Config) -> io::Result<Rules> {
+        let rules = config
+            .sections("rule")
+            .map(|(name, section)| Rule::new(name, section, config))
+            .collect::<io::Result<Vec<Rule>>>()?;
+        let workers = match config.section("rules").and_then(|s| s.get("workers")) {
+            Some(n) => n.parse().map_err(|_| invalid(format!("[rules] workers: expected a number, not {}", n)))?,
+            None => DEFAULT_WORKERS,
+        };
+        Ok(Rules{rules, workers: Workers::new(workers)})
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Runs the actions of every rule matching the event. The device is
+    /// None for events of devices that are gone.
+    pub fn apply<T: UsbContext>(&self, fields: &Fields, device: Option<&rusb::Device<T>>, notifiers: &mut Notifiers, out: &mut Output) {
+        for rule in self.rules.iter().filter(|rule| rule.matches(fields, device)) {
+            rule.apply(fields, device, &self.workers, notifiers, out);
+        }
+    }
+}
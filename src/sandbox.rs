@@ -0,0 +1,222 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc00000b7;
+
+/// Set in the numbers of x32 syscalls, which share x86_64's audit arch.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x40000000;
+
+// landlock ABI 1 filesystem rights
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+const ACCESS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_ALL: u64 = (1 << 13) - 1;
+const ACCESS_FILE: u64 = ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+const READ: u64 = ACCESS_READ_FILE | ACCESS_READ_DIR;
+const EXEC: u64 = READ | ACCESS_EXECUTE;
+
+/// Syscalls nothing in usbmon needs and an attacker would love.
+const DENIED: &[libc::c_long] = &[
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_kexec_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_open_by_handle_at,
+];
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneath {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+fn statement(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter{code: code as u16, jt, jf, k}
+}
+
+/// Makes the denied syscalls fail with EPERM, and kills the process on
+/// syscalls of a foreign architecture or, on x86_64, of the x32 ABI,
+/// which would dodge the numbers. Covers every thread of the process.
+fn seccomp() -> io::Result<()> {
+    let mut program = vec![
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 4, 0, 0),
+        statement(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, AUDIT_ARCH, 1, 0),
+        statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS, 0, 0),
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 0, 0, 0),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    program.extend([
+        statement(libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K, X32_SYSCALL_BIT, 0, 1),
+        statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS, 0, 0),
+    ]);
+    for nr in DENIED {
+        program.push(statement(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, *nr as u32, 0, 1));
+        program.push(statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ERRNO | libc::EPERM as u32, 0, 0));
+    }
+    program.push(statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW, 0, 0));
+    let fprog = libc::sock_fprog{len: program.len() as u16, filter: program.as_mut_ptr()};
+    // SAFETY: the program outlives the call, the kernel copies it
+    let result = unsafe {
+        libc::syscall(libc::SYS_seccomp, libc::SECCOMP_SET_MODE_FILTER, libc::SECCOMP_FILTER_FLAG_TSYNC, &fprog)
+    };
+    match result {
+        0 => Ok(()),
+        // the thread that has a filter of its own the others can't share
+        tid if tid > 0 => Err(io::Error::other(format!("thread {} can't take the seccomp filter", tid))),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn add_rule(ruleset: libc::c_long, path: &Path, access: u64) -> io::Result<()> {
+    let cpath = match CString::new(path.as_os_str().as_encoded_bytes()) {
+        Ok(cpath) => cpath,
+        Err(_) => return Ok(()),
+    };
+    // SAFETY: O_PATH descriptor we close right after adding the rule
+    let fd = unsafe { libc::open(cpath.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        // nothing there yet, nothing to grant
+        return Ok(())
+    }
+    // files only take file rights
+    let access = if path.is_dir() { access } else { access & ACCESS_FILE };
+    let rule = PathBeneath{allowed_access: access, parent_fd: fd};
+    // SAFETY: rule is a valid path_beneath_attr for the duration of the call
+    let result = unsafe { libc::syscall(libc::SYS_landlock_add_rule, ruleset, RULE_PATH_BENEATH, &rule, 0) };
+    // SAFETY: fd came from open above
+    unsafe { libc::close(fd) };
+    if result != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// The sysfs directories of the root hubs there are, where the
+/// authorized and power attributes of every device and port below them
+/// are. Landlock goes by where a path leads, so /sys/bus/usb/devices,
+/// which only has links into them, wouldn't cover them.
+fn usb_device_dirs() -> Vec<PathBuf> {
+    let entries = match fs::read_dir("/sys/bus/usb/devices") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("usb"))
+        .filter_map(|entry| fs::canonicalize(entry.path()).ok())
+        .collect()
+}
+
+/// Confines the filesystem to what watch needs: USB device nodes, sysfs
+/// to read and the attributes of USB devices to write, the udev database,
+/// programs for actions and plugins, and the files it was given. Files
+/// it writes may also be created, renamed and removed, as pid files and
+/// rotated logs are, and sockets made where they go. Network sockets
+/// aren't covered by landlock ABI 1. Devices on a USB controller that
+/// shows up later can't be authorized or have their power changed.
+fn landlock(read: &[&Path], write: &[&Path]) -> io::Result<()> {
+    let attr = RulesetAttr{handled_access_fs: ACCESS_ALL};
+    // SAFETY: attr is a valid ruleset_attr of the size passed
+    let ruleset = unsafe {
+        libc::syscall(libc::SYS_landlock_create_ruleset, &attr, mem::size_of::<RulesetAttr>(), 0)
+    };
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error())
+    }
+    let mut rules: Vec<(&Path, u64)> = vec![
+        (Path::new("/dev/bus/usb"), READ | ACCESS_WRITE_FILE),
+        (Path::new("/dev/null"), ACCESS_READ_FILE | ACCESS_WRITE_FILE),
+        (Path::new("/dev/urandom"), ACCESS_READ_FILE),
+        (Path::new("/sys"), READ),
+        (Path::new("/run/udev"), READ),
+        (Path::new("/proc"), READ),
+        (Path::new("/etc"), READ),
+        (Path::new("/usr"), EXEC),
+        (Path::new("/bin"), EXEC),
+        (Path::new("/sbin"), EXEC),
+        (Path::new("/lib"), EXEC),
+        (Path::new("/lib64"), EXEC),
+    ];
+    let usb = usb_device_dirs();
+    rules.extend(usb.iter().map(|dir| (dir.as_path(), READ | ACCESS_WRITE_FILE)));
+    rules.extend(read.iter().map(|file| (*file, ACCESS_READ_FILE)));
+    for file in write {
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        rules.push((dir, READ | ACCESS_WRITE_FILE | ACCESS_MAKE_REG | ACCESS_MAKE_SOCK | ACCESS_REMOVE_FILE));
+    }
+    let result = rules
+        .iter()
+        .try_for_each(|(path, access)| add_rule(ruleset, path, *access))
+        .and_then(|_| {
+            // SAFETY: restricts this thread and the threads and children it
+            // starts from now on
+            match unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) } {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            }
+        });
+    // SAFETY: the ruleset descriptor isn't used past this point
+    unsafe { libc::close(ruleset as libc::c_int) };
+    result
+}
+
+/// Applies both for the rest of the process' life, children included.
+/// Landlock only confines the calling thread and what it starts later,
+/// so this has to run before any other thread is started, libusb's
+/// among them. Kernels without landlock still get the seccomp filter.
+pub fn apply(read: &[&Path], write: &[&Path]) -> io::Result<()> {
+    let status = fs::read_to_string("/proc/self/status")?;
+    let threads = status.lines().find_map(|line| line.strip_prefix("Threads:")).map(str::trim);
+    if threads.is_some_and(|n| n != "1") {
+        return Err(io::Error::other("other threads are already running and wouldn't be confined"))
+    }
+    // SAFETY: no pointers involved
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    match landlock(read, write) {
+        Err(e) if e.raw_os_error() == Some(libc::ENOSYS) || e.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+            eprintln!("landlock isn't available, only applying seccomp");
+        },
+        result => result?,
+    }
+    seccomp()
+}
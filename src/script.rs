@@ -0,0 +1,104 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+use crate::webhook;
+
+/// Runs the `script` of a rule: a program of any language, through
+/// `sh -c` like exec, that gets the event as one JSON object on its stdin,
+/// with the same fields as plugins get and the device's descriptors:
+///
+/// ```text
+/// {"type":"event","event":"attach","id":"1a2b:5678",...,"descriptors":"12010002..."}
+/// ```
+///
+/// and decides what to do about it. It can ask for what usbmon does for
+/// rules, one request per line on its stdout, each answered with a line
+/// on its stdin:
+///
+/// ```text
+/// exec <command>            ok <exit status>
+/// http_post <url> <body>    ok
+/// ```
+///
+/// or `error <why>`. exec runs through `sh -c` with the event in the
+/// environment as for rules; http_post posts the body as JSON with curl.
+/// The script is done when it exits.
+pub fn run(rule: &str, script: &str, event: &str, env: &[(String, String)]) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .envs(env.iter().cloned())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().ok_or(io::ErrorKind::BrokenPipe)?;
+    let stdout = child.stdout.take().ok_or(io::ErrorKind::BrokenPipe)?;
+    // a script that doesn't read the event may still make requests
+    _ = writeln!(stdin, "{}", event).and_then(|_| stdin.flush());
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        let answer = match request(&line, env) {
+            Ok(text) if text.is_empty() => String::from("ok"),
+            Ok(text) => format!("ok {}", text),
+            Err(e) => {
+                eprintln!("rule {}: script: {}", rule, e);
+                format!("error {}", e)
+            },
+        };
+        // the script may have stopped listening, it can still carry on
+        _ = writeln!(stdin, "{}", answer).and_then(|_| stdin.flush());
+    }
+    drop(stdin);
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} exited with {}", script, status)))
+    }
+    Ok(())
+}
+
+fn request(line: &str, env: &[(String, String)]) -> Result<String, String> {
+    let (verb, rest) = line.split_once(' ').unwrap_or((line, ""));
+    match verb {
+        "exec" if !rest.trim().is_empty() => {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(rest)
+                .envs(env.iter().cloned())
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .status()
+                .map_err(|e| format!("exec {}: {}", rest, e))?;
+            // killed by a signal is as unsuccessful as it gets
+            Ok(status.code().unwrap_or(-1).to_string())
+        },
+        "http_post" => {
+            let (url, body) = rest.split_once(' ').ok_or("expected http_post URL BODY")?;
+            webhook::post(url, body).map_err(|e| format!("http_post {}: {}", url, e))?;
+            Ok(String::new())
+        },
+        _ => Err(format!("unknown request {}", line.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn script_reads_the_event_and_gets_answers() {
+        let out = std::env::temp_dir().join(format!("usbmon-script-{}", std::process::id()));
+        let script = format!(
+            "read event; echo 'exec exit 3'; read exec; echo 'frobnicate'; read unknown; printf '%s\\n%s\\n%s\\n' \"$event\" \"$exec\" \"$unknown\" > {}",
+            out.display());
+        let env = [(String::from("USBMON_ID"), String::from("1a2b:5678"))];
+        run("test", &script, r#"{"type":"event","id":"1a2b:5678"}"#, &env).unwrap();
+        let written = fs::read_to_string(&out).unwrap();
+        _ = fs::remove_file(&out);
+        assert_eq!(written, "{\"type\":\"event\",\"id\":\"1a2b:5678\"}\nok 3\nerror unknown request frobnicate\n");
+    }
+
+    #[test]
+    fn failing_script_is_an_error() {
+        assert!(run("test", "exit 1", "{}", &[]).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use rusb::UsbContext;
+
+use crate::inventory;
+use crate::{Class, Filter};
+
+/// How many devices get opened at most.
+const OPEN_LIMIT: usize = 5;
+const ROUNDS: u32 = 10;
+/// A poll round taking longer than this would make watch lag noticeably.
+const LATENCY_LIMIT: Duration = Duration::from_millis(250);
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Summary {
+    passed: usize,
+    failed: usize,
+}
+
+impl Summary {
+    fn record(&mut self, name: &str, result: Result<String, String>) {
+        match result {
+            Ok(detail) => {
+                self.passed += 1;
+                println!("PASS  {}: {}", name, detail);
+            },
+            Err(detail) => {
+                self.failed += 1;
+                println!("FAIL  {}: {}", name, detail);
+            },
+        }
+    }
+}
+
+fn enumeration(ctx: &rusb::Context) -> Result<String, String> {
+    let devices = ctx.devices().map_err(|e| e.to_string())?;
+    if devices.is_empty() {
+        return Err(String::from("no devices, not even root hubs"))
+    }
+    let unreadable = devices.iter().filter(|dev| dev.device_descriptor().is_err()).count();
+    if unreadable > 0 {
+        return Err(format!("{} of {} device descriptors unreadable", unreadable, devices.len()))
+    }
+    Ok(format!("{} devices", devices.len()))
+}
+
+/// Opens a few devices other than hubs and reads their string languages,
+/// which only takes a control transfer and changes nothing.
+fn open(ctx: &rusb::Context) -> Result<String, String> {
+    let devices = ctx.devices().map_err(|e| e.to_string())?;
+    let candidates: Vec<_> = devices
+        .iter()
+        .filter(|dev| dev.device_descriptor().is_ok_and(|desc| Some(desc.class_code()) != Class::Hub.code()))
+        .take(OPEN_LIMIT)
+        .collect();
+    if candidates.is_empty() {
+        return Ok(String::from("no devices besides hubs, skipped"))
+    }
+    let mut errors = Vec::new();
+    for dev in &candidates {
+        let result = dev.open().and_then(|handle| handle.read_languages(TIMEOUT));
+        if let Err(e) = result {
+            errors.push(format!("{:03}:{:03} {}", dev.bus_number(), dev.address(), e));
+        }
+    }
+    if errors.len() == candidates.len() {
+        return Err(format!("none could be opened: {}", errors.join(", ")))
+    }
+    Ok(format!("{} of {} opened", candidates.len() - errors.len(), candidates.len()))
+}
+
+/// Times the rescans watch does each interval.
+fn latency(ctx: &rusb::Context) -> Result<String, String> {
+    let filter = Filter::default();
+    let mut total = Duration::ZERO;
+    let mut worst = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        inventory::scan(ctx, &filter).map_err(|e| e.to_string())?;
+        let spent = start.elapsed();
+        total += spent;
+        worst = worst.max(spent);
+    }
+    let text = format!("{:?} average, {:?} worst over {} scans", total / ROUNDS, worst, ROUNDS);
+    if worst > LATENCY_LIMIT {
+        return Err(format!("{}, over {:?}", text, LATENCY_LIMIT))
+    }
+    Ok(text)
+}
+
+/// Runs each check, printing one line per check and a summary. Returns
+/// whether everything passed.
+pub fn run() -> bool {
+    let mut summary = Summary{passed: 0, failed: 0};
+    match rusb::Context::new() {
+        Ok(ctx) => {
+            summary.record("libusb", Ok(String::from("initialized")));
+            summary.record("enumeration", enumeration(&ctx));
+            summary.record("open", open(&ctx));
+            summary.record("poll latency", latency(&ctx));
+        },
+        Err(e) => summary.record("libusb", Err(e.to_string())),
+    }
+    println!("{} passed, {} failed", summary.passed, summary.failed);
+    summary.failed == 0
+}
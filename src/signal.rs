@@ -0,0 +1,39 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_stop(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_reload(_: libc::c_int) {
+    RELOAD.store(true, Ordering::SeqCst);
+}
+
+/// Turns SIGINT and SIGTERM into a flag long-running loops poll, so they
+/// can finish up and pick their own exit status.
+pub fn catch_stop() {
+    // SAFETY: the handler only touches an atomic
+    unsafe {
+        libc::signal(libc::SIGINT, on_stop as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_stop as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+pub fn stopped() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+/// Turns SIGHUP into a request to reload the config file.
+pub fn catch_reload() {
+    // SAFETY: the handler only touches an atomic
+    unsafe {
+        libc::signal(libc::SIGHUP, on_reload as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+/// Whether SIGHUP arrived since the last call.
+pub fn reload_requested() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,176 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::debugfs::{self, Tracker};
+use crate::inventory::Entry;
+use crate::parse_device;
+
+#[derive(Debug, Clone)]
+pub enum Kind {
+    /// Every device of this host.
+    Local,
+    /// This host's devices without the ones imported with usbip.
+    Native,
+    /// Only devices imported with usbip.
+    Usbip,
+    /// The text event lines another `usbmon watch` prints, run through
+    /// the shell, e.g. `ssh host usbmon watch`.
+    Exec(String),
+    /// The URBs of the kernel's usbmon text interface for one bus, or
+    /// all of them, which needs debugfs mounted and root but not libusb.
+    Debugfs(Option<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Spec {
+    pub label: String,
+    pub kind: Kind,
+}
+
+/// Parses `LABEL=local|native|usbip|debugfs[:BUS]|exec:COMMAND`.
+pub fn parse_source(text: &str) -> Result<Spec, String> {
+    let (label, spec) = text.split_once('=').ok_or("expected LABEL=SOURCE")?;
+    if label.is_empty() {
+        return Err(String::from("empty label"))
+    }
+    let kind = match spec {
+        "local" => Kind::Local,
+        "native" => Kind::Native,
+        "usbip" => Kind::Usbip,
+        "debugfs" => Kind::Debugfs(None),
+        _ => if let Some(bus) = spec.strip_prefix("debugfs:") {
+            Kind::Debugfs(Some(bus.parse().map_err(|_| format!("invalid bus {}", bus))?))
+        } else {
+            match spec.strip_prefix("exec:") {
+                Some(command) if !command.is_empty() => Kind::Exec(command.to_string()),
+                _ => return Err(format!("unknown source {}, expected local, native, usbip, debugfs[:BUS] or exec:COMMAND", spec)),
+            }
+        },
+    };
+    Ok(Spec{label: label.to_string(), kind})
+}
+
+/// Whether the bus is a virtual host controller usbip attaches imports to.
+fn is_usbip(bus: u8) -> bool {
+    let root = Path::new("/sys/bus/usb/devices").join(format!("usb{}", bus));
+    fs::canonicalize(root)
+        .ok()
+        .and_then(|root| fs::read_link(root.parent()?.join("driver")).ok())
+        .is_some_and(|driver| driver.file_name().is_some_and(|name| name == "vhci_hcd"))
+}
+
+/// Parses an event line as `watch` prints it in text format,
+/// `+ 1a2b:5678 001:004`.
+fn parse_event(line: &str) -> Option<(bool, Entry)> {
+    let mut words = line.split_whitespace();
+    let attached = match words.next()? {
+        "+" => true,
+        "-" => false,
+        _ => return None,
+    };
+    let id = parse_device(words.next()?).ok()?;
+    let (bus, address) = words.next()?.split_once(':')?;
+    Some((attached, Entry{bus: bus.parse().ok()?, address: address.parse().ok()?, id, port: String::new()}))
+}
+
+struct Agent {
+    label: String,
+    /// None for a debugfs stream, which is read in the agent's place.
+    child: Option<Child>,
+    events: mpsc::Receiver<(bool, Entry)>,
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Reads the events of a source's lines in a thread of its own.
+fn read(label: &str, stream: impl Read + Send + 'static, mut event: impl FnMut(&str) -> Option<(bool, Entry)> + Send + 'static) -> mpsc::Receiver<(bool, Entry)> {
+    let (sender, events) = mpsc::channel();
+    let label = label.to_string();
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if let Some(event) = event(&line) {
+                if sender.send(event).is_err() {
+                    return
+                }
+            }
+        }
+        eprintln!("source {}: agent exited", label);
+    });
+    events
+}
+
+/// The sources one watch daemon merges. Without any given, this host's
+/// devices are watched under an empty label, so events look as before.
+pub struct Sources {
+    local: Vec<Spec>,
+    agents: Vec<Agent>,
+}
+
+impl Sources {
+    pub fn start(specs: &[Spec]) -> io::Result<Sources> {
+        if specs.is_empty() {
+            return Ok(Sources{local: vec![Spec{label: String::new(), kind: Kind::Local}], agents: Vec::new()})
+        }
+        let mut sources = Sources{local: Vec::new(), agents: Vec::new()};
+        for spec in specs {
+            match &spec.kind {
+                Kind::Exec(command) => {
+                    let mut child = Command::new("sh")
+                        .args(["-c", command])
+                        .stdin(Stdio::null())
+                        .stdout(Stdio::piped())
+                        .spawn()?;
+                    let stdout = child.stdout.take().ok_or(io::ErrorKind::BrokenPipe)?;
+                    let events = read(&spec.label, stdout, parse_event);
+                    sources.agents.push(Agent{label: spec.label.clone(), child: Some(child), events});
+                },
+                Kind::Debugfs(bus) => {
+                    // 0u carries every bus
+                    let path = Path::new(debugfs::DIR).join(format!("{}u", bus.unwrap_or(0)));
+                    let file = File::open(&path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+                    let mut tracker = Tracker::default();
+                    let events = read(&spec.label, file, move |line| tracker.track(&debugfs::parse(line)?));
+                    sources.agents.push(Agent{label: spec.label.clone(), child: None, events});
+                },
+                _ => sources.local.push(spec.clone()),
+            }
+        }
+        Ok(sources)
+    }
+
+    /// The label of the first local source that covers the device, None
+    /// if only other sources were asked for.
+    pub fn label(&self, entry: &Entry) -> Option<&str> {
+        let mut usbip = None;
+        for spec in &self.local {
+            let covered = match spec.kind {
+                Kind::Native => !*usbip.get_or_insert_with(|| is_usbip(entry.bus)),
+                Kind::Usbip => *usbip.get_or_insert_with(|| is_usbip(entry.bus)),
+                _ => true,
+            };
+            if covered {
+                return Some(&spec.label)
+            }
+        }
+        None
+    }
+
+    /// Events the agents reported since the last call, with their label.
+    pub fn remote(&self) -> Vec<(&str, bool, Entry)> {
+        self.agents
+            .iter()
+            .flat_map(|agent| agent.events.try_iter().map(|(attached, entry)| (agent.label.as_str(), attached, entry)))
+            .collect()
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::invalid;
+use crate::inventory::Entry;
+use crate::matcher::Matcher;
+use crate::parse_device;
+use crate::DeviceID;
+
+/// How stale the last seen times of devices still present may get in the
+/// file while nothing changes.
+const REFRESH: Duration = Duration::from_secs(60);
+
+/// When a device was last seen, and whether it was there then.
+pub struct Record {
+    pub id: DeviceID,
+    /// Seconds since the epoch.
+    pub seen: i64,
+    pub present: bool,
+    /// Since when a present device has been there, if that was seen.
+    pub since: Option<i64>,
+}
+
+/// The last seen times of devices across runs, kept in a small file of
+/// `vid:pid present|absent SECONDS [SINCE]` lines. Every run that looks at the
+/// bus brings it up to date, so it stays useful without a daemon.
+pub struct State {
+    path: PathBuf,
+    records: Vec<Record>,
+    saved: Option<Instant>,
+}
+
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+fn parse_record(line: &str) -> Option<Record> {
+    let mut words = line.split_whitespace();
+    let id = parse_device(words.next()?).ok()?;
+    let present = match words.next()? {
+        "present" => true,
+        "absent" => false,
+        _ => return None,
+    };
+    let seen = words.next()?.parse().ok()?;
+    let since = match words.next() {
+        Some(since) => Some(since.parse().ok()?),
+        None => None,
+    };
+    Some(Record{id, present, seen, since})
+}
+
+impl fmt::Display for Record {
+    /// As a line of the file.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = if self.present { "present" } else { "absent" };
+        write!(f, "{:04x}:{:04x} {} {}", self.id.vid, self.id.pid, state, self.seen)?;
+        match self.since {
+            Some(since) => write!(f, " {}", since),
+            None => Ok(()),
+        }
+    }
+}
+
+impl State {
+    /// Reads the file, starting empty if there is none yet.
+    pub fn load(path: &Path) -> io::Result<State> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let records = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|(n, line)| parse_record(line).ok_or_else(|| invalid(format!("{}:{}: expected vid:pid present|absent SECONDS [SINCE]", path.display(), n + 1))))
+            .collect::<io::Result<Vec<Record>>>()?;
+        Ok(State{path: path.to_path_buf(), records, saved: None})
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Marks the devices present as seen now and the others the scan
+    /// looked for as gone. Returns whether anything but the times changed.
+    pub fn update(&mut self, devices: &[Entry], scanned: &Matcher, now: i64) -> bool {
+        let mut changed = false;
+        for record in self.records.iter_mut().filter(|r| scanned.matches_id(&r.id)) {
+            let present = devices.iter().any(|e| e.id == record.id);
+            // a device that went away was last seen by the scan before
+            // this one, but this is the closest time there is
+            if present || record.present {
+                record.seen = now;
+            }
+            if present != record.present {
+                record.since = present.then_some(now);
+                changed = true;
+            }
+            record.present = present;
+        }
+        for entry in devices {
+            if !self.records.iter().any(|r| r.id == entry.id) {
+                self.records.push(Record{id: entry.id.clone(), seen: now, present: true, since: Some(now)});
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Writes the file aside and renames it into place, so a reader never
+    /// sees half of it.
+    pub fn save(&mut self) -> io::Result<()> {
+        let mut text = String::from("# usbmon last seen: vid:pid present|absent seen [since], seconds since the epoch\n");
+        for record in &self.records {
+            text.push_str(&format!("{}\n", record));
+        }
+        let partial = self.path.with_extension("partial");
+        self.path.parent().filter(|dir| !dir.as_os_str().is_empty()).map_or(Ok(()), fs::create_dir_all)?;
+        fs::write(&partial, text)?;
+        fs::rename(&partial, &self.path)?;
+        self.saved = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Updates and saves when something changed or the times have gone
+    /// stale, for a watch calling it every scan.
+    pub fn record(&mut self, devices: &[Entry], scanned: &Matcher) -> io::Result<()> {
+        let changed = self.update(devices, scanned, now());
+        if changed || self.saved.is_none_or(|saved| saved.elapsed() >= REFRESH) {
+            self.save()?;
+        }
+        Ok(())
+    }
+}
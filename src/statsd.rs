@@ -0,0 +1,50 @@
+use std::io;
+use std::net::UdpSocket;
+
+use crate::inventory::Entry;
+use crate::DeviceID;
+
+/// Fire-and-forget statsd client. Lost datagrams are as good as sent, so
+/// send errors are ignored.
+pub struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl Statsd {
+    pub fn connect(addr: &str, prefix: &str) -> io::Result<Statsd> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        Ok(Statsd{socket, prefix: prefix.to_string()})
+    }
+
+    fn send(&self, metric: &str) {
+        _ = self.socket.send(format!("{}.{}", self.prefix, metric).as_bytes());
+    }
+
+    /// Counts one attach or detach of the device.
+    pub fn event(&self, attached: bool, id: &DeviceID) {
+        let kind = if attached { "attach" } else { "detach" };
+        self.send(&format!("{}.{}:1|c", kind, key(id)));
+    }
+
+    /// Reports the device count and a 1/0 gauge per id. Without ids every
+    /// present device is reported as 1.
+    pub fn presence(&self, ids: &[DeviceID], devices: &[Entry]) {
+        self.send(&format!("devices:{}|g", devices.len()));
+        if ids.is_empty() {
+            for entry in devices {
+                self.send(&format!("present.{}:1|g", key(&entry.id)));
+            }
+        }
+        for id in ids {
+            let present = devices.iter().any(|e| &e.id == id);
+            self.send(&format!("present.{}:{}|g", key(id), present as u8));
+        }
+    }
+}
+
+/// Graphite treats ':' as a separator, so ids become `vvvv_pppp`.
+fn key(id: &DeviceID) -> String {
+    format!("{:04x}_{:04x}", id.vid, id.pid)
+}
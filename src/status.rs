@@ -0,0 +1,43 @@
+use crate::inventory;
+use crate::logfile;
+use crate::matcher::Matcher;
+use crate::state::{self, State};
+use crate::{format_duration, Filter, StatusArgs};
+
+/// Prints `vid:pid connected 3d 4h` or `vid:pid last seen 12m ago, TIME`
+/// for every device in the state file the filter allows, or with --raw its
+/// line of the file, and `never seen` for the --id it has no record of. When USB can be opened the devices connected now are
+/// recorded first; without it the file is all there is.
+pub fn run(args: &StatusArgs) -> rusb::Result<()> {
+    let filter = Filter::new(&args.filter);
+    let mut state = State::load(&args.state).map_err(|e| {
+        eprintln!("{}: {}", args.state.display(), e);
+        rusb::Error::Other
+    })?;
+    let current = rusb::Context::new().and_then(|ctx| inventory::scan(&ctx, &filter));
+    let live = current.is_ok();
+    if let Ok(devices) = current {
+        state.update(&devices, &Matcher::new(&filter), state::now());
+        // reading the state is worth it even where it can't be written
+        if let Err(e) = state.save() {
+            eprintln!("{}: {}", args.state.display(), e);
+        }
+    }
+    let matcher = Matcher::new(&filter);
+    let now = state::now();
+    let ago = |time: i64| format_duration(now.saturating_sub(time).max(0) as u64);
+    for record in state.records().iter().filter(|r| matcher.matches_id(&r.id)) {
+        let seen = logfile::local_time(record.seen as libc::time_t);
+        let since = record.since.map(|since| format!(" {}", ago(since))).unwrap_or_default();
+        match record.present {
+            _ if args.raw => println!("{}", record),
+            true if live => println!("{} connected{}", record.id, since),
+            true => println!("{} connected{} as of {} ago, {}", record.id, since, ago(record.seen), seen),
+            false => println!("{} last seen {} ago, {}", record.id, ago(record.seen), seen),
+        }
+    }
+    for id in filter.ids.iter().filter(|id| !state.records().iter().any(|r| &r.id == *id)) {
+        println!("{} never seen", id);
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::time::Duration;
+
+use rusb::UsbContext;
+
+use crate::sysfs;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+const CLASS_MASS_STORAGE: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+const CBW_SIGNATURE: u32 = 0x43425355;
+const CSW_SIGNATURE: u32 = 0x53425355;
+
+const INQUIRY_LENGTH: u8 = 36;
+
+/// The identification part of a standard SCSI INQUIRY response.
+#[derive(Debug, Clone)]
+pub struct Inquiry {
+    pub vendor: String,
+    pub product: String,
+    pub revision: String,
+}
+
+impl fmt::Display for Inquiry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Vendor: {} Model: {} Rev: {}", self.vendor, self.product, self.revision)
+    }
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim().to_string()
+}
+
+struct Transport {
+    iface: u8,
+    ep_in: u8,
+    ep_out: u8,
+}
+
+fn find_transport<T: UsbContext>(device: &rusb::Device<T>) -> Option<Transport> {
+    let config = device.active_config_descriptor().ok()?;
+    for iface in config.interfaces() {
+        for alt in iface.descriptors() {
+            if alt.class_code() != CLASS_MASS_STORAGE
+                || alt.sub_class_code() != SUBCLASS_SCSI
+                || alt.protocol_code() != PROTOCOL_BULK_ONLY {
+                continue;
+            }
+            let bulk = |dir| alt.endpoint_descriptors()
+                .find(|ep| ep.transfer_type() == rusb::TransferType::Bulk && ep.direction() == dir)
+                .map(|ep| ep.address());
+            if let (Some(ep_in), Some(ep_out)) = (bulk(rusb::Direction::In), bulk(rusb::Direction::Out)) {
+                return Some(Transport{iface: alt.interface_number(), ep_in, ep_out})
+            }
+        }
+    }
+    None
+}
+
+/// The identification of LUN 0. While usb-storage or uas has the device,
+/// which may have its disk mounted, this is what the kernel's own INQUIRY
+/// got, from sysfs, once the disk is there. Only a device no driver has
+/// is sent an INQUIRY over bulk-only transport. Returns None for devices
+/// without a bulk-only SCSI interface.
+pub fn inquiry<T: UsbContext>(device: &rusb::Device<T>) -> rusb::Result<Option<Inquiry>> {
+    let transport = match find_transport(device) {
+        Some(transport) => transport,
+        None => return Ok(None),
+    };
+    let bound = sysfs::interface_drivers(device).into_iter().any(|(iface, driver)| iface == transport.iface && driver.is_some());
+    if bound {
+        let disks = sysfs::wait_for(TIMEOUT, || {
+            sysfs::block_devices(device).into_iter().filter(|block| !sysfs::is_partition(block)).collect()
+        });
+        // the driver has it, but no disk of it has shown up to read
+        let (vendor, product, revision) = disks.first().and_then(|disk| sysfs::scsi_identity(disk)).ok_or(rusb::Error::Busy)?;
+        return Ok(Some(Inquiry{vendor, product, revision}))
+    }
+    let mut handle = device.open()?;
+    handle.claim_interface(transport.iface)?;
+    let result = bulk_inquiry(&handle, &transport);
+    _ = handle.release_interface(transport.iface);
+    result.map(Some)
+}
+
+fn bulk_inquiry<T: UsbContext>(handle: &rusb::DeviceHandle<T>, transport: &Transport) -> rusb::Result<Inquiry> {
+    let tag: u32 = 0x75736200;
+    let mut cbw = [0u8; 31];
+    cbw[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+    cbw[4..8].copy_from_slice(&tag.to_le_bytes());
+    cbw[8..12].copy_from_slice(&(INQUIRY_LENGTH as u32).to_le_bytes());
+    cbw[12] = 0x80;
+    cbw[14] = 6;
+    cbw[15..21].copy_from_slice(&[0x12, 0, 0, 0, INQUIRY_LENGTH, 0]);
+    handle.write_bulk(transport.ep_out, &cbw, TIMEOUT)?;
+
+    let mut data = [0u8; INQUIRY_LENGTH as usize];
+    let n = handle.read_bulk(transport.ep_in, &mut data, TIMEOUT)?;
+
+    let mut csw = [0u8; 13];
+    let csw_len = handle.read_bulk(transport.ep_in, &mut csw, TIMEOUT)?;
+    let signature = u32::from_le_bytes([csw[0], csw[1], csw[2], csw[3]]);
+    if csw_len < 13 || signature != CSW_SIGNATURE || csw[12] != 0 || n < 36 {
+        return Err(rusb::Error::Io)
+    }
+
+    Ok(Inquiry{
+        vendor: ascii(&data[8..16]),
+        product: ascii(&data[16..32]),
+        revision: ascii(&data[32..36]),
+    })
+}
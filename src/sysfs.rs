@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rusb::UsbContext;
+
+const USB_DEVICES: &str = "/sys/bus/usb/devices";
+const CLASS: &str = "/sys/class";
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Kernel name of the device, e.g. `1-4.2`, or `usb1` for a root hub.
+pub fn device_name<T: UsbContext>(device: &rusb::Device<T>) -> Option<String> {
+    let ports = device.port_numbers().ok()?;
+    if ports.is_empty() {
+        return Some(format!("usb{}", device.bus_number()))
+    }
+    let chain = ports.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(".");
+    Some(format!("{}-{}", device.bus_number(), chain))
+}
+
+/// The kernel name of the device behind a hub port directory, e.g.
+/// `usb1-port3` is `1-3` and `1-4-port2` is `1-4.2`.
+fn port_device(port: &str) -> Option<String> {
+    let (hub, number) = port.rsplit_once("-port")?;
+    match hub.strip_prefix("usb") {
+        Some(bus) => Some(format!("{}-{}", bus, number)),
+        None => Some(format!("{}.{}", hub, number)),
+    }
+}
+
+/// How many overcurrent conditions each hub port has had since boot, by
+/// the kernel name of the device on the port. Ports of kernels before 4.20
+/// have no counter and are left out.
+pub fn overcurrent_counts() -> Vec<(String, u64)> {
+    let hubs = match fs::read_dir(USB_DEVICES) {
+        Ok(hubs) => hubs,
+        Err(_) => return Vec::new(),
+    };
+    let mut counts = Vec::new();
+    // ports hang off the hub's interface, e.g. 1-4:1.0/1-4-port2
+    for hub in hubs.flatten().filter(|e| e.file_name().to_string_lossy().contains(':')) {
+        for port in fs::read_dir(hub.path()).into_iter().flatten().flatten() {
+            let name = port.file_name().to_string_lossy().into_owned();
+            let count = fs::read_to_string(port.path().join("over_current_count")).ok().and_then(|c| c.trim().parse().ok());
+            if let (Some(device), Some(count)) = (port_device(&name), count) {
+                counts.push((device, count));
+            }
+        }
+    }
+    counts
+}
+
+/// The directory of one interface of the active configuration, e.g. `1-4.2:1.0`.
+pub fn interface_path<T: UsbContext>(device: &rusb::Device<T>, iface: u8) -> Option<PathBuf> {
+    let config = device.active_config_descriptor().ok()?.number();
+    let path = PathBuf::from(USB_DEVICES).join(format!("{}:{}.{}", device_name(device)?, config, iface));
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// The kernel driver bound to each interface of the active configuration,
+/// None for interfaces no driver has claimed.
+pub fn interface_drivers<T: UsbContext>(device: &rusb::Device<T>) -> Vec<(u8, Option<String>)> {
+    let interfaces = match device.active_config_descriptor() {
+        Ok(config) => config.interfaces().map(|iface| iface.number()).collect::<Vec<u8>>(),
+        Err(_) => return Vec::new(),
+    };
+    interfaces
+        .into_iter()
+        .map(|iface| {
+            let driver = interface_path(device, iface)
+                .and_then(|path| fs::read_link(path.join("driver")).ok())
+                .and_then(|link| Some(link.file_name()?.to_string_lossy().to_string()));
+            (iface, driver)
+        })
+        .collect()
+}
+
+/// Report descriptors the HID core parsed for an interface. They are world
+/// readable, unlike the interface itself while usbhid has it bound.
+pub fn hid_report_descriptors<T: UsbContext>(device: &rusb::Device<T>, iface: u8) -> Vec<Vec<u8>> {
+    let path = match interface_path(device, iface) {
+        Some(path) => path,
+        None => return Vec::new(),
+    };
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| fs::read(entry.path().join("report_descriptor")).ok())
+        .collect()
+}
+
+/// Whether a class device such as `/sys/class/block/sdb` sits below the
+/// USB device in the device tree.
+fn belongs_to(class_device: &Path, name: &str) -> bool {
+    fs::canonicalize(class_device)
+        .is_ok_and(|path| path.components().any(|c| c.as_os_str() == name))
+}
+
+/// Names of the devices of a class, such as `block` or `net`, that the
+/// kernel created for the USB device.
+fn class_devices<T: UsbContext>(device: &rusb::Device<T>, class: &str) -> Vec<String> {
+    let name = match device_name(device) {
+        Some(name) => name,
+        None => return Vec::new(),
+    };
+    let entries = match fs::read_dir(Path::new(CLASS).join(class)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| belongs_to(&entry.path(), &name))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Block devices, disks and their partitions, that the device's storage
+/// interfaces provide, e.g. `sdb` and `sdb1`.
+pub fn block_devices<T: UsbContext>(device: &rusb::Device<T>) -> Vec<String> {
+    class_devices(device, "block")
+}
+
+/// Device nodes the kernel created for the device's interfaces, such as
+/// `/dev/hidraw2`, `/dev/ttyACM0` or `/dev/input/event5`, that exist.
+pub fn interface_nodes<T: UsbContext>(device: &rusb::Device<T>) -> Vec<PathBuf> {
+    let classes = [("hidraw", "/dev"), ("tty", "/dev"), ("video4linux", "/dev"), ("block", "/dev"),
+        ("usbmisc", "/dev/usb"), ("input", "/dev/input"), ("sound", "/dev/snd")];
+    classes
+        .iter()
+        .flat_map(|(class, dir)| class_devices(device, class).into_iter().map(move |name| Path::new(dir).join(name)))
+        .filter(|node| node.exists())
+        .collect()
+}
+
+/// The vendor, model and revision of a SCSI disk, e.g. `sdb`, from the
+/// INQUIRY the kernel sent when it attached the disk.
+pub fn scsi_identity(block: &str) -> Option<(String, String, String)> {
+    let attribute = |name: &str| fs::read_to_string(Path::new(CLASS).join("block").join(block).join("device").join(name))
+        .map(|value| value.trim().to_string());
+    Some((attribute("vendor").ok()?, attribute("model").ok()?, attribute("rev").ok()?))
+}
+
+/// Whether a block device is a partition rather than a whole disk.
+pub fn is_partition(block: &str) -> bool {
+    Path::new(CLASS).join("block").join(block).join("partition").exists()
+}
+
+/// Network interfaces of the device, e.g. `enx001122334455` or `usb0`.
+pub fn net_interfaces<T: UsbContext>(device: &rusb::Device<T>) -> Vec<String> {
+    class_devices(device, "net")
+}
+
+/// Whether the network interface's operstate is up.
+pub fn is_up(iface: &str) -> bool {
+    fs::read_to_string(Path::new(CLASS).join("net").join(iface).join("operstate"))
+        .is_ok_and(|state| state.trim() == "up")
+}
+
+/// ALSA cards of the device as (index, id), e.g. `(2, "Headset")`.
+pub fn sound_cards<T: UsbContext>(device: &rusb::Device<T>) -> Vec<(u32, String)> {
+    class_devices(device, "sound")
+        .into_iter()
+        .filter_map(|name| {
+            let index = name.strip_prefix("card")?.parse().ok()?;
+            let id = fs::read_to_string(Path::new(CLASS).join("sound").join(&name).join("id")).unwrap_or_default();
+            Some((index, id.trim().to_string()))
+        })
+        .collect()
+}
+
+/// V4L2 nodes of the device, e.g. `video0` and `video1`.
+pub fn video_nodes<T: UsbContext>(device: &rusb::Device<T>) -> Vec<String> {
+    class_devices(device, "video4linux")
+        .into_iter()
+        .filter(|name| name.starts_with("video"))
+        .collect()
+}
+
+/// The index of a V4L2 node among the nodes of its interface.
+pub fn video_index(name: &str) -> Option<u32> {
+    fs::read_to_string(Path::new(CLASS).join("video4linux").join(name).join("index")).ok()?.trim().parse().ok()
+}
+
+/// Polls a lookup until it finds something, giving the driver up to
+/// timeout to bind and register its class devices.
+pub fn wait_for<R>(timeout: Duration, lookup: impl Fn() -> Vec<R>) -> Vec<R> {
+    let start = Instant::now();
+    loop {
+        let found = lookup();
+        if !found.is_empty() || start.elapsed() >= timeout {
+            return found
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Allows or refuses the device to bind drivers, through the authorized
+/// attribute. Needs root.
+pub fn authorize<T: UsbContext>(device: &rusb::Device<T>, allow: bool) -> std::io::Result<()> {
+    let name = device_name(device).ok_or(std::io::ErrorKind::NotFound)?;
+    fs::write(Path::new(USB_DEVICES).join(name).join("authorized"), if allow { "1" } else { "0" })
+}
+
+/// An attribute of the device, e.g. `manufacturer` of `1-4.2`, without
+/// the trailing newline.
+pub fn attribute(port: &str, attribute: &str) -> Option<String> {
+    let value = fs::read_to_string(Path::new(USB_DEVICES).join(port).join(attribute)).ok()?;
+    Some(value.trim_end().to_string())
+}
+
+/// The descriptors the kernel read at enumeration, the device descriptor
+/// then every configuration's, as the device sent them.
+pub fn descriptors<T: UsbContext>(device: &rusb::Device<T>) -> Option<Vec<u8>> {
+    fs::read(Path::new(USB_DEVICES).join(device_name(device)?).join("descriptors")).ok()
+}
+
+/// An attribute of the device's power directory, e.g. `runtime_status`
+/// of `1-4.2`, without the trailing newline.
+pub fn power(port: &str, attribute: &str) -> Option<String> {
+    let value = fs::read_to_string(Path::new(USB_DEVICES).join(port).join("power").join(attribute)).ok()?;
+    Some(value.trim_end().to_string())
+}
+
+/// Writes an attribute of the device's power directory. Needs root.
+pub fn set_power(port: &str, attribute: &str, value: &str) -> std::io::Result<()> {
+    fs::write(Path::new(USB_DEVICES).join(port).join("power").join(attribute), value)
+}
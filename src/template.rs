@@ -0,0 +1,95 @@
+use rusb::UsbContext;
+
+use crate::inventory::{self, Entry};
+use crate::DeviceID;
+
+/// Values a notification template can refer to as `{name}`.
+pub type Fields = Vec<(&'static str, String)>;
+
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: gethostname writes at most buf.len() bytes into buf
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return String::from("localhost")
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+/// Fields describing an event. The device is only looked up for its
+/// strings when it is still there, so detach events have no name.
+pub fn fields<T: UsbContext>(ctx: &T, event: &str, id: &DeviceID, entry: Option<&Entry>) -> Fields {
+    let mut fields = vec![
+        ("event", event.to_string()),
+        ("id", id.to_string()),
+        ("vid", format!("{:04x}", id.vid)),
+        ("pid", format!("{:04x}", id.pid)),
+        ("host", hostname()),
+    ];
+    if let Some(entry) = entry {
+        fields.push(("bus", format!("{:03}", entry.bus)));
+        fields.push(("address", format!("{:03}", entry.address)));
+        fields.push(("port", entry.port.clone()));
+        if let Some(dev) = inventory::find(ctx, entry) {
+            fields.push(("name", inventory::name(&dev)));
+            fields.push(("serial", inventory::serial(&dev)));
+        }
+    }
+    fields
+}
+
+/// The value of a field, or an empty string.
+pub fn get<'a>(fields: &'a Fields, name: &str) -> &'a str {
+    fields.iter().find(|(n, _)| *n == name).map_or("", |(_, v)| v.as_str())
+}
+
+/// Replaces every `{name}` with its field, in one pass over the template
+/// so a value such as a product string is never expanded itself. Unknown
+/// names are left as they are so a typo shows up in the message.
+pub fn render(template: &str, fields: &Fields) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        rest = &rest[open + 1..];
+        // the name runs to the next brace, which has to close it
+        let field = rest
+            .find(['{', '}'])
+            .filter(|&end| rest[end..].starts_with('}'))
+            .and_then(|end| fields.iter().find(|(name, _)| *name == &rest[..end]).map(|(_, value)| (end, value)));
+        match field {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &rest[end + 1..];
+            },
+            None => out.push('{'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Fields {
+        vec![("id", String::from("1d50:6018")), ("name", String::from("Probe {serial} {id}")), ("serial", String::from("{{x}}"))]
+    }
+
+    #[test]
+    fn render_substitutes_known_fields() {
+        assert_eq!(render("{id} is {name}", &fields()), "1d50:6018 is Probe {serial} {id}");
+        assert_eq!(render("{serial}{id}", &fields()), "{{x}}1d50:6018");
+        assert_eq!(render("no fields", &fields()), "no fields");
+        assert_eq!(render("", &fields()), "");
+    }
+
+    #[test]
+    fn render_leaves_the_rest_alone() {
+        assert_eq!(render("{typo} {id", &fields()), "{typo} {id");
+        assert_eq!(render("{{id}} {}", &fields()), "{1d50:6018} {}");
+        assert_eq!(render("}{ {id}}{", &fields()), "}{ 1d50:6018}{");
+        assert_eq!(render("ünï{id}çødé {", &fields()), "ünï1d50:6018çødé {");
+    }
+}
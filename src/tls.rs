@@ -0,0 +1,294 @@
+use std::fs::{self, DirBuilder};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The certificate and key one side presents, and the CA it checks the
+/// other side's certificate against. A server with a CA requires client
+/// certificates; a client without one uses the system's trust store.
+#[derive(Debug, Clone, Default)]
+pub struct Certificates {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub ca: Option<PathBuf>,
+}
+
+/// The oldest OpenSSL whose s_client and s_server have every option used
+/// here, -verify_ip and -naccept among them.
+const OPENSSL: (u32, u32, u32) = (1, 1, 1);
+
+/// Checks once that the openssl program is there and is an OpenSSL new
+/// enough to check certificates the way this module asks, rather than
+/// failing or checking less on the first connection. LibreSSL and its
+/// options are different enough not to be taken for it.
+pub fn check() -> io::Result<()> {
+    static CHECKED: OnceLock<Result<(), String>> = OnceLock::new();
+    CHECKED
+        .get_or_init(|| {
+            let output = Command::new("openssl").arg("version").output().map_err(|e| format!("openssl: {}", e))?;
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let number = version.strip_prefix("OpenSSL ").and_then(|v| v.split_whitespace().next()).unwrap_or_default();
+            // 1.1.1w, 3.0.13
+            let parts: Vec<u32> = number
+                .split('.')
+                .map(|part| part.trim_end_matches(|c: char| c.is_ascii_alphabetic()).parse().unwrap_or(0))
+                .collect();
+            let found = (parts.first().copied().unwrap_or(0), parts.get(1).copied().unwrap_or(0), parts.get(2).copied().unwrap_or(0));
+            if found < OPENSSL {
+                let (major, minor, patch) = OPENSSL;
+                return Err(format!("TLS needs OpenSSL {}.{}.{} or later as openssl, found {}", major, minor, patch, version))
+            }
+            Ok(())
+        })
+        .clone()
+        .map_err(io::Error::other)
+}
+
+/// What `--output tls:HOST:PORT` connects with, from watch's --tls flags.
+static CLIENT: OnceLock<Certificates> = OnceLock::new();
+
+pub fn configure(client: Certificates) {
+    _ = CLIENT.set(client);
+}
+
+/// One TLS connection, made by an openssl child the way curl makes the
+/// webhook requests: what is written goes out encrypted and reads give
+/// what came in. The child goes with it.
+pub struct Session {
+    child: Child,
+    input: ChildStdout,
+    output: ChildStdin,
+}
+
+impl Session {
+    fn new(mut child: Child) -> Session {
+        let input = child.stdout.take().expect("stdout is piped");
+        let output = child.stdin.take().expect("stdin is piped");
+        Session{child, input, output}
+    }
+
+    /// Makes writes fail with WouldBlock rather than wait for a peer that
+    /// doesn't read, like a write timeout does for a socket.
+    pub fn set_nonblocking(&self) -> io::Result<()> {
+        let fd = self.output.as_raw_fd();
+        // SAFETY: only changes the flags of a descriptor we own
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error())
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for Session {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Session {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        _ = self.child.kill();
+        _ = self.child.wait();
+    }
+}
+
+fn args(certs: &Certificates) -> Vec<String> {
+    let mut args = Vec::new();
+    for (flag, path) in [("-cert", &certs.cert), ("-key", &certs.key), ("-CAfile", &certs.ca)] {
+        if let Some(path) = path {
+            args.push(flag.to_string());
+            args.push(path.display().to_string());
+        }
+    }
+    args
+}
+
+/// Connects to HOST:PORT and checks that its certificate is for HOST, as
+/// a DNS name or, for an address, as an IP address; addresses aren't
+/// sent as the server name, which is only for DNS names.
+pub fn connect(addr: &str) -> io::Result<Session> {
+    connect_with(addr, &CLIENT.get().cloned().unwrap_or_default())
+}
+
+fn connect_with(addr: &str, certs: &Certificates) -> io::Result<Session> {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host).trim_start_matches('[').trim_end_matches(']');
+    let verify = match host.parse::<IpAddr>() {
+        Ok(_) => ["-verify_ip", host, "-noservername", ""],
+        Err(_) => ["-verify_hostname", host, "-servername", host],
+    };
+    let child = Command::new("openssl")
+        .args(["s_client", "-quiet", "-verify_quiet", "-verify_return_error", "-connect", addr])
+        .args(verify.into_iter().filter(|arg| !arg.is_empty()))
+        .args(args(certs))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("openssl: {}", e)))?;
+    Ok(Session::new(child))
+}
+
+/// Copies one way until either end closes, then closes the other.
+fn pump(mut from: impl Read + Send + 'static, mut to: impl Write + Send + 'static, shutdown: impl FnOnce() + Send + 'static) {
+    thread::spawn(move || {
+        _ = io::copy(&mut from, &mut to);
+        shutdown();
+    });
+}
+
+/// Takes the TLS handshake of a client that connected. openssl can't be
+/// handed the socket, so its server listens on a socket of its own in a
+/// directory only we can reach and the bytes are passed through.
+pub fn accept(stream: TcpStream, certs: &Certificates) -> io::Result<Session> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!("usbmon-tls-{}-{}", std::process::id(), COUNT.fetch_add(1, Ordering::Relaxed)));
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    let socket = dir.join("socket");
+    let mut verify = Vec::new();
+    if certs.ca.is_some() {
+        verify.extend(["-Verify", "1", "-verify_return_error"]);
+    }
+    let child = Command::new("openssl")
+        .args(["s_server", "-quiet", "-naccept", "1", "-unix"])
+        .arg(&socket)
+        .args(verify)
+        .args(args(certs))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut session = match child {
+        Ok(child) => Session::new(child),
+        Err(e) => {
+            _ = fs::remove_dir(&dir);
+            return Err(io::Error::new(e.kind(), format!("openssl: {}", e)))
+        },
+    };
+    let start = Instant::now();
+    let unix = loop {
+        if let Ok(unix) = UnixStream::connect(&socket) {
+            break Ok(unix)
+        }
+        if session.child.try_wait()?.is_some() {
+            break Err(io::Error::other("openssl s_server exited, check the certificate and key"))
+        }
+        if start.elapsed() >= TIMEOUT {
+            break Err(io::Error::new(ErrorKind::TimedOut, "openssl s_server didn't start listening"))
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    _ = fs::remove_file(&socket);
+    _ = fs::remove_dir(&dir);
+    let unix = unix?;
+    let (tcp_in, unix_in) = (stream.try_clone()?, unix.try_clone()?);
+    pump(tcp_in, unix.try_clone()?, move || _ = unix.shutdown(Shutdown::Write));
+    pump(unix_in, stream.try_clone()?, move || _ = stream.shutdown(Shutdown::Both));
+    Ok(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::path::Path;
+
+    fn openssl(dir: &Path, args: &[&str]) {
+        let status = Command::new("openssl").args(args).current_dir(dir).stderr(Stdio::null()).status().unwrap();
+        assert!(status.success(), "openssl {:?}", args);
+    }
+
+    /// A CA, and a key and certificate it signed for the server at
+    /// 127.0.0.1 and for a client, in a directory of their own.
+    fn certificates(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("usbmon-certs-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let new_key = ["-newkey", "ec", "-pkeyopt", "ec_paramgen_curve:prime256v1", "-nodes"];
+        openssl(&dir, &[&["req", "-x509", "-subj", "/CN=usbmon test CA", "-days", "1", "-keyout", "ca.key", "-out", "ca.pem"][..], &new_key].concat());
+        fs::write(dir.join("server.ext"), "subjectAltName = IP:127.0.0.1\n").unwrap();
+        for (side, ext) in [("server", Some("server.ext")), ("client", None)] {
+            let (key, csr, cert) = (format!("{}.key", side), format!("{}.csr", side), format!("{}.pem", side));
+            let subject = format!("/CN=usbmon test {}", side);
+            openssl(&dir, &[&["req", "-new", "-subj", &subject, "-keyout", &key, "-out", &csr][..], &new_key].concat());
+            let mut sign = vec!["x509", "-req", "-in", &csr, "-CA", "ca.pem", "-CAkey", "ca.key", "-CAcreateserial", "-days", "1", "-out", &cert];
+            if let Some(ext) = ext {
+                sign.extend(["-extfile", ext]);
+            }
+            openssl(&dir, &sign);
+        }
+        dir
+    }
+
+    fn side(dir: &Path, name: &str, ca: bool) -> Certificates {
+        Certificates{
+            cert: Some(dir.join(format!("{}.pem", name))),
+            key: Some(dir.join(format!("{}.key", name))),
+            ca: ca.then(|| dir.join("ca.pem")),
+        }
+    }
+
+    /// Serves one connection that echoes a line back over TLS, and returns
+    /// what the client read back, or nothing when either side gave up.
+    fn echo(server: Certificates, client: Certificates) -> Option<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let served = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let session = accept(stream, &server).ok()?;
+            let mut reader = BufReader::new(session);
+            let mut line = String::new();
+            reader.read_line(&mut line).ok()?;
+            reader.get_mut().write_all(line.as_bytes()).ok()?;
+            // the client reads before this drops the session
+            thread::sleep(Duration::from_millis(500));
+            Some(())
+        });
+        let mut session = connect_with(&addr, &client).unwrap();
+        session.write_all(b"attach 1d50:6018\n").unwrap();
+        let mut line = String::new();
+        _ = BufReader::new(&mut session).read_line(&mut line);
+        drop(session);
+        _ = served.join();
+        (!line.is_empty()).then_some(line)
+    }
+
+    #[test]
+    fn loopback_round_trip() {
+        if let Err(e) = check() {
+            eprintln!("skipped: {}", e);
+            return
+        }
+        let dir = certificates("loopback");
+        // a server without a CA takes any client; one with a CA requires
+        // a client certificate it signed
+        assert_eq!(echo(side(&dir, "server", false), Certificates{ca: Some(dir.join("ca.pem")), ..Default::default()}).as_deref(), Some("attach 1d50:6018\n"));
+        assert_eq!(echo(side(&dir, "server", true), side(&dir, "client", true)).as_deref(), Some("attach 1d50:6018\n"));
+        assert_eq!(echo(side(&dir, "server", true), Certificates{ca: Some(dir.join("ca.pem")), ..Default::default()}), None);
+        // a client that doesn't trust the CA hangs up on the server
+        assert_eq!(echo(side(&dir, "server", false), Certificates{ca: Some(dir.join("client.pem")), ..Default::default()}), None);
+        _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::descriptors;
+use crate::inventory::{self, Entry};
+use crate::{DeviceID, Filter};
+
+const REFRESH: Duration = Duration::from_millis(500);
+const EVENTS: usize = 50;
+
+/// Puts the terminal in raw mode on the alternate screen and restores it
+/// when dropped, so a panic doesn't leave the shell unusable.
+struct Terminal {
+    original: libc::termios,
+}
+
+impl Terminal {
+    fn raw() -> io::Result<Terminal> {
+        // SAFETY: termios is plain data and stdin is a valid descriptor
+        let original = unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error())
+            }
+            let original = termios;
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error())
+            }
+            original
+        };
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(Terminal{original})
+    }
+
+    fn size(&self) -> (usize, usize) {
+        // SAFETY: winsize is plain data filled in by the ioctl
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_row > 0 {
+                (size.ws_row as usize, size.ws_col as usize)
+            } else {
+                (24, 80)
+            }
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        _ = io::stdout().flush();
+        // SAFETY: restores the attributes read in raw()
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Esc,
+    Backspace,
+    Up,
+    Down,
+}
+
+fn read_key(timeout: Duration) -> io::Result<Option<Key>> {
+    let mut fds = libc::pollfd{fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0};
+    // SAFETY: a single valid pollfd
+    let ready = unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) };
+    if ready <= 0 {
+        return Ok(None)
+    }
+    let mut buf = [0u8; 8];
+    let n = io::stdin().read(&mut buf)?;
+    let key = match &buf[..n] {
+        [b'\r'] | [b'\n'] => Key::Enter,
+        [0x1b] => Key::Esc,
+        [0x7f] | [0x08] => Key::Backspace,
+        [0x03] => Key::Char('q'),
+        [0x1b, b'[', b'A'] => Key::Up,
+        [0x1b, b'[', b'B'] => Key::Down,
+        [c, ..] if c.is_ascii() && !c.is_ascii_control() => Key::Char(*c as char),
+        _ => return Ok(None),
+    };
+    Ok(Some(key))
+}
+
+fn clock() -> String {
+    // SAFETY: localtime_r writes into the tm we own
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+    }
+}
+
+struct Event {
+    time: String,
+    attached: bool,
+    entry: Entry,
+    name: String,
+}
+
+struct Dashboard {
+    ctx: rusb::Context,
+    filter: Filter,
+    search: String,
+    editing: bool,
+    devices: Vec<Entry>,
+    names: HashMap<(u8, u8), String>,
+    flaps: HashMap<DeviceID, u32>,
+    events: VecDeque<Event>,
+    selected: usize,
+    inspect: Option<(Vec<String>, usize)>,
+}
+
+impl Dashboard {
+    fn name(&mut self, entry: &Entry) -> String {
+        if let Some(name) = self.names.get(&(entry.bus, entry.address)) {
+            return name.clone()
+        }
+        let name = inventory::find(&self.ctx, entry).map(|dev| inventory::name(&dev)).unwrap_or_default();
+        self.names.insert((entry.bus, entry.address), name.clone());
+        name
+    }
+
+    fn refresh(&mut self) {
+        let devices = match inventory::scan(&self.ctx, &self.filter) {
+            Ok(devices) => devices,
+            Err(_) => return,
+        };
+        let (added, removed) = inventory::diff(&self.devices, &devices);
+        for entry in removed {
+            let name = self.names.remove(&(entry.bus, entry.address)).unwrap_or_default();
+            self.record(false, entry, name);
+        }
+        self.devices = devices;
+        for entry in added {
+            let name = self.name(&entry);
+            self.record(true, entry, name);
+        }
+    }
+
+    fn record(&mut self, attached: bool, entry: Entry, name: String) {
+        *self.flaps.entry(entry.id.clone()).or_insert(0) += 1;
+        self.events.push_front(Event{time: clock(), attached, entry, name});
+        self.events.truncate(EVENTS);
+    }
+
+    fn visible(&mut self) -> Vec<(Entry, String)> {
+        let search = self.search.to_lowercase();
+        let devices = self.devices.clone();
+        devices
+            .into_iter()
+            .map(|entry| {
+                let name = self.name(&entry);
+                (entry, name)
+            })
+            .filter(|(entry, name)| {
+                search.is_empty()
+                    || entry.id.to_string().contains(&search)
+                    || name.to_lowercase().contains(&search)
+            })
+            .collect()
+    }
+
+    fn inspect(&mut self) {
+        let visible = self.visible();
+        let entry = match visible.get(self.selected) {
+            Some((entry, _)) => entry,
+            None => return,
+        };
+        let lines = match inventory::find(&self.ctx, entry).map(|dev| descriptors::dump(&dev)) {
+            Some(Ok(dump)) => dump.lines().map(String::from).collect(),
+            Some(Err(e)) => vec![format!("failed to read descriptors: {}", e)],
+            None => vec![String::from("device is gone")],
+        };
+        self.inspect = Some((lines, 0));
+    }
+
+    /// Returns false once the user asked to quit.
+    fn key(&mut self, key: Key) -> bool {
+        if let Some((lines, scroll)) = &mut self.inspect {
+            match key {
+                Key::Up | Key::Char('k') => *scroll = scroll.saturating_sub(1),
+                Key::Down | Key::Char('j') => *scroll = (*scroll + 1).min(lines.len().saturating_sub(1)),
+                Key::Char(' ') => *scroll = (*scroll + 20).min(lines.len().saturating_sub(1)),
+                Key::Esc | Key::Enter | Key::Char('q') => self.inspect = None,
+                _ => {},
+            }
+            return true
+        }
+        if self.editing {
+            match key {
+                Key::Enter => self.editing = false,
+                Key::Esc => {
+                    self.editing = false;
+                    self.search.clear();
+                },
+                Key::Backspace => _ = self.search.pop(),
+                Key::Char(c) => self.search.push(c),
+                _ => {},
+            }
+            self.selected = 0;
+            return true
+        }
+        match key {
+            Key::Char('q') => return false,
+            Key::Char('/') => self.editing = true,
+            Key::Esc => self.search.clear(),
+            Key::Up | Key::Char('k') => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.selected += 1,
+            Key::Enter | Key::Char('i') => self.inspect(),
+            _ => {},
+        }
+        true
+    }
+
+    fn draw(&mut self, rows: usize, cols: usize) -> String {
+        let mut lines: Vec<String> = Vec::new();
+
+        if let Some((dump, scroll)) = &self.inspect {
+            lines.push(String::from("\x1b[7m descriptors  [j/k] scroll  [esc] back \x1b[0m"));
+            lines.extend(dump.iter().skip(*scroll).take(rows.saturating_sub(1)).cloned());
+            return finish(lines, cols)
+        }
+
+        let visible = self.visible();
+        self.selected = self.selected.min(visible.len().saturating_sub(1));
+        let search = if self.editing {
+            format!("  filter: {}_", self.search)
+        } else if !self.search.is_empty() {
+            format!("  filter: {}", self.search)
+        } else {
+            String::new()
+        };
+        lines.push(format!("\x1b[7m usbmon  {} device(s){}  [/] filter  [enter] inspect  [q] quit \x1b[0m",
+            visible.len(), search));
+        lines.push(format!("  {:<3} {:<4} {:<9} {:>5}  {}", "BUS", "ADDR", "ID", "FLAPS", "NAME"));
+
+        // leave room for the event log below the table
+        let table = rows.saturating_sub(4).saturating_sub(rows / 3).max(1);
+        let start = self.selected.saturating_sub(table - 1);
+        for (i, (entry, name)) in visible.iter().enumerate().skip(start).take(table) {
+            let flaps = self.flaps.get(&entry.id).copied().unwrap_or(0);
+            let line = format!("  {:03} {:03}  {:<9} {:>5}  {}", entry.bus, entry.address, entry.id.to_string(), flaps, name);
+            if i == self.selected {
+                lines.push(format!("\x1b[7m{}\x1b[0m", line));
+            } else {
+                lines.push(line);
+            }
+        }
+
+        lines.push(String::new());
+        lines.push(String::from("  Recent events"));
+        let room = rows.saturating_sub(lines.len());
+        for event in self.events.iter().take(room) {
+            lines.push(format!("  {} {} {:<9} {:03}:{:03} {}", event.time, if event.attached { "+" } else { "-" },
+                event.entry.id.to_string(), event.entry.bus, event.entry.address, event.name));
+        }
+        finish(lines, cols)
+    }
+}
+
+fn finish(lines: Vec<String>, cols: usize) -> String {
+    let mut screen = String::from("\x1b[H\x1b[2J");
+    for line in lines {
+        // escape sequences don't take up columns, only clip plain lines
+        if line.starts_with('\x1b') {
+            screen.push_str(&line);
+        } else {
+            screen.extend(line.chars().take(cols));
+        }
+        screen.push_str("\r\n");
+    }
+    screen
+}
+
+/// Runs the interactive dashboard until the user quits.
+pub fn run(filter: Filter) -> rusb::Result<()> {
+    let ctx = rusb::Context::new()?;
+    let terminal = Terminal::raw().map_err(|_| rusb::Error::Other)?;
+    let mut dashboard = Dashboard{
+        ctx,
+        filter,
+        search: String::new(),
+        editing: false,
+        devices: Vec::new(),
+        names: HashMap::new(),
+        flaps: HashMap::new(),
+        events: VecDeque::new(),
+        selected: 0,
+        inspect: None,
+    };
+    // devices present at startup aren't events
+    dashboard.devices = inventory::scan(&dashboard.ctx, &dashboard.filter)?;
+
+    let mut last = Instant::now();
+    loop {
+        let (rows, cols) = terminal.size();
+        print!("{}", dashboard.draw(rows, cols));
+        _ = io::stdout().flush();
+
+        let wait = REFRESH.saturating_sub(last.elapsed());
+        if let Ok(Some(key)) = read_key(wait) {
+            if !dashboard.key(key) {
+                return Ok(())
+            }
+        }
+        if last.elapsed() >= REFRESH {
+            dashboard.refresh();
+            last = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,470 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use rusb::UsbContext;
+
+use crate::descriptors::{field, field_desc, le16, le24, le32, string};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+
+const SUBCLASS_CONTROL: u8 = 0x01;
+const SUBCLASS_STREAMING: u8 = 0x02;
+
+const PROTOCOL_UAC2: u8 = 0x20;
+
+fn terminal_name(kind: u16) -> &'static str {
+    match kind {
+        0x0100 => "USB Undefined",
+        0x0101 => "USB Streaming",
+        0x01ff => "USB Vendor Specific",
+        0x0200 => "Input Undefined",
+        0x0201 => "Microphone",
+        0x0202 => "Desktop Microphone",
+        0x0203 => "Personal Microphone",
+        0x0204 => "Omni-directional Microphone",
+        0x0205 => "Microphone Array",
+        0x0206 => "Processing Microphone Array",
+        0x0300 => "Output Undefined",
+        0x0301 => "Speaker",
+        0x0302 => "Headphones",
+        0x0303 => "Head Mounted Display Audio",
+        0x0304 => "Desktop Speaker",
+        0x0305 => "Room Speaker",
+        0x0306 => "Communication Speaker",
+        0x0307 => "Low Frequency Effects Speaker",
+        0x0400 => "Bidirectional Undefined",
+        0x0401 => "Handset",
+        0x0402 => "Headset",
+        0x0403 => "Speakerphone, no echo reduction",
+        0x0404 => "Echo-suppressing speakerphone",
+        0x0405 => "Echo-canceling speakerphone",
+        0x0500 => "Telephony Undefined",
+        0x0501 => "Phone line",
+        0x0502 => "Telephone",
+        0x0503 => "Down Line Phone",
+        0x0600 => "External Undefined",
+        0x0601 => "Analog Connector",
+        0x0602 => "Digital Audio Interface",
+        0x0603 => "Line Connector",
+        0x0604 => "Legacy Audio Connector",
+        0x0605 => "SPDIF interface",
+        0x0606 => "1394 DA stream",
+        0x0607 => "1394 DV stream soundtrack",
+        0x0700 => "Embedded Undefined",
+        0x0703 => "CD player",
+        0x0704 => "DAT",
+        0x0705 => "DCC",
+        0x0706 => "MiniDisk",
+        0x070a => "Radio Receiver",
+        0x070b => "Radio Transmitter",
+        0x070e => "Synthesizer",
+        0x070f => "Piano",
+        0x0710 => "Guitar",
+        _ => "",
+    }
+}
+
+fn control_subtype_name(subtype: u8, uac2: bool) -> &'static str {
+    match (subtype, uac2) {
+        (0x01, _) => "HEADER",
+        (0x02, _) => "INPUT_TERMINAL",
+        (0x03, _) => "OUTPUT_TERMINAL",
+        (0x04, _) => "MIXER_UNIT",
+        (0x05, _) => "SELECTOR_UNIT",
+        (0x06, _) => "FEATURE_UNIT",
+        (0x07, false) => "PROCESSING_UNIT",
+        (0x08, false) => "EXTENSION_UNIT",
+        (0x07, true) => "EFFECT_UNIT",
+        (0x08, true) => "PROCESSING_UNIT",
+        (0x09, true) => "EXTENSION_UNIT",
+        (0x0a, true) => "CLOCK_SOURCE",
+        (0x0b, true) => "CLOCK_SELECTOR",
+        (0x0c, true) => "CLOCK_MULTIPLIER",
+        (0x0d, true) => "SAMPLE_RATE_CONVERTER",
+        _ => "unknown",
+    }
+}
+
+fn format_name(tag: u16) -> &'static str {
+    match tag {
+        0x0001 => "PCM",
+        0x0002 => "PCM8",
+        0x0003 => "IEEE_FLOAT",
+        0x0004 => "ALAW",
+        0x0005 => "MULAW",
+        0x1001 => "MPEG",
+        0x1002 => "AC-3",
+        _ => "",
+    }
+}
+
+fn feature_controls(bits: u32, uac2: bool) -> Vec<&'static str> {
+    let names = [
+        "Mute", "Volume", "Bass", "Mid", "Treble", "Graphic Equalizer",
+        "Automatic Gain", "Delay", "Bass Boost", "Loudness", "Input gain",
+        "Input gain pad", "Phase inverter", "Underflow", "Overflow",
+    ];
+    // UAC2 spends two bits per control (readable, writable), UAC1 one
+    let step = if uac2 { 2 } else { 1 };
+    names
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| (bits >> (i * step)) & if uac2 { 3 } else { 1 } != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Decodes a class-specific descriptor found on an audio interface. Returns
+/// false when the descriptor isn't one this module knows, so the caller can
+/// fall back to a hex dump.
+pub fn dump_interface<T: UsbContext>(
+    out: &mut String,
+    handle: Option<&rusb::DeviceHandle<T>>,
+    alt: &rusb::InterfaceDescriptor,
+    bytes: &[u8],
+) -> bool {
+    if bytes.len() < 3 || bytes[1] != CS_INTERFACE {
+        return false
+    }
+    let uac2 = alt.protocol_code() == PROTOCOL_UAC2;
+    match alt.sub_class_code() {
+        SUBCLASS_CONTROL => dump_control(out, handle, alt.interface_number(), uac2, bytes),
+        SUBCLASS_STREAMING => dump_streaming(out, uac2, bytes),
+        _ => false,
+    }
+}
+
+fn header(out: &mut String, kind: &str, bytes: &[u8], name: &str) {
+    _ = writeln!(out, "      {} Interface Descriptor:", kind);
+    field(out, 8, "bLength", bytes[0]);
+    field(out, 8, "bDescriptorType", bytes[1]);
+    field_desc(out, 8, "bDescriptorSubtype", bytes[2], &format!("({})", name));
+}
+
+fn dump_control<T: UsbContext>(
+    out: &mut String,
+    handle: Option<&rusb::DeviceHandle<T>>,
+    iface: u8,
+    uac2: bool,
+    bytes: &[u8],
+) -> bool {
+    let subtype = bytes[2];
+    let minimum = match (subtype, uac2) {
+        (0x01, false) => 8,
+        (0x01, true) => 9,
+        (0x02, false) => 12,
+        (0x02, true) => 17,
+        (0x03, false) => 9,
+        (0x03, true) => 12,
+        (0x05, _) => 5,
+        (0x06, false) => 7,
+        (0x06, true) => 6,
+        (0x0a, true) => 8,
+        (0x0b, true) => 5,
+        _ => return false,
+    };
+    if bytes.len() < minimum {
+        return false
+    }
+    header(out, "AudioControl", bytes, control_subtype_name(subtype, uac2));
+
+    match (subtype, uac2) {
+        (0x01, false) => {
+            field(out, 8, "bcdADC", format!("{:x}.{:02x}", bytes[4], bytes[3]));
+            field(out, 8, "wTotalLength", format!("0x{:04x}", le16(bytes, 5)));
+            field(out, 8, "bInCollection", bytes[7]);
+            for (i, nr) in bytes[8..].iter().enumerate() {
+                field(out, 8, &format!("baInterfaceNr({})", i), nr);
+            }
+        },
+        (0x01, true) => {
+            field(out, 8, "bcdADC", format!("{:x}.{:02x}", bytes[4], bytes[3]));
+            field(out, 8, "bCategory", bytes[5]);
+            field(out, 8, "wTotalLength", format!("0x{:04x}", le16(bytes, 6)));
+            field(out, 8, "bmControls", format!("0x{:02x}", bytes[8]));
+        },
+        (0x02, false) => {
+            field(out, 8, "bTerminalID", bytes[3]);
+            field_desc(out, 8, "wTerminalType", format!("0x{:04x}", le16(bytes, 4)), terminal_name(le16(bytes, 4)));
+            field(out, 8, "bAssocTerminal", bytes[6]);
+            field(out, 8, "bNrChannels", bytes[7]);
+            field(out, 8, "wChannelConfig", format!("0x{:04x}", le16(bytes, 8)));
+            field(out, 8, "iChannelNames", bytes[10]);
+            field_desc(out, 8, "iTerminal", bytes[11], &string(handle, Some(bytes[11]).filter(|i| *i != 0)));
+        },
+        (0x02, true) => {
+            field(out, 8, "bTerminalID", bytes[3]);
+            field_desc(out, 8, "wTerminalType", format!("0x{:04x}", le16(bytes, 4)), terminal_name(le16(bytes, 4)));
+            field(out, 8, "bAssocTerminal", bytes[6]);
+            field(out, 8, "bCSourceID", bytes[7]);
+            field(out, 8, "bNrChannels", bytes[8]);
+            field(out, 8, "bmChannelConfig", format!("0x{:08x}", le32(bytes, 9)));
+            field(out, 8, "iChannelNames", bytes[13]);
+            field(out, 8, "bmControls", format!("0x{:04x}", le16(bytes, 14)));
+            field_desc(out, 8, "iTerminal", bytes[16], &string(handle, Some(bytes[16]).filter(|i| *i != 0)));
+        },
+        (0x03, false) => {
+            field(out, 8, "bTerminalID", bytes[3]);
+            field_desc(out, 8, "wTerminalType", format!("0x{:04x}", le16(bytes, 4)), terminal_name(le16(bytes, 4)));
+            field(out, 8, "bAssocTerminal", bytes[6]);
+            field(out, 8, "bSourceID", bytes[7]);
+            field_desc(out, 8, "iTerminal", bytes[8], &string(handle, Some(bytes[8]).filter(|i| *i != 0)));
+        },
+        (0x03, true) => {
+            field(out, 8, "bTerminalID", bytes[3]);
+            field_desc(out, 8, "wTerminalType", format!("0x{:04x}", le16(bytes, 4)), terminal_name(le16(bytes, 4)));
+            field(out, 8, "bAssocTerminal", bytes[6]);
+            field(out, 8, "bSourceID", bytes[7]);
+            field(out, 8, "bCSourceID", bytes[8]);
+            field(out, 8, "bmControls", format!("0x{:04x}", le16(bytes, 9)));
+            field_desc(out, 8, "iTerminal", bytes[11], &string(handle, Some(bytes[11]).filter(|i| *i != 0)));
+        },
+        (0x05, _) => {
+            field(out, 8, "bUnitID", bytes[3]);
+            field(out, 8, "bNrInPins", bytes[4]);
+            let pins = bytes[4] as usize;
+            for (i, id) in bytes.iter().skip(5).take(pins).enumerate() {
+                field(out, 8, &format!("baSourceID({})", i), id);
+            }
+        },
+        (0x06, false) => {
+            field(out, 8, "bUnitID", bytes[3]);
+            field(out, 8, "bSourceID", bytes[4]);
+            let size = bytes[5] as usize;
+            field(out, 8, "bControlSize", size);
+            if size > 0 {
+                let controls = &bytes[6..bytes.len() - 1];
+                for (i, chunk) in controls.chunks(size).enumerate() {
+                    let bits = chunk.iter().rev().fold(0u32, |a, b| a << 8 | *b as u32);
+                    field(out, 8, &format!("bmaControls({})", i), format!("0x{:02x}", bits));
+                    for name in feature_controls(bits, false) {
+                        _ = writeln!(out, "          {} Control", name);
+                    }
+                }
+            }
+        },
+        (0x06, true) => {
+            field(out, 8, "bUnitID", bytes[3]);
+            field(out, 8, "bSourceID", bytes[4]);
+            let controls = &bytes[5..bytes.len() - 1];
+            for (i, chunk) in controls.chunks_exact(4).enumerate() {
+                let bits = le32(chunk, 0);
+                field(out, 8, &format!("bmaControls({})", i), format!("0x{:08x}", bits));
+                for name in feature_controls(bits, true) {
+                    _ = writeln!(out, "          {} Control", name);
+                }
+            }
+        },
+        (0x0a, true) => {
+            let kind = match bytes[4] & 0x03 {
+                0 => "External",
+                1 => "Internal fixed",
+                2 => "Internal variable",
+                _ => "Internal programmable",
+            };
+            field(out, 8, "bClockID", bytes[3]);
+            field(out, 8, "bmAttributes", format!("0x{:02x}", bytes[4]));
+            _ = writeln!(out, "          {} clock", kind);
+            field(out, 8, "bmControls", format!("0x{:02x}", bytes[5]));
+            field(out, 8, "bAssocTerminal", bytes[6]);
+            field_desc(out, 8, "iClockSource", bytes[7], &string(handle, Some(bytes[7]).filter(|i| *i != 0)));
+            if let Some(handle) = handle {
+                dump_sample_rates(out, handle, iface, bytes[3]);
+            }
+        },
+        (0x0b, true) => {
+            field(out, 8, "bClockID", bytes[3]);
+            field(out, 8, "bNrInPins", bytes[4]);
+            let pins = bytes[4] as usize;
+            for (i, id) in bytes.iter().skip(5).take(pins).enumerate() {
+                field(out, 8, &format!("baCSourceID({})", i), id);
+            }
+        },
+        _ => {},
+    }
+    true
+}
+
+/// Asks a UAC2 clock source for the sample rates it supports. UAC2 doesn't
+/// list rates in descriptors, they're only available through a RANGE request.
+fn dump_sample_rates<T: UsbContext>(out: &mut String, handle: &rusb::DeviceHandle<T>, iface: u8, clock: u8) {
+    let request_type = rusb::request_type(rusb::Direction::In, rusb::RequestType::Class, rusb::Recipient::Interface);
+    let index = (clock as u16) << 8 | iface as u16;
+    let mut buf = [0u8; 2 + 12 * 32];
+    // RANGE request on the sampling frequency control
+    let n = match handle.read_control(request_type, 0x02, 0x01 << 8, index, &mut buf, TIMEOUT) {
+        Ok(n) if n >= 2 => n,
+        _ => return,
+    };
+    let count = le16(&buf, 0) as usize;
+    let ranges = buf[2..n].chunks_exact(12).take(count);
+    for range in ranges {
+        let (min, max, res) = (le32(range, 0), le32(range, 4), le32(range, 8));
+        if min == max {
+            _ = writeln!(out, "          Sample Rate {} Hz", min);
+        } else {
+            _ = writeln!(out, "          Sample Rate {} - {} Hz, step {}", min, max, res);
+        }
+    }
+}
+
+fn dump_streaming(out: &mut String, uac2: bool, bytes: &[u8]) -> bool {
+    let subtype = bytes[2];
+    let minimum = match (subtype, uac2) {
+        (0x01, false) => 7,
+        (0x01, true) => 16,
+        (0x02, false) => 8,
+        (0x02, true) => 6,
+        _ => return false,
+    };
+    // only Type I formats share the layout decoded below
+    if bytes.len() < minimum || (subtype == 0x02 && bytes[3] != 0x01) {
+        return false
+    }
+    // UAC1 lists its sample rates, two for a continuous range
+    if (subtype, uac2) == (0x02, false) {
+        let rates = match bytes[7] {
+            0 => 2,
+            n => n as usize,
+        };
+        if bytes.len() < 8 + 3 * rates {
+            return false
+        }
+    }
+    let name = match subtype {
+        0x01 => "AS_GENERAL",
+        _ => "FORMAT_TYPE",
+    };
+    header(out, "AudioStreaming", bytes, name);
+
+    match (subtype, uac2) {
+        (0x01, false) => {
+            field(out, 8, "bTerminalLink", bytes[3]);
+            field(out, 8, "bDelay", format!("{} frames", bytes[4]));
+            field_desc(out, 8, "wFormatTag", format!("0x{:04x}", le16(bytes, 5)), format_name(le16(bytes, 5)));
+        },
+        (0x01, true) => {
+            field(out, 8, "bTerminalLink", bytes[3]);
+            field(out, 8, "bmControls", format!("0x{:02x}", bytes[4]));
+            field(out, 8, "bFormatType", bytes[5]);
+            field(out, 8, "bmFormats", format!("0x{:08x}", le32(bytes, 6)));
+            let formats = ["PCM", "PCM8", "IEEE_FLOAT", "ALAW", "MULAW"];
+            for (bit, name) in formats.iter().enumerate() {
+                if le32(bytes, 6) & (1 << bit) != 0 {
+                    _ = writeln!(out, "          {}", name);
+                }
+            }
+            field(out, 8, "bNrChannels", bytes[10]);
+            field(out, 8, "bmChannelConfig", format!("0x{:08x}", le32(bytes, 11)));
+            field(out, 8, "iChannelNames", bytes[15]);
+        },
+        (0x02, false) => {
+            field_desc(out, 8, "bFormatType", bytes[3], "(FORMAT_TYPE_I)");
+            field(out, 8, "bNrChannels", bytes[4]);
+            field(out, 8, "bSubframeSize", bytes[5]);
+            field(out, 8, "bBitResolution", bytes[6]);
+            field(out, 8, "bSamFreqType", format!("{} {}", bytes[7], if bytes[7] == 0 { "Continuous" } else { "Discrete" }));
+            if bytes[7] == 0 {
+                field(out, 8, "tLowerSamFreq", le24(bytes, 8));
+                field(out, 8, "tUpperSamFreq", le24(bytes, 11));
+            } else {
+                for i in 0..bytes[7] as usize {
+                    field(out, 8, &format!("tSamFreq[{:2}]", i), le24(bytes, 8 + i * 3));
+                }
+            }
+        },
+        (0x02, true) => {
+            field_desc(out, 8, "bFormatType", bytes[3], "(FORMAT_TYPE_I)");
+            field(out, 8, "bSubslotSize", bytes[4]);
+            field(out, 8, "bBitResolution", bytes[5]);
+        },
+        _ => {},
+    }
+    true
+}
+
+/// Decodes the class-specific isochronous endpoint descriptor of an audio
+/// streaming interface.
+pub fn dump_endpoint(out: &mut String, alt: &rusb::InterfaceDescriptor, bytes: &[u8]) -> bool {
+    let uac2 = alt.protocol_code() == PROTOCOL_UAC2;
+    let minimum = if uac2 { 8 } else { 7 };
+    if bytes.len() < minimum || bytes[1] != CS_ENDPOINT || bytes[2] != 0x01 {
+        return false
+    }
+    _ = writeln!(out, "        AudioStreaming Endpoint Descriptor:");
+    field(out, 10, "bLength", bytes[0]);
+    field(out, 10, "bDescriptorType", bytes[1]);
+    field_desc(out, 10, "bDescriptorSubtype", bytes[2], "(EP_GENERAL)");
+    field(out, 10, "bmAttributes", format!("0x{:02x}", bytes[3]));
+    if uac2 {
+        field(out, 10, "bmControls", format!("0x{:02x}", bytes[4]));
+        field(out, 10, "bLockDelayUnits", bytes[5]);
+        field(out, 10, "wLockDelay", format!("0x{:04x}", le16(bytes, 6)));
+    } else {
+        if bytes[3] & 0x01 != 0 {
+            _ = writeln!(out, "            Sampling Frequency");
+        }
+        if bytes[3] & 0x02 != 0 {
+            _ = writeln!(out, "            Pitch");
+        }
+        field(out, 10, "bLockDelayUnits", bytes[4]);
+        field(out, 10, "wLockDelay", format!("0x{:04x}", le16(bytes, 5)));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_HANDLE: Option<&rusb::DeviceHandle<rusb::Context>> = None;
+
+    // from a UAC1 headset and a UAC2 interface
+    const CONTROL: [(bool, &[u8]); 7] = [
+        (false, &[0x0a, 0x24, 0x01, 0x00, 0x01, 0x27, 0x00, 0x02, 0x01, 0x02]),
+        (false, &[0x0c, 0x24, 0x02, 0x01, 0x01, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]),
+        (false, &[0x09, 0x24, 0x03, 0x03, 0x01, 0x03, 0x00, 0x02, 0x00]),
+        (false, &[0x0a, 0x24, 0x06, 0x02, 0x01, 0x01, 0x01, 0x02, 0x02, 0x00]),
+        (true, &[0x08, 0x24, 0x0a, 0x29, 0x03, 0x07, 0x00, 0x00]),
+        (true, &[0x11, 0x24, 0x02, 0x02, 0x01, 0x01, 0x00, 0x29, 0x02, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+        (true, &[0x12, 0x24, 0x06, 0x0a, 0x02, 0x0f, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00]),
+    ];
+
+    const STREAMING: [(bool, &[u8]); 4] = [
+        (false, &[0x07, 0x24, 0x01, 0x01, 0x01, 0x01, 0x00]),
+        (false, &[0x0e, 0x24, 0x02, 0x01, 0x02, 0x02, 0x10, 0x02, 0x44, 0xac, 0x00, 0x80, 0xbb, 0x00]),
+        (true, &[0x10, 0x24, 0x01, 0x01, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x02, 0x03, 0x00, 0x00, 0x00, 0x00]),
+        (true, &[0x06, 0x24, 0x02, 0x01, 0x02, 0x10]),
+    ];
+
+    #[test]
+    fn known_descriptors_decode() {
+        for (uac2, bytes) in CONTROL {
+            assert!(dump_control(&mut String::new(), NO_HANDLE, 0, uac2, bytes), "{:02x?}", bytes);
+        }
+        for (uac2, bytes) in STREAMING {
+            assert!(dump_streaming(&mut String::new(), uac2, bytes), "{:02x?}", bytes);
+        }
+        let mut out = String::new();
+        dump_streaming(&mut out, false, STREAMING[1].1);
+        assert!(out.contains("tSamFreq[ 0]") && out.contains("44100") && out.contains("48000"), "{}", out);
+    }
+
+    // dump_interface hands on only descriptors of at least 3 bytes
+    #[test]
+    fn truncated_descriptors_dont_panic() {
+        for (uac2, bytes) in CONTROL {
+            for len in 3..bytes.len() {
+                dump_control(&mut String::new(), NO_HANDLE, 0, uac2, &bytes[..len]);
+            }
+        }
+        for (uac2, bytes) in STREAMING {
+            for len in 3..bytes.len() {
+                assert!(!dump_streaming(&mut String::new(), uac2, &bytes[..len]), "{:02x?}", &bytes[..len]);
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rusb::UsbContext;
+
+use crate::parse_device;
+
+const UDEV_DATA: &str = "/run/udev/data";
+const SYS: &str = "/sys";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The usbfs node of the device, e.g. `/dev/bus/usb/001/004`.
+pub fn node<T: UsbContext>(device: &rusb::Device<T>) -> PathBuf {
+    PathBuf::from(format!("/dev/bus/usb/{:03}/{:03}", device.bus_number(), device.address()))
+}
+
+/// udev's database entry for the device, written once its rules have run.
+/// USB devices are char major 189 with 128 minors per bus.
+fn database_entry<T: UsbContext>(device: &rusb::Device<T>) -> PathBuf {
+    let minor = (device.bus_number() as u32 - 1) * 128 + device.address() as u32 - 1;
+    Path::new(UDEV_DATA).join(format!("c189:{}", minor))
+}
+
+/// Waits until udev has processed the device: its node exists and, where
+/// udev runs at all, the rules have run for it and the event queue has
+/// drained, so interface nodes and permissions are in place too. Returns
+/// false if that took longer than the timeout.
+pub fn settle<T: UsbContext>(device: &rusb::Device<T>, timeout: Duration) -> bool {
+    let start = Instant::now();
+    let node = node(device);
+    let entry = database_entry(device);
+    let udev = Path::new(UDEV_DATA).exists();
+    while !node.exists() || (udev && !entry.exists()) {
+        if start.elapsed() >= timeout {
+            return false
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    if !udev {
+        return true
+    }
+    let left = timeout.saturating_sub(start.elapsed()).as_secs().max(1);
+    // without udevadm the database entry is the best there is
+    Command::new("udevadm")
+        .arg("settle")
+        .arg(format!("--timeout={}", left))
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+/// Seconds since boot with microseconds, as udevadm monitor stamps events.
+fn uptime() -> String {
+    // SAFETY: clock_gettime writes into the timespec we own
+    let ts = unsafe {
+        let mut ts: libc::timespec = std::mem::zeroed();
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+        ts
+    };
+    format!("{}.{:06}", ts.tv_sec, ts.tv_nsec / 1000)
+}
+
+/// The sysfs device path below /sys as udev names it, e.g.
+/// `/devices/pci0000:00/0000:00:14.0/usb1/1-4`, for a device still there.
+fn sysfs_devpath(sys: &Path, name: &str) -> Option<String> {
+    let device = fs::canonicalize(sys.join("bus/usb/devices").join(name)).ok()?;
+    let root = fs::canonicalize(sys).ok()?;
+    Some(format!("/{}", device.strip_prefix(root).ok()?.display()))
+}
+
+/// Turns events into uevents the way `udevadm monitor --udev` prints
+/// them, numbering them as it goes.
+#[derive(Default)]
+pub struct Monitor {
+    seq: u64,
+    /// The device path each device had when it was added, by port, for
+    /// its remove once sysfs no longer has it.
+    devpaths: HashMap<String, String>,
+}
+
+impl Monitor {
+    /// An attach or detach, with the `--property` block after it if asked
+    /// for. The device path is the one in sysfs where there is one. On
+    /// hosts without sysfs, and for a device that was gone before it was
+    /// seen, it is made of the bus and port, `/devices/usb1/1-4.2`, which
+    /// udev never uses. Other events have no uevent and give None.
+    pub fn event(&mut self, kind: &str, fields: &[(&str, String)], properties: bool) -> Option<String> {
+        let field = |name: &str| fields.iter().find(|(n, _)| *n == name).map_or("", |(_, value)| value.as_str());
+        let bus: u32 = field("bus").parse().ok()?;
+        let name = match field("port") {
+            "" => format!("{}-{}", bus, field("address")),
+            port => port.to_string(),
+        };
+        let devpath = match kind {
+            "attach" => sysfs_devpath(Path::new(SYS), &name),
+            "detach" => self.devpaths.remove(&name),
+            _ => return None,
+        };
+        let devpath = devpath.unwrap_or_else(|| format!("/devices/usb{}/{}", bus, name));
+        let event = monitor(kind, fields, &devpath, self.seq + 1, properties)?;
+        if kind == "attach" {
+            self.devpaths.insert(name, devpath);
+        }
+        self.seq += 1;
+        Some(event)
+    }
+}
+
+fn monitor(kind: &str, fields: &[(&str, String)], devpath: &str, seq: u64, properties: bool) -> Option<String> {
+    let action = match kind {
+        "attach" => "add",
+        "detach" => "remove",
+        _ => return None,
+    };
+    let field = |name: &str| fields.iter().find(|(n, _)| *n == name).map_or("", |(_, value)| value.as_str());
+    let bus: u32 = field("bus").parse().ok()?;
+    let address: u32 = field("address").parse().ok()?;
+    let mut out = format!("UDEV  [{}] {:<8} {} (usb)\n", uptime(), action, devpath);
+    if properties {
+        // always four digits, as udev writes them
+        let id = parse_device(field("id")).ok()?;
+        let minor = (bus.max(1) - 1) * 128 + address.max(1) - 1;
+        out.push_str(&format!("ACTION={}\nDEVPATH={}\nSUBSYSTEM=usb\nDEVNAME=/dev/bus/usb/{:03}/{:03}\nDEVTYPE=usb_device\n",
+            action, devpath, bus, address));
+        out.push_str(&format!("BUSNUM={:03}\nDEVNUM={:03}\nSEQNUM={}\nMAJOR=189\nMINOR={}\nID_VENDOR_ID={:04x}\nID_MODEL_ID={:04x}\n\n",
+            bus, address, seq, minor, id.vid, id.pid));
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a Realtek adapter plugged in, in the form udevadm monitor --udev
+    // --property prints it: full sysfs path, ids always four digits
+    const UDEVADM: &str = "\
+UDEV  [6207.718012] add      /devices/pci0000:00/0000:00:14.0/usb2/2-1 (usb)
+ACTION=add
+DEVPATH=/devices/pci0000:00/0000:00:14.0/usb2/2-1
+SUBSYSTEM=usb
+DEVNAME=/dev/bus/usb/002/003
+DEVTYPE=usb_device
+PRODUCT=bda/8153/3000
+TYPE=0/0/0
+BUSNUM=002
+DEVNUM=003
+SEQNUM=5041
+USEC_INITIALIZED=6207715314
+ID_VENDOR=Realtek
+ID_VENDOR_ENC=Realtek
+ID_VENDOR_ID=0bda
+ID_MODEL=USB_10_100_1000_LAN
+ID_MODEL_ENC=USB\\x2010\\x2f100\\x2f1000\\x20LAN
+ID_MODEL_ID=8153
+ID_REVISION=3000
+ID_SERIAL=Realtek_USB_10_100_1000_LAN_000001
+ID_SERIAL_SHORT=000001
+ID_BUS=usb
+ID_USB_INTERFACES=:ff0000:020600:0a0000:
+DRIVER=usb
+MAJOR=189
+MINOR=130
+";
+
+    fn fields() -> Vec<(&'static str, String)> {
+        vec![
+            ("id", String::from("bda:8153")),
+            ("bus", String::from("002")),
+            ("address", String::from("003")),
+            ("port", String::from("2-1")),
+        ]
+    }
+
+    #[test]
+    fn properties_are_those_udevadm_prints() {
+        let event = monitor("attach", &fields(), "/devices/pci0000:00/0000:00:14.0/usb2/2-1", 5041, true).unwrap();
+        let mut lines = event.lines();
+        let header = lines.next().unwrap();
+        let (_, rest) = header.split_once("] ").unwrap();
+        assert_eq!(rest, UDEVADM.lines().next().unwrap().split_once("] ").unwrap().1);
+        for line in lines.filter(|line| !line.is_empty()) {
+            assert!(UDEVADM.lines().any(|real| real == line), "{} isn't in udevadm's output", line);
+        }
+    }
+
+    #[test]
+    fn devpath_comes_from_sysfs() {
+        let sys = std::env::temp_dir().join(format!("usbmon-sys-{}", std::process::id()));
+        let device = sys.join("devices/pci0000:00/0000:00:14.0/usb2/2-1");
+        fs::create_dir_all(&device).unwrap();
+        fs::create_dir_all(sys.join("bus/usb/devices")).unwrap();
+        std::os::unix::fs::symlink("../../../devices/pci0000:00/0000:00:14.0/usb2/2-1", sys.join("bus/usb/devices/2-1")).unwrap();
+        let devpath = sysfs_devpath(&sys, "2-1");
+        let gone = sysfs_devpath(&sys, "2-2");
+        _ = fs::remove_dir_all(&sys);
+        assert_eq!(devpath.as_deref(), Some("/devices/pci0000:00/0000:00:14.0/usb2/2-1"));
+        assert_eq!(gone, None);
+    }
+
+    #[test]
+    fn remove_has_the_devpath_of_the_add() {
+        let mut monitor = Monitor::default();
+        let added = monitor.event("attach", &fields(), true).unwrap();
+        let removed = monitor.event("detach", &fields(), true).unwrap();
+        let devpath = |event: &str| event.lines().find_map(|line| line.strip_prefix("DEVPATH=")).unwrap().to_string();
+        assert_eq!(devpath(&added), devpath(&removed));
+        assert!(removed.contains("\nSEQNUM=2\n"));
+        assert_eq!(monitor.event("flap", &fields(), true), None);
+    }
+}
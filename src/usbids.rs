@@ -0,0 +1,158 @@
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::DeviceID;
+
+const SOURCES: [&str; 4] = [
+    "/usr/share/hwdata/usb.ids",
+    "/usr/share/misc/usb.ids",
+    "/usr/share/usb.ids",
+    "/var/lib/usbutils/usb.ids",
+];
+
+/// The index: one sorted `vvvvpppp name` line per product and `vvvv----
+/// name` per vendor, which sorts right before the vendor's products.
+/// Sorted fixed width keys allow a binary search straight over the bytes.
+enum Index {
+    Mapped(*const u8, usize),
+    Owned(Vec<u8>),
+}
+
+// SAFETY: the mapping is read only and lives as long as the process
+unsafe impl Send for Index {}
+unsafe impl Sync for Index {}
+
+impl Index {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            // SAFETY: mapped read only with this length in map()
+            Index::Mapped(ptr, len) => unsafe { std::slice::from_raw_parts(*ptr, *len) },
+            Index::Owned(bytes) => bytes,
+        }
+    }
+
+    fn map(path: &Path) -> io::Result<Index> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Index::Owned(Vec::new()))
+        }
+        // SAFETY: a private read only mapping of a file we opened
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(Index::Mapped(ptr as *const u8, len))
+    }
+
+    /// The name on the line with the key, by binary search over line starts.
+    fn find(&self, key: &[u8]) -> Option<String> {
+        let bytes = self.bytes();
+        let (mut low, mut high) = (0, bytes.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            let start = bytes[..mid].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+            let end = bytes[start..].iter().position(|&b| b == b'\n').map_or(bytes.len(), |i| start + i);
+            let line = &bytes[start..end];
+            match line.get(..key.len()).unwrap_or(line).cmp(key) {
+                std::cmp::Ordering::Equal => return Some(String::from_utf8_lossy(line.get(key.len() + 1..)?).to_string()),
+                std::cmp::Ordering::Less => low = end + 1,
+                std::cmp::Ordering::Greater => high = start,
+            }
+        }
+        None
+    }
+}
+
+fn is_hex(text: &str) -> bool {
+    text.len() == 4 && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Turns usb.ids into index lines. The vendor list comes first; the class
+/// and other tables after it aren't needed.
+fn build(source: &str) -> Vec<u8> {
+    let mut lines = Vec::new();
+    let mut vendor = None;
+    for line in source.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue
+        }
+        if let Some(product) = line.strip_prefix('\t') {
+            if product.starts_with('\t') {
+                continue
+            }
+            if let (Some(vendor), Some((pid, name))) = (&vendor, product.split_once("  ")) {
+                if is_hex(pid) {
+                    lines.push(format!("{}{} {}", vendor, pid.to_lowercase(), name.trim()));
+                }
+            }
+            continue
+        }
+        match line.split_once("  ") {
+            Some((vid, name)) if is_hex(vid) => {
+                let vid = vid.to_lowercase();
+                lines.push(format!("{}---- {}", vid, name.trim()));
+                vendor = Some(vid);
+            },
+            _ => break,
+        }
+    }
+    lines.sort();
+    let mut bytes = lines.join("\n").into_bytes();
+    bytes.push(b'\n');
+    bytes
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => Some(PathBuf::from(env::var_os("HOME")?).join(".cache")),
+    }
+}
+
+/// Maps the index cached for this version of usb.ids, building and
+/// caching it first if needed. Without a writable cache it is built in
+/// memory every run.
+fn load() -> Option<Index> {
+    let source = SOURCES.iter().map(Path::new).find(|path| path.is_file())?;
+    let meta = fs::metadata(source).ok()?;
+    let cache = cache_dir().map(|dir| dir.join("usbmon").join(format!("usb.ids.{}-{}", meta.size(), meta.mtime())));
+    if let Some(index) = cache.as_deref().and_then(|cache| Index::map(cache).ok()) {
+        return Some(index)
+    }
+    let bytes = build(&String::from_utf8_lossy(&fs::read(source).ok()?));
+    if let Some(cache) = &cache {
+        // written aside and renamed, so a concurrent run never maps half
+        let partial = cache.with_extension("partial");
+        let written = cache.parent().map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&partial, &bytes))
+            .and_then(|_| fs::rename(&partial, cache));
+        if written.is_ok() {
+            if let Ok(index) = Index::map(cache) {
+                return Some(index)
+            }
+        }
+    }
+    Some(Index::Owned(bytes))
+}
+
+static INDEX: OnceLock<Option<Index>> = OnceLock::new();
+
+/// Vendor and product names from the usb.ids database, which is only
+/// loaded the first time a name is looked up.
+pub fn lookup(id: &DeviceID) -> (Option<String>, Option<String>) {
+    let index = match INDEX.get_or_init(load) {
+        Some(index) => index,
+        None => return (None, None),
+    };
+    let vendor = index.find(format!("{:04x}----", id.vid).as_bytes());
+    let product = index.find(format!("{:04x}{:04x}", id.vid, id.pid).as_bytes());
+    (vendor, product)
+}
@@ -0,0 +1,300 @@
+use std::fmt::Write;
+
+use rusb::UsbContext;
+
+use crate::descriptors::{field, field_desc, le16, le32, string};
+
+const CS_INTERFACE: u8 = 0x24;
+
+const SUBCLASS_CONTROL: u8 = 0x01;
+const SUBCLASS_STREAMING: u8 = 0x02;
+
+fn control_subtype_name(subtype: u8) -> &'static str {
+    match subtype {
+        0x01 => "VC_HEADER",
+        0x02 => "VC_INPUT_TERMINAL",
+        0x03 => "VC_OUTPUT_TERMINAL",
+        0x04 => "VC_SELECTOR_UNIT",
+        0x05 => "VC_PROCESSING_UNIT",
+        0x06 => "VC_EXTENSION_UNIT",
+        0x07 => "VC_ENCODING_UNIT",
+        _ => "unknown",
+    }
+}
+
+fn streaming_subtype_name(subtype: u8) -> &'static str {
+    match subtype {
+        0x01 => "VS_INPUT_HEADER",
+        0x02 => "VS_OUTPUT_HEADER",
+        0x03 => "VS_STILL_IMAGE_FRAME",
+        0x04 => "VS_FORMAT_UNCOMPRESSED",
+        0x05 => "VS_FRAME_UNCOMPRESSED",
+        0x06 => "VS_FORMAT_MJPEG",
+        0x07 => "VS_FRAME_MJPEG",
+        0x0d => "VS_COLORFORMAT",
+        0x10 => "VS_FORMAT_FRAME_BASED",
+        0x11 => "VS_FRAME_FRAME_BASED",
+        _ => "unknown",
+    }
+}
+
+fn terminal_name(kind: u16) -> &'static str {
+    match kind {
+        0x0100 => "Vendor Specific",
+        0x0101 => "USB Streaming",
+        0x0200 => "External Vendor Specific",
+        0x0201 => "Camera Sensor",
+        0x0202 => "Media Transport Input",
+        0x0400 => "External Vendor Specific",
+        0x0401 => "Composite Connector",
+        0x0402 => "S-Video Connector",
+        0x0403 => "Component Connector",
+        _ => "",
+    }
+}
+
+fn guid(bytes: &[u8]) -> String {
+    format!("{{{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{}}}",
+        le32(bytes, 0), le16(bytes, 4), le16(bytes, 6), bytes[8], bytes[9],
+        bytes[10..16].iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+/// The first four bytes of the well-known format GUIDs are the fourcc.
+fn fourcc(bytes: &[u8]) -> String {
+    bytes[..4].iter().map(|b| if b.is_ascii_graphic() { *b as char } else { '.' }).collect()
+}
+
+/// Decodes a class-specific descriptor found on a video interface. Returns
+/// false when the descriptor isn't one this module knows.
+pub fn dump_interface<T: UsbContext>(
+    out: &mut String,
+    handle: Option<&rusb::DeviceHandle<T>>,
+    alt: &rusb::InterfaceDescriptor,
+    bytes: &[u8],
+) -> bool {
+    if bytes.len() < 3 || bytes[1] != CS_INTERFACE {
+        return false
+    }
+    match alt.sub_class_code() {
+        SUBCLASS_CONTROL => dump_control(out, handle, bytes),
+        SUBCLASS_STREAMING => dump_streaming(out, bytes),
+        _ => false,
+    }
+}
+
+fn header(out: &mut String, kind: &str, bytes: &[u8], name: &str) {
+    _ = writeln!(out, "      {} Interface Descriptor:", kind);
+    field(out, 8, "bLength", bytes[0]);
+    field(out, 8, "bDescriptorType", bytes[1]);
+    field_desc(out, 8, "bDescriptorSubtype", bytes[2], &format!("({})", name));
+}
+
+fn bitmap(out: &mut String, bits: u32, names: &[&str]) {
+    for (bit, name) in names.iter().enumerate() {
+        if bits & (1 << bit) != 0 {
+            _ = writeln!(out, "          {}", name);
+        }
+    }
+}
+
+fn dump_control<T: UsbContext>(out: &mut String, handle: Option<&rusb::DeviceHandle<T>>, bytes: &[u8]) -> bool {
+    let subtype = bytes[2];
+    let minimum = match subtype {
+        0x01 => 12,
+        0x02 => 8,
+        0x03 => 9,
+        0x05 => 10,
+        0x06 => 24,
+        _ => return false,
+    };
+    if bytes.len() < minimum {
+        return false
+    }
+    header(out, "VideoControl", bytes, control_subtype_name(subtype));
+    let name = |index: u8| string(handle, Some(index).filter(|i| *i != 0));
+
+    match subtype {
+        0x01 => {
+            field(out, 8, "bcdUVC", format!("{:x}.{:02x}", bytes[4], bytes[3]));
+            field(out, 8, "wTotalLength", format!("0x{:04x}", le16(bytes, 5)));
+            field(out, 8, "dwClockFrequency", format!("{}.{:06}MHz", le32(bytes, 7) / 1_000_000, le32(bytes, 7) % 1_000_000));
+            field(out, 8, "bInCollection", bytes[11]);
+            for (i, nr) in bytes[12..].iter().enumerate() {
+                field(out, 8, &format!("baInterfaceNr({:2})", i), nr);
+            }
+        },
+        0x02 => {
+            let kind = le16(bytes, 4);
+            field(out, 8, "bTerminalID", bytes[3]);
+            field_desc(out, 8, "wTerminalType", format!("0x{:04x}", kind), terminal_name(kind));
+            field(out, 8, "bAssocTerminal", bytes[6]);
+            field_desc(out, 8, "iTerminal", bytes[7], &name(bytes[7]));
+            if kind == 0x0201 && bytes.len() >= 15 {
+                field(out, 8, "wObjectiveFocalLengthMin", le16(bytes, 8));
+                field(out, 8, "wObjectiveFocalLengthMax", le16(bytes, 10));
+                field(out, 8, "wOcularFocalLength", le16(bytes, 12));
+                let size = bytes[14] as usize;
+                field(out, 8, "bControlSize", size);
+                let controls = bytes.iter().skip(15).take(size).rev().fold(0u32, |a, b| a << 8 | *b as u32);
+                field(out, 8, "bmControls", format!("0x{:08x}", controls));
+                bitmap(out, controls, &[
+                    "Scanning Mode", "Auto-Exposure Mode", "Auto-Exposure Priority",
+                    "Exposure Time (Absolute)", "Exposure Time (Relative)", "Focus (Absolute)",
+                    "Focus (Relative)", "Iris (Absolute)", "Iris (Relative)", "Zoom (Absolute)",
+                    "Zoom (Relative)", "PanTilt (Absolute)", "PanTilt (Relative)",
+                    "Roll (Absolute)", "Roll (Relative)", "Reserved", "Reserved",
+                    "Focus, Auto", "Privacy",
+                ]);
+            }
+        },
+        0x03 => {
+            let kind = le16(bytes, 4);
+            field(out, 8, "bTerminalID", bytes[3]);
+            field_desc(out, 8, "wTerminalType", format!("0x{:04x}", kind), terminal_name(kind));
+            field(out, 8, "bAssocTerminal", bytes[6]);
+            field(out, 8, "bSourceID", bytes[7]);
+            field_desc(out, 8, "iTerminal", bytes[8], &name(bytes[8]));
+        },
+        0x05 => {
+            field(out, 8, "bUnitID", bytes[3]);
+            field(out, 8, "bSourceID", bytes[4]);
+            field(out, 8, "wMaxMultiplier", le16(bytes, 5));
+            let size = bytes[7] as usize;
+            field(out, 8, "bControlSize", size);
+            let controls = bytes.iter().skip(8).take(size).rev().fold(0u32, |a, b| a << 8 | *b as u32);
+            field(out, 8, "bmControls", format!("0x{:08x}", controls));
+            bitmap(out, controls, &[
+                "Brightness", "Contrast", "Hue", "Saturation", "Sharpness", "Gamma",
+                "White Balance Temperature", "White Balance Component", "Backlight Compensation",
+                "Gain", "Power Line Frequency", "Hue, Auto", "White Balance Temperature, Auto",
+                "White Balance Component, Auto", "Digital Multiplier", "Digital Multiplier Limit",
+                "Analog Video Standard", "Analog Video Lock Status", "Contrast, Auto",
+            ]);
+            if let Some(index) = bytes.get(8 + size) {
+                field_desc(out, 8, "iProcessing", index, &name(*index));
+            }
+        },
+        0x06 => {
+            field(out, 8, "bUnitID", bytes[3]);
+            field(out, 8, "guidExtensionCode", guid(&bytes[4..20]));
+            field(out, 8, "bNumControls", bytes[20]);
+            field(out, 8, "bNrInPins", bytes[21]);
+            let pins = bytes[21] as usize;
+            for (i, id) in bytes.iter().skip(22).take(pins).enumerate() {
+                field(out, 8, &format!("baSourceID({:2})", i), id);
+            }
+        },
+        _ => {},
+    }
+    true
+}
+
+fn dump_streaming(out: &mut String, bytes: &[u8]) -> bool {
+    let subtype = bytes[2];
+    let minimum = match subtype {
+        0x01 => 13,
+        0x04 => 27,
+        0x05 | 0x07 => 26,
+        0x06 => 11,
+        0x0d => 6,
+        0x10 => 28,
+        0x11 => 26,
+        _ => return false,
+    };
+    if bytes.len() < minimum {
+        return false
+    }
+    header(out, "VideoStreaming", bytes, streaming_subtype_name(subtype));
+
+    match subtype {
+        0x01 => {
+            field(out, 8, "bNumFormats", bytes[3]);
+            field(out, 8, "wTotalLength", format!("0x{:04x}", le16(bytes, 4)));
+            field(out, 8, "bEndpointAddress", format!("0x{:02x}", bytes[6]));
+            field(out, 8, "bmInfo", bytes[7]);
+            field(out, 8, "bTerminalLink", bytes[8]);
+            field(out, 8, "bStillCaptureMethod", bytes[9]);
+            field(out, 8, "bTriggerSupport", bytes[10]);
+            field(out, 8, "bTriggerUsage", bytes[11]);
+            field(out, 8, "bControlSize", bytes[12]);
+        },
+        0x04 | 0x10 => {
+            field(out, 8, "bFormatIndex", bytes[3]);
+            field(out, 8, "bNumFrameDescriptors", bytes[4]);
+            field(out, 8, "guidFormat", guid(&bytes[5..21]));
+            _ = writeln!(out, "          {}", fourcc(&bytes[5..21]));
+            field(out, 8, "bBitsPerPixel", bytes[21]);
+            field(out, 8, "bDefaultFrameIndex", bytes[22]);
+            field(out, 8, "bAspectRatioX", bytes[23]);
+            field(out, 8, "bAspectRatioY", bytes[24]);
+            field(out, 8, "bmInterlaceFlags", format!("0x{:02x}", bytes[25]));
+            field(out, 8, "bCopyProtect", bytes[26]);
+            if subtype == 0x10 {
+                field(out, 8, "bVariableSize", bytes[27]);
+            }
+        },
+        0x06 => {
+            field(out, 8, "bFormatIndex", bytes[3]);
+            field(out, 8, "bNumFrameDescriptors", bytes[4]);
+            field(out, 8, "bFlags", bytes[5]);
+            _ = writeln!(out, "          Fixed-size samples: {}", if bytes[5] & 1 != 0 { "Yes" } else { "No" });
+            field(out, 8, "bDefaultFrameIndex", bytes[6]);
+            field(out, 8, "bAspectRatioX", bytes[7]);
+            field(out, 8, "bAspectRatioY", bytes[8]);
+            field(out, 8, "bmInterlaceFlags", format!("0x{:02x}", bytes[9]));
+            field(out, 8, "bCopyProtect", bytes[10]);
+        },
+        0x05 | 0x07 | 0x11 => dump_frame(out, subtype, bytes),
+        0x0d => {
+            field(out, 8, "bColorPrimaries", bytes[3]);
+            field(out, 8, "bTransferCharacteristics", bytes[4]);
+            field(out, 8, "bMatrixCoefficients", bytes[5]);
+        },
+        _ => {},
+    }
+    true
+}
+
+fn dump_frame(out: &mut String, subtype: u8, bytes: &[u8]) {
+    // frame based descriptors drop dwMaxVideoFrameBufferSize and append
+    // dwBytesPerLine, shifting everything after the bit rates
+    let frame_based = subtype == 0x11;
+    field(out, 8, "bFrameIndex", bytes[3]);
+    field(out, 8, "bmCapabilities", format!("0x{:02x}", bytes[4]));
+    field(out, 8, "wWidth", le16(bytes, 5));
+    field(out, 8, "wHeight", le16(bytes, 7));
+    field(out, 8, "dwMinBitRate", le32(bytes, 9));
+    field(out, 8, "dwMaxBitRate", le32(bytes, 13));
+    let (default, kind, intervals) = if frame_based {
+        field(out, 8, "dwDefaultFrameInterval", le32(bytes, 17));
+        field(out, 8, "bFrameIntervalType", bytes[21]);
+        field(out, 8, "dwBytesPerLine", le32(bytes, 22));
+        (le32(bytes, 17), bytes[21], 26)
+    } else {
+        field(out, 8, "dwMaxVideoFrameBufferSize", le32(bytes, 17));
+        field(out, 8, "dwDefaultFrameInterval", le32(bytes, 21));
+        field(out, 8, "bFrameIntervalType", bytes[25]);
+        (le32(bytes, 21), bytes[25], 26)
+    };
+    _ = writeln!(out, "          {}x{} default {}", le16(bytes, 5), le16(bytes, 7), fps(default));
+    if kind == 0 {
+        let (min, max, step) = (le32(bytes, intervals), le32(bytes, intervals + 4), le32(bytes, intervals + 8));
+        field_desc(out, 8, "dwMinFrameInterval", min, &fps(min));
+        field_desc(out, 8, "dwMaxFrameInterval", max, &fps(max));
+        field(out, 8, "dwFrameIntervalStep", step);
+    } else {
+        for i in 0..kind as usize {
+            let interval = le32(bytes, intervals + i * 4);
+            field_desc(out, 8, &format!("dwFrameInterval({:2})", i), interval, &fps(interval));
+        }
+    }
+}
+
+/// Frame intervals are in 100ns units.
+fn fps(interval: u32) -> String {
+    if interval == 0 {
+        return String::new()
+    }
+    let rate = 10_000_000.0 / interval as f64;
+    format!("({:.2} fps)", rate)
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+
+use rusb::UsbContext;
+
+use crate::sysfs;
+
+/// VIDIOC_QUERYCAP, _IOR('V', 0, struct v4l2_capability)
+const VIDIOC_QUERYCAP: libc::c_ulong = 0x80685600;
+const CAP_VIDEO_CAPTURE: u32 = 0x00000001;
+const CAP_META_CAPTURE: u32 = 0x00800000;
+const CAP_DEVICE_CAPS: u32 = 0x80000000;
+
+#[repr(C)]
+struct Capability {
+    driver: [u8; 16],
+    card: [u8; 32],
+    bus_info: [u8; 32],
+    version: u32,
+    capabilities: u32,
+    device_caps: u32,
+    reserved: [u32; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Capture,
+    Metadata,
+    Other,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Capture => write!(f, "capture"),
+            Kind::Metadata => write!(f, "metadata"),
+            Kind::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Asks the node what it does. Nodes that can't be opened fall back on
+/// the uvcvideo convention of the capture node having index 0.
+fn kind(name: &str) -> Kind {
+    let caps = File::open(format!("/dev/{}", name)).ok().and_then(|file| {
+        // SAFETY: the capability struct is plain data filled in by the ioctl
+        unsafe {
+            let mut cap: Capability = std::mem::zeroed();
+            if libc::ioctl(file.as_raw_fd(), VIDIOC_QUERYCAP, &mut cap) != 0 {
+                return None
+            }
+            Some(if cap.capabilities & CAP_DEVICE_CAPS != 0 { cap.device_caps } else { cap.capabilities })
+        }
+    });
+    match caps {
+        Some(caps) if caps & CAP_VIDEO_CAPTURE != 0 => Kind::Capture,
+        Some(caps) if caps & CAP_META_CAPTURE != 0 => Kind::Metadata,
+        Some(_) => Kind::Other,
+        None => match sysfs::video_index(name) {
+            Some(0) => Kind::Capture,
+            Some(1) => Kind::Metadata,
+            _ => Kind::Other,
+        },
+    }
+}
+
+/// The `/dev/videoN` nodes of the camera and what each of them is for.
+pub fn nodes<T: UsbContext>(device: &rusb::Device<T>) -> Vec<(String, Kind)> {
+    sysfs::video_nodes(device)
+        .into_iter()
+        .map(|name| {
+            let kind = kind(&name);
+            (format!("/dev/{}", name), kind)
+        })
+        .collect()
+}
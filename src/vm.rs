@@ -0,0 +1,113 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::inventory::{self, Entry};
+use crate::json;
+use crate::{Filter, VmAttachArgs};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// libvirt's hostdev element for the device. Matching by vendor and
+/// product survives replugs, the address picks one of several alike.
+fn hostdev(entry: &Entry) -> String {
+    format!("<hostdev mode='subsystem' type='usb' managed='yes'>\n  <source>\n    \
+        <vendor id='0x{:04x}'/>\n    <product id='0x{:04x}'/>\n    <address bus='{}' device='{}'/>\n  \
+        </source>\n</hostdev>\n",
+        entry.id.vid, entry.id.pid, entry.bus, entry.address)
+}
+
+fn virsh(domain: &str, xml: &str) -> io::Result<()> {
+    let mut child = Command::new("virsh")
+        .args(["attach-device", domain, "/dev/stdin", "--live"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    child.stdin.take().ok_or(io::ErrorKind::BrokenPipe)?.write_all(xml.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("virsh exited with {}", status)))
+    }
+    Ok(())
+}
+
+/// Reads QMP replies up to the one answering a command, skipping the
+/// asynchronous events in between.
+fn qmp_reply(reader: &mut BufReader<UnixStream>) -> io::Result<String> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into())
+        }
+        if line.contains("\"return\"") || line.contains("\"QMP\"") {
+            return Ok(line)
+        }
+        if line.contains("\"error\"") {
+            return Err(io::Error::other(line.trim().to_string()))
+        }
+    }
+}
+
+/// The QMP command that hot-plugs the device as a usb-host device.
+fn device_add(entry: &Entry) -> String {
+    json::object(&[
+        ("execute", json::string("device_add")),
+        ("arguments", json::object(&[
+            ("driver", json::string("usb-host")),
+            ("hostbus", entry.bus.to_string()),
+            ("hostaddr", entry.address.to_string()),
+            ("id", json::string(&format!("usbmon-{:04x}-{:04x}-{}-{}", entry.id.vid, entry.id.pid, entry.bus, entry.address))),
+        ])),
+    ])
+}
+
+fn qmp(socket: &Path, command: &str) -> io::Result<()> {
+    let stream = UnixStream::connect(socket)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    qmp_reply(&mut reader)?;
+    writer.write_all(b"{\"execute\":\"qmp_capabilities\"}\n")?;
+    qmp_reply(&mut reader)?;
+    writer.write_all(format!("{}\n", command).as_bytes())?;
+    qmp_reply(&mut reader).map(|_| ())
+}
+
+/// Waits for the device and then prints or applies what attaches it to
+/// the VM. Returns false if it didn't show up in time or attaching failed.
+pub fn run(args: &VmAttachArgs) -> rusb::Result<bool> {
+    let filter = Filter::new(&args.filter);
+    let ctx = rusb::Context::new()?;
+    let start = Instant::now();
+    let entry = loop {
+        if let Some(entry) = inventory::scan(&ctx, &filter)?.into_iter().next() {
+            break entry
+        }
+        if args.timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            eprintln!("timed out waiting for the device");
+            return Ok(false)
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let result = match (&args.domain, &args.qmp) {
+        (_, Some(socket)) if args.apply => qmp(socket, &device_add(&entry)),
+        (Some(domain), None) if args.apply => virsh(domain, &hostdev(&entry)),
+        (_, Some(_)) => {
+            println!("{}", device_add(&entry));
+            Ok(())
+        },
+        _ => {
+            print!("{}", hostdev(&entry));
+            Ok(())
+        },
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            eprintln!("attaching {} failed: {}", entry.id, e);
+            Ok(false)
+        },
+    }
+}
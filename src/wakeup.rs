@@ -0,0 +1,38 @@
+use crate::inventory;
+use crate::sysfs;
+use crate::{Filter, WakeupArgs};
+
+/// Shows or changes whether the matching devices may wake the machine
+/// from sleep, through their power/wakeup attribute. Devices whose
+/// configuration doesn't support remote wakeup have an empty attribute
+/// and are left alone. Returns whether every change was made.
+pub fn run(args: &WakeupArgs) -> rusb::Result<bool> {
+    let ctx = rusb::Context::new()?;
+    let value = match (args.enable, args.disable) {
+        (true, _) => Some("enabled"),
+        (_, true) => Some("disabled"),
+        _ => None,
+    };
+    let mut ok = true;
+    for entry in inventory::scan(&ctx, &Filter::new(&args.filter))?.iter().filter(|e| !e.port.is_empty()) {
+        let capable = inventory::find(&ctx, entry)
+            .and_then(|dev| dev.active_config_descriptor().ok())
+            .is_some_and(|config| config.remote_wakeup());
+        match value {
+            Some(_) if !capable => {
+                eprintln!("{}: the device doesn't support remote wakeup", entry);
+                ok = false;
+            },
+            Some(value) => {
+                if let Err(e) = sysfs::set_power(&entry.port, "wakeup", value) {
+                    eprintln!("{}: failed to set power/wakeup to {}: {}", entry, value, e);
+                    ok = false;
+                }
+            },
+            None => {},
+        }
+        let wakeup = sysfs::power(&entry.port, "wakeup").filter(|w| !w.is_empty()).unwrap_or_else(|| String::from("unsupported"));
+        println!("{} {} wakeup={}", entry, entry.port, wakeup);
+    }
+    Ok(ok)
+}
@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+use std::io;
+#[cfg(feature = "sandbox")]
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::acl::Acl;
+use crate::audit::Audit;
+use crate::coalesce::Coalescer;
+use crate::config::Config;
+use crate::control::{self, Control};
+use crate::flap::FlapDetector;
+use crate::hub;
+use crate::inventory::{self, Entry};
+use crate::logfile::{LogFile, Rotation};
+use crate::matcher::Matcher;
+use crate::mqtt::Publisher;
+use crate::notify::Notifiers;
+use crate::otlp::{self, Exporter, Replug};
+use crate::overcurrent::Overcurrent;
+use crate::output::{Output, Sink};
+use crate::pidfile::PidFile;
+use crate::power::PowerStates;
+use crate::plugin::{Plugins, Request};
+use crate::privileges;
+use crate::replay::Replay;
+use crate::rules::{self, Rules};
+#[cfg(feature = "sandbox")]
+use crate::output::{parse_target, Target};
+#[cfg(feature = "sandbox")]
+use crate::sandbox;
+use crate::signal;
+use crate::sources::Sources;
+use crate::state::{self, State};
+use crate::statsd::Statsd;
+use crate::sysfs;
+use crate::template::{self, Fields};
+use crate::tls::{self, Certificates};
+use crate::{DeviceID, Filter, WatchArgs};
+
+/// The longest watch waits between looking at signals and plugins.
+const SLICE: Duration = Duration::from_millis(100);
+
+pub enum Outcome {
+    /// Interrupted; unhealthy if a device flapped on the way.
+    Stopped { healthy: bool },
+    /// A required device stayed away for too long.
+    Absent,
+}
+
+
+/// Tracks since when each required device has been missing. Without ids
+/// any device matching the filter will do, tracked under a single key.
+struct Watchdog {
+    ids: Vec<DeviceID>,
+    missing: HashMap<Option<DeviceID>, Instant>,
+}
+
+impl Watchdog {
+    fn new(ids: &[DeviceID]) -> Watchdog {
+        Watchdog{ids: ids.to_vec(), missing: HashMap::new()}
+    }
+
+    /// Returns the devices missing for longer than allowed, restarting
+    /// their clocks.
+    fn check(&mut self, devices: &[Entry], now: Instant, args: &WatchArgs) -> Vec<Option<DeviceID>> {
+        let keys: Vec<Option<DeviceID>> = if self.ids.is_empty() {
+            vec![None]
+        } else {
+            self.ids.iter().cloned().map(Some).collect()
+        };
+        let mut overdue = Vec::new();
+        for key in keys {
+            let present = match &key {
+                Some(id) => devices.iter().any(|e| &e.id == id),
+                None => !devices.is_empty(),
+            };
+            if present {
+                self.missing.remove(&key);
+                continue;
+            }
+            let since = *self.missing.entry(key.clone()).or_insert(now);
+            if now.duration_since(since) >= args.max_absence {
+                self.missing.insert(key.clone(), now);
+                overdue.push(key);
+            }
+        }
+        overdue
+    }
+}
+
+/// Reports an overdue device and runs --on-absence if there is one.
+/// Returns true when watch should exit instead.
+fn absent(key: &Option<DeviceID>, args: &WatchArgs, out: &mut Output) -> bool {
+    let name = key.as_ref().map_or(String::from("device"), |id| id.to_string());
+    let text = format!("! {} absent for more than {}s", name, args.max_absence.as_secs());
+    out.emit("absent", &text, &[("id", name.clone())]);
+    let cmd = match &args.on_absence {
+        Some(cmd) => cmd,
+        None => return true,
+    };
+    let status = process::Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("USBMON_ID", key.as_ref().map(|id| id.to_string()).unwrap_or_default())
+        .status();
+    if let Err(e) = status {
+        eprintln!("failed to run {}: {}", cmd, e);
+    }
+    false
+}
+
+/// Carries out what a plugin asked for.
+fn handle(ctx: &rusb::Context, plugin: &str, request: Request, devices: &[Entry], notifiers: &mut Notifiers, out: &mut Output) {
+    match request {
+        Request::Log(message) => out.line(&format!("* {}: {}", plugin, message)),
+        Request::Notify(via, message) => {
+            let fields = vec![("event", String::from("plugin")), ("host", template::hostname()), ("message", message)];
+            if !notifiers.deliver(&via, &fields) {
+                eprintln!("plugin {}: no {} notifier configured", plugin, via);
+            }
+        },
+        Request::Authorize(bus, address, allow) => {
+            let device = devices
+                .iter()
+                .find(|e| e.bus == bus && e.address == address)
+                .and_then(|entry| inventory::find(ctx, entry));
+            match device.map(|dev| sysfs::authorize(&dev, allow)) {
+                Some(Ok(())) => {},
+                Some(Err(e)) => eprintln!("plugin {}: failed to authorize {:03}:{:03}: {}", plugin, bus, address, e),
+                None => eprintln!("plugin {}: no device {:03}:{:03}", plugin, bus, address),
+            }
+        },
+    }
+}
+
+/// One device, by port path and vid:pid, across replugs. Devices present
+/// at startup have no known start.
+#[derive(Default)]
+struct Session {
+    since: Option<Instant>,
+    count: u32,
+}
+
+/// Handles a made up event as if a device had come or gone, short of
+/// changing the device list, so rules and sinks can be tried out.
+fn inject(ctx: &rusb::Context, entry: &Entry, attached: bool, statsd: Option<&Statsd>, configured: &mut Configured, out: &mut Output) {
+    let (sign, kind) = if attached { ('+', "attach") } else { ('-', "detach") };
+    let mut fields = template::fields(ctx, kind, &entry.id, Some(entry));
+    fields.push(("injected", String::from("true")));
+    configured.audit(&fields);
+    out.emit(kind, &format!("{} {} injected", sign, entry), &[
+        ("id", entry.id.to_string()),
+        ("bus", format!("{:03}", entry.bus)),
+        ("address", format!("{:03}", entry.address)),
+        ("port", entry.port.clone()),
+        ("injected", String::from("true")),
+    ]);
+    if let Some(statsd) = statsd {
+        statsd.event(attached, &entry.id);
+    }
+    configured.notify::<rusb::Context>(&fields, None, out);
+}
+
+/// Everything the config file sets up, replaced as a whole on reload.
+struct Configured {
+    outputs: Vec<Sink>,
+    audit: Option<Audit>,
+    notifiers: Notifiers,
+    rules: Rules,
+    plugins: Plugins,
+    publisher: Option<Publisher>,
+    acl: Option<Acl>,
+}
+
+/// The config file, narrowed to the --profile if there is one.
+fn load_config(args: &WatchArgs) -> Result<Config, String> {
+    let path = match &args.config {
+        Some(path) => path,
+        None => return Ok(Config::default()),
+    };
+    let config = Config::load(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    match &args.profile {
+        Some(profile) => config.select(profile).map_err(|e| format!("{}: {}", path.display(), e)),
+        None => Ok(config),
+    }
+}
+
+impl Configured {
+    fn load(args: &WatchArgs) -> Result<Configured, String> {
+        let config = load_config(args)?;
+        Ok(Configured{
+            outputs: Sink::configured(&config).map_err(|e| format!("output: {}", e))?,
+            audit: config.section("audit").map(Audit::new).transpose().map_err(|e| format!("audit: {}", e))?,
+            notifiers: Notifiers::new(&config).map_err(|e| format!("config: {}", e))?,
+            rules: Rules::new(&config).map_err(|e| format!("config: {}", e))?,
+            plugins: Plugins::new(&config).map_err(|e| format!("plugins: {}", e))?,
+            publisher: config.section("mqtt").map(Publisher::new).transpose().map_err(|e| format!("mqtt: {}", e))?,
+            acl: config.section("acl").map(|s| Acl::new(s, &control::CAPABILITIES)).transpose().map_err(|e| format!("acl: {}", e))?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.audit.is_none() && self.notifiers.is_empty() && self.rules.is_empty() && self.plugins.is_empty()
+    }
+
+    /// Records an event in the audit log, which is done before it is
+    /// emitted or notified.
+    fn audit(&mut self, fields: &Fields) {
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record(fields);
+        }
+    }
+
+    /// Hands an event to the notifiers, rules and plugins.
+    fn notify<T: rusb::UsbContext>(&mut self, fields: &Fields, device: Option<&rusb::Device<T>>, out: &mut Output) {
+        self.notifiers.notify(fields);
+        self.rules.apply(fields, device, &mut self.notifiers, out);
+        self.plugins.send(fields);
+    }
+}
+
+/// Reports a hub port that went over current, with the device that was
+/// on it if it was being watched.
+fn tripped(ctx: &rusb::Context, port: &str, count: u64, devices: &[Entry], configured: &mut Configured, out: &mut Output) {
+    let entry = devices.iter().find(|e| e.port == port);
+    let text = match entry {
+        Some(entry) => format!("! overcurrent on port {}, {}", port, entry),
+        None => format!("! overcurrent on port {}", port),
+    };
+    // rules match on the device, so without one there is nothing to match
+    let notified = entry.map(|entry| {
+        let mut fields = template::fields(ctx, "overcurrent", &entry.id, Some(entry));
+        fields.push(("overcurrents", count.to_string()));
+        fields
+    });
+    if let Some(fields) = &notified {
+        configured.audit(fields);
+    }
+    let mut fields = vec![("port", port.to_string()), ("overcurrents", count.to_string())];
+    fields.extend(entry.map(|e| ("id", e.id.to_string())));
+    out.emit("overcurrent", &text, &fields);
+    if let Some(fields) = &notified {
+        configured.notify::<rusb::Context>(fields, None, out);
+    }
+}
+
+/// Reports a device the kernel suspended or resumed.
+fn power_changed(ctx: &rusb::Context, entry: &Entry, suspended: bool, configured: &mut Configured, out: &mut Output) {
+    let (kind, state) = if suspended { ("suspend", "suspended") } else { ("resume", "resumed") };
+    let fields = template::fields(ctx, kind, &entry.id, Some(entry));
+    configured.audit(&fields);
+    out.emit(kind, &format!("~ {} {}", entry, state), &[
+        ("id", entry.id.to_string()),
+        ("bus", format!("{:03}", entry.bus)),
+        ("address", format!("{:03}", entry.address)),
+        ("port", entry.port.clone()),
+    ]);
+    let device = inventory::find(ctx, entry);
+    configured.notify(&fields, device.as_ref(), out);
+}
+
+/// One record with every device present, taken from the same scan the
+/// events that follow are the difference to.
+fn snapshot(devices: &[Entry], out: &mut Output) {
+    let present: Vec<String> = devices.iter().map(Entry::to_string).collect();
+    let text = if present.is_empty() { String::from("= none") } else { format!("= {}", present.join(", ")) };
+    out.emit("snapshot", &text, &[("devices", devices.len().to_string()), ("present", present.join(","))]);
+}
+
+/// Reports why a sink or the config couldn't be set up.
+fn setup<T>(what: &str, result: io::Result<T>) -> rusb::Result<T> {
+    result.map_err(|e| {
+        eprintln!("{}: {}", what, e);
+        rusb::Error::Other
+    })
+}
+
+/// Rescans every interval and prints the difference. Unlike waiting this
+/// only needs enumeration, so it works where libusb has no hotplug support.
+pub fn run(args: &WatchArgs) -> rusb::Result<Outcome> {
+    let _pidfile = match &args.pidfile {
+        Some(path) => Some(setup(&path.display().to_string(), PidFile::create(path))?),
+        None => None,
+    };
+    tls::configure(Certificates{cert: args.tls_cert.clone(), key: args.tls_key.clone(), ca: args.tls_ca.clone()});
+    hub::configure(args.hub_token.clone());
+    let mut filter = Filter::new(&args.filter);
+    // the profile's filter stands in for one on the command line, and is
+    // needed for the first scan, before privileges are dropped
+    if filter.is_empty() && args.profile.is_some() {
+        let config = load_config(args).map_err(|e| {
+            eprintln!("{}", e);
+            rusb::Error::Other
+        })?;
+        if let Some(section) = config.section("profile") {
+            filter = setup("profile", rules::filter(section))?;
+        }
+    }
+    // before libusb starts its threads, which landlock wouldn't confine
+    #[cfg(feature = "sandbox")]
+    if args.sandbox {
+        let read: Vec<&Path> = args.config.iter().chain(&args.tls_ca).chain(&args.tls_cert).chain(&args.tls_key).map(|p| p.as_path()).collect();
+        // the sandbox outlives reloads, so only files of the config at
+        // startup can be written
+        let config = load_config(args).unwrap_or_default();
+        let mut write: Vec<&Path> = args.pidfile.iter()
+            .chain(&args.log_file)
+            .chain(&args.state)
+            .chain(&args.control)
+            .map(|p| p.as_path())
+            .collect();
+        if let Target::File(path) = &args.output {
+            write.push(path);
+        }
+        write.extend(config.section("audit").and_then(|s| s.get("path")).map(Path::new));
+        let targets: Vec<Target> = config.sections("output").filter_map(|(_, s)| parse_target(s.get("target")?).ok()).collect();
+        write.extend(targets.iter().filter_map(|t| match t {
+            Target::File(path) => Some(path.as_path()),
+            _ => None,
+        }));
+        // the state is saved into a directory of its own making, which
+        // has to be there to be granted
+        if let Some(dir) = args.state.as_deref().and_then(Path::parent).filter(|dir| !dir.as_os_str().is_empty()) {
+            setup(&dir.display().to_string(), std::fs::create_dir_all(dir))?;
+        }
+        setup("sandbox", sandbox::apply(&read, &write))?;
+    }
+    let ids = filter.ids.clone();
+    let ctx = rusb::Context::new()?;
+    let mut flaps = args.flap_limit.map(FlapDetector::new);
+    let mut watchdog = Watchdog::new(&ids);
+    let mut devices = inventory::scan(&ctx, &filter)?;
+    // config, plugins and sinks are all set up as the unprivileged user
+    if args.user.is_some() || args.group.is_some() {
+        setup("privileges", privileges::drop(args.user.as_deref(), args.group.as_deref()))?;
+    }
+    // bound as the unprivileged user, so it can still remove the socket
+    let mut control = match &args.control {
+        Some(path) => Some(setup(&path.display().to_string(), Control::bind(path))?),
+        None => None,
+    };
+    // written once up front, with what the first scan found
+    let mut state = match &args.state {
+        Some(path) => {
+            let what = path.display().to_string();
+            let mut state = setup(&what, State::load(path))?;
+            setup(&what, state.record(&devices, &Matcher::new(&filter)))?;
+            Some(state)
+        },
+        None => None,
+    };
+    // agents are started after the sandbox, which they inherit
+    let sources = setup("source", Sources::start(&args.source))?;
+    let log = args.log_file.as_ref().map(|path| LogFile::new(path, Rotation{
+        max_size: args.log_max_size,
+        max_age: args.log_max_age,
+        keep: args.log_keep,
+        gzip: args.log_gzip,
+    }));
+    let log = setup("log", log.transpose())?;
+    let mut out = Output::new(setup("output", Sink::new(&args.output, args.format))?, log);
+    if control.is_some() {
+        out.set_replay(Replay::new(args.replay_buffer));
+    }
+    let mut heartbeat = Instant::now();
+    let mut overcurrent = args.overcurrent.then(Overcurrent::new);
+    let mut power = args.power_state.then(PowerStates::default);
+    let mut coalescer = Coalescer::new(args.coalesce);
+    let statsd = args.statsd.as_ref().map(|addr| Statsd::connect(addr, &args.statsd_prefix)).transpose();
+    let statsd = setup("statsd", statsd)?;
+    let mut exporter = setup("otlp", args.otlp.as_deref().map(Exporter::new).transpose())?;
+    let mut detached: HashMap<DeviceID, u64> = HashMap::new();
+    let mut sessions: HashMap<(String, String, DeviceID), Session> = HashMap::new();
+    let mut configured = Configured::load(args).map_err(|e| {
+        eprintln!("{}", e);
+        rusb::Error::Other
+    })?;
+    out.set_configured(std::mem::take(&mut configured.outputs));
+    if let Some(control) = control.as_mut() {
+        setup("control", control.set_acl(configured.acl.take()))?;
+    }
+    let names = |entry: &Entry| inventory::find(&ctx, entry).map(|dev| inventory::name(&dev)).unwrap_or_default();
+    if let Some(publisher) = configured.publisher.as_mut() {
+        publisher.presence(&ids, &devices, &names);
+    }
+    if let Some(audit) = configured.audit.as_mut() {
+        audit.record(&vec![("event", String::from("start")), ("host", template::hostname())]);
+    }
+    if args.initial_state {
+        snapshot(&devices, &mut out);
+    }
+    signal::catch_stop();
+    signal::catch_reload();
+    let mut enumerate = args.enumerate_existing;
+    let mut next_scan = Instant::now() + args.interval;
+    while !signal::stopped() {
+        if signal::reload_requested() {
+            // the device list carries over, so nothing that happened
+            // meanwhile is lost, only sinks and rules are replaced
+            match Configured::load(args) {
+                Ok(reloaded) => {
+                    configured = reloaded;
+                    out.set_configured(std::mem::take(&mut configured.outputs));
+                    if let Some(Err(e)) = control.as_mut().map(|control| control.set_acl(configured.acl.take())) {
+                        eprintln!("control: {}", e);
+                    }
+                    if let Some(audit) = configured.audit.as_mut() {
+                        audit.record(&vec![("event", String::from("reload")), ("host", template::hostname())]);
+                    }
+                    out.emit("reload", ". config reloaded", &[]);
+                    if let Some(publisher) = configured.publisher.as_mut() {
+                        publisher.presence(&ids, &devices, &names);
+                    }
+                },
+                Err(e) => eprintln!("{}, keeping the previous config", e),
+            }
+        }
+        for (name, request) in configured.plugins.requests() {
+            handle(&ctx, &name, request, &devices, &mut configured.notifiers, &mut out);
+        }
+        for mut request in control.as_mut().map(Control::requests).unwrap_or_default() {
+            match &request.command {
+                control::Command::List => {
+                    let lines: Vec<String> = devices.iter().map(|e| format!("{} {}", e, e.port)).collect();
+                    request.reply_lines(format!("{} {}", out.sequence(), lines.len()), &lines);
+                },
+                control::Command::Subscribe{since} => {
+                    let since = *since;
+                    out.subscribe(request.into_stream(), since);
+                },
+                control::Command::Inject{attached, id, bus, address} => {
+                    let entry = Entry{bus: *bus, address: *address, id: id.clone(), port: String::new()};
+                    inject(&ctx, &entry, *attached, statsd.as_ref(), &mut configured, &mut out);
+                    request.reply(Ok(String::new()));
+                },
+            }
+        }
+        if args.require_present {
+            for key in watchdog.check(&devices, Instant::now(), args) {
+                if let Some(id) = &key {
+                    let fields = template::fields(&ctx, "absent", id, None);
+                    configured.notify::<rusb::Context>(&fields, None, &mut out);
+                }
+                if absent(&key, args, &mut out) {
+                    return Ok(Outcome::Absent)
+                }
+            }
+        }
+        // control requests are answered as they come, signals and plugins
+        // seen within a slice, while scans keep to the interval
+        let left = next_scan.saturating_duration_since(Instant::now());
+        if !enumerate && !left.is_zero() {
+            let slice = left.min(SLICE);
+            match &control {
+                Some(control) => control.wait(slice),
+                None => thread::sleep(slice),
+            }
+            continue
+        }
+        next_scan = Instant::now() + args.interval;
+        // before the detach it causes
+        for (port, count) in overcurrent.as_mut().map(Overcurrent::check).unwrap_or_default() {
+            tripped(&ctx, &port, count, &devices, &mut configured, &mut out);
+        }
+        // the first pass of --enumerate-existing reports everything there
+        let existing = std::mem::take(&mut enumerate);
+        let current = match inventory::scan(&ctx, &filter) {
+            Ok(current) => current,
+            Err(_) => continue,
+        };
+        let (added, removed) = inventory::diff(if existing { &[] } else { &devices }, &current);
+        let now = Instant::now();
+        let reported = sources.remote();
+        // remote devices aren't on this host, so they are never looked up
+        let events: Vec<(char, &Entry, &str, bool)> = removed
+            .iter()
+            .map(|e| ('-', e))
+            .chain(added.iter().map(|e| ('+', e)))
+            .filter_map(|(sign, entry)| Some((sign, entry, sources.label(entry)?, false)))
+            .chain(reported.iter().map(|(label, attached, entry)| (if *attached { '+' } else { '-' }, entry, *label, true)))
+            .filter(|(sign, entry, _, _)| coalescer.fresh(*sign == '+', entry, now))
+            .collect();
+        for (sign, entry, label, remote) in events {
+            let kind = if sign == '+' { "attach" } else { "detach" };
+            let mut fields = vec![
+                ("id", entry.id.to_string()),
+                ("bus", format!("{:03}", entry.bus)),
+                ("address", format!("{:03}", entry.address)),
+                ("port", entry.port.clone()),
+            ];
+            if !label.is_empty() {
+                fields.push(("source", label.to_string()));
+            }
+            if existing && !remote {
+                fields.push(("existing", String::from("true")));
+            }
+            let session = sessions.entry((label.to_string(), entry.port.clone(), entry.id.clone())).or_default();
+            if sign == '+' {
+                session.since = Some(now);
+                session.count += 1;
+                fields.push(("sessions", session.count.to_string()));
+            } else if let Some(since) = session.since.take() {
+                fields.push(("session", now.duration_since(since).as_secs().to_string()));
+                fields.push(("sessions", session.count.to_string()));
+            }
+            let text = match label {
+                "" => format!("{} {}", sign, entry),
+                label => format!("[{}] {} {}", label, sign, entry),
+            };
+            let notified = (!configured.is_empty()).then(|| {
+                let mut fields = template::fields(&ctx, kind, &entry.id, (!remote).then_some(entry));
+                if remote {
+                    fields.push(("bus", format!("{:03}", entry.bus)));
+                    fields.push(("address", format!("{:03}", entry.address)));
+                }
+                if !label.is_empty() {
+                    fields.push(("source", label.to_string()));
+                }
+                fields
+            });
+            if let Some(fields) = &notified {
+                configured.audit(fields);
+            }
+            out.emit(kind, &text, &fields);
+            if let Some(statsd) = &statsd {
+                statsd.event(sign == '+', &entry.id);
+            }
+            if let Some(fields) = &notified {
+                let device = if sign == '+' && !remote { inventory::find(&ctx, entry) } else { None };
+                configured.notify(fields, device.as_ref(), &mut out);
+            }
+            if let Some(exporter) = exporter.as_mut() {
+                exporter.event(sign == '+', entry);
+                if sign == '-' {
+                    detached.insert(entry.id.clone(), otlp::now());
+                } else if let Some(since) = detached.remove(&entry.id) {
+                    let attached = otlp::now();
+                    let openable = inventory::find(&ctx, entry)
+                        .filter(|_| !remote)
+                        .is_some_and(|dev| dev.open().is_ok())
+                        .then(otlp::now);
+                    exporter.replug(entry, &Replug{detached: since, attached, openable});
+                }
+            }
+            if let Some(rate) = flaps.as_mut().and_then(|f| f.record(&entry.id, now)) {
+                let text = format!("! {} flapping, {} transitions in the last minute", entry.id, rate);
+                out.emit("flap", &text, &[("id", entry.id.to_string()), ("transitions", rate.to_string())]);
+            }
+        }
+        if let Some(publisher) = configured.publisher.as_mut() {
+            if !added.is_empty() || !removed.is_empty() {
+                for entry in &removed {
+                    publisher.detached(&entry.id, &current);
+                }
+                publisher.presence(&ids, &current, &names);
+            }
+        }
+        devices = current;
+        if let Some(Err(e)) = state.as_mut().map(|s| s.record(&devices, &Matcher::new(&filter))) {
+            eprintln!("{}: {}", args.state.as_ref().unwrap().display(), e);
+        }
+        for (entry, suspended) in power.as_mut().map(|p| p.check(&devices)).unwrap_or_default() {
+            power_changed(&ctx, &entry, suspended, &mut configured, &mut out);
+        }
+        if let Some(statsd) = &statsd {
+            statsd.presence(&ids, &devices);
+        }
+        if args.heartbeat.is_some_and(|period| now.duration_since(heartbeat) >= period) {
+            let text = format!(". alive, {} device(s) present", devices.len());
+            out.emit("heartbeat", &text, &[("devices", devices.len().to_string())]);
+            heartbeat = now;
+        }
+    }
+    if let Some(audit) = configured.audit.as_mut() {
+        audit.record(&vec![("event", String::from("stop")), ("host", template::hostname())]);
+    }
+    // the devices still there were seen until now
+    if let Some(state) = state.as_mut() {
+        state.update(&devices, &Matcher::new(&filter), state::now());
+        if let Err(e) = state.save() {
+            eprintln!("{}: {}", args.state.as_ref().unwrap().display(), e);
+        }
+    }
+    Ok(Outcome::Stopped{healthy: flaps.is_none_or(|f| f.healthy())})
+}
@@ -0,0 +1,99 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use crate::config::Section;
+use crate::ratelimit::RateLimit;
+use crate::json;
+use crate::template::{self, Fields};
+use crate::workers::Workers;
+
+/// Posts notifications to a Slack or Discord incoming webhook, configured
+/// by the `[webhook]` section:
+///
+/// ```text
+/// [webhook]
+/// url = https://hooks.slack.com/services/...
+/// kind = slack
+/// events = attach, detach, absent
+/// template = {name} (serial {serial}) {event} on {host}
+/// rate_limit = 10/1m
+/// cooldown = 5m
+/// ```
+///
+/// The request is made by curl, which takes care of TLS, in the
+/// background one after another so a slow endpoint doesn't hold up
+/// watching.
+pub struct Webhook {
+    url: String,
+    /// The JSON key the message text goes in.
+    key: &'static str,
+    events: Vec<String>,
+    template: String,
+    limit: Option<RateLimit>,
+    worker: Workers,
+}
+
+impl Webhook {
+    pub fn new(section: &Section) -> io::Result<Webhook> {
+        let key = match section.get("kind").unwrap_or("slack") {
+            "slack" => "text",
+            "discord" => "content",
+            kind => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("[webhook] unknown kind {}", kind))),
+        };
+        let events = section.list("events");
+        Ok(Webhook{
+            url: section.require("url")?.to_string(),
+            key,
+            events: if events.is_empty() { vec![String::from("detach"), String::from("absent")] } else { events },
+            template: section.get("template").unwrap_or("{id} {event} on {host}").to_string(),
+            limit: RateLimit::new(section)?,
+            worker: Workers::new(1),
+        })
+    }
+
+    /// Posts the event if it is one of the configured ones.
+    pub fn notify(&mut self, fields: &Fields) {
+        if self.events.iter().any(|e| e == template::get(fields, "event")) {
+            self.deliver(fields);
+        }
+    }
+
+    /// Posts an event unless the rate limit holds it back. Suppressed
+    /// events are counted in the next message that does go out.
+    pub fn deliver(&mut self, fields: &Fields) {
+        let mut message = template::render(&self.template, fields);
+        if let Some(limit) = self.limit.as_mut() {
+            if !limit.allow(Instant::now()) {
+                return
+            }
+            let suppressed = limit.take_suppressed();
+            if suppressed > 0 {
+                message.push_str(&format!(" ({} earlier event(s) held back by the rate limit)", suppressed));
+            }
+        }
+        let body = json::object(&[(self.key, json::string(&message))]);
+        let url = self.url.clone();
+        self.worker.run("webhook", move || {
+            if let Err(e) = post(&url, &body) {
+                eprintln!("webhook: {}", e);
+            }
+        });
+    }
+}
+
+/// Posts a JSON body with curl, failing on HTTP errors.
+pub fn post(url: &str, body: &str) -> io::Result<()> {
+    let mut child = Command::new("curl")
+        .args(["-sSf", "--max-time", "10", "-H", "Content-Type: application/json", "--data-binary", "@-"])
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    child.stdin.take().ok_or(io::ErrorKind::BrokenPipe)?.write_all(body.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("curl exited with {}", status)))
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::inventory;
+use crate::sysfs;
+use crate::udev;
+use crate::{Filter, FilterArgs};
+
+/// A process holding one of the device's nodes open.
+struct Holder {
+    pid: u32,
+    command: String,
+    node: PathBuf,
+}
+
+/// Walks /proc for open descriptors pointing at any of the nodes. Other
+/// users' processes are only visible to root.
+fn holders(nodes: &[PathBuf]) -> Vec<Holder> {
+    let mut found = Vec::new();
+    let procs = match fs::read_dir("/proc") {
+        Ok(procs) => procs,
+        Err(_) => return found,
+    };
+    for proc in procs.filter_map(|entry| entry.ok()) {
+        let pid: u32 = match proc.file_name().to_string_lossy().parse() {
+            Ok(pid) => pid,
+            Err(_) => continue,
+        };
+        let fds = match fs::read_dir(proc.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        let mut open: Vec<PathBuf> = fds
+            .filter_map(|fd| fs::read_link(fd.ok()?.path()).ok())
+            .filter(|target| nodes.contains(target))
+            .collect();
+        open.sort();
+        open.dedup();
+        if open.is_empty() {
+            continue
+        }
+        let command = fs::read_to_string(proc.path().join("comm")).unwrap_or_default().trim().to_string();
+        found.extend(open.into_iter().map(|node| Holder{pid, command: command.clone(), node}));
+    }
+    found
+}
+
+/// Prints `pid command node` for every process with a node of a matching
+/// device open. Returns whether any was found.
+pub fn run(args: &FilterArgs) -> rusb::Result<bool> {
+    let filter = Filter::new(args);
+    let ctx = rusb::Context::new()?;
+    let mut any = false;
+    for entry in inventory::scan(&ctx, &filter)? {
+        let dev = match inventory::find(&ctx, &entry) {
+            Some(dev) => dev,
+            None => continue,
+        };
+        let mut nodes = vec![udev::node(&dev)];
+        nodes.extend(sysfs::interface_nodes(&dev));
+        for holder in holders(&nodes) {
+            any = true;
+            println!("{} {} {}", holder.pid, holder.command, holder.node.display());
+        }
+    }
+    Ok(any)
+}
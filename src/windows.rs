@@ -0,0 +1,71 @@
+use std::process::Command;
+
+use crate::matcher::Matcher;
+use crate::DeviceID;
+
+/// A device Windows knows about but couldn't start, as Device Manager
+/// shows with a yellow mark.
+pub struct Problem {
+    pub instance_id: String,
+    pub name: String,
+    /// The CM_PROB_ code, e.g. 28 no driver installed, 43 the device
+    /// reported a failure or its descriptor couldn't be read.
+    pub code: u32,
+    /// The code's name, e.g. CM_PROB_FAILED_POST_START.
+    pub reason: String,
+}
+
+impl Problem {
+    /// The vid:pid in an instance id such as `USB\VID_1A2B&PID_5678\0001`.
+    pub fn id(&self) -> Option<DeviceID> {
+        let upper = self.instance_id.to_uppercase();
+        let hex = |key: &str| {
+            let start = upper.find(key)? + key.len();
+            u16::from_str_radix(upper.get(start..start + 4)?, 16).ok()
+        };
+        Some(DeviceID{vid: hex("VID_")?, pid: hex("PID_")?})
+    }
+}
+
+/// Parses the `Key: value` blocks of `pnputil /enum-devices /problem`,
+/// one per device, separated by blank lines.
+fn parse(output: &str) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for block in output.replace("\r\n", "\n").split("\n\n") {
+        let value = |key: &str| block
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(key)?.trim_start().strip_prefix(':').map(|v| v.trim().to_string()));
+        let (instance_id, code) = match (value("Instance ID"), value("Problem Code")) {
+            (Some(instance_id), Some(code)) => (instance_id, code),
+            _ => continue,
+        };
+        // e.g. 43 (0x2B) [CM_PROB_FAILED_POST_START]
+        let number = code.split_whitespace().next().and_then(|n| n.parse().ok());
+        if let Some(number) = number.filter(|_| instance_id.to_uppercase().starts_with("USB\\")) {
+            problems.push(Problem{
+                instance_id,
+                name: value("Device Description").unwrap_or_default(),
+                code: number,
+                reason: code.split_once('[').map(|(_, r)| r.trim_end_matches(']').to_string()).unwrap_or_default(),
+            });
+        }
+    }
+    problems
+}
+
+/// USB devices in a problem state, from pnputil, which Windows 10 2004
+/// and later have. Empty elsewhere.
+pub fn problems() -> Vec<Problem> {
+    if !cfg!(windows) {
+        return Vec::new()
+    }
+    match Command::new("pnputil").args(["/enum-devices", "/problem"]).output() {
+        Ok(output) => parse(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The first device in a problem state that the filter's ids allow.
+pub fn problem(matcher: &Matcher) -> Option<Problem> {
+    problems().into_iter().find(|p| p.id().is_some_and(|id| matcher.matches_id(&id)))
+}
@@ -0,0 +1,38 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc;
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed pool of threads for slow actions. Jobs with the same key always
+/// go to the same thread, so they run one after another in the order they
+/// were queued while jobs with other keys run alongside.
+pub struct Workers {
+    queues: Vec<mpsc::Sender<Job>>,
+}
+
+impl Workers {
+    pub fn new(count: usize) -> Workers {
+        let queues = (0..count.max(1))
+            .map(|_| {
+                let (tx, rx) = mpsc::channel::<Job>();
+                // the thread ends once the pool is dropped and its queue drained
+                thread::spawn(move || {
+                    for job in rx {
+                        job();
+                    }
+                });
+                tx
+            })
+            .collect();
+        Workers{queues}
+    }
+
+    pub fn run(&self, key: &str, job: impl FnOnce() + Send + 'static) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let queue = &self.queues[hasher.finish() as usize % self.queues.len()];
+        _ = queue.send(Box::new(job));
+    }
+}
@@ -0,0 +1,22 @@
+use crate::json;
+
+/// Quotes a scalar. YAML reads double quoted scalars with the escapes JSON
+/// uses, so the JSON quoting serves both.
+pub fn string(s: &str) -> String {
+    json::string(s)
+}
+
+/// A flow sequence of already formatted YAML values.
+pub fn flow(values: &[String]) -> String {
+    format!("[{}]", values.join(", "))
+}
+
+/// Renders `key: value` pairs, whose values are already YAML, as one item
+/// of a block sequence.
+pub fn item(fields: &[(&str, String)]) -> String {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, (key, value))| format!("{} {}: {}\n", if i == 0 { "-" } else { " " }, key, value))
+        .collect()
+}
@@ -0,0 +1,60 @@
+use rusb::UsbContext;
+
+use crate::inventory::{self, Entry};
+use crate::json;
+use crate::DeviceID;
+
+/// Low-level discovery data, one entry per present device, to drive item
+/// prototypes keyed on {#ID}.
+pub fn discovery<T: UsbContext>(ctx: &T, devices: &[Entry]) -> String {
+    let data: Vec<String> = devices
+        .iter()
+        .map(|entry| {
+            let name = inventory::find(ctx, entry).map(|dev| inventory::name(&dev)).unwrap_or_default();
+            json::object(&[
+                ("{#ID}", json::string(&entry.id.to_string())),
+                ("{#VID}", json::string(&format!("{:04x}", entry.id.vid))),
+                ("{#PID}", json::string(&format!("{:04x}", entry.id.pid))),
+                ("{#BUS}", json::string(&format!("{:03}", entry.bus))),
+                ("{#ADDRESS}", json::string(&format!("{:03}", entry.address))),
+                ("{#NAME}", json::string(&name)),
+            ])
+        })
+        .collect();
+    json::object(&[("data", json::array(&data))])
+}
+
+/// How many devices of each id are present, for dependent items to pick
+/// apart with JSONPath such as `$["1a2b:5678"]`: one key per id, 0 for an
+/// id that isn't there. Without ids every present id is a key.
+pub fn presence(ids: &[DeviceID], devices: &[Entry]) -> String {
+    let mut keys: Vec<&DeviceID> = ids.iter().collect();
+    for entry in devices.iter().filter(|_| ids.is_empty()) {
+        if !keys.contains(&&entry.id) {
+            keys.push(&entry.id);
+        }
+    }
+    let fields: Vec<(String, String)> = keys
+        .into_iter()
+        .map(|id| (id.to_string(), devices.iter().filter(|e| &e.id == id).count().to_string()))
+        .collect();
+    json::object(&fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(address: u8, id: &str) -> Entry {
+        Entry{bus: 1, address, id: crate::parse_device(id).unwrap(), port: format!("1-{}", address)}
+    }
+
+    #[test]
+    fn presence_counts_each_id_once() {
+        let devices = [entry(2, "1d50:6018"), entry(3, "483:df11"), entry(4, "1d50:6018")];
+        assert_eq!(presence(&[], &devices), r#"{"1d50:6018":2,"483:df11":1}"#);
+        let ids = [crate::parse_device("483:df11").unwrap(), crate::parse_device("46d:c52b").unwrap()];
+        assert_eq!(presence(&ids, &devices), r#"{"483:df11":1,"46d:c52b":0}"#);
+        assert_eq!(presence(&[], &[]), "{}");
+    }
+}